@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether typing `c` ends a word, expanding an abbreviation before it.
+pub fn ends_word(c: char) -> bool {
+    !is_word(c)
+}
+
+/// Finds the abbreviation that ends at byte `x` of `line`: a whole word,
+/// not the end of a longer one. Returns where it starts and what it expands
+/// to.
+pub fn find<'a>(
+    line: &str,
+    x: usize,
+    abbreviations: &'a HashMap<String, String>,
+) -> Option<(usize, &'a str)> {
+    let before = line.get(..x)?;
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map(|(i, _)| i)?;
+    abbreviations
+        .get(&before[start..])
+        .map(|expansion| (start, expansion.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let abbreviations = HashMap::from([("teh".to_string(), "the".to_string())]);
+        assert_eq!(find("see teh", 7, &abbreviations), Some((4, "the")));
+        assert_eq!(find("see teh", 6, &abbreviations), None);
+        assert_eq!(find("steh", 4, &abbreviations), None);
+        assert_eq!(find("(teh", 4, &abbreviations), Some((1, "the")));
+        assert!(ends_word(' ') && !ends_word('_'));
+    }
+}
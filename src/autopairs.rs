@@ -0,0 +1,83 @@
+/// The characters that get closed as they're typed in insert mode.
+const PAIRS: [(char, char); 5] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// What typing a character does with autopairs on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Typed {
+    /// Inserts the character followed by its closer.
+    Pair(char),
+    /// Moves over the closer already under the cursor.
+    SkipOver,
+    /// Inserts the character as usual.
+    Plain,
+}
+
+fn closer(c: char) -> Option<char> {
+    PAIRS
+        .iter()
+        .find(|(open, _)| *open == c)
+        .map(|(_, close)| *close)
+}
+
+fn is_closer(c: char) -> bool {
+    PAIRS.iter().any(|(_, close)| *close == c)
+}
+
+/// Decides what typing `c` at `x` on `line` does. Characters in `except`
+/// aren't paired, like `'` for Rust lifetimes. Pairs are only opened in
+/// front of whitespace or a closer, and quotes not right after a word, so
+/// typing `don't` or wrapping existing text works as before.
+pub fn typed(line: &str, x: usize, c: char, except: &str) -> Typed {
+    let next = line.get(x..).and_then(|rest| rest.chars().next());
+    let prev = line.get(..x).and_then(|before| before.chars().next_back());
+    if is_closer(c) && next == Some(c) && !except.contains(c) {
+        return Typed::SkipOver;
+    }
+    let Some(close) = closer(c).filter(|_| !except.contains(c)) else {
+        return Typed::Plain;
+    };
+    let opens = next.is_none_or(|n| n.is_whitespace() || is_closer(n));
+    let after_word = prev.is_some_and(|p| p.is_alphanumeric() || p == '_');
+    match opens && !(c == close && after_word) {
+        true => Typed::Pair(close),
+        false => Typed::Plain,
+    }
+}
+
+/// Whether backspace at `x` on `line` deletes a pair: the cursor is between
+/// an opener and its closer, like `(|)`.
+pub fn deletes_pair(line: &str, x: usize, except: &str) -> bool {
+    let next = line.get(x..).and_then(|rest| rest.chars().next());
+    let prev = line.get(..x).and_then(|before| before.chars().next_back());
+    match (prev, next) {
+        (Some(open), Some(next)) => {
+            !except.contains(open) && closer(open).is_some_and(|close| close == next)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_typed() {
+        assert_eq!(typed("foo", 3, '(', ""), Typed::Pair(')'));
+        assert_eq!(typed("foo()", 4, ')', ""), Typed::SkipOver);
+        assert_eq!(typed("foo bar", 4, '(', ""), Typed::Plain);
+        assert_eq!(typed("don", 3, '\'', ""), Typed::Plain);
+        assert_eq!(typed("x = ", 4, '"', ""), Typed::Pair('"'));
+        assert_eq!(typed("\"\"", 1, '"', ""), Typed::SkipOver);
+        assert_eq!(typed("fn f<", 5, '\'', "'"), Typed::Plain);
+        assert_eq!(typed("a", 1, 'b', ""), Typed::Plain);
+    }
+
+    #[test]
+    fn test_deletes_pair() {
+        assert!(deletes_pair("f()", 2, ""));
+        assert!(deletes_pair("\"\"", 1, ""));
+        assert!(!deletes_pair("f(x)", 3, ""));
+        assert!(!deletes_pair("''", 1, "'"));
+    }
+}
@@ -0,0 +1,90 @@
+/// A rectangular selection in visual block mode: columns `left..right` of
+/// lines `top..=bottom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Block {
+    /// The block with corners at the anchor and the cursor, both `(x, line)`,
+    /// including the columns they're on.
+    pub fn new(anchor: (usize, usize), cursor: (usize, usize)) -> Block {
+        Block {
+            top: anchor.1.min(cursor.1),
+            bottom: anchor.1.max(cursor.1),
+            left: anchor.0.min(cursor.0),
+            right: anchor.0.max(cursor.0) + 1,
+        }
+    }
+
+    pub fn contains(&self, x: usize, line: usize) -> bool {
+        (self.top..=self.bottom).contains(&line) && (self.left..self.right).contains(&x)
+    }
+
+    /// The part of `text`, a line in the block, that's selected, as a byte
+    /// range. It's empty for lines that end before the block.
+    pub fn range(&self, text: &str) -> (usize, usize) {
+        let mut start = self.left.min(text.len());
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = self.right.min(text.len()).max(start);
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        (start, end)
+    }
+}
+
+/// Where a line of a pasted block goes on `line`, which gets it at column
+/// `x`, and the text to insert there: lines ending before `x` are padded with
+/// spaces, and the piece is padded to the block's `width` when there's text
+/// after it, to keep that text lined up.
+pub fn paste_text(line: &str, x: usize, piece: &str, width: usize) -> (usize, String) {
+    if line.len() < x {
+        let padding = " ".repeat(x - line.len());
+        return (line.len(), format!("{padding}{piece}"));
+    }
+    let mut x = x;
+    while !line.is_char_boundary(x) {
+        x += 1;
+    }
+    match x < line.len() {
+        true => (x, format!("{piece:width$}")),
+        false => (x, piece.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_block() {
+        let block = Block::new((4, 3), (1, 1));
+        assert_eq!(
+            block,
+            Block {
+                top: 1,
+                bottom: 3,
+                left: 1,
+                right: 5
+            }
+        );
+        assert!(block.contains(4, 2));
+        assert!(!block.contains(5, 2));
+        assert_eq!(block.range("abcdefg"), (1, 5));
+        assert_eq!(block.range("abc"), (1, 3));
+        assert_eq!(block.range(""), (0, 0));
+    }
+
+    #[test]
+    fn test_paste_text() {
+        assert_eq!(paste_text("abcdef", 2, "xy", 3), (2, "xy ".to_string()));
+        assert_eq!(paste_text("ab", 2, "xy", 3), (2, "xy".to_string()));
+        assert_eq!(paste_text("a", 3, "xy", 3), (1, "  xy".to_string()));
+    }
+}
@@ -1,31 +1,289 @@
-use std::path::Path;
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::{InputEdit, Point};
+
+use crate::{dirlist, editorconfig, encoding::Encoding, hex, language};
+
+/// How many lines a large file is read in at a time.
+const LOAD_CHUNK_LINES: usize = 50_000;
+
+pub const BRACKET_PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Returns the `(open, close)` pair `c` belongs to, if it's a bracket.
+pub fn bracket_pair(c: char) -> Option<(char, char)> {
+    BRACKET_PAIRS
+        .iter()
+        .find(|(open, close)| c == *open || c == *close)
+        .copied()
+}
+
+pub fn is_bracket(c: char) -> bool {
+    bracket_pair(c).is_some()
+}
+
+/// How lines end in the file: `\n` on unix, `\r\n` on dos. Lines are kept
+/// without either and get them back on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl FileFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            _ => None,
+        }
+    }
+
+    /// Goes by how the first line ends.
+    pub fn detect(contents: &str) -> Self {
+        match contents.find('\n') {
+            Some(end) if contents[..end].ends_with('\r') => FileFormat::Dos,
+            _ => FileFormat::Unix,
+        }
+    }
+
+    fn line_ending(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Buffer {
     pub file: Option<String>,
     pub lines: Vec<String>,
+    pub filetype: String,
+    /// Whether the buffer was edited since it was loaded or saved.
+    pub modified: bool,
+    /// Whether edits are refused, for `rustik -R` and `:set readonly`.
+    pub readonly: bool,
+    /// Whether the file ends with a newline.
+    pub final_newline: bool,
+    /// The file's modification time when it was last loaded or saved.
+    pub disk_modified: Option<SystemTime>,
+    /// Whether the file is over the `large_file` size, so it's read in the
+    /// background and the expensive features are left off.
+    pub large: bool,
+    /// Whether the lines are a hex dump of the file's bytes, for files that
+    /// aren't UTF-8 and `rustik --hex`.
+    pub hex: bool,
+    /// Whether the lines list the entries of the directory `file`, to open
+    /// them with Enter.
+    pub directory: bool,
+    /// Set by `:lcd`: the directory the finder and grep search from this
+    /// buffer, and relative files in `:e` are opened from.
+    pub local_dir: Option<PathBuf>,
+    /// Whether inlay hints are shown, when `:hints` set it for this buffer.
+    pub inlay_hints: Option<bool>,
+    /// How the file's text is stored, to write it back the same way.
+    pub encoding: Encoding,
+    pub fileformat: FileFormat,
+    loading: Option<Receiver<Loaded>>,
+    edits: Vec<InputEdit>,
+}
+
+/// What the thread reading a large file sends back.
+#[derive(Debug)]
+enum Loaded {
+    Lines(Vec<String>),
+    /// The end of the file, whether it ends with a newline and how lines
+    /// end.
+    Done(bool, FileFormat),
+}
+
+/// When `file` was last written, when it can be read.
+pub fn modified_time(file: impl AsRef<Path>) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
 }
 
 impl Buffer {
     pub fn new(file: Option<String>, contents: String) -> Self {
         let lines = contents.lines().map(|s| s.to_string()).collect();
-        Self { file, lines }
+        let final_newline = contents.is_empty() || contents.ends_with('\n');
+        let fileformat = FileFormat::detect(&contents);
+        let filetype = language::detect(file.as_deref()).name.to_string();
+        Self {
+            file,
+            lines,
+            filetype,
+            modified: false,
+            readonly: false,
+            final_newline,
+            disk_modified: None,
+            large: false,
+            hex: false,
+            directory: false,
+            local_dir: None,
+            inlay_hints: None,
+            encoding: Encoding::Utf8,
+            fileformat,
+            loading: None,
+            edits: vec![],
+        }
     }
 
-    pub fn from_file(file: Option<String>) -> anyhow::Result<Self> {
+    /// Loads `file`, or starts reading it in the background when it's over
+    /// `large_size` bytes. A directory is listed instead.
+    pub fn from_file(file: Option<String>, large_size: u64) -> anyhow::Result<Self> {
         match &file {
             Some(file) => {
                 let path = Path::new(file);
                 if !path.exists() {
                     return Err(anyhow::anyhow!("file {:?} not found", file));
                 }
-                let contents = std::fs::read_to_string(file)?;
-                Ok(Self::new(Some(file.to_string()), contents.to_string()))
+                if path.is_dir() {
+                    return Self::from_dir(file);
+                }
+                if fs::metadata(path)?.len() > large_size {
+                    return Self::from_large_file(file);
+                }
+                let bytes = fs::read(file)?;
+                // a `charset` from .editorconfig beats guessing between UTF-8
+                // and Latin-1, but not a byte order mark
+                let charset = editorconfig::properties(path).charset;
+                let encoding = match (Encoding::detect(&bytes), charset) {
+                    (
+                        Some(Encoding::Utf8 | Encoding::Latin1),
+                        Some(charset @ (Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Latin1)),
+                    ) => Some(charset),
+                    (detected, _) => detected,
+                };
+                let Some(encoding) = encoding else {
+                    return Ok(Self::from_hex(file, &bytes));
+                };
+                let mut buffer = Self::new(Some(file.to_string()), encoding.decode(&bytes));
+                buffer.encoding = encoding;
+                buffer.disk_modified = modified_time(file);
+                Ok(buffer)
             }
             None => Ok(Self::new(file, String::new())),
         }
     }
 
+    /// Loads `file` as a hex dump, to edit its bytes.
+    pub fn from_hex_file(file: &str) -> anyhow::Result<Self> {
+        Ok(Self::from_hex(file, &fs::read(file)?))
+    }
+
+    fn from_hex(file: &str, bytes: &[u8]) -> Self {
+        let mut buffer = Self::new(Some(file.to_string()), hex::dump(bytes));
+        buffer.filetype = "hex".to_string();
+        buffer.disk_modified = modified_time(file);
+        buffer.hex = true;
+        buffer
+    }
+
+    /// Lists the entries of `dir`, read-only. Its modification time changes
+    /// with its entries, so the listing is reloaded like a file.
+    fn from_dir(dir: &str) -> anyhow::Result<Self> {
+        let lines = dirlist::list(Path::new(dir))?;
+        let mut buffer = Self::new(Some(dir.to_string()), lines.join("\n"));
+        buffer.filetype = language::plain_text().name.to_string();
+        buffer.readonly = true;
+        buffer.directory = true;
+        buffer.disk_modified = modified_time(dir);
+        Ok(buffer)
+    }
+
+    /// Starts with no lines and reads them on another thread, to be picked
+    /// up by `receive_lines`. Large files are always plain text.
+    fn from_large_file(file: &str) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(fs::File::open(file)?);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut lines = Vec::with_capacity(LOAD_CHUNK_LINES);
+            let mut line = vec![];
+            let mut final_newline = true;
+            let mut fileformat = None;
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                final_newline = line.ends_with(b"\n");
+                fileformat.get_or_insert(match line.ends_with(b"\r\n") {
+                    true => FileFormat::Dos,
+                    false => FileFormat::Unix,
+                });
+                let end = line.len() - usize::from(line.ends_with(b"\n"));
+                let end = end - usize::from(line[..end].ends_with(b"\r"));
+                lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+                if lines.len() == LOAD_CHUNK_LINES {
+                    let chunk = std::mem::replace(&mut lines, Vec::with_capacity(LOAD_CHUNK_LINES));
+                    if tx.send(Loaded::Lines(chunk)).is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = tx.send(Loaded::Lines(lines));
+            _ = tx.send(Loaded::Done(final_newline, fileformat.unwrap_or_default()));
+        });
+
+        let mut buffer = Self::new(Some(file.to_string()), String::new());
+        buffer.filetype = language::plain_text().name.to_string();
+        buffer.disk_modified = modified_time(file);
+        buffer.large = true;
+        buffer.loading = Some(rx);
+        Ok(buffer)
+    }
+
+    /// Whether lines of a large file are still being read.
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Appends the lines read since the last call, returning whether there
+    /// were any.
+    pub fn receive_lines(&mut self) -> bool {
+        let Some(loading) = &self.loading else {
+            return false;
+        };
+        let mut received = false;
+        loop {
+            match loading.try_recv() {
+                Ok(Loaded::Lines(lines)) => {
+                    self.lines.extend(lines);
+                    received = true;
+                }
+                Ok(Loaded::Done(final_newline, fileformat)) => {
+                    self.final_newline = final_newline;
+                    self.fileformat = fileformat;
+                    self.loading = None;
+                    return true;
+                }
+                Err(TryRecvError::Empty) => return received,
+                Err(TryRecvError::Disconnected) => {
+                    self.loading = None;
+                    return true;
+                }
+            }
+        }
+    }
+
     pub fn get(&self, line: usize) -> Option<String> {
         if self.lines.len() > line {
             return Some(self.lines[line].clone());
@@ -39,53 +297,536 @@ impl Buffer {
     }
 
     pub fn insert(&mut self, x: usize, y: usize, c: char) {
-        if let Some(line) = self.lines.get_mut(y as usize) {
-            (*line).insert(x as usize, c);
+        // an empty buffer has no line to type on until the first character
+        if self.lines.is_empty() && y == 0 {
+            self.lines.push(String::new());
+        }
+        let start_byte = self.byte_offset(y) + x;
+        if let Some(line) = self.lines.get_mut(y) {
+            (*line).insert(x, c);
+            self.push_edit(InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte: start_byte + c.len_utf8(),
+                start_position: Point::new(y, x),
+                old_end_position: Point::new(y, x),
+                new_end_position: Point::new(y, x + c.len_utf8()),
+            });
         }
     }
 
     pub fn insert_line(&mut self, line: usize, content: String) {
+        let start_byte = self.byte_offset(line);
+        let edit = if line < self.len() {
+            // inserts `content\n` in front of the existing line
+            InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte: start_byte + content.len() + 1,
+                start_position: Point::new(line, 0),
+                old_end_position: Point::new(line, 0),
+                new_end_position: Point::new(line + 1, 0),
+            }
+        } else if line == 0 {
+            InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte: start_byte + content.len(),
+                start_position: Point::new(0, 0),
+                old_end_position: Point::new(0, 0),
+                new_end_position: Point::new(0, content.len()),
+            }
+        } else {
+            // appends `\ncontent` after the last line
+            let last = self.lines[line - 1].len();
+            InputEdit {
+                start_byte: start_byte - 1,
+                old_end_byte: start_byte - 1,
+                new_end_byte: start_byte + content.len(),
+                start_position: Point::new(line - 1, last),
+                old_end_position: Point::new(line - 1, last),
+                new_end_position: Point::new(line, content.len()),
+            }
+        };
         self.lines.insert(line, content);
+        self.push_edit(edit);
     }
 
     pub fn remove(&mut self, x: usize, y: usize) {
-        if let Some(line) = self.lines.get_mut(y as usize) {
-            (*line).remove(x as usize);
+        let start_byte = self.byte_offset(y) + x;
+        if let Some(line) = self.lines.get_mut(y) {
+            let c = (*line).remove(x);
+            self.push_edit(InputEdit {
+                start_byte,
+                old_end_byte: start_byte + c.len_utf8(),
+                new_end_byte: start_byte,
+                start_position: Point::new(y, x),
+                old_end_position: Point::new(y, x + c.len_utf8()),
+                new_end_position: Point::new(y, x),
+            });
         }
     }
 
     pub fn remove_line(&mut self, line: usize) {
         if self.len() > line {
+            let start_byte = self.byte_offset(line);
+            let len = self.lines[line].len();
+            let edit = if line + 1 < self.len() {
+                // removes `content\n`
+                InputEdit {
+                    start_byte,
+                    old_end_byte: start_byte + len + 1,
+                    new_end_byte: start_byte,
+                    start_position: Point::new(line, 0),
+                    old_end_position: Point::new(line + 1, 0),
+                    new_end_position: Point::new(line, 0),
+                }
+            } else if line > 0 {
+                // removes `\ncontent` from the end of the document
+                let prev = self.lines[line - 1].len();
+                InputEdit {
+                    start_byte: start_byte - 1,
+                    old_end_byte: start_byte + len,
+                    new_end_byte: start_byte - 1,
+                    start_position: Point::new(line - 1, prev),
+                    old_end_position: Point::new(line, len),
+                    new_end_position: Point::new(line - 1, prev),
+                }
+            } else {
+                InputEdit {
+                    start_byte,
+                    old_end_byte: start_byte + len,
+                    new_end_byte: start_byte,
+                    start_position: Point::new(line, 0),
+                    old_end_position: Point::new(line, len),
+                    new_end_position: Point::new(line, 0),
+                }
+            };
             self.lines.remove(line);
+            self.push_edit(edit);
         }
     }
 
-    pub(crate) fn viewport(&self, vtop: usize, vheight: usize) -> String {
-        let height = std::cmp::min(vtop + vheight, self.lines.len());
-        self.lines[vtop..height].join("\n")
+    /// Inserts `text`, which may span several lines, at byte `x` of line `y`.
+    /// Returns the position right after the inserted text.
+    pub fn insert_text(&mut self, x: usize, y: usize, text: &str) -> (usize, usize) {
+        if self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        let (x, y) = self.clamp_position((x, y));
+        let start_byte = self.byte_offset(y) + x;
+        let tail = self.lines[y].split_off(x);
+
+        let mut parts = text.split('\n');
+        self.lines[y].push_str(parts.next().unwrap_or_default());
+        let mut end = (self.lines[y].len(), y);
+        for part in parts {
+            end = (part.len(), end.1 + 1);
+            self.lines.insert(end.1, part.to_string());
+        }
+        self.lines[end.1].push_str(&tail);
+
+        self.push_edit(InputEdit {
+            start_byte,
+            old_end_byte: start_byte,
+            new_end_byte: start_byte + text.len(),
+            start_position: Point::new(y, x),
+            old_end_position: Point::new(y, x),
+            new_end_position: Point::new(end.1, end.0),
+        });
+        end
+    }
+
+    /// The text from `start` up to, but not including, `end`.
+    pub fn range_text(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let Some((start, end)) = self.clamp_range(start, end) else {
+            return String::new();
+        };
+        if start.1 == end.1 {
+            return self.lines[start.1][start.0..end.0].to_string();
+        }
+        let mut text = self.lines[start.1][start.0..].to_string();
+        for line in &self.lines[start.1 + 1..end.1] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&self.lines[end.1][..end.0]);
+        text
+    }
+
+    /// Removes the text from `start` up to, but not including, `end`, both
+    /// `(x, y)` positions, and returns it.
+    pub fn remove_range(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let Some((start, end)) = self.clamp_range(start, end) else {
+            return String::new();
+        };
+        let start_byte = self.byte_offset(start.1) + start.0;
+        let end_byte = self.byte_offset(end.1) + end.0;
+
+        let removed = if start.1 == end.1 {
+            self.lines[start.1].drain(start.0..end.0).collect()
+        } else {
+            let tail = self.lines[end.1].split_off(end.0);
+            let mut removed = self.lines[start.1].split_off(start.0);
+            for line in self.lines.drain(start.1 + 1..=end.1) {
+                removed.push('\n');
+                removed.push_str(&line);
+            }
+            self.lines[start.1].push_str(&tail);
+            removed
+        };
+
+        self.push_edit(InputEdit {
+            start_byte,
+            old_end_byte: end_byte,
+            new_end_byte: start_byte,
+            start_position: Point::new(start.1, start.0),
+            old_end_position: Point::new(end.1, end.0),
+            new_end_position: Point::new(start.1, start.0),
+        });
+        removed
+    }
+
+    /// `(x, y)` moved onto the text: a line past the end to the end of the
+    /// last line, and a column past the end of its line, or inside a
+    /// character, back to where one starts. The buffer must have a line.
+    fn clamp_position(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let last = self.lines.len() - 1;
+        if y > last {
+            return (self.lines[last].len(), last);
+        }
+        let line = &self.lines[y];
+        let mut x = x.min(line.len());
+        while !line.is_char_boundary(x) {
+            x -= 1;
+        }
+        (x, y)
+    }
+
+    /// The range from `start` to `end` moved onto the text, `None` when
+    /// there's no text in it.
+    fn clamp_range(
+        &self,
+        start: (usize, usize),
+        end: (usize, usize),
+    ) -> Option<((usize, usize), (usize, usize))> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let (start, end) = (self.clamp_position(start), self.clamp_position(end));
+        ((start.1, start.0) < (end.1, end.0)).then_some((start, end))
+    }
+
+    /// Returns the byte offset where `line` starts in the joined document text.
+    pub fn byte_offset(&self, line: usize) -> usize {
+        self.lines.iter().take(line).map(|l| l.len() + 1).sum()
+    }
+
+    /// Converts a byte offset in the joined document text to an `(x, y)` position.
+    pub fn position(&self, byte: usize) -> (usize, usize) {
+        let mut offset = 0;
+        for (y, line) in self.lines.iter().enumerate() {
+            if byte <= offset + line.len() {
+                return (byte - offset, y);
+            }
+            offset += line.len() + 1;
+        }
+        let last = self.len().saturating_sub(1);
+        (self.lines.get(last).map_or(0, |l| l.len()), last)
+    }
+
+    /// Finds the bracket paired with the one at `(x, y)` by counting nesting
+    /// depth, scanning forward from an opening bracket or backward from a
+    /// closing one.
+    pub fn matching_bracket(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        let c = self.lines.get(y)?.get(x..)?.chars().next()?;
+        let (open, close) = bracket_pair(c)?;
+        let mut depth = 0;
+
+        if c == open {
+            for (ly, line) in self.lines.iter().enumerate().skip(y) {
+                let from = if ly == y { x } else { 0 };
+                for (lx, ch) in line[from..].char_indices() {
+                    if ch == open {
+                        depth += 1;
+                    } else if ch == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some((from + lx, ly));
+                        }
+                    }
+                }
+            }
+        } else {
+            for ly in (0..=y).rev() {
+                let line = &self.lines[ly];
+                let to = if ly == y {
+                    x + c.len_utf8()
+                } else {
+                    line.len()
+                };
+                for (lx, ch) in line[..to].char_indices().rev() {
+                    if ch == close {
+                        depth += 1;
+                    } else if ch == open {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some((lx, ly));
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Writes the buffer to its file.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let Some(file) = &self.file else {
+            anyhow::bail!("No file name");
+        };
+        fs::write(file, self.contents()?)?;
+        self.disk_modified = modified_time(file);
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Writes the buffer to `path` without making it the buffer's file.
+    pub fn write_to(&self, path: &str) -> anyhow::Result<()> {
+        fs::write(path, self.contents()?)?;
+        Ok(())
+    }
+
+    /// The bytes the buffer is written as, in its encoding and line endings.
+    fn contents(&self) -> anyhow::Result<Vec<u8>> {
+        if self.hex {
+            return hex::parse(&self.lines);
+        }
+        let ending = self.fileformat.line_ending();
+        let mut contents = self.lines.join(ending);
+        if self.final_newline && !self.lines.is_empty() {
+            contents.push_str(ending);
+        }
+        self.encoding.encode(&contents)
+    }
+
+    /// Names the file a buffer without one is written to. The filetype comes
+    /// from the name unless one was set already.
+    pub fn set_file(&mut self, file: String) {
+        if self.filetype == language::plain_text().name {
+            self.filetype = language::detect(Some(&file)).name.to_string();
+        }
+        self.file = Some(file);
+    }
+
+    /// Returns the file's modification time if it changed since it was last
+    /// loaded or saved.
+    pub fn changed_on_disk(&self) -> Option<SystemTime> {
+        let modified = modified_time(self.file.as_deref()?)?;
+        (self.disk_modified != Some(modified)).then_some(modified)
+    }
+
+    /// Joins line `y` with the next one like vim's `J`: the next line's
+    /// indent is dropped and a space goes in between, unless the line ends in
+    /// whitespace, either line is empty or the next one starts with `)`.
+    /// Returns where they were joined, the removed text and the space added.
+    pub fn join_line(&mut self, y: usize) -> Option<(usize, String, &'static str)> {
+        let next = self.get(y + 1)?;
+        let line = self.get(y)?;
+        let x = line.len();
+        let indent = next.len() - next.trim_start().len();
+        let rest = &next[indent..];
+        let separator = match line.is_empty()
+            || line.ends_with(char::is_whitespace)
+            || rest.is_empty()
+            || rest.starts_with(')')
+        {
+            true => "",
+            false => " ",
+        };
+        let removed = self.remove_range((x, y), (indent, y + 1));
+        if !separator.is_empty() {
+            self.insert_text(x, y, separator);
+        }
+        Some((x, removed, separator))
+    }
+
+    /// Removes whitespace at the end of every line, returning the removed
+    /// text by `(x, y)` position.
+    pub fn trim_trailing_whitespace(&mut self) -> Vec<(usize, usize, String)> {
+        let mut trimmed = vec![];
+        for y in 0..self.lines.len() {
+            let len = self.lines[y].len();
+            let end = self.lines[y].trim_end().len();
+            if end < len {
+                let removed = self.remove_range((end, y), (len, y));
+                trimmed.push((end, y, removed));
+            }
+        }
+        trimmed
+    }
+
+    fn push_edit(&mut self, edit: InputEdit) {
+        self.modified = true;
+        self.edits.push(edit);
+    }
+
+    /// Drains the edits made since the last call, for incremental reparsing.
+    pub fn take_edits(&mut self) -> Vec<InputEdit> {
+        std::mem::take(&mut self.edits)
     }
 }
 
 #[cfg(test)]
-
 mod test {
     use super::*;
 
     #[test]
-    fn test_viewport() {
-        let buffer = Buffer::new(Some("sample.txt".to_string()), "a\nb".to_string());
-        assert_eq!(buffer.viewport(0, 5), "a\nb".to_string());
+    fn test_trim_trailing_whitespace() {
+        let mut buffer = Buffer::new(None, "a  \nb\n\t\nc d \t".to_string());
+        assert!(!buffer.final_newline);
+        let trimmed = buffer.trim_trailing_whitespace();
+        assert_eq!(buffer.text(), "a\nb\n\nc d");
+        assert_eq!(
+            trimmed,
+            vec![
+                (1, 0, "  ".to_string()),
+                (0, 2, "\t".to_string()),
+                (3, 3, " \t".to_string())
+            ]
+        );
+        assert!(buffer.modified);
     }
 
     #[test]
-    fn test_viewport_with_small_buffer() {
-        let buffer = Buffer::new(
-            Some("sample.txt".to_string()),
-            "fn main() {\n    println!(\"Hello, world!\");\n    }".to_string(),
-        );
-        assert_eq!(
-            buffer.viewport(0, 2),
-            "fn main() {\n    println!(\"Hello, world!\");".to_string()
-        );
+    fn test_join_line() {
+        let mut buffer = Buffer::new(None, "if a {\n    b\n\n    )\nend".to_string());
+        assert_eq!(buffer.join_line(0), Some((6, "\n    ".to_string(), " ")));
+        assert_eq!(buffer.get(0).unwrap(), "if a { b");
+        assert_eq!(buffer.join_line(0), Some((8, "\n".to_string(), "")));
+        assert_eq!(buffer.join_line(0), Some((8, "\n    ".to_string(), "")));
+        assert_eq!(buffer.text(), "if a { b)\nend");
+        assert_eq!(buffer.join_line(1), None);
+    }
+
+    #[test]
+    fn test_filetype_from_extension() {
+        let buffer = Buffer::new(Some("src/main.rs".to_string()), String::new());
+        assert_eq!(buffer.filetype, "rust");
+
+        let mut buffer = Buffer::new(None, String::new());
+        assert_eq!(buffer.filetype, "text");
+        buffer.set_file("notes.md".to_string());
+        assert_eq!(buffer.filetype, "markdown");
+
+        // a filetype set by hand stays
+        let mut buffer = Buffer::new(None, String::new());
+        buffer.filetype = "rust".to_string();
+        buffer.set_file("scratch.txt".to_string());
+        assert_eq!(buffer.filetype, "rust");
+    }
+
+    #[test]
+    fn test_edits_track_byte_offsets() {
+        let mut buffer = Buffer::new(None, "ab\ncd".to_string());
+        buffer.insert(1, 1, 'x');
+        buffer.remove_line(0);
+        buffer.insert_line(1, "ef".to_string());
+
+        let edits = buffer.take_edits();
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[0].start_byte, 4);
+        assert_eq!(edits[0].new_end_byte, 5);
+        assert_eq!(edits[1].old_end_byte, 3);
+        assert_eq!(edits[2].start_byte, 3);
+        assert_eq!(edits[2].new_end_byte, 6);
+        assert_eq!(buffer.text(), "cxd\nef");
+        assert!(buffer.take_edits().is_empty());
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let buffer = Buffer::new(None, "fn a(b: [u8; 2]) {\n    (b)\n}".to_string());
+        assert_eq!(buffer.matching_bracket(4, 0), Some((15, 0)));
+        assert_eq!(buffer.matching_bracket(15, 0), Some((4, 0)));
+        assert_eq!(buffer.matching_bracket(8, 0), Some((14, 0)));
+        assert_eq!(buffer.matching_bracket(17, 0), Some((0, 2)));
+        assert_eq!(buffer.matching_bracket(0, 2), Some((17, 0)));
+        assert_eq!(buffer.matching_bracket(0, 0), None);
+        assert_eq!(buffer.position(buffer.byte_offset(2)), (0, 2));
+    }
+
+    #[test]
+    fn test_insert_and_remove_ranges() {
+        let mut buffer = Buffer::new(None, "fn main() {\n}".to_string());
+        let end = buffer.insert_text(11, 0, "\n    let a = 1;\n    a");
+        assert_eq!(end, (5, 2));
+        assert_eq!(buffer.text(), "fn main() {\n    let a = 1;\n    a\n}");
+
+        let removed = buffer.remove_range((3, 0), (4, 2));
+        assert_eq!(removed, "main() {\n    let a = 1;\n    ");
+        assert_eq!(buffer.text(), "fn a\n}");
+
+        let edits = buffer.take_edits();
+        assert_eq!(edits[0].new_end_byte, 11 + 21);
+        assert_eq!(edits[1].start_byte, 3);
+        assert_eq!(edits[1].old_end_byte, 3 + 28);
+
+        let mut buffer = Buffer::new(None, String::new());
+        buffer.insert(0, 0, 'a');
+        assert_eq!(buffer.lines, ["a"]);
+    }
+
+    #[test]
+    fn test_ranges_out_of_bounds() {
+        let mut buffer = Buffer::new(None, String::new());
+        assert_eq!(buffer.range_text((0, 0), (1, 0)), "");
+        assert_eq!(buffer.remove_range((0, 0), (1, 0)), "");
+        assert!(buffer.take_edits().is_empty());
+
+        let mut buffer = Buffer::new(None, "ab\né".to_string());
+        assert_eq!(buffer.range_text((1, 0), (0, 5)), "b\né");
+        assert_eq!(buffer.remove_range((1, 1), (3, 0)), "");
+        assert_eq!(buffer.remove_range((0, 1), (1, 1)), "");
+        assert_eq!(buffer.insert_text(9, 7, "!"), (3, 1));
+        assert_eq!(buffer.text(), "ab\né!");
+    }
+
+    #[test]
+    fn test_large_file() {
+        let path = std::env::temp_dir().join("rustik-test-large.rs");
+        fs::write(&path, "fn a() {}\r\nb").unwrap();
+        let file = path.to_string_lossy().to_string();
+        let mut buffer = Buffer::from_file(Some(file), 4).unwrap();
+        assert!(buffer.large);
+        assert_eq!(buffer.filetype, "text");
+        while buffer.is_loading() {
+            buffer.receive_lines();
+        }
+        assert_eq!(buffer.lines, ["fn a() {}", "b"]);
+        assert!(!buffer.final_newline);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_fileformat() {
+        let path = std::env::temp_dir().join("rustik-test-dos.txt");
+        let file = path.to_string_lossy().to_string();
+        let mut buffer = Buffer::new(Some(file), "a\r\nb\r\n".to_string());
+        assert_eq!(buffer.fileformat, FileFormat::Dos);
+        assert_eq!(buffer.lines, ["a", "b"]);
+        buffer.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"a\r\nb\r\n");
+
+        buffer.fileformat = FileFormat::Unix;
+        buffer.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"a\nb\n");
+        fs::remove_file(path).unwrap();
     }
 }
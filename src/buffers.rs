@@ -0,0 +1,173 @@
+use crate::{buffer::Buffer, editor::Action};
+
+/// A buffer that's open but not being edited, with the cursor and undo
+/// history it had when it was left.
+#[derive(Debug)]
+pub struct Hidden {
+    pub buffer: Buffer,
+    pub x: usize,
+    pub line: usize,
+    pub vtop: usize,
+    pub undo_actions: Vec<Action>,
+}
+
+impl Hidden {
+    /// A buffer just opened, with the cursor at the start.
+    pub fn new(buffer: Buffer) -> Self {
+        Self {
+            buffer,
+            x: 0,
+            line: 0,
+            vtop: 0,
+            undo_actions: vec![],
+        }
+    }
+}
+
+/// The open buffers, numbered from 1 in the order they were opened. The
+/// current one lives in the editor, the rest are kept here.
+#[derive(Debug)]
+pub struct BufferList {
+    hidden: Vec<(usize, Hidden)>,
+    current: usize,
+    next: usize,
+}
+
+impl Default for BufferList {
+    fn default() -> Self {
+        Self {
+            hidden: vec![],
+            current: 1,
+            next: 2,
+        }
+    }
+}
+
+impl BufferList {
+    /// The current buffer's number.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The hidden buffers, most recently left first.
+    pub fn hidden(&self) -> impl Iterator<Item = (usize, &Hidden)> {
+        self.hidden.iter().rev().map(|(id, hidden)| (*id, hidden))
+    }
+
+    /// Puts away the current buffer and makes `id` current, or a new buffer
+    /// when it's `None`. Returns the number the current buffer now has.
+    pub fn switch(&mut self, left: Option<Hidden>, id: Option<usize>) -> usize {
+        if let Some(left) = left {
+            self.hidden.push((self.current, left));
+        }
+        self.current = id.unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        });
+        self.current
+    }
+
+    /// Adds a buffer that isn't shown yet, returning its number.
+    pub fn add(&mut self, hidden: Hidden) -> usize {
+        let id = self.next;
+        self.next += 1;
+        // left least recently
+        self.hidden.insert(0, (id, hidden));
+        id
+    }
+
+    /// Takes buffer `id` out of the list, to make it current or close it.
+    pub fn take(&mut self, id: usize) -> Option<Hidden> {
+        let index = self.hidden.iter().position(|(i, _)| *i == id)?;
+        Some(self.hidden.remove(index).1)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Hidden> {
+        self.hidden
+            .iter_mut()
+            .find(|(i, _)| *i == id)
+            .map(|(_, hidden)| hidden)
+    }
+
+    /// The number of the hidden buffer editing `file`.
+    pub fn find(&self, file: &str) -> Option<usize> {
+        self.hidden
+            .iter()
+            .find(|(_, hidden)| {
+                hidden
+                    .buffer
+                    .file
+                    .as_deref()
+                    .is_some_and(|f| same_file(f, file))
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// The buffer most recently left, to show when the current one closes.
+    pub fn last(&self) -> Option<usize> {
+        self.hidden.last().map(|(id, _)| *id)
+    }
+
+    /// Renames the files of the hidden buffers, after `:cd`.
+    pub fn rebase(&mut self, rename: impl Fn(&str) -> String) {
+        for (_, hidden) in &mut self.hidden {
+            hidden.buffer.file = hidden.buffer.file.as_deref().map(&rename);
+        }
+    }
+}
+
+/// Whether two paths name the same file. Files that don't exist yet only
+/// match by name.
+pub fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The line shown for a buffer in the list: its number, `%` for the current
+/// one, `+` when it has unsaved changes, and its file.
+pub fn describe(id: usize, buffer: &Buffer, current: bool) -> String {
+    format!(
+        "{id} {}{} {}",
+        if current { '%' } else { ' ' },
+        if buffer.modified { '+' } else { ' ' },
+        buffer.file.as_deref().unwrap_or("[No Name]")
+    )
+}
+
+/// The buffer number at the start of a line from `describe`.
+pub fn parse_id(item: &str) -> Option<usize> {
+    item.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hidden(file: &str) -> Hidden {
+        Hidden::new(Buffer::new(Some(file.to_string()), String::new()))
+    }
+
+    #[test]
+    fn test_switch() {
+        let mut list = BufferList::default();
+        assert_eq!(list.switch(Some(hidden("a")), None), 2);
+        assert_eq!(list.switch(Some(hidden("b")), None), 3);
+        assert_eq!(list.hidden().map(|(id, _)| id).collect::<Vec<_>>(), [2, 1]);
+        let a = list.take(1).unwrap();
+        assert_eq!(a.buffer.file.as_deref(), Some("a"));
+        assert_eq!(list.switch(Some(hidden("c")), Some(1)), 1);
+        assert_eq!(list.last(), Some(3));
+        assert_eq!(list.add(hidden("d")), 4);
+        assert_eq!(list.find("d"), Some(4));
+        assert_eq!(list.last(), Some(3));
+
+        let line = describe(3, &hidden("c").buffer, false);
+        assert_eq!(line, "3    c");
+        assert_eq!(parse_id(&line), Some(3));
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+
+use crate::logger::Level;
+
+const USAGE: &str =
+    "usage: rustik [-R] [--hex] [--config <path>] [--session <path>] [--log-file <path>] [--log-level <level>] [--print-position] [+line] [file[:line[:col]]]...
+       rustik --headless --script <actions.json> file";
+
+/// The command line: `rustik [-R] [--hex] [--config <path>] [--session <path>]
+/// [--log-file <path>] [--log-level <level>] [--print-position] [+line] [file[:line[:col]]]...`, where the file `-` is read from stdin, or
+/// `rustik --headless --script <actions.json> file`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub session: Option<PathBuf>,
+    /// The files to open, the first one shown.
+    pub files: Vec<String>,
+    /// The line to start on, counted from 1, `usize::MAX` for the last one.
+    pub line: Option<usize>,
+    /// The column to start on, counted from 1.
+    pub column: Option<usize>,
+    /// `-R`: opens the files read-only.
+    pub readonly: bool,
+    /// `--hex`: opens the files as hex dumps.
+    pub hex: bool,
+    /// `--headless`: runs the `--script` actions on the file and writes it,
+    /// without a terminal.
+    pub headless: bool,
+    pub script: Option<PathBuf>,
+    /// `--log-file`: where to log what happens, at `--log-level`.
+    pub log_file: Option<PathBuf>,
+    pub log_level: Option<Level>,
+    /// `--print-position`: prints `file:line:col` of the cursor on quit.
+    pub print_position: bool,
+}
+
+impl Args {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if let Some(path) = arg.strip_prefix("--config=") {
+                parsed.config = Some(path.into());
+                continue;
+            }
+            if let Some(path) = arg.strip_prefix("--session=") {
+                parsed.session = Some(path.into());
+                continue;
+            }
+            if let Some(path) = arg.strip_prefix("--script=") {
+                parsed.script = Some(path.into());
+                continue;
+            }
+            if let Some(path) = arg.strip_prefix("--log-file=") {
+                parsed.log_file = Some(path.into());
+                continue;
+            }
+            if let Some(level) = arg.strip_prefix("--log-level=") {
+                parsed.log_level = Some(log_level(level)?);
+                continue;
+            }
+            if let Some(line) = arg.strip_prefix('+') {
+                parsed.line = match line {
+                    "" => Some(usize::MAX),
+                    line => match line.parse() {
+                        Ok(line) => Some(line),
+                        Err(_) => bail!("invalid line {arg}\n{USAGE}"),
+                    },
+                };
+                continue;
+            }
+            match arg.as_str() {
+                "-R" => parsed.readonly = true,
+                "--hex" => parsed.hex = true,
+                "--headless" => parsed.headless = true,
+                "--print-position" => parsed.print_position = true,
+                "-c" | "--config" | "--session" | "--script" | "--log-file" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} needs a path\n{USAGE}");
+                    };
+                    match arg.as_str() {
+                        "--session" => parsed.session = Some(path.into()),
+                        "--script" => parsed.script = Some(path.into()),
+                        "--log-file" => parsed.log_file = Some(path.into()),
+                        _ => parsed.config = Some(path.into()),
+                    }
+                }
+                "--log-level" => {
+                    let Some(level) = args.next() else {
+                        bail!("{arg} needs a level\n{USAGE}");
+                    };
+                    parsed.log_level = Some(log_level(&level)?);
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    bail!("unknown option {arg}\n{USAGE}")
+                }
+                _ => {
+                    // only the first file's location is used, the others
+                    // open at the top
+                    let (file, line, column) = split_location(&arg);
+                    if parsed.files.is_empty() {
+                        parsed.line = line.or(parsed.line);
+                        parsed.column = column;
+                    }
+                    parsed.files.push(file.to_string());
+                }
+            }
+        }
+
+        if parsed.session.is_some() && !parsed.files.is_empty() {
+            bail!("--session can't be combined with a file\n{USAGE}");
+        }
+        if parsed.headless != parsed.script.is_some() {
+            bail!("--headless and --script go together\n{USAGE}");
+        }
+        if parsed.headless && parsed.files.len() != 1 {
+            bail!("--headless edits one file\n{USAGE}");
+        }
+        if parsed.log_level.is_some() && parsed.log_file.is_none() {
+            bail!("--log-level needs --log-file\n{USAGE}");
+        }
+        Ok(parsed)
+    }
+}
+
+fn log_level(name: &str) -> anyhow::Result<Level> {
+    match Level::parse(name) {
+        Some(level) => Ok(level),
+        None => bail!("unknown log level {name}, expected error, warn, info, debug or trace"),
+    }
+}
+
+/// Splits `file:line` and `file:line:col`, as printed by compilers and
+/// grep, into their parts. A file whose name looks like that is left alone.
+fn split_location(arg: &str) -> (&str, Option<usize>, Option<usize>) {
+    let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+    if Path::new(arg).exists() {
+        return (arg, None, None);
+    }
+    let mut numbers = vec![];
+    let mut file = trimmed;
+    while numbers.len() < 2 {
+        let Some((rest, number)) = file.rsplit_once(':') else {
+            break;
+        };
+        let Ok(number) = number.parse::<usize>() else {
+            break;
+        };
+        numbers.push(number);
+        file = rest;
+    }
+    match numbers[..] {
+        [line] => (file, Some(line), None),
+        [column, line] => (file, Some(line), Some(column)),
+        _ => (arg, None, None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["--config", "my.toml", "src/main.rs"]).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("my.toml")));
+        assert_eq!(args.files, ["src/main.rs"]);
+
+        let args = parse(&["--config=my.toml"]).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("my.toml")));
+        assert!(args.files.is_empty());
+
+        let args = parse(&["--session", "s.json"]).unwrap();
+        assert_eq!(args.session, Some(PathBuf::from("s.json")));
+        assert!(parse(&["--session=s.json", "a.rs"]).is_err());
+
+        assert!(parse(&["-R", "a.rs"]).unwrap().readonly);
+        assert!(parse(&["--hex", "a.bin"]).unwrap().hex);
+        assert!(parse(&["--print-position", "a.rs"]).unwrap().print_position);
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert_eq!(parse(&["a.rs", "b.rs"]).unwrap().files, ["a.rs", "b.rs"]);
+
+        let args = parse(&["--headless", "--script", "edits.json", "a.rs"]).unwrap();
+        assert!(args.headless);
+        assert_eq!(args.script, Some(PathBuf::from("edits.json")));
+        assert!(parse(&["--headless", "a.rs"]).is_err());
+        assert!(parse(&["--script=edits.json", "a.rs"]).is_err());
+        assert!(parse(&["--headless", "--script=edits.json"]).is_err());
+
+        let args = parse(&["--log-file", "rustik.log", "--log-level=debug"]).unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("rustik.log")));
+        assert_eq!(args.log_level, Some(Level::Debug));
+        assert!(parse(&["--log-file=rustik.log", "--log-level", "loud"]).is_err());
+        assert!(parse(&["--log-level", "info"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_location() {
+        let args = parse(&["src/none.rs:120:8"]).unwrap();
+        assert_eq!(args.files, ["src/none.rs"]);
+        assert_eq!((args.line, args.column), (Some(120), Some(8)));
+
+        let args = parse(&["+12", "a.rs"]).unwrap();
+        assert_eq!((args.files[0].as_str(), args.line), ("a.rs", Some(12)));
+
+        let args = parse(&["a.rs:7:"]).unwrap();
+        assert_eq!((args.files[0].as_str(), args.line), ("a.rs", Some(7)));
+        assert_eq!(parse(&["a.rs", "+"]).unwrap().line, Some(usize::MAX));
+        assert!(parse(&["+x"]).is_err());
+    }
+}
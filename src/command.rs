@@ -0,0 +1,514 @@
+use crate::{
+    buffer::FileFormat,
+    config::{KeyAction, LineNumbers},
+    editor::Action,
+    encoding::Encoding,
+    language,
+};
+
+/// Parses a command typed at the `:` prompt into the actions it runs.
+pub fn parse(command: &str) -> anyhow::Result<KeyAction> {
+    let command = command.trim();
+    let (name, args) = command
+        .split_once(char::is_whitespace)
+        .map(|(name, args)| (name, args.trim()))
+        .unwrap_or((command, ""));
+
+    if let Some(shell) = command.strip_prefix('!') {
+        return Ok(KeyAction::Single(Action::Shell(shell_command(shell)?)));
+    }
+    if let Some((range, shell)) = command.split_once('!') {
+        if let Some((from, to)) = parse_range(range) {
+            let shell = shell_command(shell)?;
+            return Ok(KeyAction::Single(Action::FilterLines(from, to, shell)));
+        }
+    }
+    if let Ok(line) = name.parse() {
+        return Ok(KeyAction::Single(Action::GotoLine(line)));
+    }
+
+    match name {
+        "$" => Ok(KeyAction::Single(Action::GotoLine(usize::MAX))),
+        "q" | "quit" => Ok(KeyAction::Single(Action::Quit)),
+        "q!" | "quit!" => Ok(KeyAction::Single(Action::ForceQuit)),
+        "w" | "write" | "w!" | "write!" if !args.is_empty() => Ok(KeyAction::Single(
+            Action::SaveAs(args.to_string(), name.ends_with('!')),
+        )),
+        "w" | "write" | "w!" | "write!" => Ok(KeyAction::Single(Action::Save)),
+        "wq" | "x" => Ok(KeyAction::Single(Action::SaveAndQuit)),
+        "e!" | "edit!" => Ok(KeyAction::Single(Action::ReloadFile)),
+        "e" | "edit" => match args {
+            "" => anyhow::bail!("Argument required"),
+            file => Ok(KeyAction::Single(Action::OpenFile(file.to_string()))),
+        },
+        "new" | "enew" => match args {
+            "" => Ok(KeyAction::Single(Action::NewBuffer)),
+            filetype => {
+                let Some(language) = language::find(filetype) else {
+                    anyhow::bail!("Unknown filetype: {filetype}");
+                };
+                Ok(KeyAction::Multiple(vec![
+                    Action::NewBuffer,
+                    Action::SetFiletype(language.name.to_string()),
+                ]))
+            }
+        },
+        "recover" | "rec" => Ok(KeyAction::Single(Action::RecoverSwap)),
+        "delswap" => Ok(KeyAction::Single(Action::DeleteSwap)),
+        "mksession" | "mks" => Ok(KeyAction::Single(Action::MakeSession(
+            (!args.is_empty()).then(|| args.to_string()),
+        ))),
+        "set" | "se" => parse_set(args),
+        "lint" => Ok(KeyAction::Single(Action::Lint)),
+        "mes" | "messages" => Ok(KeyAction::Single(Action::ShowMessages)),
+        "log" => Ok(KeyAction::Single(Action::ShowLog)),
+        "profile" => Ok(KeyAction::Single(Action::ToggleProfile)),
+        "st" | "stop" | "sus" | "suspend" => Ok(KeyAction::Single(Action::Suspend)),
+        "blame" => Ok(KeyAction::Single(Action::ToggleBlame)),
+        "theme" | "colo" | "colorscheme" => match args {
+            "" => Ok(KeyAction::Single(Action::PickTheme)),
+            name => Ok(KeyAction::Single(Action::SetTheme(name.to_string()))),
+        },
+        "vscode-theme" => match args {
+            "" => anyhow::bail!("Argument required"),
+            name => Ok(KeyAction::Single(Action::ImportTheme(name.to_string()))),
+        },
+        "config-reload" => Ok(KeyAction::Single(Action::ReloadConfig)),
+        "term" | "terminal" => Ok(KeyAction::Single(Action::ToggleTerminal)),
+        "r" | "read" => match args.strip_prefix('!') {
+            Some(shell) => Ok(KeyAction::Single(Action::ReadShell(shell_command(shell)?))),
+            None => anyhow::bail!("Only :r !cmd is supported"),
+        },
+        "format" => Ok(KeyAction::Single(Action::Format)),
+        "prose" => Ok(KeyAction::Single(Action::ToggleWrap)),
+        "mak" | "make" => Ok(KeyAction::Single(Action::Make(args.to_string()))),
+        "output" => Ok(KeyAction::Single(Action::ToggleMakeOutput)),
+        "rename" => Ok(KeyAction::Single(match args {
+            "" => Action::Rename,
+            name => Action::RenameTo(name.to_string()),
+        })),
+        "preview" => Ok(KeyAction::Single(Action::TogglePreview)),
+        "diff" => match args {
+            "" => anyhow::bail!("Argument required"),
+            file => Ok(KeyAction::Single(Action::DiffWith(file.to_string()))),
+        },
+        "diffthis" => Ok(KeyAction::Single(Action::DiffHead)),
+        "diffoff" => Ok(KeyAction::Single(Action::CloseDiff)),
+        "status" => Ok(KeyAction::Single(Action::GitStatus)),
+        "commit" => Ok(KeyAction::Single(Action::Commit)),
+        "zen" => Ok(KeyAction::Single(Action::ToggleZen)),
+        "hints" => Ok(KeyAction::Single(Action::ToggleInlayHints)),
+        "cn" | "cnext" => Ok(KeyAction::Single(Action::NextQuickfix)),
+        "cp" | "cprev" | "cN" | "cNext" => Ok(KeyAction::Single(Action::PrevQuickfix)),
+        "cope" | "copen" | "cw" | "cwindow" => Ok(KeyAction::Single(Action::OpenQuickfix)),
+        "cdiag" | "cdiagnostics" => Ok(KeyAction::Single(Action::QuickfixFromDiagnostics)),
+        "n" | "next" => Ok(KeyAction::Single(Action::NextArg)),
+        "prev" | "previous" | "N" | "Next" => Ok(KeyAction::Single(Action::PrevArg)),
+        "tabnew" | "tabe" | "tabedit" => Ok(KeyAction::Single(Action::TabNew(
+            (!args.is_empty()).then(|| args.to_string()),
+        ))),
+        "tabc" | "tabclose" => Ok(KeyAction::Single(Action::TabClose)),
+        "tabn" | "tabnext" => Ok(KeyAction::Single(Action::NextTab)),
+        "tabp" | "tabprevious" | "tabN" | "tabNext" => Ok(KeyAction::Single(Action::PrevTab)),
+        "ls" | "buffers" | "files" => Ok(KeyAction::Single(Action::ListBuffers)),
+        "recent" | "ol" | "oldfiles" => Ok(KeyAction::Single(Action::RecentFiles)),
+        "b" | "buffer" => match args.parse() {
+            Ok(id) => Ok(KeyAction::Single(Action::SwitchBuffer(id))),
+            Err(_) => anyhow::bail!("Invalid buffer number: {args}"),
+        },
+        "bd" | "bdelete" | "bd!" | "bdelete!" => {
+            let id = match args {
+                "" => None,
+                id => match id.parse() {
+                    Ok(id) => Some(id),
+                    Err(_) => anyhow::bail!("Invalid buffer number: {id}"),
+                },
+            };
+            let force = name.ends_with('!');
+            Ok(KeyAction::Single(Action::CloseBuffer(id, force)))
+        }
+        "grep" | "gr" => {
+            if args.is_empty() {
+                anyhow::bail!("Argument required");
+            }
+            Ok(KeyAction::Single(Action::Grep(args.to_string())))
+        }
+        "cd" | "chdir" => Ok(KeyAction::Single(Action::ChangeDir(
+            (!args.is_empty()).then(|| args.to_string()),
+        ))),
+        "lcd" | "lchdir" => Ok(KeyAction::Single(Action::LocalChangeDir(
+            (!args.is_empty()).then(|| args.to_string()),
+        ))),
+        "pwd" => Ok(KeyAction::Single(Action::PrintDir)),
+        _ => anyhow::bail!("Not an editor command: {command}"),
+    }
+}
+
+/// The commands whose arguments are file names or shell commands, where
+/// `%` stands for the current file.
+const EXPANDED: &[&str] = &[
+    "e",
+    "edit",
+    "w",
+    "write",
+    "w!",
+    "write!",
+    "r",
+    "read",
+    "mak",
+    "make",
+    "diff",
+    "tabnew",
+    "tabe",
+    "tabedit",
+    "grep",
+    "gr",
+    "cd",
+    "chdir",
+    "lcd",
+    "lchdir",
+    "mks",
+    "mksession",
+];
+
+/// Replaces `%` in the arguments of a command taking files or a shell
+/// command with the current `file`: `%:p` is its full path, `%:h` the
+/// directory it's in and `%:t` its name. `\%` is a `%` left alone.
+pub fn expand(command: &str, file: Option<&str>) -> anyhow::Result<String> {
+    let trimmed = command.trim_start();
+    let (name, args) = match trimmed.strip_prefix('!') {
+        Some(shell) => ("!", shell),
+        None => trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, "")),
+    };
+    if (name != "!" && !EXPANDED.contains(&name)) || !args.contains('%') {
+        return Ok(command.to_string());
+    }
+
+    let mut expanded = String::new();
+    let mut rest = args;
+    while let Some(at) = rest.find('%') {
+        let (before, after) = rest.split_at(at);
+        if let Some(before) = before.strip_suffix('\\') {
+            expanded.push_str(before);
+            expanded.push('%');
+            rest = &after[1..];
+            continue;
+        }
+        expanded.push_str(before);
+        let Some(file) = file else {
+            anyhow::bail!("No file name for %");
+        };
+        let path = std::path::Path::new(file);
+        let (replacement, len) = match after.get(1..3) {
+            Some(":p") => (std::env::current_dir()?.join(path).display().to_string(), 3),
+            Some(":h") => (
+                path.parent()
+                    .map(|dir| dir.display().to_string())
+                    .filter(|dir| !dir.is_empty())
+                    .unwrap_or_else(|| ".".to_string()),
+                3,
+            ),
+            Some(":t") => (
+                path.file_name()
+                    .map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+                3,
+            ),
+            _ => (file.to_string(), 1),
+        };
+        expanded.push_str(&replacement);
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    let head = &command[..command.len() - args.len()];
+    Ok(format!("{head}{expanded}"))
+}
+
+fn shell_command(command: &str) -> anyhow::Result<String> {
+    match command.trim() {
+        "" => anyhow::bail!("Argument required"),
+        command => Ok(command.to_string()),
+    }
+}
+
+/// Parses the lines a command applies to, `%` for all of them, `N` or
+/// `N,M`, into an inclusive range of line indexes.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let range = range.trim();
+    if range == "%" {
+        return Some((0, usize::MAX));
+    }
+    let (from, to) = range.split_once(',').unwrap_or((range, range));
+    let from: usize = from.trim().parse().ok()?;
+    let to: usize = to.trim().parse().ok()?;
+    let (from, to) = (from.min(to), from.max(to));
+    Some((from.saturating_sub(1), to.saturating_sub(1)))
+}
+
+fn parse_set(args: &str) -> anyhow::Result<KeyAction> {
+    let line_numbers = match args {
+        "number" | "nu" => Some(LineNumbers::Number),
+        "relativenumber" | "rnu" => Some(LineNumbers::RelativeNumber),
+        "nonumber" | "nonu" | "norelativenumber" | "nornu" => Some(LineNumbers::None),
+        _ => None,
+    };
+    if let Some(line_numbers) = line_numbers {
+        return Ok(KeyAction::Single(Action::SetLineNumbers(line_numbers)));
+    }
+    match args {
+        "list" => return Ok(KeyAction::Single(Action::SetList(true))),
+        "readonly" | "ro" => return Ok(KeyAction::Single(Action::SetReadonly(true))),
+        "noreadonly" | "noro" => return Ok(KeyAction::Single(Action::SetReadonly(false))),
+        "nolist" => return Ok(KeyAction::Single(Action::SetList(false))),
+        "wrap" => return Ok(KeyAction::Single(Action::SetWrap(true))),
+        "nowrap" => return Ok(KeyAction::Single(Action::SetWrap(false))),
+        _ => {}
+    }
+
+    let Some((option, value)) = args.split_once('=') else {
+        anyhow::bail!("Invalid argument: {args}");
+    };
+
+    match option.trim() {
+        "colorcolumn" | "cc" => {
+            let columns = value
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(|c| match c.parse() {
+                    Ok(column) if column > 0 => Ok(column),
+                    _ => anyhow::bail!("Invalid column: {c}"),
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok(KeyAction::Single(Action::SetColorColumn(columns)))
+        }
+        "scrolloff" | "so" => match value.trim().parse() {
+            Ok(lines) => Ok(KeyAction::Single(Action::SetScrolloff(lines))),
+            Err(_) => anyhow::bail!("Invalid number: {value}"),
+        },
+        "sidescrolloff" | "siso" => match value.trim().parse() {
+            Ok(columns) => Ok(KeyAction::Single(Action::SetSidescrolloff(columns))),
+            Err(_) => anyhow::bail!("Invalid number: {value}"),
+        },
+        "textwidth" | "tw" => match value.trim().parse() {
+            Ok(columns) => Ok(KeyAction::Single(Action::SetTextwidth(columns))),
+            Err(_) => anyhow::bail!("Invalid number: {value}"),
+        },
+        "fileencoding" | "fenc" => match Encoding::find(value.trim()) {
+            Some(encoding) => Ok(KeyAction::Single(Action::SetFileEncoding(encoding))),
+            None => anyhow::bail!("Unknown encoding: {value}"),
+        },
+        "fileformat" | "ff" => match FileFormat::find(value.trim()) {
+            Some(format) => Ok(KeyAction::Single(Action::SetFileFormat(format))),
+            None => anyhow::bail!("Unknown fileformat: {value} (unix or dos)"),
+        },
+        "filetype" | "ft" => {
+            let value = value.trim();
+            let Some(language) = language::find(value) else {
+                anyhow::bail!("Unknown filetype: {value}");
+            };
+            Ok(KeyAction::Single(Action::SetFiletype(
+                language.name.to_string(),
+            )))
+        }
+        option => anyhow::bail!("Unknown option: {option}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_shell() {
+        let KeyAction::Single(Action::Shell(shell)) = parse("!ls -a").unwrap() else {
+            panic!("expected Shell");
+        };
+        assert_eq!(shell, "ls -a");
+
+        let KeyAction::Single(Action::FilterLines(from, to, shell)) = parse("5,3!sort").unwrap()
+        else {
+            panic!("expected FilterLines");
+        };
+        assert_eq!((from, to, shell.as_str()), (2, 4, "sort"));
+
+        let KeyAction::Single(Action::ReadShell(shell)) = parse("r !date").unwrap() else {
+            panic!("expected ReadShell");
+        };
+        assert_eq!(shell, "date");
+        assert!(parse("%!").is_err());
+        assert!(matches!(
+            parse("e!").unwrap(),
+            KeyAction::Single(Action::ReloadFile)
+        ));
+    }
+
+    #[test]
+    fn test_expand() {
+        let file = Some("src/main.rs");
+        assert_eq!(expand("e %:h/lib.rs", file).unwrap(), "e src/lib.rs");
+        assert_eq!(expand("!rustfmt %", file).unwrap(), "!rustfmt src/main.rs");
+        assert_eq!(expand("w %.bak", file).unwrap(), "w src/main.rs.bak");
+        assert_eq!(expand("grep %:t", file).unwrap(), "grep main.rs");
+        assert_eq!(expand("!printf 100\\%", file).unwrap(), "!printf 100%");
+        // a range, not a file
+        assert_eq!(expand("%s/a/b/", file).unwrap(), "%s/a/b/");
+        assert_eq!(expand("%!sort", file).unwrap(), "%!sort");
+        assert_eq!(expand("e %:h", Some("main.rs")).unwrap(), "e .");
+        assert!(expand("e %", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_diff() {
+        let KeyAction::Single(Action::DiffWith(file)) = parse("diff old.rs").unwrap() else {
+            panic!("expected DiffWith");
+        };
+        assert_eq!(file, "old.rs");
+        assert!(matches!(
+            parse("diffthis").unwrap(),
+            KeyAction::Single(Action::DiffHead)
+        ));
+        assert!(parse("diff").is_err());
+    }
+
+    #[test]
+    fn test_parse_scratch() {
+        let KeyAction::Single(Action::SaveAs(path, force)) = parse("w notes.md").unwrap() else {
+            panic!("expected SaveAs");
+        };
+        assert_eq!((path.as_str(), force), ("notes.md", false));
+        assert!(matches!(
+            parse("w! out.txt").unwrap(),
+            KeyAction::Single(Action::SaveAs(_, true))
+        ));
+
+        let KeyAction::Multiple(actions) = parse("new rs").unwrap() else {
+            panic!("expected NewBuffer and SetFiletype");
+        };
+        assert!(
+            matches!(&actions[..], [Action::NewBuffer, Action::SetFiletype(ft)] if ft == "rust")
+        );
+        assert!(parse("new cobol").is_err());
+    }
+
+    #[test]
+    fn test_parse_buffers() {
+        let KeyAction::Single(Action::SwitchBuffer(id)) = parse("b 3").unwrap() else {
+            panic!("expected SwitchBuffer");
+        };
+        assert_eq!(id, 3);
+
+        let KeyAction::Single(Action::CloseBuffer(id, force)) = parse("bd! 2").unwrap() else {
+            panic!("expected CloseBuffer");
+        };
+        assert_eq!((id, force), (Some(2), true));
+        assert!(matches!(
+            parse("bd").unwrap(),
+            KeyAction::Single(Action::CloseBuffer(None, false))
+        ));
+        assert!(parse("b").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_filetype() {
+        let KeyAction::Single(Action::SetFiletype(ft)) = parse("set filetype=rs").unwrap() else {
+            panic!("expected SetFiletype");
+        };
+        assert_eq!(ft, "rust");
+
+        let KeyAction::Single(Action::SetFiletype(ft)) = parse("se ft=toml").unwrap() else {
+            panic!("expected SetFiletype");
+        };
+        assert_eq!(ft, "toml");
+
+        let KeyAction::Single(Action::SetFileEncoding(encoding)) =
+            parse("set fenc=latin1").unwrap()
+        else {
+            panic!("expected SetFileEncoding");
+        };
+        assert_eq!(encoding, Encoding::Latin1);
+        assert!(parse("set fenc=ebcdic").is_err());
+
+        let KeyAction::Single(Action::SetFileFormat(format)) = parse("set ff=dos").unwrap() else {
+            panic!("expected SetFileFormat");
+        };
+        assert_eq!(format, FileFormat::Dos);
+    }
+
+    #[test]
+    fn test_parse_set_line_numbers() {
+        let KeyAction::Single(Action::SetLineNumbers(mode)) = parse("set rnu").unwrap() else {
+            panic!("expected SetLineNumbers");
+        };
+        assert_eq!(mode, LineNumbers::RelativeNumber);
+
+        let KeyAction::Single(Action::SetLineNumbers(mode)) = parse("set nonumber").unwrap() else {
+            panic!("expected SetLineNumbers");
+        };
+        assert_eq!(mode, LineNumbers::None);
+    }
+
+    #[test]
+    fn test_parse_set_colorcolumn() {
+        let KeyAction::Single(Action::SetColorColumn(columns)) = parse("set cc=80,100").unwrap()
+        else {
+            panic!("expected SetColorColumn");
+        };
+        assert_eq!(columns, vec![80, 100]);
+
+        let KeyAction::Single(Action::SetColorColumn(columns)) = parse("set cc=").unwrap() else {
+            panic!("expected SetColorColumn");
+        };
+        assert!(columns.is_empty());
+        assert!(parse("set cc=0").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_list() {
+        assert!(matches!(
+            parse("set list").unwrap(),
+            KeyAction::Single(Action::SetList(true))
+        ));
+        assert!(matches!(
+            parse("set nolist").unwrap(),
+            KeyAction::Single(Action::SetList(false))
+        ));
+    }
+
+    #[test]
+    fn test_parse_wrap() {
+        assert!(matches!(
+            parse("set nowrap").unwrap(),
+            KeyAction::Single(Action::SetWrap(false))
+        ));
+        assert!(matches!(
+            parse("set tw=72").unwrap(),
+            KeyAction::Single(Action::SetTextwidth(72))
+        ));
+        assert!(matches!(
+            parse("prose").unwrap(),
+            KeyAction::Single(Action::ToggleWrap)
+        ));
+    }
+
+    #[test]
+    fn test_parse_goto_line() {
+        assert!(matches!(
+            parse("42").unwrap(),
+            KeyAction::Single(Action::GotoLine(42))
+        ));
+        assert!(matches!(
+            parse("$").unwrap(),
+            KeyAction::Single(Action::GotoLine(usize::MAX))
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("set filetype=cobol").is_err());
+        assert!(parse("set spell").is_err());
+        assert!(parse("frobnicate").is_err());
+        assert!(parse("grep").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+/// A change to one line when toggling comments: the byte range of the line
+/// to replace with `text`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// The edits that toggle the line comment `prefix` on `lines`, numbered from
+/// `first`. When every non-blank line is commented the comments are removed,
+/// otherwise all of them get one, lined up at the smallest indent. Blank
+/// lines are left alone.
+pub fn toggle(lines: &[String], first: usize, prefix: &str) -> Vec<Edit> {
+    let code: Vec<(usize, &String)> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let commented = code
+        .iter()
+        .all(|(_, line)| line.trim_start().starts_with(prefix));
+
+    if commented && !code.is_empty() {
+        return code
+            .into_iter()
+            .map(|(i, line)| {
+                let start = indent(line);
+                let mut end = start + prefix.len();
+                if line[end..].starts_with(' ') {
+                    end += 1;
+                }
+                Edit {
+                    line: first + i,
+                    start,
+                    end,
+                    text: String::new(),
+                }
+            })
+            .collect();
+    }
+
+    let column = code.iter().map(|(_, line)| indent(line)).min().unwrap_or(0);
+    code.into_iter()
+        .map(|(i, _)| Edit {
+            line: first + i,
+            start: column,
+            end: column,
+            text: format!("{prefix} "),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toggled(lines: &[&str], prefix: &str) -> Vec<String> {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        let mut result = lines.clone();
+        for edit in toggle(&lines, 0, prefix) {
+            result[edit.line].replace_range(edit.start..edit.end, &edit.text);
+        }
+        result
+    }
+
+    #[test]
+    fn test_toggle() {
+        assert_eq!(
+            toggled(&["fn f() {", "    x();", "", "}"], "//"),
+            ["// fn f() {", "//     x();", "", "// }"]
+        );
+        assert_eq!(toggled(&["  // a", "", "  //b"], "//"), ["  a", "", "  b"]);
+        assert_eq!(toggled(&["    # a", "  b"], "#"), ["  #   # a", "  # b"]);
+        assert_eq!(toggled(&["", " "], "#"), ["", " "]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub text: String,
+}
+
+/// The completion popup: candidates for the word being typed, which starts
+/// at byte `start` of `line`. Only the candidates matching what has been
+/// typed so far are shown.
+#[derive(Debug)]
+pub struct CompletionMenu {
+    items: Vec<Completion>,
+    matches: Vec<usize>,
+    prefix: String,
+    pub selected: usize,
+    pub line: usize,
+    pub start: usize,
+}
+
+impl CompletionMenu {
+    /// Returns `None` when no candidate matches `prefix`.
+    pub fn new(items: Vec<Completion>, line: usize, start: usize, prefix: &str) -> Option<Self> {
+        let mut menu = Self {
+            items,
+            matches: vec![],
+            prefix: String::new(),
+            selected: 0,
+            line,
+            start,
+        };
+        menu.refilter(prefix);
+        (!menu.matches.is_empty()).then_some(menu)
+    }
+
+    /// Narrows the candidates down to the ones starting with `prefix`,
+    /// returning whether any are left. The selection is kept as long as the
+    /// prefix doesn't change.
+    pub fn filter(&mut self, prefix: &str) -> bool {
+        if prefix != self.prefix {
+            self.refilter(prefix);
+        }
+        !self.matches.is_empty()
+    }
+
+    fn refilter(&mut self, prefix: &str) {
+        self.prefix = prefix.to_string();
+        self.matches = (0..self.items.len())
+            .filter(|i| {
+                let text = &self.items[*i].text;
+                text.starts_with(prefix) && text != prefix
+            })
+            .collect();
+        self.selected = 0;
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.matches.len();
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self
+            .selected
+            .checked_sub(1)
+            .unwrap_or(self.matches.len() - 1);
+    }
+
+    pub fn selected(&self) -> &Completion {
+        &self.items[self.matches[self.selected]]
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.matches.iter().map(|i| self.items[*i].label.as_str())
+    }
+}
+
+/// Collects the distinct words in `lines` as completion candidates.
+pub fn buffer_words(lines: &[String]) -> Vec<Completion> {
+    let mut seen = HashSet::new();
+    lines
+        .iter()
+        .flat_map(|line| line.split(|c: char| !is_word_char(c)))
+        .filter(|word| !word.is_empty() && !word.starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|word| seen.insert(*word))
+        .map(|word| Completion {
+            label: word.to_string(),
+            text: word.to_string(),
+        })
+        .collect()
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the word ending at byte `x` of `line`.
+pub fn word_before(line: &str, x: usize) -> &str {
+    let line = line.get(..x).unwrap_or(line);
+    let start = line
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(line.len(), |(i, _)| i);
+    &line[start..]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_word_before() {
+        assert_eq!(word_before("let foo_bar", 11), "foo_bar");
+        assert_eq!(word_before("x.le", 4), "le");
+        assert_eq!(word_before("x.", 2), "");
+    }
+
+    #[test]
+    fn test_menu_filters_buffer_words() {
+        let lines = vec![
+            "let first = 1;".to_string(),
+            "fn find(f: u8) {}".to_string(),
+        ];
+        let mut menu = CompletionMenu::new(buffer_words(&lines), 1, 0, "fi").unwrap();
+        assert_eq!(menu.labels().collect::<Vec<_>>(), vec!["first", "find"]);
+
+        menu.select_prev();
+        assert_eq!(menu.selected().text, "find");
+        menu.select_next();
+        assert_eq!(menu.selected().text, "first");
+        menu.select_next();
+        assert!(menu.filter("fi"));
+        assert_eq!(menu.selected().text, "find");
+
+        assert!(menu.filter("fin"));
+        assert_eq!(menu.labels().collect::<Vec<_>>(), vec!["find"]);
+        assert!(!menu.filter("find"));
+        assert!(CompletionMenu::new(buffer_words(&lines), 1, 0, "zz").is_none());
+    }
+}
@@ -1,8 +1,26 @@
-use crate::editor::{Action, Mode};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::{
+    diagnostics::LintConfig, editor::Action, lsp::ServerConfig, statusline::StatuslineConfig,
+    theme::ColorDepth,
+};
+use anyhow::Context;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// The config used when there's no config file.
+const DEFAULT_CONFIG: &str = include_str!("fixtures/config.toml");
+
+/// The name of the config file of a project, in its directory or one above.
+const PROJECT_CONFIG: &str = ".rustik.toml";
+
+/// What a project's config can't set, as it would start programs as soon as
+/// a file of the project is opened.
+const USER_ONLY: &[&str] = &["lsp", "lint"];
+
+#[derive(Debug, Serialize, Clone)]
 #[serde(untagged)]
 pub enum KeyAction {
     Single(Action),
@@ -10,18 +28,707 @@ pub enum KeyAction {
     Nested(HashMap<String, KeyAction>),
 }
 
+impl KeyAction {
+    fn from_value(value: toml::Value) -> Result<Self, String> {
+        match value {
+            toml::Value::String(name) => action(toml::Value::String(name)).map(KeyAction::Single),
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(action)
+                .collect::<Result<_, _>>()
+                .map(KeyAction::Multiple),
+            toml::Value::Table(table) => {
+                if let Ok(action) = toml::Value::Table(table.clone()).try_into() {
+                    return Ok(KeyAction::Single(action));
+                }
+                table
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, KeyAction::from_value(value)?)))
+                    .collect::<Result<_, String>>()
+                    .map(KeyAction::Nested)
+            }
+            value => Err(format!("expected an action, found {}", value.type_str())),
+        }
+    }
+}
+
+fn action(value: toml::Value) -> Result<Action, String> {
+    match value {
+        toml::Value::String(name) => toml::Value::String(name.clone())
+            .try_into()
+            .map_err(|_| format!("unknown action `{name}`")),
+        value => value
+            .try_into()
+            .map_err(|e: toml::de::Error| e.message().to_string()),
+    }
+}
+
+/// Parsed by hand rather than as an untagged enum, so that a misspelled
+/// action is reported by name instead of as a mapping that matches nothing.
+impl<'de> Deserialize<'de> for KeyAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = toml::Value::deserialize(deserializer)?;
+        KeyAction::from_value(value).map_err(de::Error::custom)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Keys {
+    /// The key `<leader>` stands for in mappings, `\\` by default.
+    #[serde(default)]
+    pub leader: Option<String>,
     #[serde(default)]
     pub normal: HashMap<String, KeyAction>,
     #[serde(default)]
     pub insert: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub command: HashMap<String, KeyAction>,
+    /// Keys that take precedence in insert mode while the completion menu
+    /// is open.
+    #[serde(default)]
+    pub completion: HashMap<String, KeyAction>,
+    /// Keys that take precedence while a popup is open.
+    #[serde(default)]
+    pub popup: HashMap<String, KeyAction>,
+    /// Keys used instead of the normal mode ones while a diff shows.
+    #[serde(default)]
+    pub diff: HashMap<String, KeyAction>,
+    /// Keys used instead of the normal mode ones in the `:status` panel.
+    #[serde(default)]
+    pub status: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub picker: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub terminal: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub visual: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub replace: HashMap<String, KeyAction>,
+    #[serde(default)]
+    pub visual_block: HashMap<String, KeyAction>,
+    /// How long to wait for the rest of a key sequence, in milliseconds,
+    /// keyed by mode. 0 waits indefinitely.
+    #[serde(default)]
+    pub timeouts: HashMap<String, u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub keys: Keys,
     pub theme: String,
+    /// Language servers keyed by filetype.
+    #[serde(default)]
+    pub lsp: HashMap<String, ServerConfig>,
+    /// Lint commands keyed by filetype.
+    #[serde(default)]
+    pub lint: HashMap<String, LintConfig>,
+    #[serde(default)]
+    pub diagnostics: DiagnosticsConfig,
+    #[serde(default)]
+    pub statusline: StatuslineConfig,
+    /// Capture the mouse to move the cursor, select and scroll. Turn it off
+    /// to keep the terminal's own selection.
+    #[serde(default = "default_true")]
+    pub mouse: bool,
+    /// Set the terminal window's title to the file being edited, putting
+    /// the old one back on quit.
+    #[serde(default = "default_true")]
+    pub title: bool,
+    /// How many colors the terminal shows: `"truecolor"`, `"256"` or `"16"`.
+    /// Detected from the environment when not set.
+    #[serde(default)]
+    pub colors: Option<ColorDepth>,
+    #[serde(default)]
+    pub line_numbers: LineNumbers,
+    #[serde(default)]
+    pub cursor: CursorConfig,
+    /// Lines to keep visible above and below the cursor when scrolling.
+    #[serde(default)]
+    pub scrolloff: usize,
+    /// Columns to keep visible to the left and right of the cursor when
+    /// scrolling sideways.
+    #[serde(default)]
+    pub sidescrolloff: usize,
+    /// Show the language server's inlay hints, like types after variables.
+    /// `:hints` turns them on or off for a buffer.
+    #[serde(default = "default_true")]
+    pub inlay_hints: bool,
+    /// Milliseconds yanked text flashes for, 0 to not flash it.
+    #[serde(default = "default_yank_flash")]
+    pub yank_flash: u64,
+    /// Columns to tint, counted from 1, like 80 to mark the line length.
+    #[serde(default)]
+    pub colorcolumn: Vec<usize>,
+    /// Show tabs, trailing spaces and line ends with `listchars`.
+    #[serde(default)]
+    pub list: bool,
+    #[serde(default)]
+    pub listchars: ListChars,
+    /// Files bigger than this many megabytes are read in the background and
+    /// opened without highlighting, git signs, swap or undo files.
+    #[serde(default = "default_large_file")]
+    pub large_file: u64,
+    #[serde(default)]
+    pub save: SaveConfig,
+    #[serde(default)]
+    pub swap: SwapConfig,
+    #[serde(default)]
+    pub autosave: AutosaveConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub undo: UndoConfig,
+    #[serde(default)]
+    pub autopairs: AutopairsConfig,
+    /// Build commands for `:make` keyed by filetype. Filetypes without one
+    /// run `make`.
+    #[serde(default = "default_make")]
+    pub make: HashMap<String, String>,
+    /// Line comment prefixes keyed by filetype, for `gcc`.
+    #[serde(default = "default_comments")]
+    pub comments: HashMap<String, String>,
+    /// How wide a tab is, and how many spaces `Tab` inserts with `expandtab`.
+    #[serde(default = "default_tabstop")]
+    pub tabstop: usize,
+    /// Insert spaces instead of a tab character.
+    #[serde(default = "default_true")]
+    pub expandtab: bool,
+    /// Wrap long lines onto the rows below, for writing prose.
+    #[serde(default)]
+    pub wrap: bool,
+    /// The column wrapped lines break at, or the window edge when that's
+    /// closer. 0 always wraps at the window edge.
+    #[serde(default = "default_textwidth")]
+    pub textwidth: usize,
+    /// The width of the text column `:zen` centers.
+    #[serde(default = "default_textwidth")]
+    pub zen_width: usize,
+    /// Words expanded as they're typed in insert mode, like `teh = "the"`.
+    #[serde(default)]
+    pub abbreviations: HashMap<String, String>,
+    /// Options that differ per filetype.
+    #[serde(default)]
+    pub filetype: HashMap<String, FiletypeConfig>,
+    /// Named chains of actions, run with `:name` or mapped to keys with
+    /// `{ Run = "name" }`, like `format-save = ["Format", "Save"]`.
+    #[serde(default)]
+    pub commands: HashMap<String, KeyAction>,
+}
+
+/// Options for one filetype, applied when a buffer has it. Unset ones keep
+/// the global setting.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FiletypeConfig {
+    pub tabstop: Option<usize>,
+    pub expandtab: Option<bool>,
+    /// Line comment prefix, in place of the one in `comments`.
+    pub comment: Option<String>,
+    /// Command `:format` pipes the buffer through, like `rustfmt`.
+    pub formatter: Option<String>,
+    /// Abbreviations for this filetype, on top of the global ones.
+    #[serde(default)]
+    pub abbreviations: HashMap<String, String>,
+    /// Wrap long lines, like `wrap = true` for Markdown.
+    pub wrap: Option<bool>,
+}
+
+/// The options in effect for a filetype.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FiletypeOptions {
+    pub tabstop: usize,
+    pub expandtab: bool,
+    pub comment: Option<String>,
+    pub formatter: Option<String>,
+    pub abbreviations: HashMap<String, String>,
+    pub wrap: bool,
+}
+
+/// Closing brackets and quotes as they're opened in insert mode.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutopairsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Characters not to close, keyed by filetype.
+    #[serde(default = "default_autopairs_except")]
+    pub except: HashMap<String, String>,
+}
+
+impl Default for AutopairsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            except: default_autopairs_except(),
+        }
+    }
+}
+
+/// `'` starts lifetimes and chars in Rust, where a closing one gets in the
+/// way more often than not.
+fn default_autopairs_except() -> HashMap<String, String> {
+    HashMap::from([("rust".to_string(), "'".to_string())])
+}
+
+fn default_make() -> HashMap<String, String> {
+    HashMap::from([(
+        "rust".to_string(),
+        "cargo build --message-format short".to_string(),
+    )])
+}
+
+fn default_textwidth() -> usize {
+    80
+}
+
+fn default_yank_flash() -> u64 {
+    200
+}
+
+fn default_tabstop() -> usize {
+    4
+}
+
+fn default_comments() -> HashMap<String, String> {
+    [
+        ("rust", "//"),
+        ("javascript", "//"),
+        ("python", "#"),
+        ("toml", "#"),
+    ]
+    .into_iter()
+    .map(|(filetype, prefix)| (filetype.to_string(), prefix.to_string()))
+    .collect()
+}
+
+impl AutopairsConfig {
+    /// The characters not to close in `filetype`, `None` when autopairs are
+    /// off.
+    pub fn except(&self, filetype: &str) -> Option<&str> {
+        self.enabled
+            .then(|| self.except.get(filetype).map_or("", String::as_str))
+    }
+}
+
+/// Keeping the undo history of files between runs.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UndoConfig {
+    #[serde(default = "default_true")]
+    pub persist: bool,
+    /// How many changes to keep per file.
+    #[serde(default = "default_undo_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for UndoConfig {
+    fn default() -> Self {
+        Self {
+            persist: true,
+            max_entries: default_undo_entries(),
+        }
+    }
+}
+
+fn default_undo_entries() -> usize {
+    1000
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Save the session of the working directory on quit, and restore it
+    /// when starting there without a file.
+    #[serde(default)]
+    pub auto: bool,
+}
+
+/// Writing modified files without `:w`. Autosaves leave trailing whitespace
+/// alone, as it's likely still being typed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutosaveConfig {
+    /// Save after this many seconds without input.
+    #[serde(default)]
+    pub delay: Option<u64>,
+    /// Save when the terminal loses focus.
+    #[serde(default)]
+    pub on_focus_lost: bool,
+}
+
+/// Swap files keep unsaved edits on disk to recover them after a crash.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwapConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// How often to write the swap file while there are new edits, in
+    /// seconds.
+    #[serde(default = "default_swap_interval")]
+    pub interval: u64,
+}
+
+impl Default for SwapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: default_swap_interval(),
+        }
+    }
+}
+
+fn default_large_file() -> u64 {
+    64
+}
+
+fn default_swap_interval() -> u64 {
+    4
+}
+
+/// The characters `list` mode draws in place of invisible ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListChars {
+    #[serde(default = "default_tab_char")]
+    pub tab: char,
+    #[serde(default = "default_trail_char")]
+    pub trail: char,
+    /// Drawn after the last character of each line, nothing by default.
+    #[serde(default)]
+    pub eol: Option<char>,
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: default_tab_char(),
+            trail: default_trail_char(),
+            eol: None,
+        }
+    }
+}
+
+fn default_tab_char() -> char {
+    '→'
+}
+
+fn default_trail_char() -> char {
+    '·'
+}
+
+/// The cursor's shape in each mode.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CursorConfig {
+    /// Set the shape at all. Turned off, the cursor is left the way the
+    /// terminal has it.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub normal: CursorShape,
+    #[serde(default = "default_bar")]
+    pub insert: CursorShape,
+    #[serde(default = "default_underline")]
+    pub replace: CursorShape,
+    #[serde(default)]
+    pub visual: CursorShape,
+    /// On the command line and in the picker.
+    #[serde(default = "default_bar")]
+    pub command: CursorShape,
+    /// While a key waits for a character, like `r`, or for the rest of a
+    /// sequence.
+    #[serde(default = "default_underline")]
+    pub pending: CursorShape,
+    #[serde(default)]
+    pub terminal: CursorShape,
+}
+
+impl Default for CursorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            normal: CursorShape::Default,
+            insert: default_bar(),
+            replace: default_underline(),
+            visual: CursorShape::Default,
+            command: default_bar(),
+            pending: default_underline(),
+            terminal: CursorShape::Default,
+        }
+    }
+}
+
+fn default_bar() -> CursorShape {
+    CursorShape::Bar
+}
+
+fn default_underline() -> CursorShape {
+    CursorShape::Underline
+}
+
+/// A cursor shape, steady unless it's one of the blinking ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CursorShape {
+    /// The shape the terminal is set to use.
+    #[default]
+    Default,
+    Block,
+    BlinkingBlock,
+    Bar,
+    BlinkingBar,
+    Underline,
+    BlinkingUnderline,
+}
+
+/// What the gutter shows next to each line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    #[default]
+    Number,
+    /// Distances from the cursor line, which shows its own number.
+    RelativeNumber,
+    None,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiagnosticsConfig {
+    /// Show the message of the most severe diagnostic at the end of its line.
+    #[serde(default = "default_true")]
+    pub virtual_text: bool,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self { virtual_text: true }
+    }
+}
+
+/// What to clean up when writing a file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaveConfig {
+    #[serde(default)]
+    pub trim_trailing_whitespace: bool,
+    /// End the file with a newline, even if it was loaded without one.
+    #[serde(default = "default_true")]
+    pub final_newline: bool,
+    /// Copy the file to `file~` before overwriting it.
+    #[serde(default)]
+    pub backup: bool,
+    /// Overrides keyed by filetype.
+    #[serde(default)]
+    pub filetype: HashMap<String, SaveOverrides>,
+}
+
+impl Default for SaveConfig {
+    fn default() -> Self {
+        Self {
+            trim_trailing_whitespace: false,
+            final_newline: true,
+            backup: false,
+            filetype: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaveOverrides {
+    pub trim_trailing_whitespace: Option<bool>,
+    pub final_newline: Option<bool>,
+}
+
+/// The save options in effect for a filetype.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    pub trim_trailing_whitespace: bool,
+    pub final_newline: bool,
+}
+
+impl SaveConfig {
+    pub fn for_filetype(&self, filetype: &str) -> SaveOptions {
+        let overrides = self.filetype.get(filetype);
+        SaveOptions {
+            trim_trailing_whitespace: overrides
+                .and_then(|o| o.trim_trailing_whitespace)
+                .unwrap_or(self.trim_trailing_whitespace),
+            final_newline: overrides
+                .and_then(|o| o.final_newline)
+                .unwrap_or(self.final_newline),
+        }
+    }
+}
+
+impl Config {
+    pub fn color_depth(&self) -> ColorDepth {
+        self.colors.unwrap_or_else(ColorDepth::detect)
+    }
+
+    /// The options for `filetype`: its `[filetype.<name>]` table over the
+    /// global ones.
+    pub fn for_filetype(&self, filetype: &str) -> FiletypeOptions {
+        let overrides = self.filetype.get(filetype);
+        FiletypeOptions {
+            tabstop: overrides
+                .and_then(|o| o.tabstop)
+                .unwrap_or(self.tabstop)
+                .max(1),
+            expandtab: overrides
+                .and_then(|o| o.expandtab)
+                .unwrap_or(self.expandtab),
+            comment: overrides
+                .and_then(|o| o.comment.clone())
+                .or_else(|| self.comments.get(filetype).cloned()),
+            formatter: overrides.and_then(|o| o.formatter.clone()),
+            abbreviations: self
+                .abbreviations
+                .iter()
+                .chain(overrides.iter().flat_map(|o| &o.abbreviations))
+                .map(|(from, to)| (from.clone(), to.clone()))
+                .collect(),
+            wrap: overrides.and_then(|o| o.wrap).unwrap_or(self.wrap),
+        }
+    }
+
+    /// The `large_file` size in bytes.
+    pub fn large_file_size(&self) -> u64 {
+        self.large_file.saturating_mul(1024 * 1024)
+    }
+
+    /// Loads the config file at `path`, or the default config without one,
+    /// with the `project` config over it.
+    pub fn load(path: Option<&Path>, project: Option<&Path>) -> anyhow::Result<Config> {
+        let (toml, config) = match path {
+            Some(path) => {
+                let toml = fs::read_to_string(path)
+                    .with_context(|| format!("can't read config {}", path.display()))?;
+                let config = parse(&toml, path)?;
+                (toml, config)
+            }
+            None => (DEFAULT_CONFIG.to_string(), toml::from_str(DEFAULT_CONFIG)?),
+        };
+        let Some(project) = project else {
+            return Ok(config);
+        };
+        let overrides = fs::read_to_string(project)
+            .with_context(|| format!("can't read config {}", project.display()))?;
+        merge(&toml, &overrides, project)
+    }
+}
+
+/// Parses the config `toml` with a project's `overrides` over it. Tables
+/// are merged key by key, so a project can remap a single key or set a
+/// single option of a filetype, while the other values replace the user's
+/// whole.
+fn merge(toml: &str, overrides: &str, project: &Path) -> anyhow::Result<Config> {
+    let mut table: toml::Table = toml::from_str(toml)?;
+    let overrides: toml::Table = toml::from_str(overrides).map_err(|err| {
+        let line = err
+            .span()
+            .map_or(1, |span| overrides[..span.start].matches('\n').count() + 1);
+        anyhow::anyhow!("{}:{line}: {}", project.display(), err.message())
+    })?;
+    if let Some(key) = USER_ONLY.iter().find(|key| overrides.contains_key(**key)) {
+        anyhow::bail!(
+            "{}: `{key}` can only be set in the user config",
+            project.display()
+        );
+    }
+    merge_tables(&mut table, overrides);
+    Config::deserialize(toml::Value::Table(table))
+        .map_err(|err| anyhow::anyhow!("{}: {}", project.display(), err.message()))
+}
+
+fn merge_tables(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(value)) => {
+                merge_tables(table, value)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The `.rustik.toml` in `dir` or the closest directory above it.
+pub fn find_project_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Parses a config, reporting errors on a single `path:line: message` line.
+fn parse(toml: &str, path: &Path) -> anyhow::Result<Config> {
+    toml::from_str(toml).map_err(|err| {
+        let line = err
+            .span()
+            .map_or(1, |span| toml[..span.start].matches('\n').count() + 1);
+        anyhow::anyhow!("{}:{line}: {}", path.display(), err.message())
+    })
+}
+
+/// The `rustik` directories config is looked for in: under
+/// `$XDG_CONFIG_HOME`, `~/.config`, then the platform's own.
+fn config_dirs() -> impl Iterator<Item = PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let dirs = [
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+        home.as_ref().map(|home| home.join(".config")),
+        home.filter(|_| cfg!(target_os = "macos"))
+            .map(|home| home.join("Library/Application Support")),
+        env::var_os("APPDATA").map(PathBuf::from),
+    ];
+    dirs.into_iter().flatten().map(|dir| dir.join("rustik"))
+}
+
+/// Returns the first `rustik/config.toml` that exists in the config
+/// directories.
+pub fn find_path() -> Option<PathBuf> {
+    config_dirs()
+        .map(|dir| dir.join("config.toml"))
+        .find(|path| path.is_file())
+}
+
+/// Returns the first `rustik/plugins` directory that exists in the config
+/// directories.
+pub fn plugin_dir() -> Option<PathBuf> {
+    config_dirs()
+        .map(|dir| dir.join("plugins"))
+        .find(|path| path.is_dir())
+}
+
+/// Where state kept between runs goes: `$XDG_STATE_HOME/rustik`, falling
+/// back to `~/.local/state/rustik` or the platform's data directory.
+pub fn state_dir() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let dir = if let Some(dir) = env::var_os("XDG_STATE_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("LOCALAPPDATA") {
+        dir.into()
+    } else if cfg!(target_os = "macos") {
+        home?.join("Library/Application Support")
+    } else {
+        home?.join(".local/state")
+    };
+    Some(dir.join("rustik"))
+}
+
+/// A JSON file in the `kind` subdirectory of the state directory, named
+/// after `path` with its separators replaced by `%`.
+pub fn state_file(kind: &str, path: &Path) -> Option<PathBuf> {
+    let name = path.to_string_lossy().replace(['/', '\\', ':'], "%");
+    Some(state_dir()?.join(kind).join(format!("{name}.json")))
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[cfg(test)]
@@ -29,12 +736,14 @@ mod test {
     use std::fs;
 
     use super::*;
+    use crate::editor::Mode;
 
     #[test]
     fn test_persist_config() {
         let config = Config {
             theme: "themes/dracula.json".to_string(),
             keys: Keys {
+                leader: Some(",".to_string()),
                 normal: HashMap::from([
                     (
                         "o".to_string(),
@@ -46,7 +755,19 @@ mod test {
                     ),
                 ]),
                 insert: HashMap::new(),
+                command: HashMap::new(),
+                completion: HashMap::new(),
+                popup: HashMap::new(),
+                diff: HashMap::new(),
+                status: HashMap::new(),
+                picker: HashMap::new(),
+                terminal: HashMap::new(),
+                visual: HashMap::new(),
+                replace: HashMap::new(),
+                visual_block: HashMap::new(),
+                timeouts: HashMap::from([("insert".to_string(), 200)]),
             },
+            ..Default::default()
         };
 
         let toml = toml::to_string(&config).unwrap();
@@ -59,4 +780,100 @@ mod test {
         let config: Config = toml::from_str(&toml).unwrap();
         println!("{config:#?}");
     }
+
+    #[test]
+    fn test_invalid_config() {
+        let path = Path::new("config.toml");
+        let unknown_field = "theme = \"mocha.json\"\nmouse = true\nmosue = false\n[keys]\n";
+        let err = parse(unknown_field, path).unwrap_err().to_string();
+        assert!(
+            err.starts_with("config.toml:3: unknown field `mosue`"),
+            "{err}"
+        );
+
+        let bad_action = "theme = \"mocha.json\"\n[keys.normal]\n\"x\" = \"Undo\"\n\"g\" = { \"g\" = \"MoveTop\" }\n";
+        let err = parse(bad_action, path).unwrap_err().to_string();
+        assert_eq!(err, "config.toml:4: unknown action `MoveTop`");
+    }
+
+    #[test]
+    fn test_renamed_action() {
+        let old_name =
+            "theme = \"mocha.json\"\n[keys.normal]\n\"z\" = [{ SetWaitingKeyAction = \"Quit\" }]\n";
+        let config = parse(old_name, Path::new("config.toml")).unwrap();
+        assert!(matches!(
+            &config.keys.normal["z"],
+            KeyAction::Multiple(actions) if matches!(actions[..], [Action::SetWaitingKey(_)])
+        ));
+    }
+
+    #[test]
+    fn test_commands() {
+        let toml = "theme = \"mocha.json\"\n[keys.normal]\n\"<leader>s\" = { Run = \"check\" }\n[commands]\nformat-save = [\"Format\", \"Save\"]\ncheck = [{ Run = \"format-save\" }, { Shell = \"cargo check\" }]\n";
+        let config = parse(toml, Path::new("config.toml")).unwrap();
+        assert!(matches!(
+            &config.commands["format-save"],
+            KeyAction::Multiple(actions) if matches!(actions[..], [Action::Format, Action::Save])
+        ));
+        assert!(matches!(
+            &config.commands["check"],
+            KeyAction::Multiple(actions)
+                if matches!(&actions[..], [Action::Run(name), Action::Shell(_)] if name == "format-save")
+        ));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let toml = "theme = \"mocha.json\"\n[keys]\n[cursor]\nnormal = \"blinking-block\"\ninsert = \"underline\"\n";
+        let config = parse(toml, Path::new("config.toml")).unwrap();
+        assert!(config.cursor.enabled);
+        assert_eq!(config.cursor.normal, CursorShape::BlinkingBlock);
+        assert_eq!(config.cursor.insert, CursorShape::Underline);
+        assert_eq!(config.cursor.command, CursorShape::Bar);
+
+        let toml = "theme = \"mocha.json\"\n[keys]\n[cursor]\nnormal = \"beam\"\n";
+        assert!(parse(toml, Path::new("config.toml")).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let toml = "theme = \"mocha.json\"\ntabstop = 4\n[keys.normal]\nx = \"Undo\"\ny = \"Redo\"\n[filetype.go]\ntabstop = 8\nexpandtab = false\n";
+        let project = Path::new(".rustik.toml");
+        let overrides =
+            "theme = \"dracula.json\"\n[keys.normal]\ny = \"Save\"\n[filetype.go]\ntabstop = 2\n";
+        let config = merge(toml, overrides, project).unwrap();
+        assert_eq!(config.theme, "dracula.json");
+        assert_eq!(config.tabstop, 4);
+        assert!(matches!(
+            config.keys.normal["x"],
+            KeyAction::Single(Action::Undo)
+        ));
+        assert!(matches!(
+            config.keys.normal["y"],
+            KeyAction::Single(Action::Save)
+        ));
+        let go = config.for_filetype("go");
+        assert_eq!((go.tabstop, go.expandtab), (2, false));
+
+        let err = merge(toml, "tabstop = \"wide\"\n", project).unwrap_err();
+        assert!(err.to_string().starts_with(".rustik.toml: "), "{err}");
+        let err = merge(toml, "\n[lsp.rust]\ncommand = \"sh\"\n", project).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            ".rustik.toml: `lsp` can only be set in the user config"
+        );
+    }
+
+    #[test]
+    fn test_for_filetype() {
+        let toml = "theme = \"mocha.json\"\n[keys]\n[abbreviations]\nteh = \"the\"\n[filetype.go]\ntabstop = 8\nexpandtab = false\nformatter = \"gofmt\"\nabbreviations = { iferr = \"if err != nil {\" }\n";
+        let config = parse(toml, Path::new("config.toml")).unwrap();
+        let go = config.for_filetype("go");
+        assert_eq!((go.tabstop, go.expandtab), (8, false));
+        assert_eq!(go.formatter.as_deref(), Some("gofmt"));
+        assert_eq!(go.abbreviations.len(), 2);
+        let rust = config.for_filetype("rust");
+        assert_eq!((rust.tabstop, rust.expandtab), (4, true));
+        assert_eq!(rust.comment.as_deref(), Some("//"));
+    }
 }
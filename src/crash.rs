@@ -0,0 +1,117 @@
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fs,
+    io::stdout,
+    panic,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::{
+    cursor::{self, SetCursorStyle},
+    event::{DisableBracketedPaste, DisableFocusChange, DisableMouseCapture},
+    terminal, ExecutableCommand,
+};
+
+use crate::{config, log};
+
+/// How many of the last actions a crash report lists.
+const RECENT_ACTIONS: usize = 50;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Remembers an action run, for the report of a crash that follows it.
+pub fn record(action: &impl std::fmt::Debug) {
+    let mut recent = RECENT.lock().unwrap_or_else(|err| err.into_inner());
+    if recent.len() == RECENT_ACTIONS {
+        recent.pop_front();
+    }
+    recent.push_back(format!("{action:?}"));
+}
+
+/// Sets up what a panic does: the terminal is handed back as it was found,
+/// a crash report is written to the state directory, and what to do next is
+/// printed. A panic on another thread than the editor's only writes the
+/// report, as the editor may well carry on.
+pub fn install() {
+    panic::set_hook(Box::new(|info| {
+        let main = thread::current().name() == Some("main");
+        if main && terminal::is_raw_mode_enabled().unwrap_or(false) {
+            restore_terminal();
+        }
+
+        let backtrace = Backtrace::force_capture().to_string();
+        let actions: Vec<String> = RECENT
+            .lock()
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default();
+        let report = format_report(&info.to_string(), &backtrace, &actions);
+        log!(Error, "{info}");
+        let written = path().and_then(|path| {
+            fs::create_dir_all(path.parent()?).ok()?;
+            fs::write(&path, report).ok().map(|_| path)
+        });
+
+        eprintln!("rustik crashed: {info}");
+        if let Some(path) = written {
+            eprintln!("A crash report was written to {}", path.display());
+        }
+        if main {
+            eprintln!(
+                "Edits not saved yet are in the swap file next to the file: \
+                 open it again and run :recover to get them back."
+            );
+        }
+    }));
+}
+
+fn restore_terminal() {
+    let mut stdout = stdout();
+    _ = stdout.execute(DisableMouseCapture);
+    _ = stdout.execute(DisableBracketedPaste);
+    _ = stdout.execute(DisableFocusChange);
+    _ = stdout.execute(SetCursorStyle::DefaultUserShape);
+    _ = stdout.execute(cursor::Show);
+    _ = stdout.execute(terminal::LeaveAlternateScreen);
+    _ = terminal::disable_raw_mode();
+}
+
+/// `crash-{secs}.txt` in the state directory, by when it happened.
+fn path() -> Option<PathBuf> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(config::state_dir()?.join(format!("crash-{}.txt", time.as_secs())))
+}
+
+fn format_report(panic: &str, backtrace: &str, actions: &[String]) -> String {
+    let mut report = format!(
+        "rustik {} crashed: {panic}\n\nLast actions, oldest first:\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for action in actions {
+        report.push_str(&format!("  {action}\n"));
+    }
+    report.push_str(&format!("\nBacktrace:\n{backtrace}\n"));
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_report() {
+        let report = format_report(
+            "panicked at src/editor.rs:1:1:\nindex out of bounds",
+            "   0: rustik::main",
+            &["MoveDown".to_string(), "DeleteCurrentLine".to_string()],
+        );
+        assert!(report.starts_with("rustik "));
+        assert!(report.contains("crashed: panicked at src/editor.rs:1:1:\nindex out of bounds\n"));
+        assert!(report.contains(
+            "Last actions, oldest first:\n  MoveDown\n  DeleteCurrentLine\n\nBacktrace:\n   0: rustik::main\n"
+        ));
+    }
+}
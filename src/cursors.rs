@@ -0,0 +1,132 @@
+use tree_sitter::InputEdit;
+
+use crate::fold;
+
+/// The cursors added besides the main one for multi-cursor editing, as
+/// `(x, line)` positions. Typing and deleting happen at all of them.
+#[derive(Debug, Default)]
+pub struct Cursors {
+    extra: Vec<(usize, usize)>,
+}
+
+impl Cursors {
+    pub fn is_empty(&self) -> bool {
+        self.extra.is_empty()
+    }
+
+    pub fn positions(&self) -> &[(usize, usize)] {
+        &self.extra
+    }
+
+    pub fn add(&mut self, pos: (usize, usize)) {
+        if !self.extra.contains(&pos) {
+            self.extra.push(pos);
+        }
+    }
+
+    pub fn set(&mut self, positions: Vec<(usize, usize)>) {
+        self.extra = positions;
+    }
+
+    pub fn clear(&mut self) {
+        self.extra.clear();
+    }
+
+    pub fn contains(&self, pos: (usize, usize)) -> bool {
+        self.extra.contains(&pos)
+    }
+
+    /// Keeps the cursors on the same lines when lines are inserted or removed
+    /// above them.
+    pub fn apply_edit(&mut self, edit: &InputEdit) {
+        if edit.old_end_position.row == edit.new_end_position.row {
+            return;
+        }
+        for (_, line) in self.extra.iter_mut() {
+            *line = fold::shift_row(*line, edit);
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The byte range of the word under `x` on `line`.
+pub fn word_at(line: &str, x: usize) -> Option<(usize, usize)> {
+    let c = line.get(x..)?.chars().next()?;
+    if !is_word_char(c) {
+        return None;
+    }
+    let start = line[..x]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(x, |(i, _)| i);
+    let end = line[x..]
+        .char_indices()
+        .find(|(_, c)| !is_word_char(*c))
+        .map_or(line.len(), |(i, _)| x + i);
+    Some((start, end))
+}
+
+/// Finds the next occurrence of `word` as a whole word after `from`, an
+/// `(x, line)` position, wrapping around the end of `lines`. Occurrences
+/// `skip` returns true for are passed over.
+pub fn find_next(
+    lines: &[String],
+    word: &str,
+    from: (usize, usize),
+    skip: impl Fn((usize, usize)) -> bool,
+) -> Option<(usize, usize)> {
+    let whole_word = |line: &str, start: usize| {
+        let before = line[..start].chars().next_back();
+        let after = line[start + word.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    };
+    let count = lines.len();
+    // the cursor's line comes around again at the end, for matches before it
+    for i in 0..=count {
+        let y = (from.1 + i) % count.max(1);
+        let line = lines.get(y)?;
+        let found = line
+            .match_indices(word)
+            .map(|(x, _)| x)
+            .filter(|&x| match i {
+                0 => x > from.0,
+                i if i == count => x <= from.0,
+                _ => true,
+            })
+            .find(|&x| whole_word(line, x) && !skip((x, y)));
+        if let Some(x) = found {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_next() {
+        let lines: Vec<String> = ["let foo = foobar;", "foo(foo)"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        assert_eq!(word_at(&lines[0], 5), Some((4, 7)));
+        assert_eq!(word_at(&lines[0], 3), None);
+
+        let none = |_| false;
+        assert_eq!(find_next(&lines, "foo", (4, 0), none), Some((0, 1)));
+        assert_eq!(find_next(&lines, "foo", (0, 1), none), Some((4, 1)));
+        assert_eq!(find_next(&lines, "foo", (4, 1), none), Some((4, 0)));
+        assert_eq!(
+            find_next(&lines, "foo", (4, 1), |pos| pos == (4, 0)),
+            Some((0, 1))
+        );
+        assert_eq!(find_next(&lines, "bar", (0, 0), none), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    path::Path,
+    process::Command,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::{InputEdit, Point};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "error" | "fatal" => Some(Severity::Error),
+            "warning" | "warn" => Some(Severity::Warning),
+            "info" | "note" => Some(Severity::Info),
+            "hint" | "help" => Some(Severity::Hint),
+            _ => None,
+        }
+    }
+
+    pub fn sign(&self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Info => 'I',
+            Severity::Hint => 'H',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    /// Byte column of the start of the diagnostic.
+    pub x: usize,
+    pub severity: Severity,
+    pub message: String,
+    pub source: String,
+}
+
+/// External lint command for a filetype. `{file}` in `args` is replaced with
+/// the buffer's path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Diagnostics for the current buffer, sorted by position. Each source (the
+/// language server or a lint command) replaces only its own diagnostics.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn replace(&mut self, source: &str, items: Vec<Diagnostic>) {
+        self.items.retain(|d| d.source != source);
+        self.items.extend(items);
+        self.items.sort_by_key(|d| (d.line, d.x, d.severity));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn on_line(&self, line: usize) -> impl Iterator<Item = &Diagnostic> {
+        let start = self.items.partition_point(|d| d.line < line);
+        self.items[start..]
+            .iter()
+            .take_while(move |d| d.line == line)
+    }
+
+    /// The most severe diagnostic on `line`.
+    pub fn worst_on_line(&self, line: usize) -> Option<&Diagnostic> {
+        self.on_line(line).min_by_key(|d| d.severity)
+    }
+
+    pub fn next(&self, line: usize, x: usize) -> Option<&Diagnostic> {
+        self.items
+            .iter()
+            .find(|d| (d.line, d.x) > (line, x))
+            .or_else(|| self.items.first())
+    }
+
+    pub fn prev(&self, line: usize, x: usize) -> Option<&Diagnostic> {
+        self.items
+            .iter()
+            .rev()
+            .find(|d| (d.line, d.x) < (line, x))
+            .or_else(|| self.items.last())
+    }
+
+    /// Keeps diagnostics on their lines when lines are added or removed above
+    /// them. Diagnostics on removed lines move to where the edit ended.
+    pub fn apply_edit(&mut self, edit: &InputEdit) {
+        if edit.old_end_position.row == edit.new_end_position.row {
+            return;
+        }
+        for d in self.items.iter_mut() {
+            let point = Point::new(d.line, d.x);
+            if point >= edit.old_end_position {
+                d.line = d.line + edit.new_end_position.row - edit.old_end_position.row;
+            } else if point > edit.start_position {
+                d.line = edit.new_end_position.row;
+            }
+        }
+        self.items.sort_by_key(|d| (d.line, d.x, d.severity));
+    }
+}
+
+/// Runs `config` for `file` on a separate thread, sending back the parsed
+/// diagnostics once it finishes.
+pub fn lint(config: &LintConfig, file: &str) -> Receiver<anyhow::Result<Vec<Diagnostic>>> {
+    let (tx, rx) = mpsc::channel();
+    let config = config.clone();
+    let file = file.to_string();
+
+    thread::spawn(move || {
+        let args = config.args.iter().map(|arg| arg.replace("{file}", &file));
+        let result = Command::new(&config.command)
+            .args(args)
+            .output()
+            .map(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                parse_lint_output(&format!("{stdout}\n{stderr}"), &file, &config.command)
+            })
+            .map_err(|err| anyhow::anyhow!("{}: {err}", config.command));
+        _ = tx.send(result);
+    });
+
+    rx
+}
+
+/// Parses `path:line:col: severity: message` lines, the format shared by most
+/// compilers and linters, keeping only the ones about `file`. The column and
+/// severity are optional.
+pub fn parse_lint_output(output: &str, file: &str, source: &str) -> Vec<Diagnostic> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, ':');
+            let path = parts.next()?.trim();
+            if !same_file(path, file) {
+                return None;
+            }
+            let line_number: usize = parts.next()?.trim().parse().ok()?;
+
+            let rest: Vec<&str> = parts.collect();
+            let (x, rest) = match rest
+                .first()
+                .and_then(|col| col.trim().parse::<usize>().ok())
+            {
+                Some(col) => (col.saturating_sub(1), rest[1..].join(":")),
+                None => (0, rest.join(":")),
+            };
+
+            let (severity, message) = match rest.split_once(':') {
+                Some((severity, message)) => match Severity::parse(severity) {
+                    Some(severity) => (severity, message),
+                    None => (Severity::Error, rest.as_str()),
+                },
+                None => (Severity::Error, rest.as_str()),
+            };
+
+            Some(Diagnostic {
+                line: line_number.checked_sub(1)?,
+                x,
+                severity,
+                message: message.trim().to_string(),
+                source: source.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    if a.is_empty() {
+        return false;
+    }
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => Path::new(a) == Path::new(b),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn diagnostic(line: usize, x: usize, severity: Severity) -> Diagnostic {
+        Diagnostic {
+            line,
+            x,
+            severity,
+            message: String::new(),
+            source: "test".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_lint_output() {
+        let output = "\
+src/main.rs:3:5: warning: unused variable: `x`
+src/other.rs:1:1: error: not this file
+src/main.rs:10: missing semicolon
+Compiling rustik";
+        let diagnostics = parse_lint_output(output, "src/main.rs", "cargo");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].x, 4);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].message, "unused variable: `x`");
+        assert_eq!(diagnostics[1].line, 9);
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[1].message, "missing semicolon");
+    }
+
+    #[test]
+    fn test_navigation_wraps() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.replace(
+            "test",
+            vec![
+                diagnostic(5, 0, Severity::Warning),
+                diagnostic(1, 2, Severity::Error),
+                diagnostic(5, 0, Severity::Error),
+            ],
+        );
+        assert_eq!(diagnostics.next(1, 2).unwrap().line, 5);
+        assert_eq!(diagnostics.next(5, 0).unwrap().line, 1);
+        assert_eq!(diagnostics.prev(1, 2).unwrap().line, 5);
+        assert_eq!(
+            diagnostics.worst_on_line(5).unwrap().severity,
+            Severity::Error
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_follow_line_edits() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.replace("test", vec![diagnostic(3, 0, Severity::Error)]);
+        // the line above is removed
+        diagnostics.apply_edit(&InputEdit {
+            start_byte: 0,
+            old_end_byte: 2,
+            new_end_byte: 0,
+            start_position: Point::new(1, 0),
+            old_end_position: Point::new(2, 0),
+            new_end_position: Point::new(1, 0),
+        });
+        assert_eq!(diagnostics.on_line(2).count(), 1);
+    }
+}
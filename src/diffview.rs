@@ -0,0 +1,144 @@
+use std::ops::Range;
+
+use crate::git;
+
+/// The buffer side by side with another version of it, from `:diff` or
+/// `:diffthis`. Each row holds a line of the other version (`old`), of the
+/// buffer (`new`), or of both.
+#[derive(Debug)]
+pub struct DiffView {
+    /// What the other version is, shown above it.
+    pub title: String,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+    pub rows: Vec<(Option<usize>, Option<usize>)>,
+    /// The row the cursor is on.
+    pub row: usize,
+    /// The first row shown.
+    pub top: usize,
+}
+
+impl DiffView {
+    pub fn new(title: String, old: Vec<String>, new: Vec<String>) -> Self {
+        let rows = {
+            let a: Vec<&str> = old.iter().map(String::as_str).collect();
+            let b: Vec<&str> = new.iter().map(String::as_str).collect();
+            git::align(&a, &b)
+        };
+        Self {
+            title,
+            old,
+            new,
+            rows,
+            row: 0,
+            top: 0,
+        }
+    }
+
+    /// Whether the sides differ on `row`.
+    pub fn changed(&self, row: usize) -> bool {
+        match self.rows.get(row) {
+            Some((Some(a), Some(b))) => self.old[*a] != self.new[*b],
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// The first rows of the blocks of changed rows.
+    pub fn hunks(&self) -> Vec<usize> {
+        (0..self.rows.len())
+            .filter(|row| self.changed(*row) && (*row == 0 || !self.changed(row - 1)))
+            .collect()
+    }
+
+    /// The first row of the next block of changes after the cursor,
+    /// wrapping around to the first one.
+    pub fn next_hunk(&self) -> Option<usize> {
+        let hunks = self.hunks();
+        hunks
+            .iter()
+            .find(|row| **row > self.row)
+            .or(hunks.first())
+            .copied()
+    }
+
+    /// The first row of the block of changes before the cursor's, wrapping
+    /// around to the last one.
+    pub fn prev_hunk(&self) -> Option<usize> {
+        let hunks = self.hunks();
+        hunks
+            .iter()
+            .rev()
+            .find(|row| **row < self.row)
+            .or(hunks.last())
+            .copied()
+    }
+
+    /// Puts the cursor on `row`, scrolling it into the `height` rows shown.
+    pub fn move_to(&mut self, row: usize, height: usize) {
+        self.row = row.min(self.rows.len().saturating_sub(1));
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + height {
+            self.top = self.row + 1 - height.max(1);
+        }
+    }
+
+    /// The buffer line of the cursor's row, or of the row below it with one
+    /// when the buffer has a gap there.
+    pub fn buffer_line(&self) -> usize {
+        self.rows[self.row..]
+            .iter()
+            .find_map(|(_, new)| *new)
+            .unwrap_or(self.new.len().saturating_sub(1))
+    }
+}
+
+/// The bytes of `old` and `new` that differ, between the start and end the
+/// lines have in common.
+pub fn changed_bytes(old: &str, new: &str) -> (Range<usize>, Range<usize>) {
+    let prefix: usize = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    let suffix: usize = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    (prefix..old.len() - suffix, prefix..new.len() - suffix)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_hunks() {
+        let mut view = DiffView::new("old".to_string(), lines("a b c d e"), lines("a B c e f"));
+        assert_eq!(view.hunks(), [1, 3, 5]);
+        assert_eq!(view.next_hunk(), Some(1));
+        view.move_to(3, 2);
+        assert_eq!((view.row, view.top), (3, 2));
+        assert_eq!(view.buffer_line(), 3);
+        assert_eq!(view.next_hunk(), Some(5));
+        assert_eq!(view.prev_hunk(), Some(1));
+        view.move_to(5, 2);
+        assert_eq!(view.next_hunk(), Some(1));
+    }
+
+    #[test]
+    fn test_changed_bytes() {
+        assert_eq!(changed_bytes("let x = 1;", "let xy = 2;"), (5..9, 5..10));
+        assert_eq!(changed_bytes("aé", "aéb"), (3..3, 3..4));
+        assert_eq!(changed_bytes("abc", "abc"), (3..3, 3..3));
+    }
+}
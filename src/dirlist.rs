@@ -0,0 +1,75 @@
+use std::{fs, path::Path};
+
+/// The lines listing `dir` for browsing it: `../` first, then the
+/// directories with a `/` after their names, then the files, each sorted by
+/// name.
+pub fn list(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut dirs = vec![];
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // follows symlinks, so a link to a directory is entered like one
+        match entry.path().is_dir() {
+            true => dirs.push(format!("{name}/")),
+            false => files.push(name),
+        }
+    }
+    dirs.sort();
+    files.sort();
+    let mut lines = vec!["../".to_string()];
+    lines.extend(dirs);
+    lines.extend(files);
+    Ok(lines)
+}
+
+/// The path of the entry on `line` of the listing of `dir`. `../` goes to
+/// the parent, dropping the directory's name when it has one to drop.
+pub fn entry_path(dir: &str, line: &str) -> String {
+    let dir = dir.trim_end_matches('/');
+    let line = line.trim_end_matches('/');
+    if line == ".." {
+        return match dir.rsplit_once('/') {
+            _ if dir.is_empty() => "/".to_string(),
+            _ if dir == "." => "..".to_string(),
+            _ if dir == ".." || dir.ends_with("/..") => format!("{dir}/.."),
+            Some(("", _)) => "/".to_string(),
+            Some((parent, _)) => parent.to_string(),
+            None => ".".to_string(),
+        };
+    }
+    match dir {
+        "." => line.to_string(),
+        _ => format!("{dir}/{line}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_list() {
+        let dir = std::env::temp_dir().join(format!("rustik-dirlist-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("main.rs"), "").unwrap();
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        let lines = list(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(lines, ["../", ".git/", "src/", "Cargo.toml", "main.rs"]);
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(entry_path("src", "main.rs"), "src/main.rs");
+        assert_eq!(entry_path("src/", "git/"), "src/git");
+        assert_eq!(entry_path(".", "src/"), "src");
+        assert_eq!(entry_path("src/git/", "../"), "src");
+        assert_eq!(entry_path("src", "../"), ".");
+        assert_eq!(entry_path(".", "../"), "..");
+        assert_eq!(entry_path("..", "../"), "../..");
+        assert_eq!(entry_path("/home", "../"), "/");
+        assert_eq!(entry_path("/", "../"), "/");
+    }
+}
@@ -0,0 +1,120 @@
+use tree_sitter::{Node, Parser};
+
+use crate::language::Language;
+
+/// Finds the documentation of `word` in `text` with the language's grammar:
+/// the first line of the definition naming it, then the comments right
+/// above that definition, without their markers.
+pub fn lookup(language: &Language, text: &str, word: &str) -> Option<Vec<String>> {
+    let mut parser = Parser::new();
+    parser.set_language(language.grammar?()).ok()?;
+    let tree = parser.parse(text, None)?;
+    let definition = find_definition(tree.root_node(), text.as_bytes(), word)?;
+
+    let mut comments = vec![];
+    let mut row = definition.start_position().row;
+    let mut node = definition.prev_sibling();
+    while let Some(n) = node {
+        // attributes can go between the comments and the definition
+        let kind = n.kind();
+        if !(kind.contains("comment") || kind.contains("attribute"))
+            || n.end_position().row + 1 < row
+        {
+            break;
+        }
+        if kind.contains("comment") {
+            comments.push(n.utf8_text(text.as_bytes()).ok()?);
+        }
+        row = n.start_position().row;
+        node = n.prev_sibling();
+    }
+
+    let signature = text[definition.start_byte()..]
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(['{', ' ']);
+    let mut lines = vec![signature.to_string()];
+    let docs: Vec<String> = comments
+        .iter()
+        .rev()
+        .flat_map(|comment| comment.lines())
+        .map(strip_comment)
+        .collect();
+    if docs.iter().any(|line| !line.is_empty()) {
+        lines.push(String::new());
+        lines.extend(docs);
+    }
+    Some(lines)
+}
+
+/// The first definition under `node` named `word`: an item, declaration or
+/// variant with a `name` field.
+fn find_definition<'a>(node: Node<'a>, source: &[u8], word: &str) -> Option<Node<'a>> {
+    let kind = node.kind();
+    let defines = kind.ends_with("_item")
+        || kind.ends_with("variant")
+        || kind.contains("declaration")
+        || kind.contains("definition");
+    let name = node
+        .child_by_field_name("name")
+        .and_then(|name| name.utf8_text(source).ok());
+    if defines && name == Some(word) {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let found = node
+        .children(&mut cursor)
+        .find_map(|child| find_definition(child, source, word));
+    found
+}
+
+/// A line of a comment without the comment markers.
+fn strip_comment(line: &str) -> String {
+    let mut line = line.trim();
+    for marker in ["///", "//!", "//", "/**", "/*", "*", "#"] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            line = rest;
+            break;
+        }
+    }
+    let line = line.strip_suffix("*/").unwrap_or(line).trim_end();
+    line.strip_prefix(' ').unwrap_or(line).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::language;
+
+    #[test]
+    fn test_lookup() {
+        let rust = language::find("rust").unwrap();
+        let text = "\
+/// Adds two numbers.
+///
+/// Wraps on overflow.
+#[inline]
+pub fn add(a: u8, b: u8) -> u8 {
+    a.wrapping_add(b)
+}
+
+// not about `sub`
+
+fn sub() {}
+";
+        assert_eq!(
+            lookup(rust, text, "add").unwrap(),
+            [
+                "pub fn add(a: u8, b: u8) -> u8",
+                "",
+                "Adds two numbers.",
+                "",
+                "Wraps on overflow."
+            ]
+        );
+        assert_eq!(lookup(rust, text, "sub").unwrap(), ["fn sub() {}"]);
+        assert_eq!(lookup(rust, text, "mul"), None);
+        assert_eq!(lookup(language::plain_text(), text, "add"), None);
+    }
+}
@@ -1,37 +1,144 @@
 use std::{
     collections::HashMap,
+    fs,
     io::{stdout, Write},
-    mem, usize,
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    time::{Duration, Instant, SystemTime},
 };
 
 use serde::{Deserialize, Serialize};
 
 use crossterm::{
     cursor::{self, Hide, MoveTo, Show},
-    event::{self, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    style::{self, Color, StyledContent, Stylize},
-    terminal::{self, Clear, ClearType},
-    ExecutableCommand, QueueableCommand,
+    event::{
+        self, read, DisableBracketedPaste, DisableFocusChange, DisableMouseCapture,
+        EnableBracketedPaste, EnableFocusChange, EnableMouseCapture, Event, KeyCode, KeyEvent,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    style, terminal, ExecutableCommand, QueueableCommand,
 };
 
 use crate::{
-    buffer::Buffer,
+    abbrev,
+    autopairs::{self, Typed},
+    block::{self, Block},
+    buffer::{self, Buffer, FileFormat},
+    buffers::{self, BufferList, Hidden},
+    command, comment,
+    completion::{self, CompletionMenu},
     config::KeyAction,
-    highlighter::Highlighter,
-    theme::{Style, Theme},
+    crash,
+    cursors::{self, Cursors},
+    diagnostics::{self, Diagnostic, Diagnostics, Severity},
+    diffview::{self, DiffView},
+    dirlist, docs, editorconfig,
+    encoding::Encoding,
+    finder,
+    float::{Float, Layer},
+    fold::{self, Folds},
+    git::{self, BlameLine, GitSigns, Hunk, HunkKind},
+    grep,
+    highlighter::{self, HighlightWorker, Highlighter, Request},
+    history::{self, History},
+    increment,
+    inlay::Hints,
+    jumplist::{Jump, JumpList},
+    keymap::{self, Keymap, Keymaps, Lookup},
+    language, log,
+    lsp::{self, Location, LspClient, Signature, TextEdit},
+    make::Make,
+    markdown,
+    marks::{self, Mark, Marks},
+    message::{Level, Messages},
+    motion::{self, Find, Motion},
+    picker::{Picker, PickerKind, Source},
+    plugin::{self, Plugins},
+    profile::{self, Profile},
+    project,
+    quickfix::{self, Entry, Quickfix},
+    recent::{self, Recent},
+    session::{self, BufferState, Session},
+    shell,
+    signals::{self, Signal, Signals},
+    status::GitStatus,
+    statusline,
+    surround::{self, Surrounding},
+    swap,
+    tabs::{Tab, Tabs},
+    tags,
+    term::{self, Terminal},
+    theme::{self, Style, Theme},
+    undofile, wrap,
 };
 
-use crate::config::Config;
+use crate::config::{Config, CursorShape, FiletypeOptions, LineNumbers, SaveOptions};
+
+const HIGHLIGHT_POLL_INTERVAL: Duration = Duration::from_millis(16);
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Rows of the viewport the `:make` output panel takes.
+const MAKE_PANEL_HEIGHT: usize = 10;
+const COMPLETION_MENU_HEIGHT: usize = 10;
+const POPUP_HEIGHT: usize = 15;
+const MOUSE_SCROLL_LINES: usize = 3;
+const GIT_EDIT_DELAY: Duration = Duration::from_millis(300);
+const GIT_REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+/// What the `:status` panel runs to list the changed files.
+const STATUS_ARGS: &[&str] = &["status", "--porcelain", "-z", "--untracked-files=all"];
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How deep `[commands]` can run one another before one is taken to run
+/// itself.
+const MAX_COMMAND_DEPTH: usize = 16;
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many queued events are handled at most before drawing a frame.
+const MAX_EVENT_BATCH: usize = 256;
+/// How soon a signal that doesn't come with an event is handled.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Action {
     Undo,
+    /// Quits unless a buffer has unsaved changes.
     Quit,
+    /// Quits, dropping unsaved changes.
+    ForceQuit,
+    Save,
+    /// `:w path`: names the file of a buffer without one and writes it, or
+    /// writes a copy of a named one. A file already there is only written
+    /// over when forced.
+    SaveAs(String, bool),
+    SaveAndQuit,
+    /// `:new`: opens an empty buffer without a file.
+    NewBuffer,
+    Autosave,
+    ReloadFile,
+    DiffFile,
+    RecoverSwap,
+    DeleteSwap,
+    MakeSession(Option<String>),
+    JumpBack,
+    JumpForward,
+    /// Goes to a line, counted from 1.
+    GotoLine(usize),
+    Motion(Motion),
+    /// Waits for a motion and applies the operator to the text it moves over.
+    Operator(Operator),
+    WaitForChar(CharAction),
+    WithChar(CharAction, char),
+    /// `;`: repeats the last `f`, `F`, `t` or `T`.
+    RepeatFind,
+    /// `,`: repeats the last find in the other direction.
+    RepeatFindReverse,
 
     MoveUp,
     MoveDown,
     MoveLeft,
     MoveRight,
+    /// `gk` / `gj`: moves up or down a row of a wrapped line.
+    MoveVisualUp,
+    MoveVisualDown,
     PageUp,
     PageDown,
 
@@ -39,14 +146,32 @@ pub enum Action {
     MoveToLineEnd,
 
     InsertCharAtCursorPos(char),
+    /// Types over the character under the cursor in replace mode.
+    ReplaceCharAtCursorPos(char),
+    /// Backspace in replace mode: brings back the character typed over.
+    RestoreReplacedChar,
     DeleteCharAtCursorPos,
+    /// `~`: toggles the case of the character under the cursor and moves on.
+    ToggleCaseChar,
+    ChangeSelectionCase(Case),
+    /// Comments out or uncomments the current line or the selected ones.
+    ToggleComment,
+    /// `Ctrl-a` / `Ctrl-x`: adds to the number under or after the cursor.
+    Increment(i64),
     DeleteCurrentLine,
     DeleteLineAt(usize),
+    /// `J`: joins the next line onto the current one.
+    JoinLines,
+    /// Joins that many lines, at least two, starting with the cursor's. A
+    /// count before `J` turns it into this.
+    JoinLinesCount(usize),
 
     NewLine,
 
     EnterMode(Mode),
-    SetWaitingKeyAction(Box<KeyAction>),
+    /// Still read under its old name, `SetWaitingKeyAction`, from configs.
+    #[serde(alias = "SetWaitingKeyAction")]
+    SetWaitingKey(Box<KeyAction>),
     InsertLineAt(usize, Option<String>),
     MoveLineToViewportCenter,
     InsertLineAtCursor,
@@ -56,14 +181,484 @@ pub enum Action {
     RemoveCharAt(usize, usize),
     UndoMultiple(Vec<Action>),
     DeletePreviousChar,
+
+    InsertCommandChar(char),
+    DeleteCommandChar,
+    ExecuteCommand,
+    SetFiletype(String),
+    SetFileEncoding(Encoding),
+    SetFileFormat(FileFormat),
+    /// Inserts a tab, or spaces up to the next tab stop with `expandtab`.
+    InsertTab,
+    /// Pipes the buffer through the filetype's `formatter`.
+    Format,
+    SetLineNumbers(LineNumbers),
+    SetColorColumn(Vec<usize>),
+    SetList(bool),
+    SetWrap(bool),
+    /// `:prose`: turns soft wrapping on or off.
+    ToggleWrap,
+    SetTextwidth(usize),
+    SetReadonly(bool),
+    ListBuffers,
+    /// Shows earlier commands in a picker, like vim's `q:`.
+    CommandHistory,
+    /// `:recent`: picks a file opened lately to open again.
+    RecentFiles,
+    CommandHistoryPrev,
+    CommandHistoryNext,
+    OpenFile(String),
+    /// `:cd [dir]`: changes the working directory, to the project root
+    /// without a directory.
+    ChangeDir(Option<String>),
+    /// `:lcd [dir]`: changes the directory of the current buffer only.
+    LocalChangeDir(Option<String>),
+    /// `:pwd`: shows the working directory, or the buffer's own.
+    PrintDir,
+    /// Opens the entry under the cursor of a directory listing, or moves
+    /// down a line elsewhere.
+    OpenEntry,
+    NextArg,
+    PrevArg,
+    /// Opens a tab page with a file, or an empty buffer.
+    TabNew(Option<String>),
+    TabClose,
+    NextTab,
+    PrevTab,
+    SwitchBuffer(usize),
+    /// Closes a buffer, the current one without a number, even with unsaved
+    /// changes when forced.
+    CloseBuffer(Option<usize>, bool),
+    PickerDelete,
+    HalfPageDown,
+    HalfPageUp,
+    MoveLineToViewportTop,
+    MoveLineToViewportBottom,
+    SetScrolloff(usize),
+    SetSidescrolloff(usize),
+    MatchBracket,
+    FoldToggle,
+    FoldAll,
+    UnfoldAll,
+    Hover,
+    PopupScrollDown(usize),
+    PopupScrollUp(usize),
+    GotoDefinition,
+    FindReferences,
+    /// Prompts for a new name for the symbol under the cursor.
+    Rename,
+    /// `:rename name`: renames the symbol under the cursor everywhere.
+    RenameTo(String),
+    Completion,
+    CompletionNext,
+    CompletionPrev,
+    CompletionAccept,
+    CompletionCancel,
+    NextDiagnostic,
+    PrevDiagnostic,
+    ShowDiagnostic,
+    Lint,
+    FindFile,
+    Grep(String),
+    LiveGrep,
+    /// Jumps to the next / previous location in the quickfix list.
+    NextQuickfix,
+    PrevQuickfix,
+    /// Picks a location from the quickfix list.
+    OpenQuickfix,
+    /// Fills the quickfix list with the buffer's diagnostics.
+    QuickfixFromDiagnostics,
+    /// `:make`: runs the filetype's build command with the arguments given.
+    Make(String),
+    /// Shows / hides the output panel of the last `:make`.
+    ToggleMakeOutput,
+    /// `:preview`: shows / hides the rendered Markdown next to the buffer.
+    TogglePreview,
+    /// `:diff file`: shows the buffer side by side with another file.
+    DiffWith(String),
+    /// `:diffthis`: shows the buffer side by side with its version at the
+    /// git HEAD.
+    DiffHead,
+    /// Moves the cursor of the diff down / up.
+    DiffDown(usize),
+    DiffUp(usize),
+    /// Closes the diff, going to the line under its cursor.
+    DiffGoto,
+    CloseDiff,
+    /// `:zen`: hides the gutter and statusline and centers the text.
+    ToggleZen,
+    /// `:hints`: shows or hides the inlay hints in the buffer.
+    ToggleInlayHints,
+    /// `:!cmd`: runs a shell command and shows what it printed.
+    Shell(String),
+    /// `:r !cmd`: inserts the output of a shell command below the cursor.
+    ReadShell(String),
+    /// `!` in visual mode: prompts for a command to filter the selected lines
+    /// through.
+    FilterSelection,
+    /// `:{range}!cmd`: replaces the lines, counted from 0, with the output of
+    /// a shell command they're fed to.
+    FilterLines(usize, usize, String),
+    PickerInsertChar(char),
+    PickerDeleteChar,
+    PickerNext,
+    PickerPrev,
+    PickerAccept,
+    ToggleTerminal,
+    TerminalInput(String),
+    NextHunk,
+    PrevHunk,
+    /// Shows the change under the cursor since the last commit in a popup.
+    PreviewHunk,
+    /// Stages the change under the cursor, leaving the rest of the file as
+    /// it is in the git index.
+    StageHunk,
+    /// Puts the lines of the change under the cursor back to how they are
+    /// at the git HEAD.
+    RevertHunk,
+    /// `:status`: lists the changed files of the repository in a panel.
+    GitStatus,
+    /// Moves the cursor of the status panel to the next / previous file.
+    StatusNext,
+    StatusPrev,
+    /// Stages / unstages the file under the cursor of the status panel.
+    StatusStage,
+    StatusUnstage,
+    /// Opens the file under the cursor of the status panel.
+    StatusOpen,
+    CloseStatus,
+    /// `:commit`: opens a buffer for the message of a commit of the staged
+    /// changes, which saving it makes.
+    Commit,
+    /// Runs a command from `[commands]` in the config.
+    Run(String),
+    /// Runs a command a plugin added, with the arguments typed after it.
+    PluginCommand(String, String),
+    /// Replaces the lines in the range with others, undoable in one step.
+    SetLines(usize, usize, Vec<String>),
+    ToggleBlame,
+    ShowMessages,
+    /// `:log`: opens what `--log-file` logged so far in a read-only buffer.
+    ShowLog,
+    /// `:profile`: shows or hides how long the last frames took to draw.
+    ToggleProfile,
+    /// `Ctrl-z`: stops the editor and goes back to the shell, until `fg`.
+    Suspend,
+    DeleteSelection,
+    /// Copies the selection into the register.
+    Yank,
+    /// Adds a cursor on the next occurrence of the word under the cursor.
+    AddCursorAtNextMatch,
+    ClearCursors,
+    /// Pastes the register after the cursor.
+    PasteAfter,
+    /// Pastes the register before the cursor.
+    PasteBefore,
+    /// `I` in visual block mode: inserts the text typed on the first line in
+    /// front of the block on every line.
+    BlockInsert,
+    /// `A` in visual block mode: appends the text after the block.
+    BlockAppend,
+    InsertTextAt(usize, usize, String),
+    /// Scrolls the viewport by this many lines, keeping the cursor on its
+    /// line while it's visible.
+    ScrollUp(usize),
+    ScrollDown(usize),
+    ClickAt(u16, u16),
+    DragTo(u16, u16),
+    Paste(String),
+    RemoveRange((usize, usize), (usize, usize)),
+    ReloadConfig,
+    PickTheme,
+    SetTheme(String),
+    /// `:vscode-theme <name>`: switches to a theme of the VSCode extensions
+    /// installed.
+    ImportTheme(String),
+}
+
+impl Action {
+    /// Whether a count typed before the action repeats it.
+    fn is_repeatable(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveVisualUp
+                | Action::MoveVisualDown
+                | Action::PageUp
+                | Action::PageDown
+                | Action::ScrollUp(_)
+                | Action::ScrollDown(_)
+                | Action::DeleteCharAtCursorPos
+                | Action::ToggleCaseChar
+                | Action::DeleteCurrentLine
+                | Action::Undo
+                | Action::NextDiagnostic
+                | Action::PrevDiagnostic
+                | Action::NextHunk
+                | Action::PrevHunk
+                | Action::JumpBack
+                | Action::JumpForward
+                | Action::Motion(_)
+                | Action::RepeatFind
+                | Action::RepeatFindReverse
+        )
+    }
+
+    /// Whether the action changes the buffer or starts changing it, which a
+    /// read-only buffer refuses. Writing counts too, since it can trim
+    /// whitespace.
+    fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Action::Undo
+                | Action::Save
+                | Action::SaveAs(_, _)
+                | Action::SaveAndQuit
+                | Action::RecoverSwap
+                | Action::SetFileEncoding(_)
+                | Action::SetFileFormat(_)
+                | Action::InsertTab
+                | Action::Format
+                | Action::Operator(_)
+                | Action::WaitForChar(
+                    CharAction::Replace
+                        | CharAction::Surround
+                        | CharAction::SurroundSelection
+                        | CharAction::DeleteSurround
+                        | CharAction::ChangeSurround
+                        | CharAction::ChangeSurroundTo(_)
+                )
+                | Action::WithChar(
+                    CharAction::Replace
+                        | CharAction::Surround
+                        | CharAction::SurroundSelection
+                        | CharAction::DeleteSurround
+                        | CharAction::ChangeSurround
+                        | CharAction::ChangeSurroundTo(_),
+                    _
+                )
+                | Action::InsertCharAtCursorPos(_)
+                | Action::ReplaceCharAtCursorPos(_)
+                | Action::RestoreReplacedChar
+                | Action::DeleteCharAtCursorPos
+                | Action::ToggleCaseChar
+                | Action::ChangeSelectionCase(_)
+                | Action::ToggleComment
+                | Action::Increment(_)
+                | Action::DeleteCurrentLine
+                | Action::DeleteLineAt(_)
+                | Action::JoinLines
+                | Action::JoinLinesCount(_)
+                | Action::NewLine
+                | Action::EnterMode(Mode::Insert | Mode::Replace)
+                | Action::InsertLineAt(_, _)
+                | Action::InsertLineAtCursor
+                | Action::InsertLineBelowCursor
+                | Action::RemoveCharAt(_, _)
+                | Action::UndoMultiple(_)
+                | Action::DeletePreviousChar
+                | Action::CompletionAccept
+                | Action::ReadShell(_)
+                | Action::FilterSelection
+                | Action::FilterLines(_, _, _)
+                | Action::DeleteSelection
+                | Action::PasteAfter
+                | Action::PasteBefore
+                | Action::BlockInsert
+                | Action::BlockAppend
+                | Action::InsertTextAt(_, _, _)
+                | Action::Paste(_)
+                | Action::RemoveRange(_, _)
+                | Action::RevertHunk
+                | Action::SetLines(_, _, _)
+        )
+    }
+
+    /// Whether the action moves the cursor in a way an operator can apply to.
+    fn is_motion(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveToLineStart
+                | Action::MoveToLineEnd
+                | Action::MoveToTop
+                | Action::MoveToBottom
+                | Action::PageUp
+                | Action::PageDown
+                | Action::GotoLine(_)
+                | Action::Motion(_)
+                | Action::WithChar(
+                    CharAction::GotoMark | CharAction::GotoMarkLine | CharAction::Find(_),
+                    _
+                )
+                | Action::RepeatFind
+                | Action::RepeatFindReverse
+        )
+    }
+
+    /// Whether an operator applies to whole lines after the motion, like
+    /// `dj` deleting both lines.
+    fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveToTop
+                | Action::MoveToBottom
+                | Action::PageUp
+                | Action::PageDown
+                | Action::GotoLine(_)
+                | Action::WithChar(CharAction::GotoMarkLine, _)
+        )
+    }
+
+    /// Whether an operator includes the character the motion lands on, like
+    /// `d$` deleting the last character. Repeated finds depend on the last
+    /// one, see `Editor::is_inclusive`.
+    fn is_inclusive(&self) -> bool {
+        match self {
+            Action::WithChar(CharAction::Find(find), _) => find.is_forward(),
+            action => matches!(action, Action::MoveToLineEnd),
+        }
+    }
+}
+
+/// Yanked text, as a selection or a block.
+#[derive(Debug, Clone, Copy)]
+enum Flash {
+    Range((usize, usize), (usize, usize)),
+    Block(Block),
+}
+
+impl Flash {
+    fn contains(&self, x: usize, line: usize) -> bool {
+        match self {
+            Flash::Range(start, end) => {
+                (line, x) >= (start.1, start.0) && (line, x) < (end.1, end.0)
+            }
+            Flash::Block(block) => block.contains(x, line),
+        }
+    }
+}
+
+/// What an operator does to the text a motion moves over.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum Operator {
+    Delete,
+    /// Deletes the text and starts insert mode.
+    Change,
+    /// `gu`, `gU` and `g~`.
+    Case(Case),
+    /// `ys`: waits for a character and puts its delimiters around the text.
+    Surround,
+    /// `!`: prompts for a command to filter the lines through.
+    Filter,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum Case {
+    Lower,
+    Upper,
+    Toggle,
+}
+
+impl Case {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Case::Lower => text.to_lowercase(),
+            Case::Upper => text.to_uppercase(),
+            Case::Toggle => text
+                .chars()
+                .flat_map(|c| match c.is_uppercase() {
+                    true => c.to_lowercase().collect::<Vec<_>>(),
+                    false => c.to_uppercase().collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The most times a count repeats an action.
+const MAX_COUNT: usize = 10_000;
+
+/// Applies a count typed before a key: `G` and `gg` go to that line and
+/// repeatable actions run that many times.
+fn apply_count(ka: KeyAction, count: usize) -> KeyAction {
+    if let KeyAction::Nested(_) = ka {
+        return ka;
+    }
+    let actions = keymap::into_actions(ka)
+        .into_iter()
+        .flat_map(|action| match action {
+            Action::MoveToTop | Action::MoveToBottom => vec![Action::GotoLine(count)],
+            Action::JoinLines => vec![Action::JoinLinesCount(count.min(MAX_COUNT))],
+            Action::Increment(delta) => vec![Action::Increment(delta.saturating_mul(count as i64))],
+            action if action.is_repeatable() => vec![action; count.min(MAX_COUNT)],
+            action => vec![action],
+        })
+        .collect();
+    KeyAction::Multiple(actions)
 }
 
-impl Action {}
+/// Actions that take the next character typed, like the mark name after `m`.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum CharAction {
+    SetMark,
+    GotoMark,
+    GotoMarkLine,
+    Find(Find),
+    /// `r`: replaces the character under the cursor.
+    Replace,
+    /// The delimiters to put around the text after `ys{motion}`.
+    Surround,
+    /// `S` in visual mode.
+    SurroundSelection,
+    /// `ds`: deletes the delimiters around the cursor.
+    DeleteSurround,
+    /// `cs`: picks the delimiters around the cursor to change, then waits
+    /// for the new ones.
+    ChangeSurround,
+    ChangeSurroundTo(char),
+}
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Mode {
     Normal,
     Insert,
+    Command,
+    Picker,
+    Terminal,
+    Visual,
+    /// Typed characters replace the ones under the cursor, entered with `R`.
+    Replace,
+    /// Selects a rectangle of columns, entered with `Ctrl-v`.
+    VisualBlock,
+}
+
+/// Text copied with `y` or deleted from a selection, for `p` to paste.
+#[derive(Debug, Clone)]
+pub enum Register {
+    Text(String),
+    /// The lines of a visual block, pasted as a block again.
+    Block(Vec<String>),
+}
+
+/// An insert started with `I` or `A` in visual block mode, repeated on the
+/// rest of the block's lines when it ends.
+#[derive(Debug, Clone, Copy)]
+struct BlockInsert {
+    x: usize,
+    top: usize,
+    bottom: usize,
+    /// The length of the first line when the insert started.
+    len: usize,
+    append: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -85,12 +680,20 @@ struct Cell {
     style: Style,
 }
 
+/// The screen as it's drawn, cell by cell. Only rows drawn to since the last
+/// frame are compared with what the terminal shows, and only the cells that
+/// differ get written.
 #[derive(Debug, Clone)]
 pub struct RenderBuffer {
     cells: Vec<Cell>,
+    /// What the terminal shows, as of the last frame.
+    shown: Vec<Cell>,
+    /// The rows drawn to since the last frame.
+    dirty: Vec<bool>,
     width: usize,
-    #[allow(unused)]
     height: usize,
+    /// The floats to draw over the cells, see `draw_floats`.
+    floats: Vec<Float>,
 }
 
 impl RenderBuffer {
@@ -111,11 +714,7 @@ impl RenderBuffer {
                 });
             }
         }
-        RenderBuffer {
-            cells,
-            width,
-            height,
-        }
+        RenderBuffer::with_cells(cells, width, height)
     }
 
     fn new(width: usize, height: usize, default_style: Style) -> Self {
@@ -127,23 +726,62 @@ impl RenderBuffer {
             width * height
         ];
 
-        RenderBuffer {
+        RenderBuffer::with_cells(cells, width, height)
+    }
+
+    /// A buffer that hasn't been shown yet, so every cell of the first frame
+    /// gets written.
+    fn with_cells(cells: Vec<Cell>, width: usize, height: usize) -> Self {
+        let mut buffer = RenderBuffer {
+            shown: vec![],
+            dirty: vec![],
             cells,
             width,
             height,
+            floats: vec![],
+        };
+        buffer.invalidate();
+        buffer
+    }
+
+    /// Forgets what the terminal shows, for when it's been cleared or
+    /// resized.
+    fn invalidate(&mut self) {
+        let unknown = Cell {
+            c: '\0',
+            style: Style::default(),
+        };
+        self.shown = vec![unknown; self.cells.len()];
+        self.dirty = vec![true; self.height];
+    }
+
+    /// Marks the rows `len` cells starting at `pos` span as drawn to.
+    fn touch(&mut self, pos: usize, len: usize) {
+        let first = pos / self.width.max(1);
+        let last = (pos + len.max(1) - 1) / self.width.max(1);
+        for row in first..=last.min(self.height.saturating_sub(1)) {
+            self.dirty[row] = true;
         }
     }
 
     fn set_char(&mut self, x: usize, y: usize, c: char, style: &Style) {
         let pos = (y * self.width) + x;
+        self.touch(pos, 1);
         self.cells[pos] = Cell {
             c,
             style: style.clone(),
         };
     }
 
+    fn set_bg(&mut self, x: usize, y: usize, bg: Option<style::Color>) {
+        let pos = (y * self.width) + x;
+        self.touch(pos, 1);
+        self.cells[pos].style.bg = bg;
+    }
+
     fn set_text(&mut self, x: usize, y: usize, s: &str, style: &Style) {
         let pos = (y * self.width) + x;
+        self.touch(pos, s.chars().count());
         for (i, c) in s.chars().enumerate() {
             self.cells[pos + i] = Cell {
                 c,
@@ -152,19 +790,92 @@ impl RenderBuffer {
         }
     }
 
-    fn diff(&self, other: &RenderBuffer) -> Vec<Change> {
+    /// Queues a float to draw with the others.
+    fn add_float(&mut self, float: Float) {
+        self.floats.push(float);
+    }
+
+    /// Draws the queued floats over whatever is there, the lowest layer
+    /// first, so the ones above cover them.
+    fn draw_floats(&mut self) {
+        let mut floats = mem::take(&mut self.floats);
+        floats.sort_by_key(|float| float.layer);
+        for float in &floats {
+            self.draw_float(float);
+        }
+    }
+
+    /// Draws a float, clipped to the buffer.
+    fn draw_float(&mut self, float: &Float) {
+        let right = (float.x + float.width).min(self.width);
+        let bottom = (float.y + float.height).min(self.height);
+        if float.x >= right || float.y >= bottom {
+            return;
+        }
+        if float.border && right - float.x >= 2 && bottom - float.y >= 2 {
+            let line = "─".repeat(right - float.x - 2);
+            self.set_text(float.x, float.y, &format!("┌{line}┐"), &float.style);
+            for y in float.y + 1..bottom - 1 {
+                self.set_char(float.x, y, '│', &float.style);
+                self.set_char(right - 1, y, '│', &float.style);
+            }
+            self.set_text(float.x, bottom - 1, &format!("└{line}┘"), &float.style);
+        }
+
+        let (x, y, width, height) = float.inner();
+        let right = (x + width).min(self.width);
+        for row in 0..height.min(bottom.saturating_sub(y)) {
+            let pieces = float.rows.get(row).map_or(&[][..], |r| r.as_slice());
+            let mut col = x;
+            for (text, style) in pieces {
+                for c in text.chars().take(right.saturating_sub(col)) {
+                    self.set_char(col, y + row, c, style);
+                    col += 1;
+                }
+            }
+            let fill = pieces.last().map_or(&float.style, |(_, style)| style);
+            let blanks = " ".repeat(right.saturating_sub(col));
+            self.set_text(col, y + row, &blanks, fill);
+        }
+    }
+
+    #[cfg(test)]
+    fn diff(&self, other: &RenderBuffer) -> Vec<Change<'_>> {
+        self.diff_rows(&other.cells, |_| true)
+    }
+
+    /// The cells of the rows drawn to since the last frame that differ from
+    /// what the terminal shows. Call `present` once they're written.
+    fn changes(&self) -> Vec<Change<'_>> {
+        self.diff_rows(&self.shown, |row| self.dirty[row])
+    }
+
+    fn diff_rows(&self, other: &[Cell], rows: impl Fn(usize) -> bool) -> Vec<Change<'_>> {
         let mut changes = vec![];
 
-        for (pos, cell) in self.cells.iter().enumerate() {
-            if *cell != other.cells[pos] {
-                let y = pos / self.width;
-                let x = pos % self.width;
-                changes.push(Change { x, y, cell });
+        for y in (0..self.height).filter(|y| rows(*y)) {
+            let start = y * self.width;
+            for x in 0..self.width {
+                let cell = &self.cells[start + x];
+                if *cell != other[start + x] {
+                    changes.push(Change { x, y, cell });
+                }
             }
         }
 
         changes
     }
+
+    /// Records the rows drawn to as shown on the terminal.
+    fn present(&mut self) {
+        for y in 0..self.height {
+            if self.dirty[y] {
+                let row = y * self.width..(y + 1) * self.width;
+                self.shown[row.clone()].clone_from_slice(&self.cells[row]);
+                self.dirty[y] = false;
+            }
+        }
+    }
 }
 
 pub struct Change<'a> {
@@ -176,26 +887,142 @@ pub struct Change<'a> {
 pub struct Editor {
     config: Config,
     theme: Theme,
-    highlighter: Highlighter,
+    highlighter: HighlightWorker,
+    styles: Vec<StyleInfo>,
+    brackets: Vec<(usize, usize)>,
+    bracket_match: Option<(usize, usize)>,
+    folds: Folds,
+    jumps: JumpList,
+    marks: Marks,
+    /// Set by `WaitForChar` until the next key is typed.
+    waiting_char: Option<CharAction>,
+    /// The count typed before a normal mode key, like the 3 in `3j`.
+    count: Option<usize>,
+    /// Set by an operator key like `d` until the motion after it, in what
+    /// vim calls operator-pending mode.
+    pending_operator: Option<Operator>,
+    /// The text `ys{motion}` is putting delimiters around.
+    surround_range: Option<((usize, usize), (usize, usize))>,
+    /// The last `f`, `F`, `t` or `T` and its character, for `;` and `,`.
+    last_find: Option<(Find, char)>,
+    version: usize,
+    highlighted_version: usize,
     buffer: Buffer,
     stdout: std::io::Stdout,
     size: (u16, u16),
     vtop: usize,
     vleft: usize,
+    /// Whether long lines wrap onto the rows below instead of scrolling
+    /// sideways.
+    wrap: bool,
+    /// The screen row the viewport starts on, below the tabline when there
+    /// is one.
+    vy: usize,
     cx: usize,
     cy: usize,
     vx: usize,
     mode: Mode,
     waiting_key_action: Option<KeyAction>,
+    keymaps: Keymaps,
+    /// The keys typed so far of a sequence that isn't complete yet.
+    pending_keys: Vec<KeyEvent>,
+    pending_at: Instant,
     undo_actions: Vec<Action>,
     insert_undo_actions: Vec<Action>,
+    register: Option<Register>,
+    cursors: Cursors,
+    block_insert: Option<BlockInsert>,
+    command: String,
+    history: History,
+    /// The files opened lately, for `:recent` and to reopen them where the
+    /// cursor was left.
+    recent: Recent,
+    messages: Messages,
+    popup: Option<Vec<String>>,
+    /// How many lines of the popup are scrolled past.
+    popup_scroll: usize,
+    completion: Option<CompletionMenu>,
+    /// The signature of the call being typed, from the language server.
+    signature: Option<Signature>,
+    picker: Option<Picker>,
+    buffers: BufferList,
+    /// The files given on the command line, for `:next` and `:prev`, and
+    /// the one last gone to.
+    arglist: Vec<String>,
+    arg_index: usize,
+    tabs: Tabs,
+    /// The buffer `Ctrl-d` in the buffer picker warned has unsaved changes,
+    /// closed when it's pressed on it again.
+    confirm_close: Option<usize>,
+    quickfix: Quickfix,
+    lsp: Option<LspClient>,
+    diagnostics: Diagnostics,
+    hints: Hints,
+    /// The running lint job, with the command it runs, which names the
+    /// source of its diagnostics.
+    lint: Option<(String, Receiver<anyhow::Result<Vec<Diagnostic>>>)>,
+    terminal: Option<Terminal>,
+    git_signs: GitSigns,
+    git_diff: Option<Receiver<anyhow::Result<Vec<Hunk>>>>,
+    /// When and for which version the buffer was last diffed against `HEAD`.
+    git_diffed: Option<(Instant, usize)>,
+    git_tracked: bool,
+    show_blame: bool,
+    blame: Vec<BlameLine>,
+    blame_job: Option<Receiver<anyhow::Result<Vec<BlameLine>>>>,
+    /// The last build `:make` started, and whether its output panel shows.
+    make: Option<Make>,
+    show_make: bool,
+    /// Whether Markdown buffers show rendered next to the text.
+    show_preview: bool,
+    /// The diff shown over the viewport.
+    diff: Option<DiffView>,
+    /// The `:status` panel shown over the viewport.
+    status: Option<GitStatus>,
+    /// The repository and the message file of the commit saving the buffer
+    /// makes.
+    commit_message: Option<(PathBuf, String)>,
+    plugins: Plugins,
+    /// The timings of the last frames, shown over the viewport while set.
+    profile: Option<Profile>,
+    /// Whether `run` took over the terminal, to hand it back on drop.
+    in_terminal: bool,
+    /// The signals `run` handles between events.
+    signals: Option<Signals>,
+    /// The window title last set, `None` until it's set.
+    title: Option<String>,
+    /// How deep `[commands]` running each other are nested.
+    running_commands: usize,
+    /// Whether the text is centered without a gutter or statusline.
+    zen: bool,
+    /// The text just yanked, flashing until the deadline.
+    flash: Option<(Flash, Instant)>,
+    blamed_version: usize,
+    branch: Option<String>,
+    /// Where the selection started in visual mode, as `(x, line)`.
+    selection_anchor: Option<(usize, usize)>,
+    /// The config file, reloaded when its modification time changes.
+    config_path: Option<PathBuf>,
+    config_modified: Option<SystemTime>,
+    /// The project's `.rustik.toml` merged over it, reloaded along with it.
+    project_config: Option<PathBuf>,
+    project_modified: Option<SystemTime>,
+    config_checked: Instant,
+    file_checked: Instant,
+    /// Set when the file changed on disk while the buffer has unsaved edits,
+    /// until the next key picks which version to keep.
+    file_conflict: bool,
+    /// The version last written to the swap file and when, or `None` while
+    /// there's no swap file.
+    swapped: Option<(Instant, usize)>,
+    last_input: Instant,
 }
 
 impl Drop for Editor {
     fn drop(&mut self) {
-        _ = self.stdout.flush();
-        _ = self.stdout.execute(terminal::LeaveAlternateScreen);
-        _ = terminal::disable_raw_mode();
+        if self.in_terminal {
+            self.leave_terminal();
+        }
     }
 }
 
@@ -209,26 +1036,108 @@ impl Editor {
     ) -> anyhow::Result<Self> {
         let stdout = stdout();
 
-        let vx = buffer.len().to_string().len() + 2;
+        let vx = gutter_width(config.line_numbers, buffer.len()) + 1;
+        let wrap = config.for_filetype(&buffer.filetype).wrap;
         let size = (width as u16, height as u16);
-        let highlighter = Highlighter::new(&theme)?;
+        let language = language::find(&buffer.filetype).unwrap_or_else(language::plain_text);
+        let highlighter = HighlightWorker::spawn(Highlighter::new(&theme, language)?);
+        highlighter.send(Request::Update {
+            version: 1,
+            code: buffer.text(),
+        })?;
 
         Ok(Editor {
+            keymaps: Keymaps::new(&config.keys),
             config,
             theme,
             highlighter,
+            styles: vec![],
+            brackets: vec![],
+            bracket_match: None,
+            folds: Folds::default(),
+            jumps: JumpList::default(),
+            marks: Marks::default(),
+            waiting_char: None,
+            count: None,
+            pending_operator: None,
+            last_find: None,
+            surround_range: None,
+            version: 1,
+            highlighted_version: 0,
             buffer,
             stdout,
             vtop: 0,
             vleft: 0,
+            wrap,
+            vy: 0,
             cx: 0,
             cy: 0,
             vx,
             mode: Mode::Normal,
             size,
             waiting_key_action: None,
+            pending_keys: vec![],
+            pending_at: Instant::now(),
             undo_actions: vec![],
             insert_undo_actions: vec![],
+            register: None,
+            cursors: Cursors::default(),
+            block_insert: None,
+            command: String::new(),
+            history: history::path()
+                .map(|path| History::load(&path))
+                .unwrap_or_default(),
+            recent: Recent::default(),
+            messages: Messages::default(),
+            popup: None,
+            popup_scroll: 0,
+            completion: None,
+            signature: None,
+            picker: None,
+            buffers: BufferList::default(),
+            arglist: vec![],
+            arg_index: 0,
+            tabs: Tabs::default(),
+            confirm_close: None,
+            quickfix: Quickfix::default(),
+            lsp: None,
+            diagnostics: Diagnostics::default(),
+            hints: Hints::default(),
+            lint: None,
+            terminal: None,
+            git_signs: GitSigns::default(),
+            git_diff: None,
+            git_diffed: None,
+            git_tracked: true,
+            show_blame: false,
+            blame: vec![],
+            blame_job: None,
+            make: None,
+            show_make: false,
+            show_preview: false,
+            diff: None,
+            status: None,
+            commit_message: None,
+            plugins: Plugins::default(),
+            profile: None,
+            in_terminal: false,
+            signals: None,
+            title: None,
+            running_commands: 0,
+            zen: false,
+            flash: None,
+            blamed_version: 0,
+            branch: None,
+            config_path: None,
+            config_modified: None,
+            project_config: None,
+            project_modified: None,
+            config_checked: Instant::now(),
+            file_checked: Instant::now(),
+            file_conflict: false,
+            swapped: None,
+            last_input: Instant::now(),
+            selection_anchor: None,
         })
     }
 
@@ -238,7 +1147,8 @@ impl Editor {
     }
 
     fn vheight(&self) -> usize {
-        self.size.1 as usize - 2
+        // zen mode takes the statusline's row too
+        self.size.1 as usize - 2 - self.vy + usize::from(self.zen)
     }
 
     fn vwidth(&self) -> usize {
@@ -252,590 +1162,6202 @@ impl Editor {
         0
     }
 
-    fn buffer_line(&self) -> usize {
-        self.vtop + self.cy as usize
-    }
+    /// The selected text in visual mode, from the anchor to the cursor, as
+    /// a start and an exclusive end position. The character under the later
+    /// one is included, and so is the line break of an empty line.
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        if matches!(self.mode, Mode::VisualBlock) {
+            return None;
+        }
+        let anchor = self.selection_anchor?;
+        let cursor = (self.cx, self.buffer_line());
+        let (start, end) = if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        };
 
-    fn viewport_line(&self, n: usize) -> Option<String> {
-        let buffer_line = self.vtop + n;
-        self.buffer.get(buffer_line)
+        let line = self.buffer.lines.get(end.1).map_or("", |l| l.as_str());
+        let end = match line.get(end.0..).and_then(|rest| rest.chars().next()) {
+            Some(c) => (end.0 + c.len_utf8(), end.1),
+            None if end.1 + 1 < self.buffer.len() => (0, end.1 + 1),
+            None => (line.len(), end.1),
+        };
+        Some((start, end))
     }
 
-    fn set_cursor_style(&mut self) -> anyhow::Result<()> {
-        self.stdout.queue(match self.waiting_key_action {
-            Some(_) => cursor::SetCursorStyle::SteadyUnderScore,
-            _ => match self.mode {
-                Mode::Normal => cursor::SetCursorStyle::DefaultUserShape,
-                Mode::Insert => cursor::SetCursorStyle::SteadyBar,
-            },
-        })?;
-
-        Ok(())
+    /// The selected block in visual block mode.
+    fn block(&self) -> Option<Block> {
+        let anchor = self.selection_anchor.filter(|_| self.is_visual_block())?;
+        let mut block = Block::new(anchor, (self.cx, self.buffer_line()));
+        // the cursor can be below the last line until `check_bounds` runs
+        let last = self.buffer.len().saturating_sub(1);
+        block.top = block.top.min(last);
+        block.bottom = block.bottom.min(last);
+        Some(block)
     }
 
-    pub fn highlight(&mut self, code: &str) -> anyhow::Result<Vec<StyleInfo>> {
-        self.highlighter.highlight(code)
+    fn is_visual(&self) -> bool {
+        matches!(self.mode, Mode::Visual | Mode::VisualBlock)
     }
 
-    fn fill_line(&mut self, buffer: &mut RenderBuffer, x: usize, y: usize, style: &Style) {
-        let width = self.vwidth().saturating_sub(x);
-        let line_fill = " ".repeat(width);
-        buffer.set_text(x, y, &line_fill, style);
+    fn is_visual_block(&self) -> bool {
+        matches!(self.mode, Mode::VisualBlock)
     }
 
-    pub fn draw_viewport(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
-        let vbuffer = self.buffer.viewport(self.vtop, self.vheight() as usize);
-        let style_info = self.highlight(&vbuffer)?;
-        let vheight = self.vheight();
-        let default_style = self.theme.style.clone();
-
-        let mut x = self.vx;
-        let mut y = 0;
-        let mut iter = vbuffer.chars().enumerate().peekable();
-
-        while let Some((pos, c)) = iter.next() {
-            if c == '\n' || iter.peek().is_none() {
-                if c != '\n' {
-                    buffer.set_char(x, y, c, &default_style);
-                    x += 1;
-                }
-                self.fill_line(buffer, x, y, &default_style);
-                x = self.vx;
-                y += 1;
-                if y > vheight {
+    /// The buffer position shown at screen cell `(x, y)`, as `(x, line)`.
+    /// Clicks past the end of a line or below the text land on the closest
+    /// position.
+    fn position_at(&self, x: u16, y: u16) -> Option<(usize, usize)> {
+        let y = (y as usize).checked_sub(self.vy)?;
+        if y >= self.vheight() {
+            return None;
+        }
+        let rows = self.viewport_rows();
+        let (line, range) = rows.get(y).or(rows.last())?;
+        let text = &self.buffer.lines[*line];
+        let rest = text.get(range.start.min(text.len())..).unwrap_or_default();
+        let mut col = (x as usize).saturating_sub(self.vx);
+        // clicks on an inlay hint land on the character after it
+        if self.show_hints() {
+            for hint in self.hints.in_range(*line, range.clone()) {
+                let at = text
+                    .get(range.start..hint.x)
+                    .map_or(0, |part| part.chars().count());
+                if col < at {
                     break;
                 }
-                continue;
-            }
-
-            if x < self.vwidth() {
-                if let Some(style) = determine_style_for_position(&style_info, pos) {
-                    buffer.set_char(x, y, c, &style);
-                } else {
-                    buffer.set_char(x, y, c, &default_style);
-                }
+                col = col.saturating_sub(hint.width()).max(at);
             }
-
-            x += 1;
-        }
-
-        while y < vheight {
-            self.fill_line(buffer, 0, y, &default_style);
-            y += 1;
         }
+        // clicks past the end of a wrapped row land on its last character
+        let x = match rest.char_indices().nth(col) {
+            Some((i, _)) if range.start + i < range.end => range.start + i,
+            _ if range.end > text.len() => text.len(),
+            _ => rest[..range.end - range.start]
+                .char_indices()
+                .last()
+                .map_or(range.start, |(i, _)| range.start + i),
+        };
+        Some((x, *line))
+    }
 
-        self.draw_gutter(buffer);
+    fn buffer_line(&self) -> usize {
+        self.folds.nth_visible(self.vtop, self.cy)
+    }
 
-        Ok(())
+    fn viewport_line(&self, n: usize) -> Option<String> {
+        let buffer_line = self.folds.nth_visible(self.vtop, n);
+        self.buffer.get(buffer_line)
     }
 
-    fn gutter_width(&self) -> usize {
-        let len = self.buffer.len().to_string().len();
-        len + 1
+    /// Buffer lines shown in the viewport, skipping folded lines. Wrapped
+    /// lines take as many rows as they need, and only the first line is
+    /// shown when it doesn't fit.
+    fn viewport_lines(&self) -> Vec<usize> {
+        let mut lines = vec![];
+        let mut rows = 0;
+        let mut line = self.vtop;
+        while line < self.buffer.len() && rows < self.vheight() {
+            rows += self.row_starts(line).len();
+            if rows > self.vheight() && !lines.is_empty() {
+                break;
+            }
+            lines.push(line);
+            line = self.folds.next_visible(line);
+        }
+        lines
     }
 
-    fn draw_gutter(&mut self, buffer: &mut RenderBuffer) {
-        let width = self.gutter_width();
-        let fg = self
-            .theme
-            .gutter_style
-            .fg
-            .unwrap_or(self.theme.style.fg.expect("fg is defined for theme"));
-        let bg = self
-            .theme
-            .gutter_style
-            .bg
-            .unwrap_or(self.theme.style.bg.expect("bg is defined for theme"));
+    /// The columns wrapped lines fit in: `textwidth`, or the text area when
+    /// that's narrower.
+    fn wrap_width(&self) -> usize {
+        let area = self.text_right().saturating_sub(self.vx).max(1);
+        match self.config.textwidth {
+            0 => area,
+            width => width.min(area),
+        }
+    }
 
-        for n in 0..self.vheight() as usize {
-            let line_number = n + 1 + self.vtop as usize;
+    /// Where the rows `line` is drawn on start, just the one at 0 without
+    /// `wrap`.
+    fn row_starts(&self, line: usize) -> Vec<usize> {
+        match (self.wrap, self.buffer.lines.get(line)) {
+            (true, Some(text)) => wrap::row_starts(text, self.wrap_width()),
+            _ => vec![0],
+        }
+    }
 
-            let text = if line_number <= self.buffer.len() {
-                line_number.to_string()
-            } else {
-                " ".repeat(width)
-            };
+    /// The viewport rows: the line each one shows and the bytes of it that
+    /// are drawn there, up to the end of the line on its last row.
+    fn viewport_rows(&self) -> Vec<(usize, Range<usize>)> {
+        let mut rows = vec![];
+        for line in self.viewport_lines() {
+            if !self.wrap {
+                rows.push((line, self.vleft..usize::MAX));
+                continue;
+            }
+            let starts = self.row_starts(line);
+            for (i, start) in starts.iter().enumerate() {
+                let end = starts.get(i + 1).copied().unwrap_or(usize::MAX);
+                rows.push((line, *start..end));
+            }
+        }
+        rows.truncate(self.vheight());
+        rows
+    }
 
-            buffer.set_text(
-                0,
-                n,
-                &format!("{text:>width$} ", width = width,),
-                &Style {
-                    fg: Some(fg),
-                    bg: Some(bg),
-                    ..Default::default()
-                },
-            );
+    /// The viewport column and row the cursor is drawn at, which differ from
+    /// `cx` and `cy` when long lines wrap.
+    fn cursor_cell(&self) -> (usize, usize) {
+        if !self.wrap {
+            let hints = self.hints_width(self.buffer_line(), self.vleft..self.cx + 1);
+            return (self.cx.saturating_sub(self.vleft) + hints, self.cy);
         }
+        let lines = self.viewport_lines();
+        let above: usize = lines
+            .iter()
+            .take(self.cy)
+            .map(|line| self.row_starts(*line).len())
+            .sum();
+        let line = self.buffer_line();
+        let starts = self.row_starts(line);
+        let row = wrap::row_of(&starts, self.cx);
+        let text = self.buffer.lines.get(line).map_or("", |l| l.as_str());
+        let col = text
+            .get(starts[row]..self.cx.min(text.len()))
+            .map_or(0, |part| part.chars().count());
+        let hints = self.hints_width(line, starts[row]..self.cx + 1);
+        (
+            col + hints + self.cx.saturating_sub(text.len()),
+            above + row,
+        )
     }
 
-    pub fn draw(&mut self) -> anyhow::Result<()> {
-        // self.stdout.queue(cursor::Hide)?;
-        // self.set_cursor_style()?;
-        // self.draw_gutter()?;
-        // self.draw_viewport()?;
-        // self.draw_statusline()?;
-        // self.stdout
-        //     .queue(cursor::MoveTo(self.vx + self.cx, self.cy))?;
-        // self.stdout.queue(cursor::Show)?;
-        // self.stdout.flush()?;
+    /// How many columns the inlay hints shown in `range` of `line` take.
+    fn hints_width(&self, line: usize, range: Range<usize>) -> usize {
+        match self.show_hints() {
+            true => self.hints.width(line, range),
+            false => 0,
+        }
+    }
 
-        todo!();
+    /// Scrolls down until the cursor's line fits in the viewport, after
+    /// moving past the wrapped lines at the bottom. Returns whether the
+    /// viewport moved.
+    fn scroll_to_wrapped_cursor(&mut self) -> bool {
+        if !self.wrap || self.buffer.lines.is_empty() {
+            return false;
+        }
+        let line = self.buffer_line().min(self.buffer.len() - 1);
+        let vtop = self.vtop;
+        if line < self.vtop {
+            self.vtop = line;
+        }
+        self.cursor_to_line(line);
+        self.vtop != vtop
+    }
 
-        // Ok(())
+    /// Moves the viewport down until `line` is on it, which long wrapped
+    /// lines above it can keep it from being.
+    fn scroll_down_to(&mut self, line: usize) {
+        while self.viewport_row(line).is_none() && self.vtop < line {
+            self.vtop = self.folds.next_visible(self.vtop);
+        }
     }
 
-    fn draw_cursor(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
-        self.set_cursor_style()?;
-        self.stdout
-            .queue(cursor::MoveTo((self.vx + self.cx) as u16, self.cy as u16))?;
-        self.draw_statusline(buffer);
-        Ok(())
+    /// Puts the cursor on `line`, scrolling down to it if wrapped lines
+    /// above push it off the viewport.
+    fn cursor_to_line(&mut self, line: usize) {
+        self.scroll_down_to(line);
+        self.cy = self.viewport_row(line).unwrap_or_default();
     }
 
-    pub fn draw_statusline(&mut self, buffer: &mut RenderBuffer) {
-        let mode = format!(" {:?} ", self.mode).to_uppercase();
-        let file = format!(" {}", self.buffer.file.as_deref().unwrap_or("No Name"));
-        let pos = format!(" {}:{} ", self.cx + 1, self.cy + self.vtop + 1);
+    fn viewport_row(&self, line: usize) -> Option<usize> {
+        self.viewport_lines().iter().position(|l| *l == line)
+    }
 
-        let file_width = self.size.0 - mode.len() as u16 - pos.len() as u16 - 2;
-        let y = self.size.1 as usize - 2;
+    fn has_grammar(&self) -> bool {
+        language::find(&self.buffer.filetype).is_some_and(|l| l.grammar.is_some())
+    }
 
-        let transition_style = Style {
-            fg: self.theme.statusline_style.outer_style.bg,
-            bg: self.theme.statusline_style.inner_style.bg,
-            ..Default::default()
-        };
+    /// Foldable line ranges: multi-line bracket pairs from the syntax tree when
+    /// the filetype has a grammar, indentation otherwise.
+    fn fold_ranges(&self) -> Vec<Range<usize>> {
+        if !self.has_grammar() {
+            return fold::indent_ranges(&self.buffer.lines);
+        }
 
-        buffer.set_text(0, y, &mode, &self.theme.statusline_style.outer_style);
+        let mut ranges: Vec<Range<usize>> = self
+            .brackets
+            .iter()
+            .map(|(open, close)| self.buffer.position(*open).1..self.buffer.position(*close).1 + 1)
+            .filter(|r| r.end - r.start > 1)
+            .collect();
+        ranges.sort_by_key(|r| (r.start, r.end));
+        ranges.dedup();
+        ranges
+    }
 
-        buffer.set_text(
-            mode.len(),
-            y,
-            &self.theme.statusline_style.outer_chars[1].to_string(),
-            &transition_style,
-        );
+    fn is_foldable(&self, line: usize, line_start: usize) -> bool {
+        if !self.has_grammar() {
+            return fold::indent_range_at(&self.buffer.lines, line).is_some();
+        }
 
-        buffer.set_text(
-            mode.len() + 1,
-            y,
-            &format!("{:<width$}", file, width = file_width as usize),
-            &self.theme.statusline_style.inner_style,
-        );
+        let line_end = line_start + self.buffer.lines[line].len();
+        let first = self
+            .brackets
+            .partition_point(|(open, _)| *open < line_start);
+        self.brackets[first..]
+            .iter()
+            .take_while(|(open, _)| *open < line_end)
+            .any(|(_, close)| *close > line_end)
+    }
 
-        buffer.set_text(
-            mode.len() + 1 + file_width as usize,
-            y,
-            &self.theme.statusline_style.outer_chars[2].to_string(),
-            &transition_style,
-        );
+    fn set_cursor_style(&mut self) -> anyhow::Result<()> {
+        let cursor = &self.config.cursor;
+        if !cursor.enabled {
+            return Ok(());
+        }
+        let waiting = self.waiting_key_action.is_some() || !self.pending_keys.is_empty();
+        let shape = match waiting {
+            true => cursor.pending,
+            false => match self.mode {
+                Mode::Normal => cursor.normal,
+                Mode::Insert => cursor.insert,
+                Mode::Command | Mode::Picker => cursor.command,
+                Mode::Visual | Mode::VisualBlock => cursor.visual,
+                Mode::Replace => cursor.replace,
+                Mode::Terminal => cursor.terminal,
+            },
+        };
+        self.stdout.queue(match shape {
+            CursorShape::Default => cursor::SetCursorStyle::DefaultUserShape,
+            CursorShape::Block => cursor::SetCursorStyle::SteadyBlock,
+            CursorShape::BlinkingBlock => cursor::SetCursorStyle::BlinkingBlock,
+            CursorShape::Bar => cursor::SetCursorStyle::SteadyBar,
+            CursorShape::BlinkingBar => cursor::SetCursorStyle::BlinkingBar,
+            CursorShape::Underline => cursor::SetCursorStyle::SteadyUnderScore,
+            CursorShape::BlinkingUnderline => cursor::SetCursorStyle::BlinkingUnderScore,
+        })?;
 
-        buffer.set_text(
-            mode.len() + 2 + file_width as usize,
-            y,
-            &pos,
-            &self.theme.statusline_style.outer_style,
-        );
+        Ok(())
     }
 
-    fn is_insert(&self) -> bool {
-        matches!(self.mode, Mode::Insert)
+    /// Forwards pending buffer edits to the highlight worker. Cached styles are
+    /// shifted right away so the text renders unstyled where it changed until
+    /// the worker's result arrives.
+    fn sync_highlights(&mut self) -> anyhow::Result<()> {
+        let edits = self.buffer.take_edits();
+        if edits.is_empty() {
+            return Ok(());
+        }
+        for edit in edits {
+            self.folds.apply_edit(&edit);
+            self.marks.apply_edit(self.buffer.file.as_ref(), &edit);
+            self.cursors.apply_edit(&edit);
+            self.diagnostics.apply_edit(&edit);
+            self.hints.apply_edit(&edit);
+            highlighter::apply_edit(&mut self.styles, &edit);
+            highlighter::apply_edit_to_brackets(&mut self.brackets, &edit);
+            self.highlighter.send(Request::Edit(edit))?;
+        }
+        if let Some(lsp) = &mut self.lsp {
+            let result = lsp.did_change(self.buffer.text());
+            self.check_lsp(result);
+            self.request_hints();
+        }
+        self.request_highlights()
     }
 
-    fn check_bounds(&mut self) {
-        let line_length = self.line_length();
+    fn show_hints(&self) -> bool {
+        self.buffer.inlay_hints.unwrap_or(self.config.inlay_hints)
+    }
 
-        if self.cx >= line_length && !self.is_insert() {
-            if line_length > 0 {
-                self.cx = self.line_length() - 1;
-            } else if !self.is_insert() {
-                self.cx = 0;
-            }
+    /// Asks the language server for the buffer's inlay hints, when they're
+    /// shown.
+    fn request_hints(&mut self) {
+        if !self.show_hints() {
+            return;
         }
-        if self.cx >= self.vwidth() {
-            self.cx = self.vwidth() - 1;
+        let lines = self.buffer.len();
+        if let Some(lsp) = &mut self.lsp {
+            let result = lsp.inlay_hints(lines);
+            self.check_lsp(result);
         }
+    }
 
-        let line_on_buffer = self.cy as usize + self.vtop;
-        if line_on_buffer > self.buffer.len().saturating_sub(1) {
-            self.cy = self.buffer.len() - self.vtop - 1;
+    fn request_highlights(&mut self) -> anyhow::Result<()> {
+        if self.buffer.large {
+            return Ok(());
         }
+        self.version += 1;
+        self.highlighter.send(Request::Update {
+            version: self.version,
+            code: self.buffer.text(),
+        })
     }
 
-    fn render_diff(&mut self, change_set: Vec<Change>) -> anyhow::Result<()> {
-        for change in change_set {
-            let x = change.x;
-            let y = change.y;
-            let cell = change.cell;
+    fn highlights_pending(&self) -> bool {
+        self.highlighted_version < self.version
+    }
 
-            self.stdout.queue(MoveTo(x as u16, y as u16))?;
-            if let Some(bg) = cell.style.bg {
-                self.stdout.queue(style::SetBackgroundColor(bg))?;
-            }
-            if let Some(fg) = cell.style.fg {
-                self.stdout.queue(style::SetForegroundColor(fg))?;
+    fn receive_highlights(&mut self) -> bool {
+        match self.highlighter.try_recv() {
+            Some(result) if result.version == self.version => {
+                self.styles = result.styles;
+                self.brackets = result.brackets;
+                self.highlighted_version = result.version;
+                if let Some(profile) = &mut self.profile {
+                    profile.highlight = Some(result.elapsed);
+                }
+                true
             }
-            self.stdout.queue(style::Print(cell.c))?;
+            _ => false,
         }
+    }
 
-        self.set_cursor_style()?;
-        self.stdout
-            .queue(cursor::MoveTo((self.vx + self.cx) as u16, self.cy as u16))?
-            .flush()?;
+    /// Starts the language server configured for the buffer's filetype. A
+    /// server that fails to start is reported and the editor carries on
+    /// without it.
+    fn start_lsp(&mut self) {
+        let Some(file) = self.buffer.file.clone() else {
+            return;
+        };
+        let Some(server) = self.config.lsp.get(&self.buffer.filetype) else {
+            return;
+        };
 
-        Ok(())
+        match LspClient::start(server, &file, &self.buffer.filetype, self.buffer.text()) {
+            Ok(lsp) => {
+                self.lsp = Some(lsp);
+                self.request_hints();
+            }
+            Err(err) => {
+                log!(Error, "failed to start {}: {err}", server.command);
+                self.notify(
+                    Level::Error,
+                    format!("failed to start {}: {err}", server.command),
+                );
+            }
+        }
     }
 
-    // Draw the current render buffer to the terminal
-    fn render(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
-        self.draw_viewport(buffer)?;
-        self.draw_gutter(buffer);
-        self.draw_statusline(buffer);
+    /// Runs the lint command configured for the buffer's filetype in the
+    /// background.
+    fn start_lint(&mut self) {
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        if let Some(config) = self.config.lint.get(&self.buffer.filetype) {
+            self.lint = Some((config.command.clone(), diagnostics::lint(config, file)));
+        }
+    }
 
-        self.stdout
-            .queue(Clear(ClearType::All))?
-            .queue(cursor::MoveTo(0, 0))?;
+    fn receive_lint(&mut self) -> bool {
+        let Some(result) = self.lint.as_ref().and_then(|(_, rx)| rx.try_recv().ok()) else {
+            return false;
+        };
+        let (source, _) = self.lint.take().expect("lint job is running");
+        match result {
+            Ok(items) => self.diagnostics.replace(&source, items),
+            Err(err) => self.notify(Level::Error, format!("lint failed: {err}")),
+        }
+        true
+    }
 
-        let mut current_style = &self.theme.style;
+    /// Diffs the buffer against its `HEAD` version again, shortly after it
+    /// changed or once in a while to catch commits made outside the editor.
+    fn start_git_diff(&mut self) {
+        if self.git_diff.is_some()
+            || !self.git_tracked
+            || self.buffer.large
+            || self.buffer.directory
+        {
+            return;
+        }
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        let due = self.git_diffed.is_none_or(|(at, version)| {
+            let elapsed = at.elapsed();
+            elapsed >= GIT_REFRESH_INTERVAL
+                || (version != self.version && elapsed >= GIT_EDIT_DELAY)
+        });
+        if due {
+            self.git_diff = Some(git::diff_against_head(file, self.buffer.lines.clone()));
+            self.git_diffed = Some((Instant::now(), self.version));
+        }
+    }
 
-        for cell in buffer.cells.iter() {
-            if cell.style != *current_style {
-                if let Some(bg) = cell.style.bg {
-                    self.stdout.queue(style::SetBackgroundColor(bg))?;
-                }
-                if let Some(fg) = cell.style.fg {
-                    self.stdout.queue(style::SetForegroundColor(fg))?;
-                }
-                current_style = &cell.style;
-            }
-            self.stdout.queue(style::Print(cell.c))?;
+    /// The file's version from `base`, the buffer's lines, and the change
+    /// between them under the cursor.
+    fn cursor_change<'a>(
+        &'a self,
+        base: &'a str,
+    ) -> anyhow::Result<(Vec<&'a str>, Vec<&'a str>, git::Change)> {
+        let old: Vec<&str> = base.lines().collect();
+        let new: Vec<&str> = self.buffer.lines.iter().map(String::as_str).collect();
+        match git::change_at(&old, &new, self.buffer_line()) {
+            Some(change) => Ok((old, new, change)),
+            None => anyhow::bail!("No change on this line"),
         }
+    }
 
-        self.draw_cursor(buffer)?;
-        self.stdout.flush()?;
+    fn preview_hunk(&mut self) -> anyhow::Result<()> {
+        let file = self
+            .buffer
+            .file
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No file name"))?;
+        let head = git::head_contents(file)?;
+        let (old, new, change) = self.cursor_change(&head)?;
+        let lines = git::hunk_lines(&old, &new, &change);
+        self.show_popup(lines);
+        Ok(())
+    }
 
+    /// Stages the change under the cursor as a patch against the index, so
+    /// changes staged before and the rest of the buffer stay as they are.
+    fn stage_hunk(&mut self) -> anyhow::Result<()> {
+        let file = self
+            .buffer
+            .file
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No file name"))?;
+        let index = git::index_contents(file)?;
+        let (old, new, change) = self.cursor_change(&index)?;
+        let name = Path::new(file)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        git::stage(file, &git::hunk_patch(&name, &old, &new, &change))?;
+        let lines = git::hunk_lines(&old, &new, &change);
+        self.show_popup(lines);
+        self.notify(Level::Info, "hunk staged");
         Ok(())
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        terminal::enable_raw_mode()?;
-        self.stdout
-            .execute(terminal::EnterAlternateScreen)?
-            .execute(terminal::Clear(terminal::ClearType::All))?;
+    fn revert_hunk(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let file = self
+            .buffer
+            .file
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No file name"))?;
+        let head = git::head_contents(file)?;
+        let (old, new, change) = self.cursor_change(&head)?;
+        let lines = git::hunk_lines(&old, &new, &change);
+        let edit = replace_lines_edit(change.new.clone(), &old[change.old], new.len());
+        let undo = apply_text_edits(&mut self.buffer, &[edit]);
+        self.undo_actions.push(undo);
+        // the signs are redrawn from a fresh diff right away
+        self.git_diffed = None;
+        self.move_to(0, change.new.start, buffer)?;
+        self.draw_viewport(buffer)?;
+        self.show_popup(lines);
+        Ok(())
+    }
 
-        let mut buffer = RenderBuffer::new(
-            self.size.0 as usize,
-            self.size.1 as usize,
-            self.theme.style.clone(),
-        );
+    /// The top directory of the repository the buffer's file is in, or the
+    /// working directory's.
+    fn git_root(&self) -> anyhow::Result<PathBuf> {
+        let dir = match self.buffer.file.as_deref().map(Path::new) {
+            Some(file) => file.parent().unwrap_or(Path::new("")),
+            None => Path::new(""),
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let root = git::run(dir, &["rev-parse", "--show-toplevel"])?;
+        Ok(PathBuf::from(root.trim()))
+    }
 
-        self.render(&mut buffer)?;
+    /// Writes the message file of a commit with the staged changes listed
+    /// below the line the message goes on, and opens it. Saving it commits.
+    fn start_commit(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let root = match self.status.take() {
+            Some(status) => status.root,
+            None => self.git_root()?,
+        };
+        let staged = git::run(&root, &["diff", "--cached", "--name-status"])?;
+        if staged.trim().is_empty() {
+            anyhow::bail!("Nothing staged to commit");
+        }
+        let dir = git::run(&root, &["rev-parse", "--absolute-git-dir"])?;
+        let path = Path::new(dir.trim()).join("COMMIT_EDITMSG");
+        let mut template = "\n\
+            # Write the message above and save to commit. Lines starting with '#'\n\
+            # are left out, and an empty message aborts the commit.\n\
+            #\n\
+            # Changes to be committed:\n"
+            .to_string();
+        for line in staged.lines() {
+            template.push_str(&format!("#   {}\n", line.replace('\t', "  ")));
+        }
+        fs::write(&path, template)?;
+        let file = path.to_string_lossy().to_string();
+        self.open_file(&file, buffer)?;
+        self.commit_message = Some((root, file));
+        self.draw_viewport(buffer)
+    }
 
-        loop {
-            let current_buffer = buffer.clone();
-            self.check_bounds();
+    fn is_commit_message(&self) -> bool {
+        matches!((&self.commit_message, &self.buffer.file), (Some((_, message)), Some(file)) if message == file)
+    }
 
-            let ev = read()?;
+    /// Commits the staged changes with the message just saved, closing its
+    /// buffer. It stays open when git refuses, like for an empty message.
+    fn commit(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some((root, file)) = self.commit_message.take() else {
+            return Ok(());
+        };
+        match git::run(&root, &["commit", "--cleanup=strip", "-F", &file]) {
+            Ok(output) => {
+                let summary = output.lines().next().unwrap_or("committed").to_string();
+                self.close_buffer(None, false, buffer)?;
+                self.notify(Level::Info, summary);
+                Ok(())
+            }
+            Err(err) => {
+                self.commit_message = Some((root, file));
+                Err(err)
+            }
+        }
+    }
 
-            if let event::Event::Resize(width, height) = ev {
-                self.size = (width, height);
-                buffer = RenderBuffer::new(
-                    self.size.0 as usize,
-                    self.size.1 as usize,
-                    self.theme.style.clone(),
-                );
-                self.render(&mut buffer)?;
-                continue;
+    fn receive_git_diff(&mut self) -> bool {
+        let Some(result) = self.git_diff.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return false;
+        };
+        self.git_diff = None;
+        match result {
+            Ok(hunks) => self.git_signs = GitSigns::new(hunks),
+            Err(err) => {
+                // not worth retrying until another file is opened
+                log!(Debug, "git diff: {err}");
+                self.git_tracked = false;
+                self.git_signs = GitSigns::default();
             }
+        }
+        true
+    }
 
-            if let Some(action) = self.handle_event(ev) {
-                let quit = match action {
-                    KeyAction::Single(action) => self.execute(&action, &mut buffer)?,
-                    KeyAction::Multiple(actions) => {
-                        let mut quit = false;
-                        for action in actions {
-                            if self.execute(&action, &mut buffer)? {
-                                quit = true;
-                                break;
-                            }
-                        }
-                        quit
-                    }
-                    KeyAction::Nested(actions) => {
-                        self.waiting_key_action = Some(KeyAction::Nested(actions));
-                        false
-                    }
-                };
+    /// Blames the buffer again when it changed since it was last blamed.
+    fn start_blame(&mut self) {
+        if !self.show_blame
+            || self.blame_job.is_some()
+            || self.blamed_version == self.version
+            || self.buffer.large
+        {
+            return;
+        }
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        self.blame_job = Some(git::blame(file, self.buffer.lines.clone()));
+        self.blamed_version = self.version;
+    }
 
-                if quit {
-                    break;
-                }
+    fn receive_blame(&mut self) -> bool {
+        let Some(result) = self.blame_job.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return false;
+        };
+        self.blame_job = None;
+        match result {
+            Ok(blame) => self.blame = blame,
+            Err(err) => {
+                self.notify(Level::Error, format!("blame failed: {err}"));
+                self.show_blame = false;
             }
-
-            self.stdout.execute(Hide)?;
-            self.draw_statusline(&mut buffer);
-            self.render_diff(buffer.diff(&current_buffer))?;
-            self.draw_cursor(&mut buffer)?;
-            self.stdout.execute(Show)?;
         }
+        true
+    }
 
+    /// Starts the build command configured for the filetype, `make` without
+    /// one, showing its output as it runs.
+    fn start_make(&mut self, args: &str) -> anyhow::Result<()> {
+        if self.make.as_ref().is_some_and(Make::is_running) {
+            self.notify(Level::Warning, "a build is already running");
+            return Ok(());
+        }
+        let program = self.config.make.get(&self.buffer.filetype);
+        let command = match (program.map_or("make", String::as_str), args) {
+            (program, "") => program.to_string(),
+            (program, args) => format!("{program} {args}"),
+        };
+        self.make = Some(Make::start(&command)?);
+        self.show_make = true;
         Ok(())
     }
 
-    fn handle_event(&mut self, ev: event::Event) -> Option<KeyAction> {
-        if let event::Event::Resize(width, height) = ev {
-            self.size = (width, height);
-            return None;
+    /// Takes the output of a running build. Once it finishes, the locations
+    /// in it become the quickfix list and a successful build's panel closes.
+    fn receive_make(&mut self) -> bool {
+        let Some(make) = self.make.as_mut() else {
+            return false;
+        };
+        let running = make.is_running();
+        if !make.receive() {
+            return false;
+        }
+        if running && !make.is_running() {
+            let entries = quickfix::parse(&make.lines.join("\n"));
+            let message = match (make.succeeded(), entries.len()) {
+                (true, 0) => "build finished".to_string(),
+                (true, n) => format!("build finished with {n} locations, ]q to jump"),
+                (false, n) => format!("build failed with {n} locations, ]q to jump"),
+            };
+            let level = match make.succeeded() {
+                true => Level::Info,
+                false => Level::Error,
+            };
+            if make.succeeded() && entries.is_empty() {
+                self.show_make = false;
+            }
+            self.quickfix.set(entries);
+            self.notify(level, message);
         }
+        true
+    }
 
-        if let Some(ka) = self.waiting_key_action.take() {
-            return self.handle_waiting_command(ka, ev);
+    /// Shows `text` on the message line until it times out, keeping it in
+    /// the `:messages` history.
+    pub fn notify(&mut self, level: Level, text: impl Into<String>) {
+        let text = text.into();
+        match level {
+            Level::Info => log!(Info, "{text}"),
+            Level::Warning => log!(Warn, "{text}"),
+            Level::Error => log!(Error, "{text}"),
         }
+        self.messages.push(level, text);
+    }
 
-        match self.mode {
-            Mode::Normal => self.handle_normal_event(ev),
-            Mode::Insert => self.handle_insert_event(ev),
+    /// Shuts the language server down when writing to it failed, which
+    /// usually means it exited.
+    fn check_lsp(&mut self, result: anyhow::Result<()>) {
+        if let Err(err) = result {
+            self.notify(Level::Error, format!("language server stopped: {err}"));
+            self.lsp = None;
         }
     }
 
-    fn handle_insert_event(&mut self, ev: event::Event) -> Option<KeyAction> {
-        if let Some(ka) = event_to_key_action(&self.config.keys.insert, &ev) {
-            return Some(ka);
+    fn poll_interval(&self) -> Option<Duration> {
+        let interval = if self.highlights_pending() {
+            Some(HIGHLIGHT_POLL_INTERVAL)
+        } else if self.lsp.is_some()
+            || self.lint.is_some()
+            || self.terminal.is_some()
+            || self.git_diff.is_some()
+            || self.blame_job.is_some()
+            || self.make.as_ref().is_some_and(Make::is_running)
+            || self.picker.as_ref().is_some_and(|p| p.is_loading())
+            || self.buffer.is_loading()
+            || !self.plugins.is_empty()
+        {
+            Some(BACKGROUND_POLL_INTERVAL)
+        } else if self.git_tracked && self.buffer.file.is_some() {
+            Some(GIT_EDIT_DELAY)
+        } else {
+            self.messages.remaining()
+        };
+
+        let config = (self.config_path.is_some() || self.project_config.is_some())
+            .then_some(CONFIG_POLL_INTERVAL);
+        let signals = self.signals.as_ref().map(|_| SIGNAL_POLL_INTERVAL);
+        let file = self.buffer.file.as_ref().map(|_| FILE_POLL_INTERVAL);
+        [
+            interval,
+            self.pending_keys_remaining(),
+            self.flash_remaining(),
+            config,
+            file,
+            self.autosave_remaining(),
+            signals,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Starts the plugins in `dir`.
+    pub fn load_plugins(&mut self, dir: &Path) {
+        for err in self.plugins.load(dir) {
+            self.notify(Level::Error, err);
         }
+    }
 
-        match ev {
-            Event::Key(event) => match event.code {
-                KeyCode::Char(c) => KeyAction::Single(Action::InsertCharAtCursorPos(c)).into(),
-                _ => None,
-            },
-            _ => None,
+    /// Carries out what the plugins asked for since the last call, returning
+    /// whether they asked for anything.
+    fn receive_plugins(&mut self, buffer: &mut RenderBuffer) -> bool {
+        let requests = self.plugins.receive();
+        for request in &requests {
+            let result = match request {
+                Ok(plugin::Request::Command { .. } | plugin::Request::Status { .. }) => Ok(false),
+                Ok(plugin::Request::Map { key, command }) => {
+                    let action = Action::PluginCommand(command.clone(), String::new());
+                    let leader = self.keymaps.leader.clone();
+                    self.keymaps
+                        .normal
+                        .map(key, &KeyAction::Single(action), &leader);
+                    Ok(false)
+                }
+                Ok(plugin::Request::SetLines { start, end, lines }) => {
+                    let action = Action::SetLines(*start, *end, lines.clone());
+                    self.execute_key_action(KeyAction::Single(action), buffer)
+                }
+                Ok(plugin::Request::Actions { actions }) => {
+                    self.execute_key_action(KeyAction::Multiple(actions.clone()), buffer)
+                }
+                Ok(plugin::Request::Notify { message }) => {
+                    self.notify(Level::Info, message.clone());
+                    Ok(false)
+                }
+                Err(err) => Err(anyhow::anyhow!("{err}")),
+            };
+            if let Err(err) = result {
+                self.notify(Level::Error, err.to_string());
+            }
         }
+        !requests.is_empty()
     }
 
-    fn handle_normal_event(&mut self, ev: event::Event) -> Option<KeyAction> {
-        event_to_key_action(&self.config.keys.normal, &ev)
+    /// Runs the actions of the command `name` from `[commands]`, which can
+    /// run other commands in turn. Returns whether one of them quit.
+    fn run_command(&mut self, name: &str, buffer: &mut RenderBuffer) -> anyhow::Result<bool> {
+        let Some(action) = self.config.commands.get(name).cloned() else {
+            anyhow::bail!("Unknown command: {name}");
+        };
+        if let KeyAction::Nested(_) = action {
+            anyhow::bail!("Command {name} maps keys rather than running actions");
+        }
+        if self.running_commands >= MAX_COMMAND_DEPTH {
+            anyhow::bail!("Command {name} runs itself");
+        }
+        self.running_commands += 1;
+        let quit = self.execute_key_action(action, buffer);
+        self.running_commands -= 1;
+        quit
     }
 
-    fn handle_waiting_command(&mut self, ka: KeyAction, ev: event::Event) -> Option<KeyAction> {
-        let KeyAction::Nested(nested_mappings) = ka else {
-            panic!("Expected nested key action");
+    /// Sends the plugin that added `name` the buffer and cursor to run the
+    /// command on.
+    fn run_plugin_command(&mut self, name: &str, args: &str) -> anyhow::Result<()> {
+        let event = plugin::Event::Command {
+            name,
+            args,
+            file: self.buffer.file.as_deref(),
+            filetype: &self.buffer.filetype,
+            line: self.buffer_line(),
+            column: self.cx,
+            lines: &self.buffer.lines,
         };
+        self.plugins.send(name, &event)
+    }
 
-        event_to_key_action(&nested_mappings, &ev)
+    /// Reloads the config from `path` whenever the file changes.
+    pub fn watch_config(&mut self, path: Option<PathBuf>, project: Option<PathBuf>) {
+        self.config_modified = path.as_deref().and_then(buffer::modified_time);
+        self.config_path = path;
+        self.project_modified = project.as_deref().and_then(buffer::modified_time);
+        self.project_config = project;
     }
 
-    fn current_line_contents(&self) -> Option<String> {
-        self.buffer.get(self.buffer_line())
+    /// Checks whether the config file or the project's changed, at most once
+    /// per `CONFIG_POLL_INTERVAL`.
+    fn config_changed(&mut self) -> bool {
+        if self.config_path.is_none() && self.project_config.is_none() {
+            return false;
+        }
+        if self.config_checked.elapsed() < CONFIG_POLL_INTERVAL {
+            return false;
+        }
+        self.config_checked = Instant::now();
+
+        let mut changed = false;
+        for (path, last) in [
+            (&self.config_path, &mut self.config_modified),
+            (&self.project_config, &mut self.project_modified),
+        ] {
+            let modified = path.as_deref().and_then(buffer::modified_time);
+            if modified.is_some() && modified != *last {
+                *last = modified;
+                changed = true;
+            }
+        }
+        changed
     }
 
-    pub fn cleanup(&mut self) -> anyhow::Result<()> {
-        self.stdout.execute(terminal::LeaveAlternateScreen)?;
-        self.stdout.execute(cursor::Show)?;
-        self.stdout.flush()?;
+    /// Checks whether the file changed on disk, at most once per
+    /// `FILE_POLL_INTERVAL`.
+    fn file_changed(&mut self) -> bool {
+        if self.file_checked.elapsed() < FILE_POLL_INTERVAL {
+            return false;
+        }
+        self.file_checked = Instant::now();
+
+        let Some(modified) = self.buffer.changed_on_disk() else {
+            return false;
+        };
+        self.buffer.disk_modified = Some(modified);
+        true
+    }
+
+    /// Reloads a file that changed on disk right away, unless that would
+    /// lose unsaved edits, in which case the user is asked what to do.
+    fn handle_file_change(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        if !self.buffer.modified {
+            return self.reload_file(buffer);
+        }
+        let file = self.buffer.file.clone().unwrap_or_default();
+        self.file_conflict = true;
+        self.notify(
+            Level::Warning,
+            format!("{file} changed on disk: (r)eload, (k)eep, (d)iff"),
+        );
         Ok(())
     }
 
-    fn draw_line(&mut self, buffer: &mut RenderBuffer) {
-        let line = self.viewport_line(self.cy).unwrap_or_default();
-        let style_info = self.highlight(&line).unwrap_or_default();
-        let default_style = self.theme.style.clone();
+    fn resolve_file_conflict(&mut self, event: KeyEvent) -> Option<KeyAction> {
+        let action = match event.code {
+            KeyCode::Char('r') => Action::ReloadFile,
+            KeyCode::Char('d') => Action::DiffFile,
+            _ => {
+                self.file_conflict = false;
+                self.notify(Level::Info, "Kept the buffer");
+                return None;
+            }
+        };
+        Some(KeyAction::Single(action))
+    }
 
-        let mut x = self.vx;
-        let mut iter = line.chars().enumerate().peekable();
+    /// Loads the file again, keeping the cursor on the same line.
+    fn reload_file(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(file) = self.buffer.file.clone() else {
+            return Ok(());
+        };
+        let (x, line) = (self.cx, self.buffer_line());
+        self.file_conflict = false;
+        self.load_file(&file, buffer)?;
+        let line = line.min(self.buffer.len().saturating_sub(1));
+        let x = x.min(self.buffer.get(line).map_or(0, |l| l.len()));
+        self.move_to(x, line, buffer)?;
+        self.notify(Level::Info, format!("{file} reloaded"));
+        Ok(())
+    }
 
-        while let Some((pos, c)) = iter.next() {
-            if c == '\n' || iter.peek().is_none() {
-                if c != '\n' {
-                    buffer.set_char(x, self.cy, c, &default_style);
-                    x += 1;
-                }
-                self.fill_line(buffer, x, self.cy, &default_style);
-                break;
-            }
+    /// Shows how the file on disk differs from the buffer in a popup.
+    fn diff_file(&mut self) -> anyhow::Result<()> {
+        let Some(file) = &self.buffer.file else {
+            return Ok(());
+        };
+        let disk = fs::read_to_string(file)?;
+        let disk: Vec<&str> = disk.lines().collect();
+        let lines: Vec<&str> = self.buffer.lines.iter().map(String::as_str).collect();
 
-            if x < self.vwidth() {
-                if let Some(style) = determine_style_for_position(&style_info, pos) {
-                    buffer.set_char(x, self.cy, c, &style);
-                } else {
-                    buffer.set_char(x, self.cy, c, &default_style);
-                }
-            }
-            x += 1;
+        let mut popup = vec![];
+        for hunk in git::diff(&lines, &disk) {
+            let kind = format!("{:?}", hunk.kind).to_lowercase();
+            popup.push(format!("@@ line {}: {kind} on disk", hunk.start + 1));
+            popup.extend(disk[hunk.start..hunk.end].iter().map(|l| format!("+ {l}")));
         }
+        if popup.is_empty() {
+            popup.push("No differences".to_string());
+        }
+        popup.truncate(self.vheight().saturating_sub(1));
+        self.show_popup(popup);
+        Ok(())
     }
 
-    fn execute(&mut self, action: &Action, buffer: &mut RenderBuffer) -> anyhow::Result<bool> {
-        match action {
-            Action::Quit => return Ok(true),
-            Action::MoveUp => {
-                if self.cy == 0 {
-                    if self.vtop > 0 {
-                        self.vtop -= 1;
-                        self.draw_viewport(buffer)?;
-                    }
-                } else {
-                    self.cy = self.cy.saturating_sub(1);
-                }
-            }
-            Action::MoveDown => {
-                self.cy += 1;
-                if self.cy >= self.vheight() {
-                    self.vtop += 1;
-                    self.cy -= 1;
-                    self.draw_viewport(buffer)?;
-                }
-            }
-            Action::MoveLeft => {
-                self.cx = self.cx.saturating_sub(1);
-                if self.cx < self.vleft {
-                    self.cx = self.vleft;
-                }
-            }
-            Action::MoveRight => {
-                self.cx += 1;
+    /// How long yanked text keeps flashing, while it does.
+    fn flash_remaining(&self) -> Option<Duration> {
+        let (_, until) = self.flash?;
+        Some(until.saturating_duration_since(Instant::now()))
+    }
+
+    /// Stops flashing yanked text once it's been long enough, returning
+    /// whether it did.
+    fn expire_flash(&mut self) -> bool {
+        if self.flash_remaining() != Some(Duration::ZERO) {
+            return false;
+        }
+        self.flash = None;
+        true
+    }
+
+    /// How long until the buffer is autosaved, when it has unsaved edits.
+    fn autosave_remaining(&self) -> Option<Duration> {
+        let delay = Duration::from_secs(self.config.autosave.delay?);
+        (self.buffer.modified && self.buffer.file.is_some())
+            .then(|| delay.saturating_sub(self.last_input.elapsed()))
+    }
+
+    /// The current state of the editor, to restore with `restore_session`:
+    /// the buffers with a file and the tab pages showing them.
+    pub fn session(&self) -> anyhow::Result<Session> {
+        let mut open: Vec<(usize, BufferState)> = self
+            .buffers
+            .hidden()
+            .filter_map(|(id, hidden)| {
+                let state = BufferState {
+                    file: hidden.buffer.file.clone()?,
+                    x: hidden.x,
+                    line: hidden.line,
+                    vtop: hidden.vtop,
+                };
+                Some((id, state))
+            })
+            .collect();
+        if let Some(file) = &self.buffer.file {
+            let state = BufferState {
+                file: file.clone(),
+                x: self.cx,
+                line: self.buffer_line(),
+                vtop: self.vtop,
+            };
+            open.push((self.buffers.current(), state));
+        }
+        open.sort_by_key(|(id, _)| *id);
+        let current = open
+            .iter()
+            .position(|(id, _)| *id == self.buffers.current())
+            .unwrap_or(open.len());
+
+        let mut tabs = vec![];
+        let mut current_tab = 0;
+        for (i, tab) in self.tabs.iter().enumerate() {
+            // the current tab's entry isn't kept up to date
+            let tab = match i == self.tabs.current() {
+                true => self.current_tab(),
+                false => *tab,
+            };
+            let Some((_, state)) = open.iter().find(|(id, _)| *id == tab.buffer) else {
+                continue;
+            };
+            if i == self.tabs.current() {
+                current_tab = tabs.len();
             }
-            Action::MoveToLineStart => {
-                self.cx = 0;
+            tabs.push(BufferState {
+                file: state.file.clone(),
+                x: tab.x,
+                line: tab.line,
+                vtop: tab.vtop,
+            });
+        }
+
+        Ok(Session {
+            cwd: std::env::current_dir()?,
+            buffers: open.into_iter().map(|(_, state)| state).collect(),
+            current,
+            tabs,
+            current_tab,
+        })
+    }
+
+    /// Opens the other buffers and the tab pages of `session` around the
+    /// buffer being edited, and puts its cursor back, before the editor runs.
+    pub fn restore_session(&mut self, session: &Session) {
+        for state in &session.buffers {
+            if self.buffer_for(&state.file).is_some() {
+                continue;
             }
-            Action::MoveToLineEnd => {
-                self.cx = self.line_length().saturating_sub(1);
+            if let Some(mut hidden) = self.load_hidden(&state.file, false, false) {
+                (hidden.line, hidden.x, hidden.vtop) = (state.line, state.x, state.vtop);
+                self.buffers.add(hidden);
             }
-            Action::PageUp => {
-                if self.vtop > 0 {
-                    self.vtop = self.vtop.saturating_sub(self.vheight() as usize);
-                    self.draw_viewport(buffer)?;
-                }
+        }
+
+        let tabs: Vec<Tab> = session
+            .tabs
+            .iter()
+            .filter_map(|state| {
+                Some(Tab {
+                    buffer: self.buffer_for(&state.file)?,
+                    x: state.x,
+                    line: state.line,
+                    vtop: state.vtop,
+                })
+            })
+            .collect();
+        self.tabs = Tabs::restore(tabs, session.current_tab);
+        self.vy = usize::from(self.tabs.len() > 1);
+
+        let current = session.current().filter(|state| {
+            self.buffer
+                .file
+                .as_deref()
+                .is_some_and(|file| buffers::same_file(file, &state.file))
+        });
+        if let Some(state) = current {
+            self.restore_position(state);
+        }
+    }
+
+    /// The number of the open buffer editing `file`, the current one or a
+    /// hidden one.
+    fn buffer_for(&self, file: &str) -> Option<usize> {
+        let current = self
+            .buffer
+            .file
+            .as_deref()
+            .is_some_and(|current| buffers::same_file(current, file));
+        match current {
+            true => Some(self.buffers.current()),
+            false => self.buffers.find(file),
+        }
+    }
+
+    /// Moves the cursor and viewport to where they were left in the buffer
+    /// being edited, before the editor runs.
+    fn restore_position(&mut self, state: &BufferState) {
+        let line = state.line.min(self.buffer.len().saturating_sub(1));
+        let vtop = state.vtop.min(line);
+        self.vtop = if line - vtop < self.vheight() {
+            vtop
+        } else {
+            line
+        };
+        self.cy = line - self.vtop;
+        self.cx = state.x.min(self.buffer.get(line).map_or(0, |l| l.len()));
+    }
+
+    /// Loads the recent files saved at `path`, putting the cursor back where
+    /// it was left in the file being edited.
+    pub fn load_recent(&mut self, path: &Path) {
+        self.recent = Recent::load(path);
+        let state = self.buffer.file.as_deref().and_then(|f| self.recent.get(f));
+        if let Some(state) = state.cloned() {
+            self.restore_position(&state);
+        }
+    }
+
+    /// Remembers where the cursor is in the file being edited, for opening
+    /// it there again.
+    fn remember_position(&mut self) {
+        if let Some(file) = &self.buffer.file {
+            self.recent.add(BufferState {
+                file: file.clone(),
+                x: self.cx,
+                line: self.buffer_line(),
+                vtop: self.vtop,
+            });
+        }
+    }
+
+    /// Opens the files after the first one given on the command line in
+    /// buffers of their own, keeping them all for `:next` and `:prev`. Files
+    /// that don't exist yet get empty buffers, and all of them are read-only
+    /// with `readonly` and hex dumps with `hex`.
+    pub fn set_arglist(&mut self, files: Vec<String>, readonly: bool, hex: bool) {
+        for file in files.iter().skip(1) {
+            if self.buffers.find(file).is_some() {
+                continue;
             }
-            Action::PageDown => {
-                if self.buffer.len() > self.vtop + self.vheight() as usize {
-                    self.vtop += self.vheight() as usize;
-                }
+            if let Some(hidden) = self.load_hidden(file, readonly, hex) {
+                self.buffers.add(hidden);
             }
-            Action::EnterMode(new_mode) => {
-                if !self.is_insert() && matches!(new_mode, Mode::Insert) {
-                    self.insert_undo_actions = Vec::new();
-                }
-                if self.is_insert() && matches!(new_mode, Mode::Normal) {
-                    if !self.insert_undo_actions.is_empty() {
-                        let actions = mem::take(&mut self.insert_undo_actions);
-                        self.undo_actions.push(Action::UndoMultiple(actions));
-                    }
+        }
+        self.arglist = files;
+        self.arg_index = 0;
+    }
+
+    /// Loads `file` in a buffer to keep hidden, at the position it was left
+    /// in last time. A file that doesn't exist yet gets an empty buffer, and
+    /// one that can't be read is reported and left out.
+    fn load_hidden(&mut self, file: &str, readonly: bool, hex: bool) -> Option<Hidden> {
+        let mut opened = if Path::new(file).exists() {
+            let opened = match hex {
+                true => Buffer::from_hex_file(file),
+                false => Buffer::from_file(Some(file.to_string()), self.config.large_file_size()),
+            };
+            match opened {
+                Ok(opened) => opened,
+                Err(err) => {
+                    self.notify(Level::Error, format!("{file}: {err}"));
+                    return None;
                 }
-                self.mode = *new_mode;
-                self.draw_statusline(buffer);
-            }
-            Action::InsertCharAtCursorPos(c) => {
-                self.insert_undo_actions
-                    .push(Action::RemoveCharAt(self.cx, self.buffer_line()));
-                self.buffer.insert(self.cx, self.buffer_line(), *c);
-                self.cx += 1;
-                self.draw_line(buffer);
-            }
-            Action::RemoveCharAt(cx, line) => {
-                self.buffer.remove(*cx, *line);
-                self.draw_line(buffer);
-            }
-            Action::DeleteCharAtCursorPos => {
-                self.buffer.remove(self.cx, self.buffer_line());
-                self.draw_line(buffer);
-            }
-            Action::NewLine => {
-                self.cx = 0;
-                self.cy += 1;
-                self.buffer.insert_line(self.buffer_line(), String::new());
-                self.draw_viewport(buffer)?;
             }
-            Action::SetWaitingKeyAction(key_action) => {
-                self.waiting_key_action = Some(*(key_action.clone()));
+        } else {
+            Buffer::new(Some(file.to_string()), String::new())
+        };
+        opened.readonly |= readonly;
+        let mut hidden = Hidden::new(opened);
+        if let Some(state) = self.recent.get(file) {
+            (hidden.line, hidden.x, hidden.vtop) = (state.line, state.x, state.vtop);
+        }
+        Some(hidden)
+    }
+
+    /// Puts the cursor on `line` and byte column `x`, both counted from 1,
+    /// centering the line in the viewport as far as the end of the buffer
+    /// allows, before the editor runs.
+    pub fn start_at(&mut self, line: usize, x: usize) {
+        let line = line
+            .saturating_sub(1)
+            .min(self.buffer.len().saturating_sub(1));
+        self.vtop = line
+            .saturating_sub(self.vheight() / 2)
+            .min(self.buffer.len().saturating_sub(self.vheight()));
+        self.cy = line - self.vtop;
+        let len = self.buffer.get(line).map_or(0, |l| l.len());
+        self.cx = x.saturating_sub(1).min(len.saturating_sub(1));
+    }
+
+    /// Keeps the undo history of the file as it was just written.
+    fn save_undo(&self) {
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        if !self.config.undo.persist || self.buffer.large {
+            return;
+        }
+        let Some(path) = undofile::path(file) else {
+            return;
+        };
+        let max_entries = self.config.undo.max_entries;
+        if let Err(err) =
+            undofile::save(&path, &self.buffer.text(), &self.undo_actions, max_entries)
+        {
+            log!(Warn, "undo history of {file}: {err}");
+        }
+    }
+
+    /// Picks up the undo history kept when the file was last written.
+    fn load_undo(&mut self) {
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        if !self.config.undo.persist || self.buffer.large {
+            return;
+        }
+        if let Some(actions) =
+            undofile::path(file).and_then(|p| undofile::load(&p, &self.buffer.text()))
+        {
+            self.undo_actions = actions;
+        }
+    }
+
+    /// Writes unsaved edits to the swap file when they changed since it was
+    /// last written, and removes it once there are none.
+    fn update_swap(&mut self) {
+        let Some(file) = &self.buffer.file else {
+            return;
+        };
+        if !self.buffer.modified {
+            self.remove_swap();
+            return;
+        }
+        if !self.config.swap.enabled || self.buffer.large {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.swap.interval);
+        let due = self
+            .swapped
+            .is_none_or(|(at, version)| version != self.version && at.elapsed() >= interval);
+        if !due {
+            return;
+        }
+        match swap::write(file, &self.buffer.text()) {
+            Ok(()) => self.swapped = Some((Instant::now(), self.version)),
+            Err(err) => {
+                log!(Warn, "swap file for {file}: {err}");
+                // retried after another interval
+                self.swapped = Some((Instant::now(), self.version.wrapping_sub(1)));
             }
-            Action::DeleteCurrentLine => {
-                let line = self.buffer_line();
-                let contents = self.current_line_contents();
+        }
+    }
 
-                self.buffer.remove_line(self.buffer_line());
-                self.undo_actions.push(Action::InsertLineAt(line, contents));
+    fn remove_swap(&mut self) {
+        if let (Some(file), Some(_)) = (&self.buffer.file, self.swapped.take()) {
+            swap::remove(file);
+        }
+    }
+
+    /// Warns about a swap file left behind by a session that didn't exit
+    /// cleanly. One that matches the file has nothing to recover and is
+    /// removed. Large files have no swap file, which is pointed out instead.
+    fn check_swap(&mut self) {
+        let Some(file) = self.buffer.file.clone() else {
+            return;
+        };
+        if self.buffer.large {
+            self.notify(
+                Level::Info,
+                "Large file: highlighting, git signs, swap and undo files are off",
+            );
+            return;
+        }
+        let Some(text) = swap::read(&file) else {
+            return;
+        };
+        if text == self.buffer.text() {
+            swap::remove(&file);
+            return;
+        }
+        self.notify(
+            Level::Warning,
+            format!("Found a swap file for {file}: :recover to restore it, :delswap to delete it"),
+        );
+    }
+
+    /// Replaces the buffer's text with the swap file's, undoable in one
+    /// step.
+    fn recover_swap(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(file) = self.buffer.file.clone() else {
+            anyhow::bail!("No file name");
+        };
+        let Some(text) = swap::read(&file) else {
+            anyhow::bail!("No swap file for {file}");
+        };
+        let last = self.buffer.len().saturating_sub(1);
+        let end = (self.buffer.get(last).map_or(0, |l| l.len()), last);
+        let old = self.buffer.remove_range((0, 0), end);
+        let end = self.buffer.insert_text(0, 0, &text);
+        self.undo_actions.push(Action::UndoMultiple(vec![
+            Action::InsertTextAt(0, 0, old),
+            Action::RemoveRange((0, 0), end),
+        ]));
+        self.move_to(0, 0, buffer)?;
+        self.draw_viewport(buffer)?;
+        self.notify(Level::Info, format!("Recovered {file}"));
+        Ok(())
+    }
+
+    /// Loads the config again and applies its keymaps, theme and options. A
+    /// config that fails to load leaves the current one in place.
+    fn reload_config(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let config = Config::load(self.config_path.as_deref(), self.project_config.as_deref())?;
+        let theme = theme::load(&config.theme, config.color_depth())?;
+
+        if config.mouse != self.config.mouse {
+            match config.mouse {
+                true => self.stdout.execute(EnableMouseCapture)?,
+                false => self.stdout.execute(DisableMouseCapture)?,
+            };
+        }
+        self.keymaps = Keymaps::new(&config.keys);
+        for (key, command) in &self.plugins.mappings {
+            let action = Action::PluginCommand(command.clone(), String::new());
+            self.keymaps
+                .normal
+                .map(key, &KeyAction::Single(action), &self.keymaps.leader);
+        }
+        self.pending_keys.clear();
+        self.wrap = config.for_filetype(&self.buffer.filetype).wrap;
+        self.config = config;
+        self.set_theme(theme, buffer)?;
+        self.notify(Level::Info, "Config reloaded");
+        Ok(())
+    }
+
+    /// Where `:theme` looks for themes: the directory of the configured
+    /// theme, then `themes` next to the config file.
+    fn theme_dirs(&self) -> Vec<PathBuf> {
+        let configured = Path::new(&self.config.theme)
+            .parent()
+            .map_or(PathBuf::from("."), Path::to_path_buf);
+        let mut dirs = vec![configured];
+        if let Some(dir) = self.config_path.as_deref().and_then(Path::parent) {
+            dirs.push(dir.join("themes"));
+        }
+        dirs
+    }
+
+    /// Switches to the theme called `name` in the theme directories.
+    fn switch_theme(&mut self, name: &str, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let themes = theme::discover(&self.theme_dirs());
+        let Some((_, path)) = themes.into_iter().find(|(n, _)| n == name) else {
+            anyhow::bail!("Theme not found: {name}");
+        };
+        let path = path.to_string_lossy().to_string();
+        let theme = theme::load(&path, self.config.color_depth())?;
+        self.config.theme = path;
+        self.set_theme(theme, buffer)
+    }
+
+    /// Switches to `theme`, highlighting the buffer again with its colors and
+    /// redrawing the whole screen.
+    fn set_theme(&mut self, theme: Theme, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let language = language::find(&self.buffer.filetype).unwrap_or_else(language::plain_text);
+        self.highlighter = HighlightWorker::spawn(Highlighter::new(&theme, language)?);
+        self.theme = theme;
+        self.styles.clear();
+        self.brackets.clear();
+        self.request_highlights()?;
+
+        *buffer = RenderBuffer::new(
+            self.size.0 as usize,
+            self.size.1 as usize,
+            self.theme.style.clone(),
+        );
+        self.render(buffer)
+    }
+
+    /// How long until the pending keys time out.
+    fn pending_keys_remaining(&self) -> Option<Duration> {
+        if self.pending_keys.is_empty() {
+            return None;
+        }
+        let timeout = self.keymap().timeout()?;
+        Some(timeout.saturating_sub(self.pending_at.elapsed()))
+    }
+
+    /// Shows `lines` in a popup next to the cursor, scrolled to the top.
+    fn show_popup(&mut self, lines: Vec<String>) {
+        self.popup = Some(lines);
+        self.popup_scroll = 0;
+    }
+
+    /// How many lines of the popup fit on screen.
+    fn popup_height(&self) -> usize {
+        POPUP_HEIGHT.min(self.vheight())
+    }
+
+    /// Shows the documentation of the word under the cursor, found next to
+    /// its definition in the buffer, when there is no language server to ask.
+    fn hover_docs(&mut self) {
+        let line = self.current_line_contents().unwrap_or_default();
+        let Some((start, end)) = cursors::word_at(&line, self.cx) else {
+            self.notify(Level::Info, "no hover information");
+            return;
+        };
+        let word = &line[start..end];
+        let language = language::find(&self.buffer.filetype).unwrap_or_else(language::plain_text);
+        match docs::lookup(language, &self.buffer.lines.join("\n"), word) {
+            Some(lines) => self.show_popup(lines),
+            None => self.notify(Level::Info, format!("no documentation for {word}")),
+        }
+    }
+
+    /// Returns the cursor position as an LSP (line, UTF-16 character) pair.
+    fn lsp_position(&self) -> (usize, usize) {
+        let line = self.buffer_line();
+        let contents = self.current_line_contents().unwrap_or_default();
+        (line, lsp::utf16_column(&contents, self.cx))
+    }
+
+    fn handle_lsp_event(
+        &mut self,
+        event: lsp::Event,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        match event {
+            lsp::Event::Hover(text) => {
+                // code fences only mark the signature's language
+                let lines: Vec<String> = text
+                    .trim()
+                    .lines()
+                    .filter(|l| !l.starts_with("```"))
+                    .map(String::from)
+                    .collect();
+                match lines.is_empty() {
+                    true => self.hover_docs(),
+                    false => self.show_popup(lines),
+                }
+            }
+            lsp::Event::Definition(locations) => {
+                let mut entries = self.location_entries(&locations);
+                if entries.is_empty() {
+                    entries = self.tag_definitions()?;
+                }
+                self.show_locations(entries, "definition", buffer);
+            }
+            lsp::Event::References(locations) => {
+                let entries = self.location_entries(&locations);
+                self.show_locations(entries, "references", buffer);
+            }
+            lsp::Event::Rename(changes) => self.apply_workspace_edit(changes, buffer)?,
+            lsp::Event::InlayHints(mut hints) => {
+                for hint in hints.iter_mut() {
+                    let contents = self.buffer.get(hint.line).unwrap_or_default();
+                    hint.x = lsp::byte_column(&contents, hint.x);
+                }
+                self.hints.set(hints);
+                self.draw_viewport(buffer)?;
+            }
+            lsp::Event::SignatureHelp(signature) => {
+                if self.is_insert() {
+                    self.signature = signature;
+                }
+            }
+            lsp::Event::Completion(items) => {
+                if self.is_insert() {
+                    self.open_completion(items);
+                }
+            }
+            lsp::Event::Diagnostics(mut items) => {
+                for d in items.iter_mut() {
+                    let contents = self.buffer.get(d.line).unwrap_or_default();
+                    d.x = lsp::byte_column(&contents, d.x);
+                }
+                self.diagnostics.replace("lsp", items);
+                self.draw_viewport(buffer)?;
+            }
+            lsp::Event::Error(err) => self.notify(Level::Error, err),
+            lsp::Event::Exited(status) => {
+                self.notify(Level::Warning, format!("language server exited: {status}"));
+                self.lsp = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_line(&mut self, buffer: &mut RenderBuffer, x: usize, y: usize, style: &Style) {
+        let width = self.vwidth().saturating_sub(x);
+        let line_fill = " ".repeat(width);
+        buffer.set_text(x, y, &line_fill, style);
+    }
+
+    pub fn draw_viewport(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        self.sync_highlights()?;
+        let lines = self.viewport_lines();
+        let start = self.buffer.byte_offset(self.vtop);
+        let end = lines.last().map_or(start, |line| {
+            self.buffer.byte_offset(*line) + self.buffer.lines[*line].len()
+        });
+        let style_info = highlighter::styles_in(&self.styles, start, end);
+        let default_style = self.theme.style.clone();
+
+        let rows = self.viewport_rows();
+        let mut offset = 0;
+        let mut next = self.vtop;
+        for (y, (line, range)) in rows.iter().enumerate() {
+            while next < *line {
+                offset += self.buffer.lines[next].len() + 1;
+                next += 1;
+            }
+            self.draw_text(buffer, y, *line, range.clone(), &style_info, offset);
+        }
+
+        for y in rows.len()..self.vheight() {
+            self.fill_line(buffer, 0, self.vy + y, &default_style);
+        }
+
+        self.draw_gutter(buffer);
+
+        Ok(())
+    }
+
+    fn gutter_width(&self) -> usize {
+        gutter_width(self.config.line_numbers, self.buffer.len())
+    }
+
+    /// The column the text starts at: after the gutter, or where it's
+    /// centered in zen mode.
+    fn text_left(&self) -> usize {
+        match self.zen {
+            true => self.vwidth().saturating_sub(self.config.zen_width) / 2,
+            false => self.gutter_width() + 1,
+        }
+    }
+
+    fn draw_gutter(&mut self, buffer: &mut RenderBuffer) {
+        // zen mode leaves a blank margin instead
+        if self.zen {
+            let margin = " ".repeat(self.vx);
+            for y in self.vy..self.vy + self.vheight() {
+                buffer.set_text(0, y, &margin, &self.theme.style);
+            }
+            return;
+        }
+        let width = self.gutter_width();
+        let fg = self
+            .theme
+            .gutter_style
+            .fg
+            .unwrap_or(self.theme.style.fg.expect("fg is defined for theme"));
+        let bg = self
+            .theme
+            .gutter_style
+            .bg
+            .unwrap_or(self.theme.style.bg.expect("bg is defined for theme"));
+
+        let rows = self.viewport_rows();
+        let mut line_start = self.buffer.byte_offset(self.vtop);
+        let mut next = self.vtop;
+        // the index of the line in the viewport, which differs from the row
+        // when lines wrap
+        let mut n = 0;
+
+        for row in 0..self.vheight() {
+            let wrapped = row > 0 && rows.get(row).map(|r| r.0) == rows.get(row - 1).map(|r| r.0);
+            if row > 0 && !wrapped {
+                n += 1;
+            }
+            let (text, indicator, sign, git_sign) = match rows.get(row).map(|r| &r.0) {
+                // only the first row of a wrapped line is numbered
+                Some(_) if wrapped => (String::new(), ' ', None, None),
+                Some(line) => {
+                    while next < *line {
+                        line_start += self.buffer.lines[next].len() + 1;
+                        next += 1;
+                    }
+                    let indicator = if self.folds.folded_at(*line).is_some() {
+                        '▸'
+                    } else if self.is_foldable(*line, line_start) {
+                        '▾'
+                    } else {
+                        ' '
+                    };
+                    let sign = self.diagnostics.worst_on_line(*line).map(|d| d.severity);
+                    let git_sign = self.git_signs.on_line(*line);
+                    let number = match self.config.line_numbers {
+                        LineNumbers::RelativeNumber if n != self.cy => n.abs_diff(self.cy),
+                        _ => line + 1,
+                    };
+                    (number.to_string(), indicator, sign, git_sign)
+                }
+                None => (" ".repeat(width), ' ', None, None),
+            };
+
+            let style = Style {
+                fg: Some(fg),
+                bg: Some(bg),
+                ..Default::default()
+            };
+            let text = match self.config.line_numbers {
+                LineNumbers::None => String::new(),
+                // the cursor line's own number is aligned to the left
+                LineNumbers::RelativeNumber if n == self.cy && !wrapped => {
+                    format!("{text:<width$}", width = width - 1)
+                }
+                _ => format!("{text:>width$}", width = width - 1),
+            };
+            let y = self.vy + row;
+            buffer.set_text(0, y, &format!("{indicator}{text} "), &style);
+
+            // diagnostic signs take the place of the fold indicator
+            if let Some(severity) = sign {
+                let sign_style = Style {
+                    fg: self.theme.diagnostic_style(severity).fg,
+                    ..style
+                };
+                buffer.set_char(0, y, severity.sign(), &sign_style);
+            }
+
+            // git signs go in the space between the line number and the text
+            if let Some(kind) = git_sign {
+                let sign_style = Style {
+                    fg: self.theme.git_style(kind).fg,
+                    ..style
+                };
+                buffer.set_char(width, y, kind.sign(), &sign_style);
+            }
+        }
+    }
+
+    /// Switches to `file`, loading it unless it's already open. The buffer
+    /// being edited stays open in the buffer list.
+    fn open_file(&mut self, file: &str, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let same_file = self
+            .buffer
+            .file
+            .as_deref()
+            .is_some_and(|current| buffers::same_file(current, file));
+        if same_file {
+            return Ok(());
+        }
+        if let Some(id) = self.buffers.find(file) {
+            return self.switch_buffer(id, buffer);
+        }
+        let opened = Buffer::from_file(Some(file.to_string()), self.config.large_file_size())?;
+        let left = self.leave_buffer();
+        self.buffers.switch(left, None);
+        self.show_buffer(Hidden::new(opened), true, buffer)
+    }
+
+    /// Where the finder and grep search: the buffer's own directory after
+    /// `:lcd`, or else the root of the project the working directory is in.
+    fn project_root(&self) -> anyhow::Result<PathBuf> {
+        if let Some(dir) = &self.buffer.local_dir {
+            return Ok(dir.clone());
+        }
+        let cwd = std::env::current_dir()?;
+        Ok(project::root(&cwd).unwrap_or(cwd))
+    }
+
+    /// A file the finder or grep found, relative to the working directory.
+    fn project_file(&self, file: &str) -> anyhow::Result<String> {
+        let cwd = std::env::current_dir()?;
+        Ok(project::rebase(file, &self.project_root()?, &cwd))
+    }
+
+    /// Renames the files of the buffers, lists and marks, relative to `old`,
+    /// to be relative to `new`, the working directory `:cd` went to.
+    fn rebase_files(&mut self, old: &Path, new: &Path) {
+        let rename = |file: &str| project::rebase(file, old, new);
+        self.buffer.file = self.buffer.file.as_deref().map(rename);
+        self.buffers.rebase(rename);
+        self.jumps.rebase(rename);
+        self.marks.rebase(rename);
+        self.quickfix.rebase(rename);
+        for file in &mut self.arglist {
+            *file = rename(file);
+        }
+    }
+
+    /// Opens the log in a read-only buffer without a file, at its last line.
+    /// It shows the log as it was, `:log` again shows what came after.
+    fn show_log(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(logger) = crate::LOGGER.get() else {
+            anyhow::bail!("Logging is off, start with --log-file <path> to turn it on");
+        };
+        let mut log = Buffer::new(None, fs::read_to_string(logger.path())?);
+        log.readonly = true;
+        let left = self.leave_buffer();
+        self.buffers.switch(left, None);
+        self.show_buffer(Hidden::new(log), false, buffer)?;
+        self.execute(&Action::MoveToBottom, buffer)?;
+        Ok(())
+    }
+
+    /// Edits an empty buffer without a file, keeping the current one in the
+    /// buffer list.
+    fn new_buffer(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let left = self.leave_buffer();
+        self.buffers.switch(left, None);
+        let empty = Buffer::new(None, String::new());
+        self.show_buffer(Hidden::new(empty), true, buffer)
+    }
+
+    /// Gives the buffer without a file `file` and writes it, then starts
+    /// what goes with a file: the language server, linting and git signs.
+    fn name_buffer(&mut self, file: String, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let filetype = self.buffer.filetype.clone();
+        self.buffer.set_file(file.clone());
+        if let Err(err) = self.save(buffer, false) {
+            self.buffer.file = None;
+            self.buffer.filetype = filetype;
+            return Err(err);
+        }
+        if self.buffer.filetype != filetype {
+            let filetype = self.buffer.filetype.clone();
+            self.execute(&Action::SetFiletype(filetype), buffer)?;
+        }
+        self.branch = git::branch(&file);
+        self.git_tracked = true;
+        self.git_diffed = None;
+        self.start_lsp();
+        self.start_lint();
+        self.update_tabline(buffer)
+    }
+
+    /// Makes buffer `id` from the buffer list the one being edited.
+    fn switch_buffer(&mut self, id: usize, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        if id == self.buffers.current() {
+            return Ok(());
+        }
+        let Some(hidden) = self.buffers.take(id) else {
+            anyhow::bail!("No buffer {id}");
+        };
+        let left = self.leave_buffer();
+        self.buffers.switch(left, Some(id));
+        self.show_buffer(hidden, false, buffer)
+    }
+
+    /// Closes buffer `id`, or the current one, refusing when it has unsaved
+    /// changes unless `force` is set. Closing the current buffer shows the
+    /// one left last, or an empty buffer when it was the only one.
+    fn close_buffer(
+        &mut self,
+        id: Option<usize>,
+        force: bool,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let id = id.unwrap_or(self.buffers.current());
+        let modified = if id == self.buffers.current() {
+            self.buffer.modified
+        } else {
+            let Some((_, hidden)) = self.buffers.hidden().find(|(i, _)| *i == id) else {
+                anyhow::bail!("No buffer {id}");
+            };
+            hidden.buffer.modified
+        };
+        if modified && !force {
+            anyhow::bail!("Buffer {id} has unsaved changes (add ! to close it anyway)");
+        }
+
+        if id != self.buffers.current() {
+            if let Some(file) = self.buffers.take(id).and_then(|hidden| hidden.buffer.file) {
+                swap::remove(&file);
+            }
+            return Ok(());
+        }
+        self.remember_position();
+        self.remove_swap();
+        match self.buffers.last() {
+            Some(last) => {
+                let hidden = self.buffers.take(last).expect("last buffer is hidden");
+                self.buffers.switch(None, Some(last));
+                self.show_buffer(hidden, false, buffer)
+            }
+            None => {
+                self.buffers.switch(None, None);
+                let empty = Buffer::new(None, String::new());
+                self.show_buffer(Hidden::new(empty), true, buffer)
+            }
+        }
+    }
+
+    /// The name of a buffer with unsaved changes, the current one first.
+    fn unsaved_buffer(&self) -> Option<String> {
+        let name = |buffer: &Buffer| {
+            buffer
+                .file
+                .clone()
+                .unwrap_or_else(|| "[No Name]".to_string())
+        };
+        if self.buffer.modified {
+            return Some(name(&self.buffer));
+        }
+        self.buffers
+            .hidden()
+            .find(|(_, hidden)| hidden.buffer.modified)
+            .map(|(id, hidden)| format!("buffer {id} ({})", name(&hidden.buffer)))
+    }
+
+    /// The view of the current tab page.
+    fn current_tab(&self) -> Tab {
+        Tab {
+            buffer: self.buffers.current(),
+            x: self.cx,
+            line: self.buffer_line(),
+            vtop: self.vtop,
+        }
+    }
+
+    /// Shows a tab page's buffer the way it was left. When the buffer was
+    /// closed since, the current one stays.
+    fn show_tab(&mut self, tab: Tab, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        if tab.buffer != self.buffers.current() {
+            if self.buffers.hidden().all(|(id, _)| id != tab.buffer) {
+                return self.draw_viewport(buffer);
+            }
+            self.switch_buffer(tab.buffer, buffer)?;
+        }
+        let line = tab.line.min(self.buffer.len().saturating_sub(1));
+        self.vtop = tab.vtop.min(line);
+        self.cx = tab.x;
+        self.cursor_to_line(line);
+        self.draw_viewport(buffer)
+    }
+
+    /// Shows the tabline above the viewport while there's more than one tab
+    /// page, keeping the cursor line in view when the viewport shrinks.
+    fn update_tabline(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let vy = usize::from(self.tabs.len() > 1);
+        if vy == self.vy {
+            return Ok(());
+        }
+        let line = self.buffer_line();
+        self.vy = vy;
+        if self.viewport_row(line).is_none() {
+            self.vtop = self.folds.nth_visible_back(line, self.vheight() - 1);
+        }
+        self.cursor_to_line(line);
+        self.draw_viewport(buffer)
+    }
+
+    /// Draws the tab pages' numbers and file names along the top, with the
+    /// current one highlighted and `+` on modified buffers.
+    fn draw_tabline(&self, buffer: &mut RenderBuffer) {
+        if self.vy == 0 {
+            return;
+        }
+        let style = &self.theme.statusline_style;
+        let mut x = 0;
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let current = i == self.tabs.current();
+            // the current tab's entry isn't kept up to date
+            let shown = if current || tab.buffer == self.buffers.current() {
+                Some(&self.buffer)
+            } else {
+                self.buffers
+                    .hidden()
+                    .find(|(id, _)| *id == tab.buffer)
+                    .map(|(_, hidden)| &hidden.buffer)
+            };
+            let name = shown
+                .and_then(|b| b.file.as_deref())
+                .map(|file| {
+                    Path::new(file)
+                        .file_name()
+                        .map_or(file.into(), |n| n.to_string_lossy())
+                })
+                .unwrap_or("[No Name]".into());
+            let modified = if shown.is_some_and(|b| b.modified) {
+                " +"
+            } else {
+                ""
+            };
+            let label = format!(" {}: {name}{modified} ", i + 1);
+            let label: String = label
+                .chars()
+                .take(self.vwidth().saturating_sub(x))
+                .collect();
+            let tab_style = if current {
+                &style.outer_style
+            } else {
+                &style.inner_style
+            };
+            buffer.set_text(x, 0, &label, tab_style);
+            x += label.chars().count();
+        }
+        let fill = " ".repeat(self.vwidth().saturating_sub(x));
+        buffer.set_text(x, 0, &fill, &style.inner_style);
+    }
+
+    /// Takes the buffer being edited to keep in the buffer list, unless it's
+    /// an unnamed buffer without changes. A modified buffer keeps its swap
+    /// file.
+    fn leave_buffer(&mut self) -> Option<Hidden> {
+        self.remember_position();
+        if self.buffer.modified {
+            self.swapped = None;
+        } else {
+            self.remove_swap();
+        }
+        let left = Hidden {
+            buffer: std::mem::replace(&mut self.buffer, Buffer::new(None, String::new())),
+            x: self.cx,
+            line: self.buffer_line(),
+            vtop: self.vtop,
+            undo_actions: std::mem::take(&mut self.undo_actions),
+        };
+        (left.buffer.file.is_some() || left.buffer.modified).then_some(left)
+    }
+
+    /// Loads `file` again in place of the buffer being edited, as a hex dump
+    /// when it is one.
+    fn load_file(&mut self, file: &str, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let opened = match self.buffer.hex {
+            true => Buffer::from_hex_file(file)?,
+            false => Buffer::from_file(Some(file.to_string()), self.config.large_file_size())?,
+        };
+        self.remove_swap();
+        self.show_buffer(Hidden::new(opened), true, buffer)
+    }
+
+    /// Edits `shown`, resetting everything tied to the old buffer and
+    /// starting the language server and linter for the new one. A `fresh`
+    /// buffer was just loaded, so its swap and undo files are checked.
+    fn show_buffer(
+        &mut self,
+        mut shown: Hidden,
+        fresh: bool,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let state = shown
+            .buffer
+            .file
+            .as_deref()
+            .and_then(|f| self.recent.get(f));
+        if let Some(state) = state.filter(|_| fresh) {
+            (shown.line, shown.x, shown.vtop) = (state.line, state.x, state.vtop);
+        }
+        self.buffer = shown.buffer;
+        if fresh {
+            self.check_swap();
+        }
+        self.styles.clear();
+        self.brackets.clear();
+        self.bracket_match = None;
+        self.folds.clear();
+        self.diagnostics = Diagnostics::default();
+        self.hints = Hints::default();
+        self.diff = None;
+        self.status = None;
+        self.git_signs = GitSigns::default();
+        self.git_diff = None;
+        self.git_diffed = None;
+        self.git_tracked = true;
+        self.blame.clear();
+        self.blame_job = None;
+        self.blamed_version = 0;
+        self.completion = None;
+        self.signature = None;
+        self.popup = None;
+        self.flash = None;
+        self.undo_actions = shown.undo_actions;
+        self.insert_undo_actions.clear();
+        if fresh {
+            self.load_undo();
+        }
+        let line = shown.line.min(self.buffer.len().saturating_sub(1));
+        self.vtop = shown.vtop.min(line);
+        self.vleft = 0;
+        self.vx = self.text_left();
+        self.wrap = self.config.for_filetype(&self.buffer.filetype).wrap;
+        self.cx = shown.x;
+        self.cursor_to_line(line);
+
+        let language = language::find(&self.buffer.filetype).unwrap_or_else(language::plain_text);
+        self.highlighter.send(Request::SetLanguage(language))?;
+        self.request_highlights()?;
+
+        self.branch = self.buffer.file.as_deref().and_then(git::branch);
+        self.lsp = None;
+        self.lint = None;
+        self.start_lsp();
+        self.start_lint();
+
+        self.draw_viewport(buffer)
+    }
+
+    /// Moves the cursor to `x` on `line` of `file`, opening the file first
+    /// unless it's the one being edited.
+    fn goto_location(
+        &mut self,
+        file: &str,
+        line: usize,
+        x: usize,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        self.push_jump();
+        if !self.is_current_file(file) {
+            self.open_file(file, buffer)?;
+        }
+        let line = line.min(self.buffer.len().saturating_sub(1));
+        self.move_to(x, line, buffer)
+    }
+
+    fn is_current_file(&self, file: &str) -> bool {
+        self.buffer.file.as_ref().is_some_and(|current| {
+            std::fs::canonicalize(current).ok() == std::fs::canonicalize(file).ok()
+        })
+    }
+
+    /// Turns the locations a language server sent into quickfix entries,
+    /// with byte columns and the text of their lines.
+    fn location_entries(&self, locations: &[Location]) -> Vec<Entry> {
+        let mut files: HashMap<&str, Vec<String>> = HashMap::new();
+        locations
+            .iter()
+            .map(|location| {
+                let contents = match self.is_current_file(&location.path) {
+                    true => self.buffer.get(location.line),
+                    false => files
+                        .entry(&location.path)
+                        .or_insert_with(|| {
+                            fs::read_to_string(&location.path)
+                                .map(|c| c.lines().map(String::from).collect())
+                                .unwrap_or_default()
+                        })
+                        .get(location.line)
+                        .cloned(),
+                }
+                .unwrap_or_default();
+                Entry {
+                    file: display_path(&location.path),
+                    line: location.line,
+                    x: lsp::byte_column(&contents, location.character),
+                    text: contents.trim().to_string(),
+                }
+            })
+            .collect()
+    }
+
+    /// Applies the edits a rename brought, as one undoable change in each
+    /// buffer. Files that aren't open are opened in hidden buffers, left
+    /// unsaved to look over.
+    fn apply_workspace_edit(
+        &mut self,
+        changes: Vec<(String, Vec<TextEdit>)>,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let changes: Vec<_> = changes
+            .into_iter()
+            .filter(|(_, edits)| !edits.is_empty())
+            .collect();
+        for (file, edits) in &changes {
+            if self.is_current_file(file) && self.buffer.is_loading() {
+                anyhow::bail!("{file} is still loading");
+            }
+            if self.is_current_file(file) {
+                let undo = apply_text_edits(&mut self.buffer, edits);
+                self.undo_actions.push(undo);
+                continue;
+            }
+            let id = match self.buffers.find(file) {
+                Some(id) => id,
+                None => {
+                    // read in full, the edits can be anywhere in it
+                    let opened = Buffer::from_file(Some(display_path(file)), u64::MAX)?;
+                    self.buffers.add(Hidden::new(opened))
+                }
+            };
+            if let Some(hidden) = self.buffers.get_mut(id) {
+                if hidden.buffer.is_loading() {
+                    anyhow::bail!("{file} is still loading");
+                }
+                let undo = apply_text_edits(&mut hidden.buffer, edits);
+                hidden.undo_actions.push(undo);
+            }
+        }
+        self.check_bounds();
+        self.draw_viewport(buffer)?;
+        let text = match changes.len() {
+            0 => "nothing to rename".to_string(),
+            1 => "renamed in 1 file".to_string(),
+            n => format!("renamed in {n} files"),
+        };
+        self.notify(Level::Info, text);
+        Ok(())
+    }
+
+    /// The definitions of the word under the cursor in the `tags` file
+    /// ctags wrote for the current directory.
+    fn tag_definitions(&self) -> anyhow::Result<Vec<Entry>> {
+        let line = self.current_line_contents().unwrap_or_default();
+        let Some((start, end)) = cursors::word_at(&line, self.cx) else {
+            return Ok(vec![]);
+        };
+        let Some(path) = tags::find_file(&std::env::current_dir()?) else {
+            anyhow::bail!("no tags file found");
+        };
+        tags::lookup(&path, &line[start..end])
+    }
+
+    /// Jumps to the only location found, or makes several the quickfix list
+    /// and jumps to the first.
+    fn show_locations(&mut self, entries: Vec<Entry>, what: &str, buffer: &mut RenderBuffer) {
+        match &entries[..] {
+            [] => self.notify(Level::Info, format!("no {what} found")),
+            [entry] => {
+                if let Err(err) = self.goto_location(&entry.file, entry.line, entry.x, buffer) {
+                    self.notify(Level::Error, err.to_string());
+                }
+            }
+            _ => {
+                self.quickfix.set(entries);
+                let entry = self.quickfix.next().cloned();
+                self.goto_quickfix(entry, buffer);
+            }
+        }
+    }
+
+    /// Jumps to a quickfix entry, showing its message and where it is in
+    /// the list.
+    fn goto_quickfix(&mut self, entry: Option<Entry>, buffer: &mut RenderBuffer) {
+        let Some(entry) = entry else {
+            self.notify(Level::Info, "quickfix list is empty");
+            return;
+        };
+        if let Err(err) = self.goto_location(&entry.file, entry.line, entry.x, buffer) {
+            self.notify(Level::Error, err.to_string());
+            return;
+        }
+        let count = self.quickfix.entries().len();
+        let position = self.quickfix.position();
+        self.notify(
+            Level::Info,
+            format!("({position} of {count}) {}", entry.text),
+        );
+    }
+
+    fn current_jump(&self) -> Jump {
+        Jump {
+            file: self.buffer.file.clone(),
+            x: self.cx,
+            line: self.buffer_line(),
+        }
+    }
+
+    /// Records the cursor position before jumping away from it, in the jump
+    /// list and as the `'` mark.
+    fn push_jump(&mut self) {
+        let jump = self.current_jump();
+        self.marks.set('\'', self.current_mark());
+        self.jumps.push(jump);
+    }
+
+    fn current_mark(&self) -> Mark {
+        Mark {
+            file: self.buffer.file.clone(),
+            x: self.cx,
+            line: self.buffer_line(),
+        }
+    }
+
+    fn execute_char_action(
+        &mut self,
+        action: CharAction,
+        c: char,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        match action {
+            CharAction::SetMark => {
+                if !marks::is_valid(c) || c == '\'' {
+                    anyhow::bail!("Invalid mark: {c}");
+                }
+                self.marks.set(c, self.current_mark());
+            }
+            CharAction::GotoMark | CharAction::GotoMarkLine => {
+                if !marks::is_valid(c) {
+                    anyhow::bail!("Invalid mark: {c}");
+                }
+                let Some(mark) = self.marks.get(c, self.buffer.file.as_ref()) else {
+                    anyhow::bail!("Mark not set: {c}");
+                };
+                self.push_jump();
+                let x = match action {
+                    CharAction::GotoMark => mark.x,
+                    _ => usize::MAX,
+                };
+                self.jump_to(
+                    Jump {
+                        file: mark.file,
+                        x,
+                        line: mark.line,
+                    },
+                    buffer,
+                )?;
+                if matches!(action, CharAction::GotoMarkLine) {
+                    let line = self.current_line_contents().unwrap_or_default();
+                    self.cx = line.len() - line.trim_start().len();
+                }
+            }
+            CharAction::Replace => {
+                if self.line_length() > 0 {
+                    let undo = self.replace_char(c);
+                    self.undo_actions.push(undo);
+                    self.draw_line(buffer);
+                }
+            }
+            CharAction::Surround | CharAction::SurroundSelection => {
+                let range = match action {
+                    CharAction::Surround => self.surround_range.take(),
+                    _ => self.selection(),
+                };
+                let Some((start, end)) = range else {
+                    return Ok(());
+                };
+                let Some((open, close)) = surround::delimiters(c) else {
+                    anyhow::bail!("Can't surround with {c}");
+                };
+                if self.is_visual() {
+                    self.execute(&Action::EnterMode(Mode::Normal), buffer)?;
+                }
+                let (start, end) = (self.buffer_offset(start), self.buffer_offset(end));
+                let surrounding = Surrounding {
+                    open: (start, start),
+                    close: (end, end),
+                };
+                self.replace_surrounding(surrounding, &open, &close, buffer)?;
+            }
+            CharAction::DeleteSurround => {
+                let surrounding = self.find_surrounding(c)?;
+                self.replace_surrounding(surrounding, "", "", buffer)?;
+            }
+            CharAction::ChangeSurround => {
+                self.find_surrounding(c)?;
+                self.waiting_char = Some(CharAction::ChangeSurroundTo(c));
+            }
+            CharAction::ChangeSurroundTo(from) => {
+                let Some((open, close)) = surround::delimiters(c) else {
+                    anyhow::bail!("Can't surround with {c}");
+                };
+                let surrounding = self.find_surrounding(from)?;
+                self.replace_surrounding(surrounding, &open, &close, buffer)?;
+            }
+            CharAction::Find(find) => {
+                self.last_find = Some((find, c));
+                if let Some(x) = self.find_target(find, c, false) {
+                    self.cx = x;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the case of the text from `start` up to `end`, returning the
+    /// action that undoes it, or `None` when there's no text there.
+    fn change_case(
+        &mut self,
+        case: Case,
+        start: (usize, usize),
+        end: (usize, usize),
+    ) -> Option<Action> {
+        let text = self.buffer.remove_range(start, end);
+        if text.is_empty() {
+            return None;
+        }
+        let new = case.apply(&text);
+        Some(self.insert_replacement(start, text, &new))
+    }
+
+    /// Shows what a shell command printed: a single line as a message, more
+    /// in a popup.
+    fn run_shell(&mut self, command: &str) -> anyhow::Result<()> {
+        let output = shell::run(command, "")?;
+        let text = format!("{}{}", output.stdout, output.stderr);
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        match lines.len() {
+            0 => {}
+            1 => self.notify(Level::Info, lines[0].clone()),
+            _ => self.show_popup(lines),
+        }
+        if let Some(err) = output.error() {
+            self.notify(Level::Warning, err);
+        }
+        Ok(())
+    }
+
+    fn read_shell(&mut self, command: &str, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let output = shell::run(command, "")?;
+        if let Some(err) = output.error() {
+            self.notify(Level::Error, err);
+            return Ok(());
+        }
+        let line = self.buffer_line();
+        let (start, text, first) = match self.buffer.get(line) {
+            Some(contents) => (
+                (contents.len(), line),
+                format!("\n{}", output.lines()),
+                line + 1,
+            ),
+            // an empty buffer has no line to go below, the output is its first
+            None => ((0, 0), output.lines().to_string(), 0),
+        };
+        let end = self.buffer.insert_text(start.0, start.1, &text);
+        self.undo_actions.push(Action::RemoveRange(start, end));
+        self.move_to(0, first, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// Feeds lines `from..=to` to a shell command and replaces them with what
+    /// it prints, leaving them as they are when it fails. What it writes to
+    /// stderr goes to the messages.
+    fn filter_lines(
+        &mut self,
+        from: usize,
+        to: usize,
+        command: &str,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        // the range is kept to the lines there are, and an empty buffer is
+        // filled with what the command prints without any
+        let (from, to) = match self.buffer.len().checked_sub(1) {
+            Some(last) => (from.min(last), to.min(last)),
+            None => (0, 0),
+        };
+        let input = match self.buffer.lines.get(from..=to) {
+            Some(lines) => lines.join("\n") + "\n",
+            None => String::new(),
+        };
+        let output = shell::run(command, &input)?;
+        for line in output.stderr.lines() {
+            self.notify(Level::Warning, line);
+        }
+        if let Some(err) = output.error() {
+            self.notify(Level::Error, err);
+            return Ok(());
+        }
+        let start = (0, from);
+        let end = (self.buffer.get(to).map_or(0, |l| l.len()), to);
+        let old = self.buffer.remove_range(start, end);
+        let undo = self.insert_replacement(start, old, output.lines());
+        self.undo_actions.push(undo);
+        self.move_to(0, from, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// The options for the buffer's filetype, with the indentation from any
+    /// `.editorconfig` over them.
+    fn filetype_options(&self) -> FiletypeOptions {
+        let mut options = self.config.for_filetype(&self.buffer.filetype);
+        if let Some(file) = &self.buffer.file {
+            let properties = editorconfig::properties(Path::new(file));
+            options.tabstop = properties.tabstop.unwrap_or(options.tabstop).max(1);
+            options.expandtab = properties.expandtab.unwrap_or(options.expandtab);
+        }
+        options
+    }
+
+    /// Replaces the abbreviation before the cursor with its expansion, as a
+    /// word ends in insert mode.
+    fn expand_abbreviation(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let abbreviations = self
+            .config
+            .for_filetype(&self.buffer.filetype)
+            .abbreviations;
+        let contents = self.current_line_contents().unwrap_or_default();
+        let Some((start, expansion)) = abbrev::find(&contents, self.cx, &abbreviations) else {
+            return Ok(());
+        };
+        let line = self.buffer_line();
+        let word = self.buffer.remove_range((start, line), (self.cx, line));
+        self.insert_undo_actions
+            .push(Action::InsertTextAt(start, line, word));
+        let end = self.buffer.insert_text(start, line, expansion);
+        self.insert_undo_actions
+            .push(Action::RemoveRange((start, line), end));
+        if end.1 == line {
+            self.cx = end.0;
+            self.draw_line(buffer);
+            return Ok(());
+        }
+        self.draw_viewport(buffer)?;
+        self.move_to(end.0, end.1, buffer)
+    }
+
+    /// The save options for the buffer's filetype, with any `.editorconfig`
+    /// over them.
+    fn save_options(&self) -> SaveOptions {
+        let mut options = self.config.save.for_filetype(&self.buffer.filetype);
+        if let Some(file) = &self.buffer.file {
+            let properties = editorconfig::properties(Path::new(file));
+            options.trim_trailing_whitespace = properties
+                .trim_trailing_whitespace
+                .unwrap_or(options.trim_trailing_whitespace);
+            options.final_newline = properties.final_newline.unwrap_or(options.final_newline);
+        }
+        options
+    }
+
+    /// Toggles the filetype's line comment on the current line, or on the
+    /// lines of the selection, which ends.
+    fn toggle_comment(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(prefix) = self.filetype_options().comment else {
+            self.notify(
+                Level::Warning,
+                format!("no comment string for {}", self.buffer.filetype),
+            );
+            return Ok(());
+        };
+        let line = self.buffer_line();
+        let (top, bottom) = match self.selection_anchor.filter(|_| self.is_visual()) {
+            Some((_, anchor)) => (anchor.min(line), anchor.max(line)),
+            None => (line, line),
+        };
+        let lines = self.buffer.lines.get(top..=bottom).unwrap_or_default();
+        let mut undo = vec![];
+        for edit in comment::toggle(lines, top, &prefix) {
+            let start = (edit.start, edit.line);
+            let old = self.buffer.remove_range(start, (edit.end, edit.line));
+            undo.push(self.insert_replacement(start, old, &edit.text));
+        }
+        if !undo.is_empty() {
+            self.undo_actions.push(Action::UndoMultiple(undo));
+        }
+        if self.is_visual() {
+            self.selection_anchor = None;
+            self.mode = Mode::Normal;
+            self.move_to(self.cx, top, buffer)?;
+        }
+        self.cx = self.cx.min(self.line_length().saturating_sub(1));
+        self.draw_viewport(buffer)
+    }
+
+    /// Inserts `new` where `old` was just removed from, returning the action
+    /// that puts `old` back.
+    fn insert_replacement(&mut self, start: (usize, usize), old: String, new: &str) -> Action {
+        let new_end = self.buffer.insert_text(start.0, start.1, new);
+        Action::UndoMultiple(vec![
+            Action::InsertTextAt(start.0, start.1, old),
+            Action::RemoveRange(start, new_end),
+        ])
+    }
+
+    /// Extra cursors show as the character under them in reverse video.
+    fn extra_cursor_style(&self, style: &Style) -> Style {
+        Style {
+            fg: style.bg.or(self.theme.style.bg),
+            bg: style.fg.or(self.theme.style.fg),
+            ..style.clone()
+        }
+    }
+
+    /// Adds a cursor where the main one is and moves the main one to the next
+    /// occurrence of the word under it, at the same place in the word.
+    fn add_cursor_at_next_match(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let contents = self.current_line_contents().unwrap_or_default();
+        let Some((start, end)) = cursors::word_at(&contents, self.cx) else {
+            self.notify(Level::Info, "no word under the cursor");
+            return Ok(());
+        };
+        let offset = self.cx - start;
+        let main = (self.cx, line);
+        let word = &contents[start..end];
+        let found = cursors::find_next(&self.buffer.lines, word, (start, line), |(x, y)| {
+            let pos = (x + offset, y);
+            pos == main || self.cursors.contains(pos)
+        });
+        let Some((x, y)) = found else {
+            self.notify(Level::Info, "no more matches");
+            return Ok(());
+        };
+        self.cursors.add(main);
+        self.move_to(x + offset, y, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// Runs an insert or a delete at every cursor. They're applied from the
+    /// last cursor to the first so the positions before each one still hold,
+    /// then the cursors after an edit on the same line are shifted by it.
+    fn edit_at_cursors(
+        &mut self,
+        action: &Action,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let main = (self.cx, self.buffer_line());
+        let mut positions: Vec<(usize, usize)> = self
+            .cursors
+            .positions()
+            .iter()
+            .copied()
+            .filter(|&(_, line)| line < self.buffer.len())
+            .chain([main])
+            .collect();
+        positions.sort_by_key(|&(x, line)| (line, x));
+        positions.dedup();
+
+        let mut undo = vec![];
+        // where each cursor was, where it ends up and how much longer its
+        // edit made the line
+        let mut moved = vec![];
+        for &(x, line) in positions.iter().rev() {
+            let text = self.buffer.get(line).unwrap_or_default();
+            let at = x.min(text.len());
+            let (new_x, delta) = match action {
+                Action::InsertCharAtCursorPos(c) => {
+                    self.buffer.insert(at, line, *c);
+                    undo.push(Action::RemoveCharAt(at, line));
+                    (at + c.len_utf8(), c.len_utf8() as isize)
+                }
+                Action::DeletePreviousChar => match text[..at].chars().next_back() {
+                    Some(c) => {
+                        let prev = at - c.len_utf8();
+                        self.buffer.remove(prev, line);
+                        undo.push(Action::InsertTextAt(prev, line, c.to_string()));
+                        (prev, -(c.len_utf8() as isize))
+                    }
+                    None => (at, 0),
+                },
+                _ => match text[at..].chars().next() {
+                    Some(c) => {
+                        self.buffer.remove(at, line);
+                        undo.push(Action::InsertTextAt(at, line, c.to_string()));
+                        (at, -(c.len_utf8() as isize))
+                    }
+                    None => (at, 0),
+                },
+            };
+            moved.push(((x, line), new_x, delta));
+        }
+
+        let mut extra = vec![];
+        for &((x, line), new_x, _) in &moved {
+            let shift: isize = moved
+                .iter()
+                .filter(|((other, other_line), _, _)| *other_line == line && *other < x)
+                .map(|(_, _, delta)| delta)
+                .sum();
+            let new_x = (new_x as isize + shift).max(0) as usize;
+            match (x, line) == main {
+                true => self.cx = new_x,
+                false => extra.push((new_x, line)),
+            }
+        }
+        self.cursors.set(extra);
+
+        if self.is_typing() {
+            self.insert_undo_actions.extend(undo);
+        } else {
+            self.undo_actions.push(Action::UndoMultiple(undo));
+        }
+        self.draw_viewport(buffer)
+    }
+
+    /// The selected part of each line of a block.
+    fn block_lines(&self, block: &Block) -> Vec<String> {
+        (block.top..=block.bottom)
+            .map(|line| {
+                let text = self.buffer.get(line).unwrap_or_default();
+                let (start, end) = block.range(&text);
+                text[start..end].to_string()
+            })
+            .collect()
+    }
+
+    fn delete_block(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(block) = self.block() else {
+            return Ok(());
+        };
+        let mut removed = vec![];
+        let mut undo = vec![];
+        for line in block.top..=block.bottom {
+            let text = self.buffer.get(line).unwrap_or_default();
+            let (start, end) = block.range(&text);
+            let text = self.buffer.remove_range((start, line), (end, line));
+            if !text.is_empty() {
+                undo.push(Action::InsertTextAt(start, line, text.clone()));
+            }
+            removed.push(text);
+        }
+        self.register = Some(Register::Block(removed));
+        if !undo.is_empty() {
+            self.undo_actions.push(Action::UndoMultiple(undo));
+        }
+        self.execute(&Action::EnterMode(Mode::Normal), buffer)?;
+        self.move_to(block.left, block.top, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// Pastes the register after or before the cursor. A block goes in at the
+    /// cursor's column on as many lines as it has, adding lines at the end of
+    /// the buffer when needed.
+    fn paste(&mut self, after: bool, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let contents = self.current_line_contents().unwrap_or_default();
+        let x = match contents[self.cx.min(contents.len())..].chars().next() {
+            Some(c) if after => self.cx + c.len_utf8(),
+            _ => self.cx.min(contents.len()),
+        };
+        let mut undo = vec![];
+        match self.register.clone() {
+            Some(Register::Text(text)) => {
+                let end = self.buffer.insert_text(x, line, &text);
+                undo.push(Action::RemoveRange((x, line), end));
+                // on the last character pasted, like vim
+                let last = self.buffer.get(end.1).unwrap_or_default()[..end.0]
+                    .char_indices()
+                    .last()
+                    .map_or(end.0, |(i, _)| i);
+                self.move_to(last, end.1, buffer)?;
+            }
+            Some(Register::Block(lines)) => {
+                let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                for (i, piece) in lines.iter().enumerate() {
+                    let y = line + i;
+                    if y >= self.buffer.len() {
+                        self.buffer.insert_line(y, String::new());
+                        undo.push(Action::DeleteLineAt(y));
+                    }
+                    let text = self.buffer.get(y).unwrap_or_default();
+                    let (at, piece) = block::paste_text(&text, x, piece, width);
+                    self.buffer.insert_text(at, y, &piece);
+                    undo.push(Action::RemoveRange((at, y), (at + piece.len(), y)));
+                }
+                self.move_to(x, line, buffer)?;
+            }
+            None => return Ok(()),
+        }
+        self.undo_actions.push(Action::UndoMultiple(undo));
+        self.draw_viewport(buffer)
+    }
+
+    /// Starts insert mode on the first line of the block, in front of it or,
+    /// to append, after it.
+    fn start_block_insert(
+        &mut self,
+        append: bool,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let Some(block) = self.block() else {
+            return Ok(());
+        };
+        let text = self.buffer.get(block.top).unwrap_or_default();
+        let (start, end) = block.range(&text);
+        self.execute(&Action::EnterMode(Mode::Insert), buffer)?;
+        let x = match append {
+            true => block.right,
+            false => start,
+        };
+        if append && text.len() < x {
+            let padding = " ".repeat(x - text.len());
+            self.buffer.insert_text(text.len(), block.top, &padding);
+            self.insert_undo_actions
+                .push(Action::RemoveRange((text.len(), block.top), (x, block.top)));
+        }
+        let x = match append {
+            true => x.max(end),
+            false => x,
+        };
+        self.block_insert = Some(BlockInsert {
+            x,
+            top: block.top,
+            bottom: block.bottom,
+            len: self.buffer.get(block.top).map_or(0, |l| l.len()),
+            append,
+        });
+        self.move_to(x, block.top, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// Repeats the text typed during a block insert on the rest of the
+    /// block's lines, as part of the same change. Lines that end before the
+    /// block are skipped by `I` and padded by `A`.
+    fn finish_block_insert(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let Some(insert) = self.block_insert.take() else {
+            return Ok(());
+        };
+        let first = self.buffer.get(insert.top).unwrap_or_default();
+        let added = first.len().saturating_sub(insert.len);
+        let Some(text) = first.get(insert.x..insert.x + added) else {
+            return Ok(());
+        };
+        if text.is_empty() || self.buffer_line() != insert.top {
+            return Ok(());
+        }
+        for line in insert.top + 1..=insert.bottom.min(self.buffer.len() - 1) {
+            let contents = self.buffer.get(line).unwrap_or_default();
+            if contents.len() < insert.x && !insert.append {
+                continue;
+            }
+            let (at, text) = block::paste_text(&contents, insert.x, text, 0);
+            self.buffer.insert_text(at, line, &text);
+            self.insert_undo_actions
+                .push(Action::RemoveRange((at, line), (at + text.len(), line)));
+        }
+        self.draw_viewport(buffer)
+    }
+
+    /// Joins `count` lines starting with the cursor's, undone as one change.
+    fn join_lines(&mut self, count: usize, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let mut undo = vec![];
+        let mut joined_at = None;
+        for _ in 1..count.max(2) {
+            let Some((x, removed, separator)) = self.buffer.join_line(line) else {
+                break;
+            };
+            undo.push(Action::InsertTextAt(x, line, removed));
+            if !separator.is_empty() {
+                undo.push(Action::RemoveRange((x, line), (x + separator.len(), line)));
+            }
+            joined_at = Some(x);
+        }
+        if let Some(x) = joined_at {
+            self.undo_actions.push(Action::UndoMultiple(undo));
+            self.cx = x;
+            self.draw_viewport(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the character under the cursor with `c`, or adds it when the
+    /// cursor is past the end of the line, returning the action that undoes it.
+    fn replace_char(&mut self, c: char) -> Action {
+        let line = self.buffer_line();
+        let contents = self.current_line_contents().unwrap_or_default();
+        let undo = match contents.get(self.cx..).and_then(|s| s.chars().next()) {
+            Some(old) => {
+                self.buffer.remove(self.cx, line);
+                Action::UndoMultiple(vec![
+                    Action::InsertTextAt(self.cx, line, old.to_string()),
+                    Action::RemoveCharAt(self.cx, line),
+                ])
+            }
+            None => Action::RemoveCharAt(self.cx, line),
+        };
+        self.buffer.insert(self.cx, line, c);
+        undo
+    }
+
+    fn buffer_offset(&self, (x, line): (usize, usize)) -> usize {
+        self.buffer.byte_offset(line) + x
+    }
+
+    /// The delimiters named by `c` around the cursor. The whitespace inside
+    /// brackets goes along when they're named by the opening one, like `ds(`.
+    fn find_surrounding(&self, c: char) -> anyhow::Result<Surrounding> {
+        let text = self.buffer.text();
+        let cursor = self.buffer_offset((self.cx, self.buffer_line()));
+        let Some(surrounding) = surround::find(&text, cursor, c) else {
+            anyhow::bail!("No surrounding {c} found");
+        };
+        Ok(match matches!(c, '(' | '{' | '[' | '<') {
+            true => surround::with_padding(&text, surrounding),
+            false => surrounding,
+        })
+    }
+
+    /// Replaces the delimiters of `surrounding` with `open` and `close` as one
+    /// change. The closing one goes first, so the opening one's position still
+    /// holds.
+    fn replace_surrounding(
+        &mut self,
+        surrounding: Surrounding,
+        open: &str,
+        close: &str,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let mut undo = vec![];
+        for (span, new) in [(surrounding.close, close), (surrounding.open, open)] {
+            let start = self.buffer.position(span.0);
+            let old = self
+                .buffer
+                .remove_range(start, self.buffer.position(span.1));
+            undo.push(self.insert_replacement(start, old, new));
+        }
+        self.undo_actions.push(Action::UndoMultiple(undo));
+        let (x, line) = self.buffer.position(surrounding.open.0);
+        self.move_to(x, line, buffer)?;
+        self.draw_viewport(buffer)
+    }
+
+    /// Where a find on the cursor's line leads, if the character is there.
+    fn find_target(&self, find: Find, c: char, repeat: bool) -> Option<usize> {
+        let line = self.current_line_contents()?;
+        motion::find_char(find, c, &line, self.cx, repeat)
+    }
+
+    /// The find a motion runs, with whether it's a repeat.
+    fn resolve_find(&self, action: &Action) -> Option<(Find, char, bool)> {
+        match action {
+            Action::WithChar(CharAction::Find(find), c) => Some((*find, *c, false)),
+            Action::RepeatFind => self.last_find.map(|(find, c)| (find, c, true)),
+            Action::RepeatFindReverse => self.last_find.map(|(find, c)| (find.reversed(), c, true)),
+            _ => None,
+        }
+    }
+
+    fn is_inclusive(&self, action: &Action) -> bool {
+        match self.resolve_find(action) {
+            Some((find, _, _)) => find.is_forward(),
+            None => action.is_inclusive(),
+        }
+    }
+
+    /// Returns to a location from the jump list, opening its file when it's
+    /// not the one being edited.
+    fn jump_to(&mut self, jump: Jump, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        if jump.file.is_some() && jump.file != self.buffer.file {
+            let file = jump.file.unwrap_or_default();
+            self.open_file(&file, buffer)?;
+        }
+        let line = jump.line.min(self.buffer.len().saturating_sub(1));
+        let x = jump.x.min(self.buffer.get(line).map_or(0, |l| l.len()));
+        self.move_to(x, line, buffer)
+    }
+
+    fn open_picker(&mut self, picker: Picker) {
+        self.picker = Some(picker);
+        self.completion = None;
+        self.mode = Mode::Picker;
+    }
+
+    /// Shows the terminal, starting a shell in it unless one is running.
+    fn open_terminal(&mut self) -> anyhow::Result<()> {
+        let (_, _, width, height) = self.terminal_area();
+        let (cols, rows) = (width - 1, height);
+        match &mut self.terminal {
+            Some(terminal) => terminal.resize(cols, rows),
+            None => {
+                let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+                self.terminal = Some(Terminal::spawn(&shell, cols, rows)?);
+            }
+        }
+        self.completion = None;
+        self.picker = None;
+        self.mode = Mode::Terminal;
+        Ok(())
+    }
+
+    /// Takes the terminal's new output, closing it once its shell exited.
+    /// Returns whether anything changed.
+    fn receive_terminal(&mut self) -> bool {
+        let Some(terminal) = &mut self.terminal else {
+            return false;
+        };
+        let changed = terminal.poll();
+        if terminal.is_alive() {
+            return changed;
+        }
+
+        self.terminal = None;
+        self.notify(Level::Info, "shell exited");
+        if matches!(self.mode, Mode::Terminal) {
+            self.mode = Mode::Normal;
+        }
+        true
+    }
+
+    /// Searches again for the query of a live picker after it changed.
+    fn restart_live_picker(&mut self) -> anyhow::Result<()> {
+        let root = self.project_root()?;
+        let Some(picker) = self.picker.as_mut().filter(|p| p.live) else {
+            return Ok(());
+        };
+        let source = match picker.query.as_str() {
+            "" => Source::new(std::sync::mpsc::channel().1),
+            query => grep::search(&root, query),
+        };
+        picker.set_source(source);
+        Ok(())
+    }
+
+    /// Opens the completion menu for the word before the cursor, offering
+    /// `items` followed by the words in the buffer.
+    fn open_completion(&mut self, items: Vec<completion::Completion>) {
+        let contents = self.current_line_contents().unwrap_or_default();
+        let prefix = completion::word_before(&contents, self.cx);
+
+        let mut items = items;
+        for word in completion::buffer_words(&self.buffer.lines) {
+            if !items.iter().any(|i| i.text == word.text) {
+                items.push(word);
+            }
+        }
+
+        let start = self.cx - prefix.len();
+        self.completion = CompletionMenu::new(items, self.buffer_line(), start, prefix);
+        if self.completion.is_none() {
+            self.notify(Level::Info, "no completions");
+        }
+    }
+
+    /// Refilters the completion menu as the word is typed, closing it once
+    /// the cursor leaves the word.
+    fn update_completion(&mut self) {
+        let line_number = self.buffer_line();
+        let line = self.buffer.get(line_number).unwrap_or_default();
+        let Some(menu) = &mut self.completion else {
+            return;
+        };
+        let typed = (menu.line == line_number && self.cx >= menu.start)
+            .then(|| line.get(menu.start..self.cx))
+            .flatten();
+        let keep = match typed {
+            Some(typed) if typed.chars().all(completion::is_word_char) => menu.filter(typed),
+            _ => false,
+        };
+        if !keep {
+            self.completion = None;
+        }
+    }
+
+    /// Asks for the signature of the call being typed after `(` and `,`, and
+    /// stops showing it after `)`. The edit has been sent to the server by
+    /// then, as drawing the line syncs it.
+    fn update_signature(&mut self, typed: char) {
+        match typed {
+            '(' | ',' => {
+                let (line, character) = self.lsp_position();
+                if let Some(lsp) = &mut self.lsp {
+                    let result = lsp.signature_help(line, character);
+                    self.check_lsp(result);
+                }
+            }
+            ')' => self.signature = None,
+            _ => {}
+        }
+    }
+
+    /// Draws the signature of the call being typed above the cursor, or below
+    /// it on the first row, with the active parameter highlighted.
+    fn draw_signature(&self, buffer: &mut RenderBuffer, signature: &Signature) {
+        let style = &self.theme.popup_style;
+        let label = &signature.label;
+        let active = signature.active.clone().unwrap_or(0..0);
+        let width = (label.chars().count() + 2).min(self.vwidth());
+        let (col, row) = self.cursor_cell();
+        let x = (self.vx + col).min(self.vwidth() - width);
+        let y = match row {
+            0 => 1,
+            row => row - 1,
+        };
+        let mut float = Float::new(x, self.vy + y, width, 1, Layer::Popup, style.clone());
+        float.rows = vec![vec![
+            (format!(" {}", &label[..active.start]), style.clone()),
+            (
+                label[active.clone()].to_string(),
+                self.theme.popup_selected_style.clone(),
+            ),
+            (format!("{} ", &label[active.end..]), style.clone()),
+        ]];
+        buffer.add_float(float);
+    }
+
+    /// Draws a float anchored below `x` on viewport row `y`, or above it when
+    /// there is no room below.
+    fn draw_float_at(
+        &self,
+        buffer: &mut RenderBuffer,
+        x: usize,
+        y: usize,
+        lines: &[(String, Style)],
+    ) {
+        let width = lines
+            .iter()
+            .map(|(l, _)| l.chars().count() + 2)
+            .max()
+            .unwrap_or_default()
+            .min(self.vwidth());
+        let height = lines.len().min(self.vheight());
+        let y = if y + 1 + height <= self.vheight() {
+            y + 1
+        } else {
+            y.saturating_sub(height)
+        };
+        let x = x.min(self.vwidth() - width);
+        let y = self.vy + y;
+        buffer.add_float(Float::lines(x, y, width, Layer::Popup, &lines[..height]));
+    }
+
+    /// Draws the popup and the completion menu over the viewport.
+    fn draw_floats(&mut self, buffer: &mut RenderBuffer) {
+        if let Some(lines) = &self.popup {
+            let lines: Vec<_> = lines
+                .iter()
+                .skip(self.popup_scroll)
+                .take(self.popup_height())
+                .map(|l| (l.clone(), self.theme.popup_style.clone()))
+                .collect();
+            let (col, row) = self.cursor_cell();
+            self.draw_float_at(buffer, self.vx + col, row, &lines);
+        }
+
+        if let Some(menu) = &self.completion {
+            let lines: Vec<_> = menu
+                .labels()
+                .enumerate()
+                .skip(menu.selected.saturating_sub(COMPLETION_MENU_HEIGHT - 1))
+                .take(COMPLETION_MENU_HEIGHT)
+                .map(|(i, label)| {
+                    let style = if i == menu.selected {
+                        &self.theme.popup_selected_style
+                    } else {
+                        &self.theme.popup_style
+                    };
+                    (label.to_string(), style.clone())
+                })
+                .collect();
+            let (col, row) = self.cursor_cell();
+            let x = self.vx + col.saturating_sub(self.cx.saturating_sub(menu.start));
+            self.draw_float_at(buffer, x, row, &lines);
+        }
+
+        if let Some(signature) = &self.signature {
+            self.draw_signature(buffer, signature);
+        }
+
+        if self.previewing() {
+            self.draw_preview(buffer);
+        }
+
+        if self.diff.is_some() {
+            self.draw_diff(buffer);
+        }
+
+        if self.status.is_some() {
+            self.draw_status(buffer);
+        }
+
+        if self.show_make {
+            self.draw_make(buffer);
+        }
+
+        if self.picker.is_some() {
+            self.draw_picker(buffer);
+        }
+
+        if matches!(self.mode, Mode::Terminal) {
+            self.draw_terminal(buffer);
+        }
+
+        if let Some(profile) = &self.profile {
+            let lines: Vec<_> = profile
+                .lines()
+                .into_iter()
+                .map(|line| (line, self.theme.popup_style.clone()))
+                .collect();
+            let width = lines[0].0.chars().count() + 2;
+            let x = self.vwidth().saturating_sub(width);
+            buffer.add_float(Float::lines(x, self.vy, width, Layer::Popup, &lines));
+        }
+
+        buffer.draw_floats();
+    }
+
+    /// Draws the output panel of `:make` along the bottom of the viewport,
+    /// following the end of the output.
+    fn draw_make(&self, buffer: &mut RenderBuffer) {
+        let Some(make) = &self.make else {
+            return;
+        };
+        let height = MAKE_PANEL_HEIGHT.min(self.vheight());
+        let status = match make.status {
+            None => "running…".to_string(),
+            Some(Some(code)) => format!("exit {code}"),
+            Some(None) => "killed".to_string(),
+        };
+        let title = format!("make: {}", make.command);
+        let gap = self
+            .vwidth()
+            .saturating_sub(title.chars().count() + status.chars().count() + 2);
+        let mut lines = vec![(
+            format!("{title}{}{status}", " ".repeat(gap)),
+            self.theme.popup_selected_style.clone(),
+        )];
+        let skip = make.lines.len().saturating_sub(height - 1);
+        lines.extend(
+            make.lines[skip..]
+                .iter()
+                .map(|line| (line.clone(), self.theme.popup_style.clone())),
+        );
+        lines.resize(height, (String::new(), self.theme.popup_style.clone()));
+        let y = self.vy + self.vheight() - height;
+        buffer.add_float(Float::lines(0, y, self.vwidth(), Layer::Panel, &lines));
+    }
+
+    fn previewing(&self) -> bool {
+        self.show_preview && self.buffer.filetype == "markdown"
+    }
+
+    /// The column the text ends before, where the preview starts when it
+    /// shows.
+    fn text_right(&self) -> usize {
+        let right = match self.previewing() {
+            true => self.preview_area().0,
+            false => self.vwidth(),
+        };
+        match self.zen {
+            true => right.min(self.vx + self.config.zen_width),
+            false => right,
+        }
+    }
+
+    /// The preview's box as `(x, y, width, height)`, the right half of the
+    /// viewport.
+    fn preview_area(&self) -> (usize, usize, usize, usize) {
+        let width = self.vwidth() / 2;
+        (self.vwidth() - width, self.vy, width, self.vheight())
+    }
+
+    /// Draws the rendered Markdown of the buffer next to it, from the line
+    /// at the top of the viewport on. It's rendered again each time, so it
+    /// follows every edit.
+    fn draw_preview(&self, buffer: &mut RenderBuffer) {
+        let (x, y, width, height) = self.preview_area();
+        let rows = markdown::render(&self.buffer.lines, width.saturating_sub(2).max(1));
+        let first = rows.partition_point(|(line, _)| *line < self.vtop);
+        let border = Style {
+            fg: self.theme.gutter_style.fg.or(self.theme.style.fg),
+            ..self.theme.style.clone()
+        };
+        let mut float = Float::new(x, y, width, height, Layer::Panel, self.theme.style.clone());
+        float.rows = (0..height)
+            .map(|row| {
+                let line = rows.get(first + row).map_or(&[][..], |(_, l)| l);
+                let text = line
+                    .iter()
+                    .map(|(text, kind)| (text.clone(), self.markdown_style(*kind)));
+                std::iter::once(("│ ".to_string(), border.clone()))
+                    .chain(text)
+                    .collect()
+            })
+            .collect();
+        buffer.add_float(float);
+    }
+
+    /// Opens the diff of the buffer against `text`, the other version, with
+    /// the cursor on the buffer's line.
+    fn open_diff(&mut self, title: String, text: &str) {
+        let old = text.lines().map(str::to_string).collect();
+        let mut diff = DiffView::new(title, old, self.buffer.lines.clone());
+        if diff.hunks().is_empty() {
+            self.notify(Level::Info, "no differences");
+            return;
+        }
+        let line = self.buffer_line();
+        let row = diff.rows.iter().position(|(_, new)| *new == Some(line));
+        diff.move_to(row.unwrap_or_default(), self.diff_height());
+        self.diff = Some(diff);
+    }
+
+    /// How many rows of the diff fit below its titles.
+    fn diff_height(&self) -> usize {
+        self.vheight().saturating_sub(1).max(1)
+    }
+
+    /// How many columns the other version takes on the left of the diff,
+    /// and the line numbers on each side.
+    fn diff_columns(&self, diff: &DiffView) -> (usize, usize) {
+        let numbers = diff.old.len().max(diff.new.len()).to_string().len();
+        (self.vwidth().saturating_sub(1) / 2, numbers)
+    }
+
+    /// Where the cursor goes while the diff shows: at the start of the
+    /// buffer's text on its row.
+    fn diff_cursor(&self) -> (usize, usize) {
+        let Some(diff) = &self.diff else {
+            return (self.vx, self.vy);
+        };
+        let (half, numbers) = self.diff_columns(diff);
+        (
+            half + 1 + numbers + 2,
+            self.vy + 1 + diff.row.saturating_sub(diff.top),
+        )
+    }
+
+    /// Draws the diff over the viewport: the other version on the left and
+    /// the buffer on the right, each line across from its counterpart and
+    /// the part of a changed line that differs highlighted.
+    fn draw_diff(&self, buffer: &mut RenderBuffer) {
+        let Some(diff) = &self.diff else {
+            return;
+        };
+        let (width, height) = (self.vwidth(), self.vheight());
+        let (half, numbers) = self.diff_columns(diff);
+        let right = width.saturating_sub(half + 1);
+        let title = &self.theme.popup_selected_style;
+        let name = self
+            .buffer
+            .file
+            .as_deref()
+            .map_or("[No Name]".to_string(), display_path);
+        let border = Style {
+            fg: self.theme.gutter_style.fg.or(self.theme.style.fg),
+            ..self.theme.style.clone()
+        };
+
+        let mut float = Float::new(
+            0,
+            self.vy,
+            width,
+            height,
+            Layer::Panel,
+            self.theme.style.clone(),
+        );
+        float.rows.push(vec![
+            (fit(&format!(" {}", diff.title), half), title.clone()),
+            ("│".to_string(), border.clone()),
+            (format!(" {name}"), title.clone()),
+        ]);
+        let end = (diff.top + self.diff_height()).min(diff.rows.len());
+        for row in diff.top..end {
+            let (old, new) = diff.rows[row];
+            let old = old.map(|n| (n, diff.old[n].as_str()));
+            let new = new.map(|n| (n, diff.new[n].as_str()));
+            let (kind, spans) = match (old, new) {
+                (Some((_, a)), Some((_, b))) if a != b => {
+                    (Some(HunkKind::Modified), diffview::changed_bytes(a, b))
+                }
+                (Some(_), Some(_)) => (None, (0..0, 0..0)),
+                (None, _) => (Some(HunkKind::Added), (0..0, 0..0)),
+                (_, None) => (Some(HunkKind::Deleted), (0..0, 0..0)),
+            };
+            let mut pieces = self.diff_side(old, kind, spans.0, numbers, half);
+            pieces.push(("│".to_string(), border.clone()));
+            pieces.extend(self.diff_side(new, kind, spans.1, numbers, right));
+            float.rows.push(pieces);
+        }
+        buffer.add_float(float);
+    }
+
+    /// One side of a row of the diff, `width` columns wide: the line's
+    /// number and text, or a gap across from a line only the other side
+    /// has. Added and deleted lines take the colors of their git signs, and
+    /// the `changed` bytes of a modified line are highlighted.
+    fn diff_side(
+        &self,
+        line: Option<(usize, &str)>,
+        kind: Option<HunkKind>,
+        changed: Range<usize>,
+        numbers: usize,
+        width: usize,
+    ) -> Vec<(String, Style)> {
+        let gutter = Style {
+            fg: self.theme.gutter_style.fg.or(self.theme.style.fg),
+            bg: self.theme.gutter_style.bg.or(self.theme.style.bg),
+            ..Default::default()
+        };
+        let room = width.saturating_sub(numbers + 2);
+        let Some((n, text)) = line else {
+            return vec![
+                (" ".repeat(numbers + 2), gutter.clone()),
+                (
+                    "╱".repeat(room),
+                    Style {
+                        bg: self.theme.style.bg,
+                        ..gutter
+                    },
+                ),
+            ];
+        };
+        let sign = match kind {
+            Some(kind) => Style {
+                fg: self.theme.git_style(kind).fg,
+                ..gutter.clone()
+            },
+            None => gutter.clone(),
+        };
+        let base = match kind {
+            Some(kind @ (HunkKind::Added | HunkKind::Deleted)) => self.theme.git_style(kind),
+            _ => self.theme.style.clone(),
+        };
+        let highlight = Style {
+            fg: self.theme.style.bg,
+            bg: Some(self.theme.git_styles.modified),
+            ..self.theme.style.clone()
+        };
+        let mut pieces = vec![
+            (format!("{:>numbers$}", n + 1), gutter),
+            (if kind.is_some() { "▎ " } else { "  " }.to_string(), sign),
+        ];
+        let mut cols = 0;
+        for (i, c) in text.char_indices().take(room) {
+            let style = match changed.contains(&i) {
+                true => &highlight,
+                false => &base,
+            };
+            let c = if c == '\t' { ' ' } else { c };
+            match pieces.last_mut() {
+                Some((piece, s)) if s == style && cols > 0 => piece.push(c),
+                _ => pieces.push((c.to_string(), style.clone())),
+            }
+            cols += 1;
+        }
+        pieces.push((" ".repeat(room - cols), self.theme.style.clone()));
+        pieces
+    }
+
+    /// The rows of the status panel and the first one shown, which keeps the
+    /// selected file in view.
+    fn status_lines(&self) -> (Vec<(String, Option<usize>)>, usize) {
+        let Some(status) = &self.status else {
+            return (vec![], 0);
+        };
+        let lines = status.lines();
+        let row = lines
+            .iter()
+            .position(|(_, i)| *i == Some(status.selected))
+            .unwrap_or_default();
+        let top = (row + 1).saturating_sub(self.vheight());
+        (lines, top)
+    }
+
+    /// Draws the status panel over the viewport: the branch, then the files
+    /// with changes to stage and the staged ones, their letters in the git
+    /// sign colors.
+    fn draw_status(&self, buffer: &mut RenderBuffer) {
+        let Some(status) = &self.status else {
+            return;
+        };
+        let (lines, top) = self.status_lines();
+        let heading = Style {
+            bold: true,
+            ..self.theme.style.clone()
+        };
+        let mut float = Float::new(
+            0,
+            self.vy,
+            self.vwidth(),
+            self.vheight(),
+            Layer::Panel,
+            self.theme.style.clone(),
+        );
+        float.rows = lines
+            .iter()
+            .skip(top)
+            .take(self.vheight())
+            .map(|(text, file)| match file {
+                Some(i) if *i == status.selected => {
+                    vec![(format!(" {text}"), self.theme.popup_selected_style.clone())]
+                }
+                Some(i) => {
+                    let kind = match status.files[*i].code {
+                        'A' | '?' => HunkKind::Added,
+                        'D' => HunkKind::Deleted,
+                        _ => HunkKind::Modified,
+                    };
+                    let (code, path) = text.trim_start().split_at(1);
+                    vec![
+                        ("   ".to_string(), self.theme.style.clone()),
+                        (code.to_string(), self.theme.git_style(kind)),
+                        (path.to_string(), self.theme.style.clone()),
+                    ]
+                }
+                None => vec![(format!(" {text}"), heading.clone())],
+            })
+            .collect();
+        buffer.add_float(float);
+    }
+
+    /// The theme's style for a kind of rendered Markdown, from the first of
+    /// the usual scopes for it the theme has. Headings and strong text are
+    /// bold and emphasis italic either way.
+    fn markdown_style(&self, kind: markdown::Kind) -> Style {
+        use markdown::Kind;
+        let scopes: &[&str] = match kind {
+            Kind::Text => &[],
+            Kind::Heading => &[
+                "markup.heading",
+                "markup.heading.markdown",
+                "entity.name.section",
+            ],
+            Kind::Emphasis => &["markup.italic", "markup.italic.markdown"],
+            Kind::Strong => &["markup.bold", "markup.bold.markdown"],
+            Kind::Code => &[
+                "markup.inline.raw",
+                "markup.inline.raw.string.markdown",
+                "markup.raw.block.markdown",
+                "string",
+            ],
+            Kind::Bullet => &["markup.list.bullet", "markup.list.unnumbered.markdown"],
+            Kind::Quote => &["markup.quote", "comment"],
+            Kind::Link => &["markup.underline.link", "markup.link"],
+            Kind::Rule => &["comment"],
+        };
+        let style = scopes
+            .iter()
+            .find_map(|scope| self.theme.get_style(scope))
+            .unwrap_or_else(|| self.theme.style.clone());
+        Style {
+            bg: style.bg.or(self.theme.style.bg),
+            bold: style.bold || matches!(kind, Kind::Heading | Kind::Strong),
+            italic: style.italic || kind == Kind::Emphasis,
+            ..style
+        }
+    }
+
+    /// The terminal's box as `(x, y, width, height)`, centered on the viewport.
+    fn terminal_area(&self) -> (usize, usize, usize, usize) {
+        let width = (self.vwidth() * 9 / 10).max(20).min(self.vwidth());
+        let height = (self.vheight() * 4 / 5).max(3).min(self.vheight());
+        let x = (self.vwidth() - width) / 2;
+        let y = self.vy + (self.vheight() - height) / 2;
+        (x, y, width, height)
+    }
+
+    /// Draws the terminal's screen, using the popup colors where the
+    /// program running in it didn't set any.
+    fn draw_terminal(&self, buffer: &mut RenderBuffer) {
+        let Some(terminal) = &self.terminal else {
+            return;
+        };
+        let (x, y, width, height) = self.terminal_area();
+        let base = &self.theme.popup_style;
+
+        let mut float = Float::new(x, y, width, height, Layer::Dialog, base.clone());
+        float.rows = (0..height.min(terminal.screen.rows))
+            .map(|row| {
+                let cells = terminal.screen.line(row).iter().map(|(c, style)| {
+                    let style = Style {
+                        fg: style.fg.or(base.fg),
+                        bg: style.bg.or(base.bg),
+                        ..style.clone()
+                    };
+                    (c.to_string(), style)
+                });
+                std::iter::once((" ".to_string(), base.clone()))
+                    .chain(cells)
+                    .collect()
+            })
+            .collect();
+        buffer.add_float(float);
+    }
+
+    /// The picker's box as `(x, y, width, height)`, centered on the viewport.
+    fn picker_area(&self) -> (usize, usize, usize, usize) {
+        let width = (self.vwidth() * 4 / 5).max(20).min(self.vwidth());
+        let height = (self.vheight() * 3 / 5).max(5).min(self.vheight());
+        let x = (self.vwidth() - width) / 2;
+        let y = self.vy + (self.vheight() - height) / 2;
+        (x, y, width, height)
+    }
+
+    /// Draws the picker: a prompt with the query and match count, followed
+    /// by the matches scrolled to keep the selection visible.
+    fn draw_picker(&self, buffer: &mut RenderBuffer) {
+        let Some(picker) = &self.picker else {
+            return;
+        };
+        let (x, y, width, height) = self.picker_area();
+        // inside the border, below the prompt
+        let (inner_width, inner_height) = (width.saturating_sub(2), height.saturating_sub(2));
+        let rows = inner_height.saturating_sub(1);
+
+        let prompt = format!("{}> {}", picker.title, picker.query);
+        let loading = if picker.is_loading() { "…" } else { "" };
+        let count = format!("{}/{}{loading}", picker.match_count(), picker.item_count());
+        let gap = inner_width.saturating_sub(prompt.chars().count() + count.chars().count() + 2);
+        let mut lines = vec![(
+            format!("{prompt}{}{count}", " ".repeat(gap)),
+            self.theme.popup_style.clone(),
+        )];
+
+        let skip = picker.selected.saturating_sub(rows.saturating_sub(1));
+        for (i, item) in picker.matches().enumerate().skip(skip).take(rows) {
+            let style = if i == picker.selected {
+                &self.theme.popup_selected_style
+            } else {
+                &self.theme.popup_style
+            };
+            lines.push((item.to_string(), style.clone()));
+        }
+        lines.resize(
+            inner_height,
+            (String::new(), self.theme.popup_style.clone()),
+        );
+
+        let mut float = Float::lines(x, y, width, Layer::Dialog, &lines).with_border();
+        float.height = height;
+        float.style = self.theme.popup_style.clone();
+        buffer.add_float(float);
+    }
+
+    fn cursor_position(&self) -> (u16, u16) {
+        match self.mode {
+            Mode::Command => ((self.command.len() + 1) as u16, self.size.1 - 1),
+            Mode::Picker => {
+                let (x, y, _, _) = self.picker_area();
+                let prompt = self
+                    .picker
+                    .as_ref()
+                    .map_or(0, |p| p.title.chars().count() + 2 + p.query.chars().count());
+                // the prompt is inside the border
+                ((x + 2 + prompt) as u16, (y + 1) as u16)
+            }
+            Mode::Terminal => {
+                let (x, y, _, _) = self.terminal_area();
+                let (col, row) = self.terminal.as_ref().map_or((0, 0), |t| t.screen.cursor);
+                ((x + 1 + col) as u16, (y + row) as u16)
+            }
+            // on the buffer's side of the diff
+            _ if self.diff.is_some() => {
+                let (x, y) = self.diff_cursor();
+                (x as u16, y as u16)
+            }
+            _ if self.status.is_some() => {
+                let (lines, top) = self.status_lines();
+                let selected = self.status.as_ref().map(|status| status.selected);
+                let row = lines.iter().position(|(_, i)| *i == selected);
+                (
+                    1,
+                    (self.vy + row.unwrap_or_default().saturating_sub(top)) as u16,
+                )
+            }
+            _ => {
+                let (col, row) = self.cursor_cell();
+                ((self.vx + col) as u16, (self.vy + row) as u16)
+            }
+        }
+    }
+
+    fn draw_commandline(&mut self, buffer: &mut RenderBuffer) {
+        let y = self.size.1 as usize - 1;
+        let (text, style) = match (self.mode, self.messages.current()) {
+            (Mode::Command, _) => (format!(":{}", self.command), self.theme.style.clone()),
+            (_, Some(message)) => {
+                let style = match message.level {
+                    Level::Info => self.theme.style.clone(),
+                    Level::Warning => self.theme.diagnostic_style(Severity::Warning),
+                    Level::Error => self.theme.diagnostic_style(Severity::Error),
+                };
+                (message.text.clone(), style)
+            }
+            _ => (String::new(), self.theme.style.clone()),
+        };
+        let text: String = text.chars().take(self.vwidth()).collect();
+
+        buffer.set_text(0, y, &text, &style);
+        self.fill_line(buffer, text.chars().count(), y, &style);
+    }
+
+    pub fn draw_statusline(&mut self, buffer: &mut RenderBuffer) {
+        if self.zen {
+            return;
+        }
+        let mode = match self.mode {
+            Mode::VisualBlock => "VISUAL BLOCK".to_string(),
+            mode => format!("{mode:?}").to_uppercase(),
+        };
+        let plugins = self.plugins.status();
+        let cx = statusline::Context {
+            mode: &mode,
+            file: self.buffer.file.as_deref(),
+            modified: self.buffer.modified,
+            readonly: self.buffer.readonly,
+            filetype: &self.buffer.filetype,
+            encoding: self.buffer.encoding.name(),
+            fileformat: self.buffer.fileformat.name(),
+            position: (self.cx + 1, self.buffer_line() + 1),
+            lines: self.buffer.len(),
+            branch: self.branch.as_deref(),
+            plugins: plugins.as_deref(),
+        };
+        let layout = statusline::layout(&self.config.statusline, &cx, self.size.0 as usize);
+        let y = self.size.1 as usize - 2;
+
+        let style = &self.theme.statusline_style;
+        let transition_style = Style {
+            fg: style.outer_style.bg,
+            bg: style.inner_style.bg,
+            ..Default::default()
+        };
+
+        let mut x = 0;
+        if !layout.left.is_empty() {
+            buffer.set_text(x, y, &layout.left, &style.outer_style);
+            x += layout.left.chars().count();
+            buffer.set_char(x, y, style.outer_chars[1], &transition_style);
+            x += 1;
+        }
+
+        buffer.set_text(x, y, &layout.middle, &style.inner_style);
+        x += layout.middle.chars().count();
+
+        if !layout.right.is_empty() {
+            buffer.set_char(x, y, style.outer_chars[2], &transition_style);
+            buffer.set_text(x + 1, y, &layout.right, &style.outer_style);
+        }
+    }
+
+    fn is_insert(&self) -> bool {
+        matches!(self.mode, Mode::Insert)
+    }
+
+    /// Whether typed characters go into the buffer, in insert and replace
+    /// mode, which also lets the cursor go past the end of the line.
+    fn is_typing(&self) -> bool {
+        matches!(self.mode, Mode::Insert | Mode::Replace)
+    }
+
+    fn check_bounds(&mut self) {
+        let line_length = self.line_length();
+
+        if self.cx >= line_length && !self.is_typing() {
+            if line_length > 0 {
+                self.cx = self.line_length() - 1;
+            } else {
+                self.cx = 0;
+            }
+        }
+
+        let rows = self.viewport_lines().len();
+        if self.cy >= rows {
+            self.cy = rows.saturating_sub(1);
+        }
+    }
+
+    /// Queues the changed cells. The cursor only moves to cells that don't
+    /// follow the last one written, and colors are only set when they change.
+    fn render_diff(&mut self, change_set: Vec<Change>) -> anyhow::Result<()> {
+        let mut next = None;
+        let mut current_style: Option<&Style> = None;
+        for change in change_set {
+            let x = change.x;
+            let y = change.y;
+            let cell = change.cell;
+
+            if next != Some((x, y)) {
+                self.stdout.queue(MoveTo(x as u16, y as u16))?;
+            }
+            if current_style != Some(&cell.style) {
+                if let Some(bg) = cell.style.bg {
+                    self.stdout.queue(style::SetBackgroundColor(bg))?;
+                }
+                if let Some(fg) = cell.style.fg {
+                    self.stdout.queue(style::SetForegroundColor(fg))?;
+                }
+                current_style = Some(&cell.style);
+            }
+            self.stdout.queue(style::Print(cell.c))?;
+            next = Some((x + 1, y));
+        }
+
+        Ok(())
+    }
+
+    // Draw the current render buffer to the terminal
+    fn render(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        self.draw_viewport(buffer)?;
+        self.draw_gutter(buffer);
+        self.draw_statusline(buffer);
+        self.draw_commandline(buffer);
+        self.draw_floats(buffer);
+
+        // every cell is written over instead of clearing the screen first,
+        // which would flicker
+        buffer.invalidate();
+        self.write_frame(buffer)?;
+        Ok(())
+    }
+
+    /// Writes the cells changed since the last frame and places the cursor,
+    /// as a single synchronized update: terminals that support it show the
+    /// whole frame at once rather than as it comes in, and the others ignore
+    /// it. Returns how many cells were written.
+    fn write_frame(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<usize> {
+        self.stdout
+            .queue(terminal::BeginSynchronizedUpdate)?
+            .queue(Hide)?;
+        let changes = buffer.changes();
+        let cells = changes.len();
+        self.render_diff(changes)?;
+        buffer.present();
+        self.stdout.queue(style::ResetColor)?;
+
+        self.set_cursor_style()?;
+        self.update_title()?;
+        let (x, y) = self.cursor_position();
+        self.stdout
+            .queue(MoveTo(x, y))?
+            .queue(Show)?
+            .queue(terminal::EndSynchronizedUpdate)?
+            .flush()?;
+
+        Ok(cells)
+    }
+
+    /// Puts the terminal in raw mode on the alternate screen, taking the
+    /// mouse if the config says so.
+    fn enter_terminal(&mut self) -> anyhow::Result<()> {
+        self.in_terminal = true;
+        terminal::enable_raw_mode()?;
+        self.stdout
+            .execute(cursor::SavePosition)?
+            .execute(terminal::EnterAlternateScreen)?
+            .execute(terminal::Clear(terminal::ClearType::All))?
+            .execute(EnableBracketedPaste)?
+            .execute(EnableFocusChange)?;
+        if self.config.mouse {
+            self.stdout.execute(EnableMouseCapture)?;
+        }
+        if self.config.title {
+            // keeps the terminal's title on its stack, to put back on leaving
+            self.stdout.execute(style::Print("\x1b[22;0t"))?;
+        }
+        Ok(())
+    }
+
+    /// Hands the terminal back the way `enter_terminal` found it, with the
+    /// cursor where the shell left it, in its own shape.
+    fn leave_terminal(&mut self) {
+        _ = self.stdout.flush();
+        _ = self.stdout.execute(DisableMouseCapture);
+        _ = self.stdout.execute(DisableBracketedPaste);
+        _ = self.stdout.execute(DisableFocusChange);
+        _ = self.stdout.execute(terminal::LeaveAlternateScreen);
+        _ = self.stdout.execute(cursor::RestorePosition);
+        if self.config.cursor.enabled {
+            _ = self
+                .stdout
+                .execute(cursor::SetCursorStyle::DefaultUserShape);
+        }
+        _ = self.stdout.execute(Show);
+        if self.config.title {
+            _ = self.stdout.execute(style::Print("\x1b[23;0t"));
+            self.title = None;
+        }
+        _ = terminal::disable_raw_mode();
+    }
+
+    /// The window title: the file, flagged when modified, and the editor.
+    fn window_title(&self) -> String {
+        let name = self
+            .buffer
+            .file
+            .as_deref()
+            .map_or("[No Name]".to_string(), display_path);
+        let modified = if self.buffer.modified { " [+]" } else { "" };
+        format!("{name}{modified} — rustik")
+    }
+
+    /// Sets the window title when it changed since the last frame, as the
+    /// file was switched or modified.
+    fn update_title(&mut self) -> anyhow::Result<()> {
+        if !self.config.title {
+            return Ok(());
+        }
+        let title = self.window_title();
+        if self.title.as_ref() != Some(&title) {
+            self.stdout.queue(terminal::SetTitle(&title))?;
+            self.title = Some(title);
+        }
+        Ok(())
+    }
+
+    /// Draws everything again at a new terminal size.
+    fn resize(&mut self, size: (u16, u16), buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        self.size = size;
+        self.vx = self.text_left();
+        *buffer = RenderBuffer::new(
+            self.size.0 as usize,
+            self.size.1 as usize,
+            self.theme.style.clone(),
+        );
+        self.render(buffer)
+    }
+
+    /// `Ctrl-z`: hands the terminal back to the shell and stops until it's
+    /// brought to the foreground again.
+    fn suspend(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        self.leave_terminal();
+        signals::suspend()?;
+        self.resume(buffer)?;
+        // continuing raised SIGCONT, which was just handled
+        if let Some(signals) = &self.signals {
+            signals.pending();
+        }
+        Ok(())
+    }
+
+    /// Takes the terminal back after being stopped, drawing everything again
+    /// as the screen and its size may have changed in the meantime.
+    fn resume(&mut self, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        self.enter_terminal()?;
+        self.resize(terminal::size()?, buffer)
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        self.enter_terminal()?;
+        match Signals::register() {
+            Ok(signals) => self.signals = Some(signals),
+            Err(err) => log!(Warn, "signals: {err}"),
+        }
+
+        let mut buffer = RenderBuffer::new(
+            self.size.0 as usize,
+            self.size.1 as usize,
+            self.theme.style.clone(),
+        );
+
+        self.branch = self.buffer.file.as_deref().and_then(git::branch);
+        self.start_lsp();
+        self.start_lint();
+        self.check_swap();
+        self.load_undo();
+        self.render(&mut buffer)?;
+
+        loop {
+            self.check_bounds();
+            self.update_swap();
+            self.start_git_diff();
+            self.start_blame();
+
+            let ev = match self.poll_interval() {
+                Some(interval) if !event::poll(interval)? => None,
+                _ => Some(read()?),
+            };
+            let received = Instant::now();
+
+            let signals = self.signals.as_ref().map(Signals::pending);
+            for signal in signals.unwrap_or_default() {
+                match signal {
+                    Signal::Suspend => self.suspend(&mut buffer)?,
+                    Signal::Resume => self.resume(&mut buffer)?,
+                    Signal::Resize => {
+                        let size = terminal::size()?;
+                        if size != self.size {
+                            self.resize(size, &mut buffer)?;
+                        }
+                    }
+                }
+            }
+
+            let highlighted = self.receive_highlights();
+            let linted = self.receive_lint();
+            let diffed = self.receive_git_diff();
+            let blamed = self.receive_blame();
+            let made = self.receive_make();
+            let plugged = self.receive_plugins(&mut buffer);
+            let loaded = self.buffer.receive_lines();
+            let timed_out = match ev {
+                None => self.expire_pending_keys(),
+                Some(_) => None,
+            };
+            let config_changed = self.config_changed();
+            let file_changed = self.file_changed();
+            let autosave = ev.is_none() && self.autosave_remaining() == Some(Duration::ZERO);
+            let picked = self.picker.as_mut().is_some_and(|p| p.receive());
+            let shell_output = self.receive_terminal();
+            let expired = self.messages.expire();
+            let unflashed = self.expire_flash();
+            let lsp_events = self.lsp.as_mut().map(|lsp| lsp.poll()).unwrap_or_default();
+            if ev.is_none()
+                && timed_out.is_none()
+                && !config_changed
+                && !file_changed
+                && !autosave
+                && !highlighted
+                && !linted
+                && !diffed
+                && !blamed
+                && !made
+                && !plugged
+                && !loaded
+                && !picked
+                && !shell_output
+                && !expired
+                && !unflashed
+                && lsp_events.is_empty()
+            {
+                continue;
+            }
+
+            // the size may have been taken from SIGWINCH already
+            if let Some(event::Event::Resize(width, height)) = ev {
+                if (width, height) != self.size {
+                    self.resize((width, height), &mut buffer)?;
+                }
+                continue;
+            }
+
+            // the events already queued are handled together and drawn as
+            // one frame, so holding a key or replaying a macro doesn't draw
+            // a frame per key. A resize ends the batch, as it redraws
+            // everything.
+            let mut queued = vec![];
+            let mut resized = false;
+            if ev.is_some() {
+                while !resized && queued.len() < MAX_EVENT_BATCH && event::poll(Duration::ZERO)? {
+                    let next = read()?;
+                    resized = matches!(next, Event::Resize(..));
+                    queued.push(next);
+                }
+            }
+
+            if loaded {
+                // more lines can mean a wider gutter
+                self.vx = self.text_left();
+            }
+            let frame = Instant::now();
+            let was_visual = self.is_visual();
+            let cursor_row = self.cy;
+            let had_floats = self.show_make
+                || self.diff.is_some()
+                || self.status.is_some()
+                || self.popup.is_some()
+                || self.completion.is_some()
+                || self.signature.is_some()
+                || self.picker.is_some()
+                || matches!(self.mode, Mode::Terminal);
+            let batch: Vec<_> = match ev {
+                Some(ev) => std::iter::once(ev).chain(queued).map(Some).collect(),
+                None => vec![None],
+            };
+            let mut quit = false;
+            let mut timed_out = timed_out;
+            for ev in batch {
+                let key = matches!(ev, Some(Event::Key(_)));
+                let action = match ev {
+                    Some(ev) => self.handle_event(ev),
+                    None => timed_out.take(),
+                };
+                // any key but the ones scrolling it dismisses the popup
+                let scrolls_popup = matches!(
+                    action,
+                    Some(KeyAction::Single(
+                        Action::PopupScrollDown(_) | Action::PopupScrollUp(_)
+                    ))
+                );
+                if key && !scrolls_popup {
+                    self.popup = None;
+                }
+                if let Some(action) = action {
+                    match self.execute_key_action(action, &mut buffer) {
+                        Ok(true) => quit = true,
+                        Ok(false) => {}
+                        Err(err) => self.notify(Level::Error, err.to_string()),
+                    }
+                }
+                if quit {
+                    break;
+                }
+                self.check_bounds();
+            }
+            if quit {
+                break;
+            }
+            let wrapped = self.scroll_to_wrapped_cursor();
+            self.check_bounds();
+            let scrolled = self.apply_sidescrolloff() || wrapped;
+
+            if config_changed {
+                if let Err(err) = self.reload_config(&mut buffer) {
+                    self.notify(Level::Error, format!("{err:#}"));
+                }
+            }
+
+            if autosave {
+                if let Err(err) = self.save(&mut buffer, true) {
+                    self.notify(Level::Error, format!("{err:#}"));
+                }
+            }
+
+            if file_changed {
+                if let Err(err) = self.handle_file_change(&mut buffer) {
+                    self.notify(Level::Error, format!("{err:#}"));
+                }
+            }
+
+            for event in lsp_events {
+                if let Err(err) = self.handle_lsp_event(event, &mut buffer) {
+                    self.notify(Level::Error, err.to_string());
+                }
+            }
+            self.update_completion();
+
+            if resized {
+                self.resize(self.size, &mut buffer)?;
+                continue;
+            }
+
+            // floats are drawn over the viewport, so it's redrawn to clear them
+            let rendering = Instant::now();
+            let selecting = was_visual || self.is_visual();
+            if highlighted
+                || linted
+                || diffed
+                || blamed
+                || made
+                || plugged
+                || loaded
+                || scrolled
+                || had_floats
+                || shell_output
+                || selecting
+                || unflashed
+            {
+                self.draw_viewport(&mut buffer)?;
+            } else if self.cy != cursor_row
+                && self.config.line_numbers == LineNumbers::RelativeNumber
+            {
+                self.draw_gutter(&mut buffer);
+            }
+            self.update_bracket_match(&mut buffer);
+            self.draw_floats(&mut buffer);
+
+            self.draw_tabline(&mut buffer);
+            self.draw_statusline(&mut buffer);
+            self.draw_commandline(&mut buffer);
+            let cells = self.write_frame(&mut buffer)?;
+            log!(Trace, "frame drawn in {:?}", frame.elapsed());
+            if let Some(profile) = &mut self.profile {
+                profile.record(profile::Frame {
+                    render: rendering.elapsed(),
+                    latency: received.elapsed(),
+                    cells,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `actions` on the buffer without drawing anything, for
+    /// `--headless`, then writes it if they changed it. An action that fails
+    /// or warns stops the script and the file is left as it was, and one
+    /// that quits ends it early.
+    pub fn run_script(&mut self, actions: Vec<Action>) -> anyhow::Result<()> {
+        let mut buffer =
+            RenderBuffer::new(self.size.0 as usize, self.size.1 as usize, Style::default());
+        for (i, action) in actions.into_iter().enumerate() {
+            self.messages.clear();
+            let quit = self
+                .execute_key_action(KeyAction::Single(action), &mut buffer)
+                .map_err(|err| anyhow::anyhow!("action {}: {err}", i + 1))?;
+            self.check_bounds();
+            if let Some(message) = self.messages.current() {
+                if message.level != Level::Info {
+                    anyhow::bail!("action {}: {}", i + 1, message.text);
+                }
+            }
+            if quit {
+                break;
+            }
+        }
+        if self.buffer.modified {
+            self.save(&mut buffer, false)?;
+        }
+        Ok(())
+    }
+
+    fn execute_key_action(
+        &mut self,
+        action: KeyAction,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<bool> {
+        let action = match self.count.take() {
+            Some(count) => apply_count(action, count),
+            None => action,
+        };
+        if let KeyAction::Nested(actions) = action {
+            self.waiting_key_action = Some(KeyAction::Nested(actions));
+            return Ok(false);
+        }
+
+        let mut actions = keymap::into_actions(action).into_iter().peekable();
+        while let Some(action) = actions.next() {
+            log!(Debug, "action {action:?}");
+            crash::record(&action);
+            if self.buffer.readonly && action.is_edit() {
+                self.pending_operator = None;
+                self.notify(
+                    Level::Warning,
+                    "Buffer is read-only (:set noreadonly to edit it)",
+                );
+                break;
+            }
+            if self.buffer.is_loading() && action.is_edit() {
+                self.pending_operator = None;
+                self.notify(Level::Warning, "The file is still loading");
+                break;
+            }
+            if let Some(operator) = self.pending_operator.take() {
+                if action.is_motion() {
+                    // a count repeats the motion: `d2}` spans two paragraphs
+                    let mut motions = vec![action];
+                    motions.extend(std::iter::from_fn(|| actions.next_if(Action::is_motion)));
+                    self.operate(operator, &motions, buffer)?;
+                    continue;
+                }
+                if let Action::WaitForChar(_) = action {
+                    // the character picks the motion, like the mark in `d'a`
+                    self.pending_operator = Some(operator);
+                }
+                // anything else cancels the operator
+            }
+            if self.execute(&action, buffer)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Applies an operator to the text between the cursor and where the
+    /// motions lead, up to but not including the target, or to all lines in
+    /// between for linewise motions.
+    fn operate(
+        &mut self,
+        operator: Operator,
+        motions: &[Action],
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let origin = (self.cx, self.buffer_line());
+        for motion in motions {
+            if let Some((find, c, repeat)) = self.resolve_find(motion) {
+                // like vim, a find that fails cancels the operator
+                if self.find_target(find, c, repeat).is_none() {
+                    return self.move_to(origin.0, origin.1, buffer);
+                }
+            }
+            self.execute(motion, buffer)?;
+        }
+        if self.buffer.len() == 0 {
+            self.move_to(origin.0, origin.1, buffer)?;
+            if let Operator::Change = operator {
+                self.execute(&Action::EnterMode(Mode::Insert), buffer)?;
+            }
+            return Ok(());
+        }
+        // motions move freely, `check_bounds` only runs after the key: `j` on
+        // the last line leaves the cursor below it
+        let line = self.buffer_line().min(self.buffer.len() - 1);
+        let target = (
+            self.cx.min(self.buffer.get(line).map_or(0, |l| l.len())),
+            line,
+        );
+        let relative = motions.iter().all(|m| {
+            matches!(
+                m,
+                Action::MoveUp | Action::MoveDown | Action::PageUp | Action::PageDown
+            )
+        });
+        if target == origin && relative {
+            // like vim, `dj` on the last line fails rather than taking it
+            return self.move_to(origin.0, origin.1, buffer);
+        }
+        let (start, end) = match (origin.1, origin.0) <= (target.1, target.0) {
+            true => (origin, target),
+            false => (target, origin),
+        };
+        if let Operator::Filter = operator {
+            // like vim, the command line starts with the lines' range
+            self.command = format!("{},{}!", start.1 + 1, end.1 + 1);
+            self.mode = Mode::Command;
+            return self.move_to(origin.0, origin.1, buffer);
+        }
+
+        let line_len = |line: usize| self.buffer.get(line).map_or(0, |l| l.len());
+        let linewise = motions.iter().any(Action::is_linewise);
+        let inclusive = motions.last().is_some_and(|m| self.is_inclusive(m));
+        let (start, end) = if !linewise {
+            let text = self.buffer.get(end.1).unwrap_or_default();
+            let width = match inclusive {
+                true => text.get(end.0..).and_then(|rest| rest.chars().next()),
+                false => None,
+            };
+            (start, (end.0 + width.map_or(0, char::len_utf8), end.1))
+        } else if !matches!(operator, Operator::Delete) {
+            // the lines are changed rather than removed
+            ((0, start.1), (line_len(end.1), end.1))
+        } else if end.1 + 1 < self.buffer.len() {
+            ((0, start.1), (0, end.1 + 1))
+        } else if start.1 > 0 {
+            (
+                (line_len(start.1 - 1), start.1 - 1),
+                (line_len(end.1), end.1),
+            )
+        } else {
+            ((0, 0), (line_len(end.1), end.1))
+        };
+        if start == end && !matches!(operator, Operator::Change) {
+            self.move_to(origin.0, origin.1, buffer)?;
+            return Ok(());
+        }
+        if let Operator::Surround = operator {
+            self.surround_range = Some((start, end));
+            self.waiting_char = Some(CharAction::Surround);
+            return self.move_to(origin.0, origin.1, buffer);
+        }
+        if let Operator::Case(case) = operator {
+            let undo = self.change_case(case, start, end);
+            self.undo_actions.extend(undo);
+            self.move_to(start.0, start.1, buffer)?;
+            return self.draw_viewport(buffer);
+        }
+
+        let text = self.buffer.remove_range(start, end);
+        let undo = Action::InsertTextAt(start.0, start.1, text);
+        let (x, line) = match linewise {
+            true => (0, start.1.min(self.buffer.len().saturating_sub(1))),
+            false => start,
+        };
+        self.move_to(x, line, buffer)?;
+        match operator {
+            Operator::Change => {
+                self.execute(&Action::EnterMode(Mode::Insert), buffer)?;
+                self.insert_undo_actions.push(undo);
+            }
+            _ => self.undo_actions.push(undo),
+        }
+        self.draw_viewport(buffer)
+    }
+
+    fn handle_event(&mut self, ev: event::Event) -> Option<KeyAction> {
+        if let event::Event::Resize(width, height) = ev {
+            self.size = (width, height);
+            return None;
+        }
+
+        self.last_input = Instant::now();
+        if let Event::FocusLost = ev {
+            let autosave = self.config.autosave.on_focus_lost
+                && self.buffer.modified
+                && self.buffer.file.is_some();
+            return autosave.then_some(KeyAction::Single(Action::Autosave));
+        }
+
+        if self.file_conflict {
+            if let Event::Key(event) = ev {
+                return self.resolve_file_conflict(event);
+            }
+        }
+
+        if let Some(action) = self.waiting_char.take() {
+            return match ev {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c),
+                    modifiers,
+                    ..
+                }) if !modifiers.contains(KeyModifiers::CONTROL) => {
+                    Some(KeyAction::Single(Action::WithChar(action, c)))
+                }
+                // any other key cancels
+                _ => None,
+            };
+        }
+
+        if let Some(ka) = self.waiting_key_action.take() {
+            return self.handle_waiting_command(ka, ev);
+        }
+
+        if let Event::Paste(text) = ev {
+            return self.handle_paste(text);
+        }
+
+        if let Event::Mouse(event) = ev {
+            return match self.mode {
+                Mode::Normal | Mode::Insert | Mode::Visual => self.handle_mouse_event(event),
+                _ => None,
+            };
+        }
+
+        match ev {
+            Event::Key(event) => self.handle_key(event),
+            _ => None,
+        }
+    }
+
+    /// Pasted text is inserted as is rather than run through the keymaps.
+    fn handle_paste(&mut self, text: String) -> Option<KeyAction> {
+        let action = match self.mode {
+            Mode::Normal | Mode::Insert => Action::Paste(text),
+            Mode::Terminal => Action::TerminalInput(text),
+            Mode::Command | Mode::Picker => {
+                let line = text.lines().next().unwrap_or_default();
+                let insert = match self.mode {
+                    Mode::Command => Action::InsertCommandChar,
+                    _ => Action::PickerInsertChar,
+                };
+                return Some(KeyAction::Multiple(line.chars().map(insert).collect()));
+            }
+            Mode::Visual | Mode::Replace | Mode::VisualBlock => return None,
+        };
+        Some(KeyAction::Single(action))
+    }
+
+    fn handle_mouse_event(&mut self, event: MouseEvent) -> Option<KeyAction> {
+        let action = match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => Action::ClickAt(event.column, event.row),
+            MouseEventKind::Drag(MouseButton::Left) => Action::DragTo(event.column, event.row),
+            MouseEventKind::ScrollUp => Action::ScrollUp(MOUSE_SCROLL_LINES),
+            MouseEventKind::ScrollDown => Action::ScrollDown(MOUSE_SCROLL_LINES),
+            _ => return None,
+        };
+        Some(KeyAction::Single(action))
+    }
+
+    fn keymap(&self) -> &Keymap {
+        match self.mode {
+            Mode::Normal if self.diff.is_some() => &self.keymaps.diff,
+            Mode::Normal if self.status.is_some() => &self.keymaps.status,
+            Mode::Normal => &self.keymaps.normal,
+            Mode::Insert => &self.keymaps.insert,
+            Mode::Command => &self.keymaps.command,
+            Mode::Picker => &self.keymaps.picker,
+            Mode::Terminal => &self.keymaps.terminal,
+            Mode::Visual => &self.keymaps.visual,
+            Mode::VisualBlock => &self.keymaps.visual_block,
+            Mode::Replace => &self.keymaps.replace,
+        }
+    }
+
+    /// Adds a key to the pending ones, returning the action once they make up
+    /// a mapped sequence. When the sequence breaks off, the keys typed before
+    /// run as if it timed out and the last key is looked up on its own.
+    fn handle_key(&mut self, event: KeyEvent) -> Option<KeyAction> {
+        if self.pending_keys.is_empty() && (self.is_visual() || matches!(self.mode, Mode::Normal)) {
+            if let KeyCode::Char(c @ '0'..='9') = event.code {
+                // 0 on its own goes to the line start
+                if event.modifiers.is_empty() && (c != '0' || self.count.is_some()) {
+                    let digit = c as usize - '0' as usize;
+                    let count = self.count.unwrap_or(0);
+                    self.count = Some(count.saturating_mul(10).saturating_add(digit));
+                    return None;
+                }
+            }
+        }
+
+        if self.pending_keys.is_empty() && self.popup.is_some() {
+            let name = keymap::key_name(&event);
+            if let Lookup::Action(ka) = self.keymaps.popup.lookup(&[name]) {
+                return Some(ka);
+            }
+        }
+
+        if self.pending_keys.is_empty()
+            && matches!(self.mode, Mode::Insert)
+            && self.completion.is_some()
+        {
+            let name = keymap::key_name(&event);
+            if let Lookup::Action(ka) = self.keymaps.completion.lookup(&[name]) {
+                return Some(ka);
+            }
+        }
+
+        self.pending_keys.push(event);
+        let names: Vec<String> = self.pending_keys.iter().map(keymap::key_name).collect();
+        match self.keymap().lookup(&names) {
+            Lookup::Action(ka) => {
+                self.pending_keys.clear();
+                Some(ka)
+            }
+            Lookup::Pending(_) => {
+                self.pending_at = Instant::now();
+                None
+            }
+            Lookup::Unmapped => {
+                let event = self.pending_keys.pop()?;
+                if self.pending_keys.is_empty() {
+                    return self.unmapped_key_action(event);
+                }
+                let mut actions = self.flush_pending_keys();
+                actions.extend(self.handle_key(event).map_or(vec![], keymap::into_actions));
+                Some(KeyAction::Multiple(actions))
+            }
+        }
+    }
+
+    /// Runs the pending keys once their timeout passed.
+    fn expire_pending_keys(&mut self) -> Option<KeyAction> {
+        if self.pending_keys_remaining() != Some(Duration::ZERO) {
+            return None;
+        }
+        Some(KeyAction::Multiple(self.flush_pending_keys()))
+    }
+
+    /// Turns the pending keys of an incomplete sequence into actions: the
+    /// longest prefix with an action of its own runs it, and keys no prefix
+    /// maps are handled like unmapped keys.
+    fn flush_pending_keys(&mut self) -> Vec<Action> {
+        let mut events = mem::take(&mut self.pending_keys);
+        let mut actions = vec![];
+        while !events.is_empty() {
+            let names: Vec<String> = events.iter().map(keymap::key_name).collect();
+            let mapped =
+                (1..=names.len())
+                    .rev()
+                    .find_map(|n| match self.keymap().lookup(&names[..n]) {
+                        Lookup::Action(ka) | Lookup::Pending(Some(ka)) => Some((n, ka)),
+                        _ => None,
+                    });
+            match mapped {
+                Some((n, ka)) => {
+                    actions.extend(keymap::into_actions(ka));
+                    events.drain(..n);
+                }
+                None => {
+                    let event = events.remove(0);
+                    let ka = self.unmapped_key_action(event);
+                    actions.extend(ka.map_or(vec![], keymap::into_actions));
+                }
+            }
+        }
+        actions
+    }
+
+    /// What keys that aren't mapped do: typed characters are inserted, and
+    /// the terminal forwards every key to the shell.
+    fn unmapped_key_action(&self, event: KeyEvent) -> Option<KeyAction> {
+        let action = match (self.mode, event.code) {
+            (Mode::Insert, KeyCode::Char(c)) => Action::InsertCharAtCursorPos(c),
+            (Mode::Replace, KeyCode::Char(c)) => Action::ReplaceCharAtCursorPos(c),
+            (Mode::Command, KeyCode::Char(c)) => Action::InsertCommandChar(c),
+            (Mode::Picker, KeyCode::Char(c)) => Action::PickerInsertChar(c),
+            (Mode::Terminal, _) => Action::TerminalInput(term::key_input(&event)?),
+            _ => return None,
+        };
+        Some(KeyAction::Single(action))
+    }
+
+    fn handle_waiting_command(&mut self, ka: KeyAction, ev: event::Event) -> Option<KeyAction> {
+        let KeyAction::Nested(nested_mappings) = ka else {
+            panic!("Expected nested key action");
+        };
+
+        let Event::Key(event) = ev else {
+            return None;
+        };
+        nested_mappings.get(&keymap::key_name(&event)).cloned()
+    }
+
+    /// Writes the buffer with the filetype's save options applied. Trimmed
+    /// whitespace comes back with a single undo. Autosaves don't trim.
+    fn save(&mut self, buffer: &mut RenderBuffer, autosave: bool) -> anyhow::Result<()> {
+        let options = self.save_options();
+        if options.trim_trailing_whitespace && !autosave {
+            let undo: Vec<Action> = self
+                .buffer
+                .trim_trailing_whitespace()
+                .into_iter()
+                .map(|(x, y, text)| Action::InsertTextAt(x, y, text))
+                .collect();
+            if !undo.is_empty() {
+                self.undo_actions.push(Action::UndoMultiple(undo));
+                self.cx = self.cx.min(self.line_length());
+                self.draw_viewport(buffer)?;
+            }
+        }
+        if options.final_newline {
+            self.buffer.final_newline = true;
+        }
+
+        if let (true, Some(file)) = (self.config.save.backup, &self.buffer.file) {
+            swap::backup(file)?;
+        }
+        self.buffer.save()?;
+        self.remove_swap();
+        self.save_undo();
+        let file = self.buffer.file.as_deref().unwrap_or_default();
+        let message = format!("\"{file}\" {}L written", self.buffer.len());
+        self.notify(Level::Info, message);
+        self.draw_statusline(buffer);
+        Ok(())
+    }
+
+    fn current_line_contents(&self) -> Option<String> {
+        self.buffer.get(self.buffer_line())
+    }
+
+    pub fn cleanup(&mut self) -> anyhow::Result<()> {
+        self.remove_swap();
+        if let Some(path) = history::path() {
+            if let Err(err) = self.history.save(&path) {
+                log!(Warn, "command history: {err}");
+            }
+        }
+        self.remember_position();
+        if let Some(path) = recent::path() {
+            if let Err(err) = self.recent.save(&path) {
+                log!(Warn, "recent files: {err}");
+            }
+        }
+        if self.in_terminal {
+            self.leave_terminal();
+            self.in_terminal = false;
+        }
+        Ok(())
+    }
+
+    /// Where the cursor is in the file, as `file:line:col` counted from 1.
+    pub fn position(&self) -> Option<String> {
+        let file = self.buffer.file.as_ref()?;
+        Some(format!("{file}:{}:{}", self.buffer_line() + 1, self.cx + 1))
+    }
+
+    fn draw_line(&mut self, buffer: &mut RenderBuffer) {
+        self.draw_line_at(buffer, self.cy);
+    }
+
+    fn draw_line_at(&mut self, buffer: &mut RenderBuffer, y: usize) {
+        // a wrapped line can change how many rows it takes
+        if self.wrap {
+            _ = self.draw_viewport(buffer);
+            return;
+        }
+        _ = self.sync_highlights();
+        let line = self.folds.nth_visible(self.vtop, y);
+        let start = self.buffer.byte_offset(line);
+        let len = self.buffer.lines.get(line).map_or(0, |l| l.len());
+        let style_info = highlighter::styles_in(&self.styles, start, start + len);
+        self.draw_text(buffer, y, line, self.vleft..usize::MAX, &style_info, 0);
+    }
+
+    /// Draws the bytes in `range` of buffer `line` on viewport row `y`, where
+    /// `offset` is the position of the line in `style_info`. Fold headers get
+    /// a count of hidden lines.
+    fn draw_text(
+        &mut self,
+        buffer: &mut RenderBuffer,
+        y: usize,
+        line: usize,
+        range: Range<usize>,
+        style_info: &[StyleInfo],
+        offset: usize,
+    ) {
+        let y = self.vy + y;
+        let right = self.text_right();
+        let text = self.buffer.get(line).unwrap_or_default();
+        let default_style = self.theme.style.clone();
+        let selection = self.selection();
+        let block = self.block();
+        let flash = self.flash.map(|(flash, _)| flash);
+        let selected = |pos: usize| {
+            selection.is_some_and(|(start, end)| {
+                (line, pos) >= (start.1, start.0) && (line, pos) < (end.1, end.0)
+            }) || block.is_some_and(|block| pos < text.len() && block.contains(pos, line))
+        };
+
+        let list = self.config.list.then(|| self.config.listchars.clone());
+        let trail_start = text.trim_end_matches([' ', '\t']).len();
+        let dim = |style: Style| Style {
+            fg: self.theme.gutter_style.fg.or(style.fg),
+            ..style
+        };
+        let hint_style = Style {
+            italic: true,
+            ..dim(default_style.clone())
+        };
+        let hints = match self.show_hints() {
+            true => self.hints.in_range(line, range.clone()).cloned().collect(),
+            false => vec![],
+        };
+        let mut hints = hints.into_iter().peekable();
+        let draw_hint = |buffer: &mut RenderBuffer, x: usize, label: &str| {
+            let label: String = label.chars().take(right.saturating_sub(x)).collect();
+            buffer.set_text(x, y, &label, &hint_style);
+            x + label.chars().count()
+        };
+
+        let mut x = self.vx;
+        // columns scrolled off to the left, or on other rows of a wrapped
+        // line, aren't drawn
+        let chars = text
+            .char_indices()
+            .skip_while(|(pos, _)| *pos < range.start);
+        for (pos, c) in chars.take_while(|(pos, _)| *pos < range.end) {
+            while let Some(hint) = hints.next_if(|hint| hint.x <= pos) {
+                x = draw_hint(buffer, x, &hint.label);
+            }
+            if x >= right {
+                break;
+            }
+            let mut style = self.style_for(style_info, offset + pos, (pos, line));
+            if selected(pos) {
+                style.fg = self.theme.selection_style.fg.or(style.fg);
+                style.bg = self.theme.selection_style.bg;
+            }
+            if flash.is_some_and(|flash| flash.contains(pos, line)) {
+                style.fg = self.theme.flash_style.fg.or(style.fg);
+                style.bg = self.theme.flash_style.bg;
+            }
+            if self.cursors.contains((pos, line)) {
+                style = self.extra_cursor_style(&style);
+            }
+            match &list {
+                Some(chars) if c == '\t' => buffer.set_char(x, y, chars.tab, &dim(style)),
+                Some(chars) if c == ' ' && pos >= trail_start => {
+                    buffer.set_char(x, y, chars.trail, &dim(style))
+                }
+                _ => buffer.set_char(x, y, c, &style),
+            }
+            x += 1;
+        }
+
+        // a selected line break shows as a selected blank, and like the rest
+        // drawn after the text only goes on the last row of a wrapped line
+        let last = range.end > text.len();
+        if last {
+            for hint in hints {
+                x = draw_hint(buffer, x, &hint.label);
+            }
+        }
+        let eol = list.and_then(|chars| chars.eol);
+        if x < right && text.len() >= range.start && last {
+            if self.cursors.contains((text.len(), line)) {
+                let style = self.extra_cursor_style(&default_style);
+                buffer.set_char(x, y, ' ', &style);
+                x += 1;
+            } else if selected(text.len()) {
+                let style = &self.theme.selection_style;
+                buffer.set_char(x, y, eol.unwrap_or(' '), &dim(style.clone()));
+                x += 1;
+            } else if let Some(eol) = eol {
+                buffer.set_char(x, y, eol, &dim(default_style.clone()));
+                x += 1;
+            }
+        }
+
+        if let Some(hidden) = self.folds.folded_at(line).filter(|_| last) {
+            let marker: String = format!(" ⋯ {hidden} lines")
+                .chars()
+                .take(right.saturating_sub(x))
+                .collect();
+            let style = Style {
+                fg: self.theme.gutter_style.fg.or(default_style.fg),
+                ..default_style.clone()
+            };
+            buffer.set_text(x, y, &marker, &style);
+            x += marker.chars().count();
+        }
+
+        if self.config.diagnostics.virtual_text && last {
+            if let Some(d) = self.diagnostics.worst_on_line(line) {
+                let message = d.message.lines().next().unwrap_or_default();
+                let text: String = format!("  ■ {message}")
+                    .chars()
+                    .take(right.saturating_sub(x))
+                    .collect();
+                buffer.set_text(x, y, &text, &self.theme.diagnostic_style(d.severity));
+                x += text.chars().count();
+            }
+        }
+
+        if self.show_blame && last {
+            if let Some(blame) = self.blame.get(line) {
+                let text: String = format!("    {}", blame.describe(git::now()))
+                    .chars()
+                    .take(right.saturating_sub(x))
+                    .collect();
+                let style = Style {
+                    fg: self.theme.gutter_style.fg.or(self.theme.style.fg),
+                    italic: true,
+                    ..self.theme.style.clone()
+                };
+                buffer.set_text(x, y, &text, &style);
+                x += text.chars().count();
+            }
+        }
+
+        self.fill_line(buffer, x, y, &default_style);
+
+        for column in &self.config.colorcolumn {
+            let Some(x) = column
+                .checked_sub(1)
+                .and_then(|column| column.checked_sub(self.vleft))
+                .map(|x| self.vx + x)
+            else {
+                continue;
+            };
+            if x < right {
+                buffer.set_bg(x, y, self.theme.ruler_style.bg);
+            }
+        }
+    }
+
+    fn style_for(&self, style_info: &[StyleInfo], pos: usize, at: (usize, usize)) -> Style {
+        let style = determine_style_for_position(style_info, pos)
+            .unwrap_or_else(|| self.theme.style.clone());
+
+        if self.bracket_match == Some(at) {
+            let highlight = &self.theme.bracket_match_style;
+            return Style {
+                fg: highlight.fg.or(style.fg),
+                bg: highlight.bg.or(style.bg),
+                bold: highlight.bold || style.bold,
+                italic: highlight.italic || style.italic,
+            };
+        }
+
+        style
+    }
+
+    /// Finds the bracket paired with the one at `(x, line)`, preferring the
+    /// pairs tree-sitter reported and scanning the text when there is no
+    /// syntax tree or it hasn't caught up with the latest edits yet.
+    fn find_matching_bracket(&self, x: usize, line: usize) -> Option<(usize, usize)> {
+        let pos = self.buffer.byte_offset(line) + x;
+        if let Some((open, close)) = self.brackets.iter().find(|(o, c)| *o == pos || *c == pos) {
+            let other = if *open == pos { *close } else { *open };
+            return Some(self.buffer.position(other));
+        }
+
+        if self.has_grammar() && !self.highlights_pending() {
+            return None;
+        }
+
+        self.buffer.matching_bracket(x, line)
+    }
+
+    fn update_bracket_match(&mut self, buffer: &mut RenderBuffer) {
+        let bracket_match = match self.mode {
+            Mode::Command | Mode::Picker | Mode::Terminal => None,
+            _ => self.find_matching_bracket(self.cx, self.buffer_line()),
+        };
+        if bracket_match == self.bracket_match {
+            return;
+        }
+
+        let previous = mem::replace(&mut self.bracket_match, bracket_match);
+        for (_, line) in previous.into_iter().chain(bracket_match) {
+            if let Some(row) = self.viewport_row(line) {
+                self.draw_line_at(buffer, row);
+            }
+        }
+    }
+
+    /// Moves the cursor to `x` on buffer `line`, opening folds that hide it
+    /// and scrolling the viewport to center the line when it's off screen.
+    fn move_to(&mut self, x: usize, line: usize, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let revealed = self.folds.reveal(line);
+        if self.viewport_row(line).is_none() {
+            self.vtop = self.folds.nth_visible_back(line, self.vheight() / 2);
+            self.scroll_down_to(line);
+            self.draw_viewport(buffer)?;
+        } else if revealed {
+            self.draw_viewport(buffer)?;
+        }
+        self.cursor_to_line(line);
+        self.cx = x;
+        if self.apply_scrolloff() {
+            self.draw_viewport(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the cursor a row down or up, within a wrapped line or onto the
+    /// nearest row of the next one, keeping its column on the screen.
+    fn move_visual(&mut self, down: bool, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        if line >= self.buffer.len() {
+            // an empty buffer has no rows to move between
+            return Ok(());
+        }
+        let starts = self.row_starts(line);
+        let row = wrap::row_of(&starts, self.cx);
+        let text = &self.buffer.lines[line];
+        let col = text
+            .get(starts[row]..self.cx.min(text.len()))
+            .map_or(0, |part| part.chars().count());
+
+        let (line, row) = if down && row + 1 < starts.len() {
+            (line, row + 1)
+        } else if !down && row > 0 {
+            (line, row - 1)
+        } else if down {
+            if self.folds.next_visible(line) >= self.buffer.len() {
+                return Ok(());
+            }
+            self.execute(&Action::MoveDown, buffer)?;
+            (self.buffer_line(), 0)
+        } else {
+            if line == 0 {
+                return Ok(());
+            }
+            self.execute(&Action::MoveUp, buffer)?;
+            let line = self.buffer_line();
+            (line, self.row_starts(line).len() - 1)
+        };
+
+        let starts = self.row_starts(line);
+        let text = &self.buffer.lines[line];
+        let end = starts.get(row + 1).copied().unwrap_or(text.len());
+        self.cx = text[starts[row]..end]
+            .char_indices()
+            .nth(col)
+            .map_or(end, |(i, _)| starts[row] + i);
+        // only the last row can put the cursor past its last character
+        if end < text.len() && self.cx == end {
+            self.cx = text[..end].char_indices().last().map_or(0, |(i, _)| i);
+        }
+        Ok(())
+    }
+
+    /// `scrolloff`, limited to half the viewport.
+    fn scrolloff(&self) -> usize {
+        self.config
+            .scrolloff
+            .min(self.vheight().saturating_sub(1) / 2)
+    }
+
+    /// Scrolls so `scrolloff` lines stay visible above and below the cursor,
+    /// as far as there are lines there. Returns whether the viewport moved.
+    fn apply_scrolloff(&mut self) -> bool {
+        let line = self.buffer_line();
+        let so = self.scrolloff();
+        if so == 0 || line >= self.buffer.len() {
+            return false;
+        }
+        let vtop = self.vtop;
+        if self.cy < so {
+            self.vtop = self.folds.nth_visible_back(line, so);
+        } else {
+            let last_row = self.vheight() - 1 - self.visible_below(line, so);
+            if self.cy > last_row {
+                self.vtop = self.folds.nth_visible_back(line, last_row);
+            }
+        }
+        self.cursor_to_line(line);
+        self.vtop != vtop
+    }
+
+    /// How many of the `n` visible lines after `line` exist in the buffer.
+    fn visible_below(&self, line: usize, n: usize) -> usize {
+        (0..n)
+            .scan(line, |next, _| {
+                *next = self.folds.next_visible(*next);
+                Some(*next)
+            })
+            .take_while(|next| *next < self.buffer.len())
+            .count()
+    }
+
+    /// Moves the viewport `n` visible lines down, or up when `down` is false,
+    /// stopping with the last line at the top. The cursor stays on its line
+    /// unless that scrolls out of view (or into the `scrolloff` margin), in
+    /// which case it moves to the nearest row that's left.
+    fn scroll_viewport(&mut self, n: usize, down: bool) -> bool {
+        let line = self.buffer_line();
+        let vtop = self.vtop;
+        if down {
+            self.vtop = self
+                .folds
+                .nth_visible(self.vtop, self.visible_below(self.vtop, n));
+        } else {
+            self.vtop = self.folds.nth_visible_back(self.vtop, n);
+        }
+        let so = self.scrolloff();
+        self.cy = match self.viewport_row(line) {
+            Some(row) => row.clamp(so, self.vheight() - 1 - so),
+            None if down => so,
+            None => self.vheight() - 1 - so,
+        };
+        self.vtop != vtop
+    }
+
+    /// Scrolls half a page, moving the cursor the same number of lines.
+    fn scroll_half_page(&mut self, down: bool, buffer: &mut RenderBuffer) -> anyhow::Result<()> {
+        let half = (self.vheight() / 2).max(1);
+        let line = self.buffer_line();
+        let target = if down {
+            self.folds.nth_visible(line, self.visible_below(line, half))
+        } else {
+            self.folds.nth_visible_back(line, half)
+        };
+        if self.scroll_viewport(half, down) {
+            self.draw_viewport(buffer)?;
+        }
+        self.move_to(self.cx, target, buffer)
+    }
+
+    /// Scrolls so the cursor line is at the top of the viewport, or the
+    /// bottom, keeping `scrolloff` lines of context.
+    fn move_line_to_viewport_edge(
+        &mut self,
+        top: bool,
+        buffer: &mut RenderBuffer,
+    ) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let so = self.scrolloff();
+        let row = if top {
+            so
+        } else {
+            self.vheight() - 1 - self.visible_below(line, so)
+        };
+        let vtop = self.folds.nth_visible_back(line, row);
+        if vtop != self.vtop {
+            self.vtop = vtop;
+            self.cursor_to_line(line);
+            self.draw_viewport(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Scrolls sideways to keep the cursor on the screen, with
+    /// `sidescrolloff` columns to either side of it. Returns whether the
+    /// viewport moved.
+    fn apply_sidescrolloff(&mut self) -> bool {
+        // wrapped lines never need scrolling sideways
+        if self.wrap {
+            return mem::take(&mut self.vleft) > 0;
+        }
+        let width = self.text_right().saturating_sub(self.vx).max(1);
+        let off = self.config.sidescrolloff.min(width.saturating_sub(1) / 2);
+        let vleft = self.vleft;
+        if self.cx < self.vleft + off {
+            self.vleft = self.cx.saturating_sub(off);
+        } else if self.cx + off >= self.vleft + width {
+            self.vleft = self.cx + off + 1 - width;
+        }
+        // a line end past the last column isn't worth scrolling for
+        if self.vleft > 0 && self.cx < width && self.line_length() < width {
+            self.vleft = 0;
+        }
+        self.vleft != vleft
+    }
+
+    fn execute(&mut self, action: &Action, buffer: &mut RenderBuffer) -> anyhow::Result<bool> {
+        match action {
+            Action::Quit => {
+                if let Some(name) = self.unsaved_buffer() {
+                    anyhow::bail!("No write since last change for {name} (add ! to override)");
+                }
+                return Ok(true);
+            }
+            Action::ForceQuit => {
+                // the swap files of the buffers left behind go too
+                for (_, hidden) in self.buffers.hidden() {
+                    if let (true, Some(file)) = (hidden.buffer.modified, &hidden.buffer.file) {
+                        swap::remove(file);
+                    }
+                }
+                return Ok(true);
+            }
+            Action::Save => match self.save(buffer, false) {
+                Err(err) => self.notify(Level::Error, format!("{err:#}")),
+                Ok(()) if self.is_commit_message() => self.commit(buffer)?,
+                Ok(()) => {}
+            },
+            Action::Autosave => {
+                if let Err(err) = self.save(buffer, true) {
+                    self.notify(Level::Error, format!("{err:#}"));
+                }
+            }
+            Action::ReloadFile => self.reload_file(buffer)?,
+            Action::RecoverSwap => self.recover_swap(buffer)?,
+            Action::GotoLine(line) => {
+                let line = line
+                    .saturating_sub(1)
+                    .min(self.buffer.len().saturating_sub(1));
+                self.push_jump();
+                self.folds.reveal(line);
+                self.vtop = self.folds.nth_visible_back(line, self.vheight() / 2);
+                self.cursor_to_line(line);
+                let contents = self.buffer.get(line).unwrap_or_default();
+                self.cx = contents.len() - contents.trim_start().len();
+                self.draw_viewport(buffer)?;
+            }
+            Action::Motion(motion) => {
+                let from = (self.cx, self.buffer_line());
+                let (x, line) = motion::target(*motion, &self.buffer.lines, from);
+                if matches!(motion, Motion::ParagraphForward | Motion::ParagraphBackward) {
+                    self.push_jump();
+                }
+                self.move_to(x, line, buffer)?;
+            }
+            Action::Operator(operator) => self.pending_operator = Some(*operator),
+            Action::WaitForChar(action) => self.waiting_char = Some(*action),
+            Action::WithChar(action, c) => {
+                if let Err(err) = self.execute_char_action(*action, *c, buffer) {
+                    self.notify(Level::Error, err.to_string());
+                }
+            }
+            Action::RepeatFind | Action::RepeatFindReverse => {
+                if let Some((find, c, repeat)) = self.resolve_find(action) {
+                    if let Some(x) = self.find_target(find, c, repeat) {
+                        self.cx = x;
+                    }
+                }
+            }
+            Action::JumpBack => {
+                let current = self.current_jump();
+                if let Some(jump) = self.jumps.back(current) {
+                    self.jump_to(jump, buffer)?;
+                }
+            }
+            Action::JumpForward => {
+                if let Some(jump) = self.jumps.forward() {
+                    self.jump_to(jump, buffer)?;
+                }
+            }
+            Action::MakeSession(path) => {
+                let path = path.as_deref().unwrap_or(session::DEFAULT_FILE);
+                self.session()?.save(Path::new(path))?;
+                self.notify(Level::Info, format!("Session written to {path}"));
+            }
+            Action::DeleteSwap => {
+                if let Some(file) = &self.buffer.file {
+                    swap::remove(file);
+                    self.swapped = None;
+                }
+            }
+            Action::DiffFile => self.diff_file()?,
+            Action::SaveAs(path, force) => {
+                if !force && Path::new(path).exists() {
+                    anyhow::bail!("{path} exists (add ! to override)");
+                }
+                match self.buffer.file {
+                    Some(_) => {
+                        self.buffer.write_to(path)?;
+                        let message = format!("\"{path}\" {}L written", self.buffer.len());
+                        self.notify(Level::Info, message);
+                    }
+                    None => self.name_buffer(path.clone(), buffer)?,
+                }
+            }
+            Action::NewBuffer => self.new_buffer(buffer)?,
+            Action::SaveAndQuit => match self.save(buffer, false) {
+                Ok(()) => return self.execute(&Action::Quit, buffer),
+                Err(err) => self.notify(Level::Error, format!("{err:#}")),
+            },
+            Action::MoveUp => {
+                if self.cy == 0 {
+                    if let Some(line) = self.folds.prev_visible(self.vtop) {
+                        self.vtop = line;
+                        self.draw_viewport(buffer)?;
+                    }
+                } else {
+                    self.cy = self.cy.saturating_sub(1);
+                    if self.apply_scrolloff() {
+                        self.draw_viewport(buffer)?;
+                    }
+                }
+            }
+            Action::MoveDown => {
+                self.cy += 1;
+                if self.cy >= self.vheight() {
+                    self.vtop = self.folds.next_visible(self.vtop);
+                    self.cy -= 1;
+                    self.draw_viewport(buffer)?;
+                } else if self.apply_scrolloff() {
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::MoveVisualUp => self.move_visual(false, buffer)?,
+            Action::MoveVisualDown => self.move_visual(true, buffer)?,
+            Action::MoveLeft => {
+                self.cx = self.cx.saturating_sub(1);
+                if self.cx < self.vleft {
+                    self.cx = self.vleft;
+                }
+            }
+            Action::MoveRight => {
+                self.cx += 1;
+            }
+            Action::MoveToLineStart => {
+                self.cx = 0;
+            }
+            Action::MoveToLineEnd => {
+                self.cx = self.line_length().saturating_sub(1);
+            }
+            Action::PageUp => {
+                if self.vtop > 0 {
+                    self.vtop = self.folds.nth_visible_back(self.vtop, self.vheight());
+                    let so = self.scrolloff();
+                    self.cy = self.cy.clamp(so, self.vheight() - 1 - so);
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::PageDown => {
+                let line = self.folds.nth_visible(self.vtop, self.vheight());
+                if self.buffer.len() > line {
+                    self.vtop = line;
+                    let so = self.scrolloff();
+                    self.cy = self.cy.clamp(so, self.vheight() - 1 - so);
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::EnterMode(new_mode) => {
+                if self.is_typing() && matches!(new_mode, Mode::Normal) {
+                    self.finish_block_insert(buffer)?;
+                }
+                if !self.is_typing() && matches!(new_mode, Mode::Insert | Mode::Replace) {
+                    self.insert_undo_actions = Vec::new();
+                }
+                if self.is_typing()
+                    && matches!(new_mode, Mode::Normal)
+                    && !self.insert_undo_actions.is_empty()
+                {
+                    let actions = mem::take(&mut self.insert_undo_actions);
+                    self.undo_actions.push(Action::UndoMultiple(actions));
+                }
+                self.command.clear();
+                self.history.reset();
+                self.messages.clear();
+                self.completion = None;
+                self.signature = None;
+                self.picker = None;
+                self.selection_anchor = match new_mode {
+                    // switching between visual modes keeps the selection
+                    Mode::Visual | Mode::VisualBlock => self
+                        .selection_anchor
+                        .filter(|_| self.is_visual())
+                        .or(Some((self.cx, self.buffer_line()))),
+                    _ => None,
+                };
+                self.mode = *new_mode;
+                self.draw_statusline(buffer);
+            }
+            Action::InsertCharAtCursorPos(_)
+            | Action::DeletePreviousChar
+            | Action::DeleteCharAtCursorPos
+                if !self.cursors.is_empty() =>
+            {
+                self.edit_at_cursors(action, buffer)?
+            }
+            Action::AddCursorAtNextMatch => self.add_cursor_at_next_match(buffer)?,
+            Action::ClearCursors => {
+                self.cursors.clear();
+                self.draw_viewport(buffer)?;
+            }
+            Action::InsertCharAtCursorPos(c) => {
+                if self.is_insert() && abbrev::ends_word(*c) {
+                    self.expand_abbreviation(buffer)?;
+                }
+                let line = self.current_line_contents().unwrap_or_default();
+                let typed = match self.config.autopairs.except(&self.buffer.filetype) {
+                    Some(except) if self.is_insert() => {
+                        autopairs::typed(&line, self.cx, *c, except)
+                    }
+                    _ => Typed::Plain,
+                };
+                if let Typed::SkipOver = typed {
+                    self.cx += c.len_utf8();
+                    self.update_signature(*c);
+                    return Ok(false);
+                }
+                if let Typed::Pair(close) = typed {
+                    self.insert_undo_actions
+                        .push(Action::RemoveCharAt(self.cx, self.buffer_line()));
+                    self.buffer.insert(self.cx, self.buffer_line(), close);
+                }
+                self.insert_undo_actions
+                    .push(Action::RemoveCharAt(self.cx, self.buffer_line()));
+                self.buffer.insert(self.cx, self.buffer_line(), *c);
+                self.cx += 1;
+                self.draw_line(buffer);
+                if self.is_insert() {
+                    self.update_signature(*c);
+                }
+            }
+            Action::InsertTab => {
+                let options = self.filetype_options();
+                if !options.expandtab {
+                    return self.execute(&Action::InsertCharAtCursorPos('\t'), buffer);
+                }
+                let line = self.current_line_contents().unwrap_or_default();
+                let column = line[..self.cx.min(line.len())].chars().count();
+                for _ in 0..options.tabstop - column % options.tabstop {
+                    self.execute(&Action::InsertCharAtCursorPos(' '), buffer)?;
+                }
+            }
+            Action::Format => {
+                let Some(formatter) = self.filetype_options().formatter else {
+                    anyhow::bail!("No formatter for {}", self.buffer.filetype);
+                };
+                if !self.buffer.lines.is_empty() {
+                    self.filter_lines(0, self.buffer.len() - 1, &formatter, buffer)?;
+                }
+            }
+            Action::ReplaceCharAtCursorPos(c) => {
+                let undo = self.replace_char(*c);
+                self.insert_undo_actions.push(undo);
+                self.cx += c.len_utf8();
+                self.draw_line(buffer);
+            }
+            Action::RestoreReplacedChar => {
+                let contents = self.current_line_contents().unwrap_or_default();
+                if let Some((x, _)) = contents[..self.cx.min(contents.len())]
+                    .char_indices()
+                    .last()
+                {
+                    self.cx = x;
+                    if let Some(undo) = self.insert_undo_actions.pop() {
+                        self.execute(&undo, buffer)?;
+                    }
+                }
+                self.draw_line(buffer);
+            }
+            Action::RemoveCharAt(cx, line) => {
+                self.buffer.remove(*cx, *line);
+                self.draw_line(buffer);
+            }
+            Action::DeleteCharAtCursorPos => {
+                self.buffer.remove(self.cx, self.buffer_line());
+                self.draw_line(buffer);
+            }
+            Action::ToggleCaseChar => {
+                let line = self.buffer_line();
+                let contents = self.current_line_contents().unwrap_or_default();
+                if let Some(c) = contents.get(self.cx..).and_then(|s| s.chars().next()) {
+                    let end = (self.cx + c.len_utf8(), line);
+                    let undo = self.change_case(Case::Toggle, (self.cx, line), end);
+                    self.undo_actions.extend(undo);
+                    let contents = self.current_line_contents().unwrap_or_default();
+                    // stays on the last character, like vim
+                    if let Some(next) = contents.get(self.cx..).and_then(|s| s.chars().next()) {
+                        self.cx = (self.cx + next.len_utf8()).min(contents.len().saturating_sub(1));
+                    }
+                    self.draw_line(buffer);
+                }
+            }
+            Action::NewLine => {
+                if self.is_insert() {
+                    self.expand_abbreviation(buffer)?;
+                }
+                self.cx = 0;
+                self.cy += 1;
+                self.buffer.insert_line(self.buffer_line(), String::new());
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetWaitingKey(key_action) => {
+                self.waiting_key_action = Some(*(key_action.clone()));
+            }
+            Action::JoinLines => self.join_lines(2, buffer)?,
+            Action::JoinLinesCount(count) => self.join_lines(*count, buffer)?,
+            Action::DeleteCurrentLine => {
+                let line = self.buffer_line();
+                let contents = self.current_line_contents();
+
+                self.buffer.remove_line(self.buffer_line());
+                self.undo_actions.push(Action::InsertLineAt(line, contents));
+                self.draw_viewport(buffer)?;
+            }
+            Action::Undo => {
+                if let Some(undo_action) = self.undo_actions.pop() {
+                    self.execute(&undo_action, buffer)?;
+                };
+            }
+            Action::InsertLineAt(y, contents) => {
+                if let Some(contents) = contents {
+                    self.buffer.insert_line(*y, contents.to_string());
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::ScrollDown(lines) => {
+                if self.scroll_viewport(*lines, true) {
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::ScrollUp(lines) => {
+                if self.scroll_viewport(*lines, false) {
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::HalfPageDown => self.scroll_half_page(true, buffer)?,
+            Action::HalfPageUp => self.scroll_half_page(false, buffer)?,
+            Action::MoveLineToViewportTop => self.move_line_to_viewport_edge(true, buffer)?,
+            Action::MoveLineToViewportBottom => self.move_line_to_viewport_edge(false, buffer)?,
+            Action::MoveLineToViewportCenter => {
+                let line = self.buffer_line();
+                let vtop = self.folds.nth_visible_back(line, self.vheight() / 2);
+                if vtop != self.vtop {
+                    self.vtop = vtop;
+                    self.cursor_to_line(line);
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::InsertLineAtCursor => {
+                self.undo_actions
+                    .push(Action::DeleteLineAt(self.buffer_line()));
+                self.buffer.insert_line(self.buffer_line(), String::new());
+                self.cx = 0;
+                self.draw_viewport(buffer)?;
+            }
+            Action::InsertLineBelowCursor => {
+                self.undo_actions
+                    .push(Action::DeleteLineAt(self.buffer_line() + 1));
+                self.buffer
+                    .insert_line(self.buffer_line() + 1, String::new());
+                self.cy += 1;
+                self.cx = 0;
+                self.draw_viewport(buffer)?;
+            }
+            Action::MoveToTop => {
+                self.push_jump();
+                self.vtop = 0;
+                self.cy = 0;
+                self.draw_viewport(buffer)?;
+            }
+            Action::MoveToBottom => {
+                self.push_jump();
+                let last = self.folds.visible(self.buffer.len().saturating_sub(1));
+                self.vtop = self.folds.nth_visible_back(last, self.vheight() - 1);
+                self.cursor_to_line(last);
+                self.draw_viewport(buffer)?;
+            }
+            Action::UndoMultiple(actions) => {
+                for action in actions.iter().rev() {
+                    self.execute(action, buffer)?;
+                }
+            }
+            Action::DeleteLineAt(y) => {
+                self.buffer.remove_line(*y);
+                self.draw_viewport(buffer)?;
+            }
+            Action::DeletePreviousChar => {
+                let line = self.buffer_line();
+                let contents = self.current_line_contents().unwrap_or_default();
+                if let Some(prev) = contents.get(..self.cx).and_then(|s| s.chars().next_back()) {
+                    let start = self.cx - prev.len_utf8();
+                    let pair = self
+                        .config
+                        .autopairs
+                        .except(&self.buffer.filetype)
+                        .is_some_and(|except| autopairs::deletes_pair(&contents, self.cx, except));
+                    // the closer goes along with its opener
+                    let end = match pair {
+                        true => {
+                            self.cx + contents[self.cx..].chars().next().map_or(0, char::len_utf8)
+                        }
+                        false => self.cx,
+                    };
+                    let text = self.buffer.remove_range((start, line), (end, line));
+                    let undo = Action::InsertTextAt(start, line, text);
+                    if self.is_typing() {
+                        self.insert_undo_actions.push(undo);
+                    } else {
+                        self.undo_actions.push(undo);
+                    }
+                    self.cx = start;
+                    self.draw_line(buffer);
+                }
+            }
+            Action::InsertCommandChar(c) => {
+                self.command.push(*c);
+                self.history.reset();
+            }
+            Action::DeleteCommandChar => {
+                if self.command.pop().is_none() {
+                    self.mode = Mode::Normal;
+                }
+                self.history.reset();
+            }
+            Action::CommandHistoryPrev => {
+                if let Some(command) = self.history.prev(&self.command) {
+                    self.command = command.to_string();
+                }
+            }
+            Action::CommandHistoryNext => {
+                if let Some(command) = self.history.next() {
+                    self.command = command.to_string();
+                }
+            }
+            Action::ExecuteCommand => {
+                let command = mem::take(&mut self.command);
+                self.history.add(&command);
+                self.mode = Mode::Normal;
+                let command = command::expand(&command, self.buffer.file.as_deref())?;
+                let (name, args) = command
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((command.trim(), ""));
+                if self.config.commands.contains_key(name) {
+                    return self.execute(&Action::Run(name.to_string()), buffer);
+                }
+                if self.plugins.has_command(name) {
+                    self.run_plugin_command(name, args.trim())?;
+                    return Ok(false);
+                }
+                match command::parse(&command) {
+                    Ok(action) => return self.execute_key_action(action, buffer),
+                    Err(err) => self.notify(Level::Error, err.to_string()),
+                }
+            }
+            Action::MatchBracket => {
+                let line = self.buffer_line();
+                let contents = self.current_line_contents().unwrap_or_default();
+                // like vim, jump from the first bracket at or after the cursor
+                let bracket = contents
+                    .char_indices()
+                    .skip_while(|(x, _)| *x < self.cx)
+                    .find(|(_, c)| buffer::is_bracket(*c));
+                if let Some((x, _)) = bracket {
+                    if let Some((x, line)) = self.find_matching_bracket(x, line) {
+                        self.push_jump();
+                        self.move_to(x, line, buffer)?;
+                    }
+                }
+            }
+            Action::FoldToggle => {
+                let line = self.buffer_line();
+                if self.folds.open_at(line) {
+                    self.draw_viewport(buffer)?;
+                } else if let Some(range) = fold::innermost(&self.fold_ranges(), line) {
+                    self.folds.close(range.clone());
+                    self.vtop = self.folds.visible(self.vtop);
+                    self.draw_viewport(buffer)?;
+                    self.move_to(self.cx, range.start, buffer)?;
+                }
+            }
+            Action::FoldAll => {
+                let line = self.buffer_line();
+                for range in self.fold_ranges() {
+                    self.folds.close(range);
+                }
+                self.vtop = self.folds.visible(self.vtop);
+                self.draw_viewport(buffer)?;
+                self.move_to(self.cx, self.folds.visible(line), buffer)?;
+            }
+            Action::UnfoldAll => {
+                let line = self.buffer_line();
+                self.folds.clear();
+                self.draw_viewport(buffer)?;
+                self.move_to(self.cx, line, buffer)?;
+            }
+            Action::SetLineNumbers(line_numbers) => {
+                self.config.line_numbers = *line_numbers;
+                self.vx = self.text_left();
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetColorColumn(columns) => {
+                self.config.colorcolumn = columns.clone();
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetList(list) => {
+                self.config.list = *list;
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetWrap(wrap) => {
+                self.wrap = *wrap;
+                self.vleft = 0;
+                self.draw_viewport(buffer)?;
+            }
+            Action::ToggleWrap => {
+                self.wrap = !self.wrap;
+                self.vleft = 0;
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetTextwidth(width) => {
+                self.config.textwidth = *width;
+                self.draw_viewport(buffer)?;
+            }
+            Action::SetReadonly(readonly) => {
+                self.buffer.readonly = *readonly;
+                self.draw_statusline(buffer);
+            }
+            Action::SetScrolloff(lines) => {
+                self.config.scrolloff = *lines;
+                if self.apply_scrolloff() {
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::SetSidescrolloff(columns) => {
+                self.config.sidescrolloff = *columns;
+            }
+            Action::SetFileEncoding(encoding) => {
+                // written the new way on the next save
+                self.buffer.encoding = *encoding;
+                self.buffer.modified = true;
+                self.draw_statusline(buffer);
+            }
+            Action::SetFileFormat(format) => {
+                self.buffer.fileformat = *format;
+                self.buffer.modified = true;
+                self.draw_statusline(buffer);
+            }
+            Action::SetFiletype(name) => {
+                if let Some(language) = language::find(name) {
+                    self.buffer.filetype = language.name.to_string();
+                    self.wrap = self.config.for_filetype(&self.buffer.filetype).wrap;
+                    self.styles.clear();
+                    self.brackets.clear();
+                    self.highlighter.send(Request::SetLanguage(language))?;
+                    self.request_highlights()?;
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::Completion if self.lsp.is_none() => self.open_completion(vec![]),
+            Action::CompletionNext | Action::CompletionPrev => {
+                if let Some(menu) = &mut self.completion {
+                    match action {
+                        Action::CompletionNext => menu.select_next(),
+                        _ => menu.select_prev(),
+                    }
+                }
+            }
+            Action::CompletionAccept => {
+                if let Some(menu) = self.completion.take() {
+                    let typed = self.cx.saturating_sub(menu.start);
+                    let text = menu.selected().text.clone();
+                    for c in text.get(typed..).unwrap_or_default().chars() {
+                        self.execute(&Action::InsertCharAtCursorPos(c), buffer)?;
+                    }
+                }
+            }
+            Action::CompletionCancel => self.completion = None,
+            Action::Hover if self.lsp.is_none() => self.hover_docs(),
+            Action::Rename => {
+                let line = self.current_line_contents().unwrap_or_default();
+                let Some((start, end)) = cursors::word_at(&line, self.cx) else {
+                    self.notify(Level::Info, "no word under the cursor");
+                    return Ok(false);
+                };
+                // like vim's filter, the command line is filled in to edit
+                self.command = format!("rename {}", &line[start..end]);
+                self.mode = Mode::Command;
+            }
+            Action::RenameTo(name) => {
+                let (line, character) = self.lsp_position();
+                let Some(lsp) = &mut self.lsp else {
+                    self.notify(Level::Warning, "no language server running");
+                    return Ok(false);
+                };
+                let result = lsp.rename(line, character, name);
+                self.check_lsp(result);
+            }
+            Action::GotoDefinition if self.lsp.is_none() => {
+                let entries = self.tag_definitions()?;
+                self.show_locations(entries, "definition", buffer);
+            }
+            Action::PopupScrollDown(n) => {
+                let lines = self.popup.as_ref().map_or(0, Vec::len);
+                let max = lines.saturating_sub(self.popup_height());
+                self.popup_scroll = (self.popup_scroll + *n).min(max);
+            }
+            Action::PopupScrollUp(n) => self.popup_scroll = self.popup_scroll.saturating_sub(*n),
+            Action::Hover
+            | Action::GotoDefinition
+            | Action::FindReferences
+            | Action::Completion => {
+                let (line, character) = self.lsp_position();
+                let Some(lsp) = &mut self.lsp else {
+                    self.notify(Level::Warning, "no language server running");
+                    return Ok(false);
+                };
+                let result = match action {
+                    Action::Hover => lsp.hover(line, character),
+                    Action::GotoDefinition => lsp.definition(line, character),
+                    Action::FindReferences => lsp.references(line, character),
+                    _ => lsp.completion(line, character),
+                };
+                self.check_lsp(result);
+            }
+            Action::NextDiagnostic | Action::PrevDiagnostic => {
+                let (line, x) = (self.buffer_line(), self.cx);
+                let diagnostic = match action {
+                    Action::NextDiagnostic => self.diagnostics.next(line, x),
+                    _ => self.diagnostics.prev(line, x),
+                };
+                match diagnostic.cloned() {
+                    Some(d) => {
+                        self.move_to(d.x, d.line, buffer)?;
+                        let text = d.message.lines().next().unwrap_or_default();
+                        self.notify(Level::Info, text);
+                    }
+                    None => self.notify(Level::Info, "no diagnostics"),
+                }
+            }
+            Action::NextHunk | Action::PrevHunk if self.diff.is_some() => {
+                let height = self.diff_height();
+                let diff = self.diff.as_mut().expect("diff is open");
+                let row = match action {
+                    Action::NextHunk => diff.next_hunk(),
+                    _ => diff.prev_hunk(),
+                };
+                match row {
+                    Some(row) => diff.move_to(row, height),
+                    None => self.notify(Level::Info, "no differences"),
+                }
+            }
+            Action::NextHunk | Action::PrevHunk => {
+                let line = self.buffer_line();
+                let hunk = match action {
+                    Action::NextHunk => self.git_signs.next(line),
+                    _ => self.git_signs.prev(line),
+                };
+                match hunk.map(Hunk::line) {
+                    Some(line) => self.move_to(0, line, buffer)?,
+                    None => self.notify(Level::Info, "no changes"),
+                }
+            }
+            Action::GitStatus => {
+                let root = self.git_root()?;
+                let porcelain = git::run(&root, STATUS_ARGS)?;
+                let branch = git::run(&root, &["rev-parse", "--abbrev-ref", "HEAD"])
+                    .ok()
+                    .map(|branch| branch.trim().to_string());
+                self.diff = None;
+                self.status = Some(GitStatus::new(root, branch, &porcelain));
+            }
+            Action::StatusNext | Action::StatusPrev => {
+                if let Some(status) = &mut self.status {
+                    status.selected = match action {
+                        Action::StatusNext => status.selected + 1,
+                        _ => status.selected.saturating_sub(1),
+                    }
+                    .min(status.files.len().saturating_sub(1));
+                }
+            }
+            Action::StatusStage | Action::StatusUnstage => {
+                let Some(status) = &mut self.status else {
+                    return Ok(false);
+                };
+                let Some(file) = status.current() else {
+                    return Ok(false);
+                };
+                let path = file.path.clone();
+                match action {
+                    Action::StatusStage => git::run(&status.root, &["add", "--", &path])?,
+                    _ => git::run(&status.root, &["reset", "-q", "--", &path])?,
+                };
+                let porcelain = git::run(&status.root, STATUS_ARGS)?;
+                status.update(&porcelain);
+            }
+            Action::StatusOpen => {
+                let Some(status) = self.status.take() else {
+                    return Ok(false);
+                };
+                if let Some(file) = status.current() {
+                    let path = status.root.join(&file.path);
+                    self.open_file(&display_path(&path.to_string_lossy()), buffer)?;
+                }
+                self.draw_viewport(buffer)?;
+            }
+            Action::CloseStatus => {
+                self.status = None;
+                self.draw_viewport(buffer)?;
+            }
+            Action::Commit => self.start_commit(buffer)?,
+            Action::PreviewHunk => self.preview_hunk()?,
+            Action::StageHunk => self.stage_hunk()?,
+            Action::RevertHunk => self.revert_hunk(buffer)?,
+            Action::SetLines(start, end, lines) => {
+                let len = self.buffer.len();
+                let start = (*start).min(len);
+                let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+                let edit = replace_lines_edit(start..(*end).clamp(start, len), &lines, len);
+                let undo = apply_text_edits(&mut self.buffer, &[edit]);
+                self.undo_actions.push(undo);
+                self.check_bounds();
+                self.draw_viewport(buffer)?;
+            }
+            Action::PluginCommand(name, args) => self.run_plugin_command(name, args)?,
+            Action::Run(name) => return self.run_command(name, buffer),
+            Action::ToggleBlame => {
+                self.show_blame = !self.show_blame;
+                self.start_blame();
+                self.draw_viewport(buffer)?;
+            }
+            Action::DeleteSelection if self.is_visual_block() => self.delete_block(buffer)?,
+            Action::DeleteSelection => {
+                if let Some((start, end)) = self.selection() {
+                    let text = self.buffer.remove_range(start, end);
+                    if !text.is_empty() {
+                        self.register = Some(Register::Text(text.clone()));
+                        self.undo_actions
+                            .push(Action::InsertTextAt(start.0, start.1, text));
+                    }
+                    self.selection_anchor = None;
+                    self.mode = Mode::Normal;
+                    self.move_to(start.0, start.1, buffer)?;
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::Yank => {
+                let (register, (x, line), flash) = match (self.selection(), self.block()) {
+                    (Some((start, end)), _) => {
+                        let text = self.buffer.range_text(start, end);
+                        (Register::Text(text), start, Flash::Range(start, end))
+                    }
+                    (_, Some(block)) => (
+                        Register::Block(self.block_lines(&block)),
+                        (block.left, block.top),
+                        Flash::Block(block),
+                    ),
+                    _ => return Ok(false),
+                };
+                self.register = Some(register);
+                if self.config.yank_flash > 0 {
+                    let duration = Duration::from_millis(self.config.yank_flash);
+                    self.flash = Some((flash, Instant::now() + duration));
+                }
+                self.execute(&Action::EnterMode(Mode::Normal), buffer)?;
+                self.move_to(x, line, buffer)?;
+                self.draw_viewport(buffer)?;
+            }
+            Action::PasteAfter | Action::PasteBefore => {
+                self.paste(matches!(action, Action::PasteAfter), buffer)?;
+            }
+            Action::BlockInsert | Action::BlockAppend => {
+                self.start_block_insert(matches!(action, Action::BlockAppend), buffer)?;
+            }
+            Action::Increment(delta) => {
+                let line = self.buffer_line();
+                let contents = self.current_line_contents().unwrap_or_default();
+                if let Some(found) = increment::increment(&contents, self.cx, *delta) {
+                    let old = self
+                        .buffer
+                        .remove_range((found.start, line), (found.end, line));
+                    let undo = self.insert_replacement((found.start, line), old, &found.text);
+                    self.undo_actions.push(undo);
+                    self.cx = found.start + found.text.len() - 1;
+                    self.draw_line(buffer);
+                }
+            }
+            Action::ChangeSelectionCase(case) => {
+                if let Some((start, end)) = self.selection() {
+                    let undo = self.change_case(*case, start, end);
+                    self.undo_actions.extend(undo);
+                    self.selection_anchor = None;
+                    self.mode = Mode::Normal;
+                    self.move_to(start.0, start.1, buffer)?;
+                    self.draw_viewport(buffer)?;
+                }
+            }
+            Action::ToggleComment => self.toggle_comment(buffer)?,
+            Action::InsertTextAt(x, line, text) => {
+                self.buffer.insert_text(*x, *line, text);
+                self.move_to(*x, *line, buffer)?;
+                self.draw_viewport(buffer)?;
+            }
+            Action::ClickAt(x, y) => {
+                if let Some((x, line)) = self.position_at(*x, *y) {
+                    if self.is_visual() {
+                        self.execute(&Action::EnterMode(Mode::Normal), buffer)?;
+                    }
+                    self.move_to(x, line, buffer)?;
+                }
+            }
+            Action::DragTo(x, y) => {
+                if let Some((x, line)) = self.position_at(*x, *y) {
+                    // the selection starts where the button was pressed
+                    if !matches!(self.mode, Mode::Visual) {
+                        self.execute(&Action::EnterMode(Mode::Visual), buffer)?;
+                    }
+                    self.move_to(x, line, buffer)?;
+                }
+            }
+            Action::Paste(text) => {
+                let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                let start = (self.cx, self.buffer_line());
+                let end = self.buffer.insert_text(start.0, start.1, &text);
+                // undone along with the rest of the insert when pasting in insert mode
+                let undo = Action::RemoveRange(start, end);
+                if self.is_insert() {
+                    self.insert_undo_actions.push(undo);
+                } else {
+                    self.undo_actions.push(undo);
+                }
+                self.move_to(end.0, end.1, buffer)?;
+                self.draw_viewport(buffer)?;
+            }
+            Action::RemoveRange(start, end) => {
+                self.buffer.remove_range(*start, *end);
+                self.move_to(start.0, start.1, buffer)?;
+                self.draw_viewport(buffer)?;
+            }
+            Action::ReloadConfig => self.reload_config(buffer)?,
+            Action::PickTheme => {
+                let names = theme::discover(&self.theme_dirs())
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect();
+                self.open_picker(Picker::new(PickerKind::Themes, "Themes").with_items(names));
+            }
+            Action::SetTheme(name) => self.switch_theme(name, buffer)?,
+            Action::ImportTheme(name) => {
+                let Some(extensions) = theme::extensions_dir() else {
+                    anyhow::bail!("No home directory to find VSCode extensions in");
+                };
+                let (path, theme) = theme::import_vscode_theme(name, &extensions)?;
+                self.config.theme = path.to_string_lossy().to_string();
+                let imported = match theme.name.as_str() {
+                    "" => name.as_str(),
+                    theme_name => theme_name,
+                };
+                let message = format!("{imported} imported from {}", path.display());
+                self.set_theme(theme.downsample(self.config.color_depth()), buffer)?;
+                self.notify(Level::Info, message);
+            }
+            Action::ShowMessages => {
+                let lines: Vec<String> = self
+                    .messages
+                    .history()
+                    .iter()
+                    .map(|m| format!("{:?}: {}", m.level, m.text))
+                    .collect();
+                // the latest messages are shown, scrolling up to older ones
+                let scroll = lines.len().saturating_sub(self.popup_height());
+                if !lines.is_empty() {
+                    self.show_popup(lines);
+                    self.popup_scroll = scroll;
+                }
+            }
+            Action::ShowLog => self.show_log(buffer)?,
+            Action::Suspend => self.suspend(buffer)?,
+            Action::ShowDiagnostic => {
+                let popup: Vec<String> = self
+                    .diagnostics
+                    .on_line(self.buffer_line())
+                    .flat_map(|d| {
+                        let mut lines = d.message.lines();
+                        let first = format!(
+                            "{:?} [{}]: {}",
+                            d.severity,
+                            d.source,
+                            lines.next().unwrap_or_default()
+                        );
+                        std::iter::once(first)
+                            .chain(lines.map(|l| format!("  {l}")))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                if !popup.is_empty() {
+                    self.show_popup(popup);
+                }
+            }
+            Action::FindFile => {
+                let root = self.project_root()?;
+                let picker =
+                    Picker::new(PickerKind::Files, "Files").with_source(finder::scan(&root));
+                self.open_picker(picker);
+            }
+            Action::Grep(pattern) => {
+                let root = self.project_root()?;
+                let picker =
+                    Picker::new(PickerKind::Grep, "Grep").with_source(grep::search(&root, pattern));
+                self.open_picker(picker);
+            }
+            Action::NextQuickfix => {
+                let entry = self.quickfix.next().cloned();
+                self.goto_quickfix(entry, buffer);
+            }
+            Action::PrevQuickfix => {
+                let entry = self.quickfix.prev().cloned();
+                self.goto_quickfix(entry, buffer);
+            }
+            Action::ListBuffers => {
+                let current = self.buffers.current();
+                let items = std::iter::once(buffers::describe(current, &self.buffer, true))
+                    .chain(
+                        self.buffers
+                            .hidden()
+                            .map(|(id, hidden)| buffers::describe(id, &hidden.buffer, false)),
+                    )
+                    .collect();
+                self.confirm_close = None;
+                self.open_picker(Picker::new(PickerKind::Buffers, "Buffers").with_items(items));
+            }
+            Action::CommandHistory => {
+                let items = self.history.entries().iter().rev().cloned().collect();
+                self.open_picker(
+                    Picker::new(PickerKind::CommandHistory, "Command History").with_items(items),
+                );
+            }
+            Action::RecentFiles => {
+                let cwd = std::env::current_dir()?;
+                let current = self
+                    .buffer
+                    .file
+                    .as_deref()
+                    .and_then(|f| fs::canonicalize(f).ok());
+                let items = self
+                    .recent
+                    .files()
+                    .filter(|file| current.as_deref() != Some(Path::new(file)))
+                    .map(|file| project::rebase(file, &cwd, &cwd))
+                    .collect();
+                self.open_picker(Picker::new(PickerKind::Recent, "Recent Files").with_items(items));
+            }
+            Action::OpenFile(file) => {
+                let file = match &self.buffer.local_dir {
+                    Some(dir) => project::rebase(file, dir, &std::env::current_dir()?),
+                    None => file.clone(),
+                };
+                self.push_jump();
+                self.open_file(&file, buffer)?;
+            }
+            Action::ChangeDir(dir) => {
+                let old = std::env::current_dir()?;
+                let new = match dir {
+                    Some(dir) => old.join(dir),
+                    None => self.project_root()?,
+                };
+                std::env::set_current_dir(&new)
+                    .map_err(|err| anyhow::anyhow!("Can't cd to {}: {err}", new.display()))?;
+                let new = std::env::current_dir()?;
+                self.rebase_files(&old, &new);
+                self.notify(Level::Info, new.display().to_string());
+                // the statusline and tabline show the files by their names
+                self.draw_viewport(buffer)?;
+            }
+            Action::LocalChangeDir(dir) => {
+                let new = match dir {
+                    Some(dir) => std::env::current_dir()?.join(dir),
+                    None => self.project_root()?,
+                };
+                let new = new
+                    .canonicalize()
+                    .map_err(|err| anyhow::anyhow!("Can't cd to {}: {err}", new.display()))?;
+                if !new.is_dir() {
+                    anyhow::bail!("Not a directory: {}", new.display());
+                }
+                self.notify(Level::Info, new.display().to_string());
+                self.buffer.local_dir = Some(new);
+            }
+            Action::PrintDir => {
+                let dir = match &self.buffer.local_dir {
+                    Some(dir) => dir.clone(),
+                    None => std::env::current_dir()?,
+                };
+                self.notify(Level::Info, dir.display().to_string());
+            }
+            Action::OpenEntry => {
+                let entry = self
+                    .buffer
+                    .get(self.buffer_line())
+                    .filter(|_| self.buffer.directory);
+                match (&self.buffer.file, entry) {
+                    (Some(dir), Some(entry)) => {
+                        let path = dirlist::entry_path(dir, &entry);
+                        self.push_jump();
+                        self.open_file(&path, buffer)?;
+                    }
+                    _ => {
+                        self.execute(&Action::MoveDown, buffer)?;
+                    }
+                }
+            }
+            Action::NextArg | Action::PrevArg => {
+                let index = if matches!(action, Action::NextArg) {
+                    Some(self.arg_index + 1).filter(|i| *i < self.arglist.len())
+                } else {
+                    self.arg_index.checked_sub(1)
+                };
+                let Some(index) = index else {
+                    anyhow::bail!("No more files in the argument list");
+                };
+                let file = self.arglist[index].clone();
+                self.push_jump();
+                self.open_file(&file, buffer)?;
+                self.arg_index = index;
+                let count = self.arglist.len();
+                self.notify(
+                    Level::Info,
+                    format!("\"{file}\" ({} of {count})", index + 1),
+                );
+            }
+            Action::TabNew(file) => {
+                let left = self.current_tab();
+                match file {
+                    Some(file) => self.open_file(file, buffer)?,
+                    None => self.new_buffer(buffer)?,
+                }
+                self.tabs.open(left, self.current_tab());
+                self.update_tabline(buffer)?;
+            }
+            Action::TabClose => {
+                let Some(tab) = self.tabs.close() else {
+                    anyhow::bail!("Cannot close the last tab page");
+                };
+                self.show_tab(tab, buffer)?;
+                self.update_tabline(buffer)?;
+            }
+            Action::NextTab | Action::PrevTab => {
+                let offset = if matches!(action, Action::NextTab) {
+                    1
+                } else {
+                    -1
+                };
+                let tab = self.tabs.go(self.current_tab(), offset);
+                self.show_tab(tab, buffer)?;
+            }
+            Action::SwitchBuffer(id) => {
+                self.push_jump();
+                self.switch_buffer(*id, buffer)?;
+            }
+            Action::CloseBuffer(id, force) => self.close_buffer(*id, *force, buffer)?,
+            Action::PickerDelete => {
+                let Some(picker) = &self.picker else {
+                    return Ok(false);
+                };
+                if picker.kind != PickerKind::Buffers {
+                    return Ok(false);
+                }
+                let Some(id) = picker.selected().and_then(buffers::parse_id) else {
+                    return Ok(false);
+                };
+                let modified = if id == self.buffers.current() {
+                    self.buffer.modified
+                } else {
+                    self.buffers
+                        .hidden()
+                        .any(|(i, hidden)| i == id && hidden.buffer.modified)
+                };
+                if modified && self.confirm_close != Some(id) {
+                    self.confirm_close = Some(id);
+                    self.notify(
+                        Level::Warning,
+                        format!("Buffer {id} has unsaved changes, Ctrl-d again closes it"),
+                    );
+                    return Ok(false);
+                }
+                let query = self.picker.as_ref().map(|p| p.query.clone());
+                self.close_buffer(Some(id), true, buffer)?;
+                self.execute(&Action::ListBuffers, buffer)?;
+                if let (Some(picker), Some(query)) = (&mut self.picker, query) {
+                    for c in query.chars() {
+                        picker.push_char(c);
+                    }
+                }
+            }
+            Action::OpenQuickfix => {
+                if self.quickfix.is_empty() {
+                    self.notify(Level::Info, "quickfix list is empty");
+                } else {
+                    let items = self
+                        .quickfix
+                        .entries()
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect();
+                    self.open_picker(
+                        Picker::new(PickerKind::Quickfix, "Quickfix").with_items(items),
+                    );
+                }
+            }
+            Action::QuickfixFromDiagnostics => {
+                let Some(file) = self.buffer.file.clone() else {
+                    self.notify(Level::Warning, "no file name");
+                    return Ok(false);
+                };
+                let entries = self
+                    .diagnostics
+                    .iter()
+                    .map(|d| Entry {
+                        file: file.clone(),
+                        line: d.line,
+                        x: d.x,
+                        text: format!("{:?}: {}", d.severity, d.message),
+                    })
+                    .collect();
+                self.quickfix.set(entries);
+                self.notify(
+                    Level::Info,
+                    format!("{} diagnostics", self.quickfix.entries().len()),
+                );
+            }
+            Action::Make(args) => self.start_make(args)?,
+            Action::ToggleMakeOutput => {
+                self.show_make = !self.show_make && self.make.is_some();
+                self.draw_viewport(buffer)?;
+            }
+            Action::ToggleInlayHints => {
+                self.buffer.inlay_hints = Some(!self.show_hints());
+                self.request_hints();
+                self.draw_viewport(buffer)?;
+            }
+            Action::ToggleProfile => {
+                self.profile = match self.profile {
+                    Some(_) => None,
+                    None => Some(Profile::default()),
+                };
+                // clears the overlay off the text
                 self.draw_viewport(buffer)?;
             }
-            Action::Undo => {
-                if let Some(undo_action) = self.undo_actions.pop() {
-                    self.execute(&undo_action, buffer)?;
+            Action::ToggleZen => {
+                self.zen = !self.zen;
+                self.vx = self.text_left();
+                self.vleft = 0;
+                self.check_bounds();
+                self.draw_viewport(buffer)?;
+            }
+            Action::DiffWith(file) => {
+                let text = fs::read_to_string(file)
+                    .map_err(|err| anyhow::anyhow!("Can't read {file}: {err}"))?;
+                self.open_diff(file.clone(), &text);
+            }
+            Action::DiffHead => {
+                let Some(file) = &self.buffer.file else {
+                    anyhow::bail!("No file name");
                 };
+                let text = git::head_contents(file)?;
+                self.open_diff("HEAD".to_string(), &text);
             }
-            Action::InsertLineAt(y, contents) => {
-                if let Some(contents) = contents {
-                    self.buffer.insert_line(*y, contents.to_string());
-                    self.draw_viewport(buffer)?;
+            Action::DiffDown(n) | Action::DiffUp(n) => {
+                let height = self.diff_height();
+                if let Some(diff) = &mut self.diff {
+                    let row = match action {
+                        Action::DiffDown(_) => diff.row + n,
+                        _ => diff.row.saturating_sub(*n),
+                    };
+                    diff.move_to(row, height);
                 }
             }
-            Action::MoveLineToViewportCenter => {
-                let viewport_center = self.vheight() / 2;
-                let distance_to_center = self.cy as isize - viewport_center as isize;
-
-                if distance_to_center > 0 {
-                    // if distance_to_center is negative, we need to move the scroll up
-                    let distance_to_center = distance_to_center.abs() as usize;
-                    if self.vtop > distance_to_center {
-                        let new_vtop = self.vtop + distance_to_center;
-                        self.vtop = new_vtop;
-                        self.cy = viewport_center;
-                        self.draw_viewport(buffer)?;
-                    }
-                } else if distance_to_center < 0 {
-                    // if distance_to_center is negative, we need to move the scroll down
-                    let distance_to_center = distance_to_center.abs() as usize;
-                    let distance_to_go = self.vtop + distance_to_center;
-                    let new_vtop = self.vtop.saturating_sub(distance_to_center);
-                    if self.buffer.len() > distance_to_go && new_vtop != self.vtop {
-                        self.vtop = new_vtop;
-                        self.cy = viewport_center;
-                        self.draw_viewport(buffer)?;
-                    }
+            Action::DiffGoto => {
+                if let Some(diff) = self.diff.take() {
+                    self.move_to(0, diff.buffer_line(), buffer)?;
+                    self.draw_viewport(buffer)?;
                 }
             }
-            Action::InsertLineAtCursor => {
-                self.undo_actions
-                    .push(Action::DeleteLineAt(self.buffer_line()));
-                self.buffer.insert_line(self.buffer_line(), String::new());
-                self.cx = 0;
-                self.draw_viewport(buffer)?;
-            }
-            Action::InsertLineBelowCursor => {
-                self.undo_actions
-                    .push(Action::DeleteLineAt(self.buffer_line() + 1));
-                self.buffer
-                    .insert_line(self.buffer_line() + 1, String::new());
-                self.cy += 1;
-                self.cx = 0;
+            Action::CloseDiff => {
+                self.diff = None;
                 self.draw_viewport(buffer)?;
             }
-            Action::MoveToTop => {
-                self.vtop = 0;
-                self.cy = 0;
+            Action::TogglePreview => {
+                if !self.show_preview && self.buffer.filetype != "markdown" {
+                    anyhow::bail!("Only Markdown buffers have a preview");
+                }
+                self.show_preview = !self.show_preview;
                 self.draw_viewport(buffer)?;
             }
-            Action::MoveToBottom => {
-                if self.buffer.len() > self.vheight() as usize {
-                    self.vtop = self.buffer.len() - self.vheight() as usize;
-                    self.cy = self.vheight() - 1;
+            Action::Shell(command) => self.run_shell(command)?,
+            Action::ReadShell(command) => self.read_shell(command, buffer)?,
+            Action::FilterSelection => {
+                if let Some((_, anchor)) = self.selection_anchor {
+                    let line = self.buffer_line();
+                    let (top, bottom) = (anchor.min(line), anchor.max(line));
+                    self.execute(&Action::EnterMode(Mode::Command), buffer)?;
+                    self.command = format!("{},{}!", top + 1, bottom + 1);
                     self.draw_viewport(buffer)?;
+                }
+            }
+            Action::FilterLines(from, to, command) => {
+                self.filter_lines(*from, *to, command, buffer)?;
+            }
+            Action::ToggleTerminal => {
+                if matches!(self.mode, Mode::Terminal) {
+                    self.mode = Mode::Normal;
                 } else {
-                    self.cy = self.buffer.len() - 1;
+                    self.open_terminal()?;
                 }
             }
-            Action::UndoMultiple(actions) => {
-                for action in actions.iter().rev() {
-                    self.execute(&action, buffer)?;
+            Action::TerminalInput(input) => {
+                if let Some(terminal) = &mut self.terminal {
+                    terminal.write(input)?;
                 }
             }
-            Action::DeleteLineAt(y) => {
-                self.buffer.remove_line(*y);
-                self.draw_viewport(buffer)?;
+            Action::LiveGrep => {
+                self.open_picker(Picker::new(PickerKind::Grep, "Live grep").live());
             }
-            Action::DeletePreviousChar => {
-                if self.cx > 0 {
-                    self.cx -= 1;
-                    self.buffer.remove(self.cx, self.buffer_line());
-                    self.draw_line(buffer);
+            Action::PickerInsertChar(c) => {
+                if let Some(picker) = &mut self.picker {
+                    picker.push_char(*c);
+                }
+                self.restart_live_picker()?;
+            }
+            Action::PickerDeleteChar => {
+                if let Some(picker) = &mut self.picker {
+                    picker.pop_char();
+                }
+                self.restart_live_picker()?;
+            }
+            Action::PickerNext => {
+                if let Some(picker) = &mut self.picker {
+                    picker.select_next();
+                }
+            }
+            Action::PickerPrev => {
+                if let Some(picker) = &mut self.picker {
+                    picker.select_prev();
+                }
+            }
+            Action::PickerAccept => {
+                let picker = self.picker.take();
+                self.mode = Mode::Normal;
+                if let Some(picker) = picker {
+                    if let Some(item) = picker.selected() {
+                        match picker.kind {
+                            PickerKind::Files => {
+                                self.push_jump();
+                                let file = self.project_file(item)?;
+                                if let Err(err) = self.open_file(&file, buffer) {
+                                    self.notify(Level::Error, err.to_string());
+                                }
+                            }
+                            PickerKind::Recent => {
+                                self.push_jump();
+                                if let Err(err) = self.open_file(item, buffer) {
+                                    self.notify(Level::Error, err.to_string());
+                                }
+                            }
+                            PickerKind::CommandHistory => {
+                                // put back on the prompt, to edit or run
+                                self.command = item.to_string();
+                                self.mode = Mode::Command;
+                            }
+                            PickerKind::Buffers => {
+                                if let Some(id) = buffers::parse_id(item) {
+                                    self.push_jump();
+                                    if let Err(err) = self.switch_buffer(id, buffer) {
+                                        self.notify(Level::Error, err.to_string());
+                                    }
+                                }
+                            }
+                            PickerKind::Quickfix => {
+                                let entries = self.quickfix.entries();
+                                let index = entries.iter().position(|e| e.to_string() == item);
+                                if let Some(index) = index {
+                                    let entry = self.quickfix.select(index).cloned();
+                                    self.goto_quickfix(entry, buffer);
+                                }
+                            }
+                            PickerKind::Grep => {
+                                // the matches become the quickfix list, for ]q
+                                let matches: Vec<&str> = picker.matches().collect();
+                                let root = self.project_root()?;
+                                let cwd = std::env::current_dir()?;
+                                self.quickfix.set(quickfix::parse(&matches.join("\n")));
+                                self.quickfix
+                                    .rebase(|file| project::rebase(file, &root, &cwd));
+                                if let Some(index) = matches.iter().position(|m| *m == item) {
+                                    self.quickfix.select(index);
+                                }
+                                if let Some((file, line, x)) = grep::parse_result(item) {
+                                    let file = project::rebase(file, &root, &cwd);
+                                    if let Err(err) = self.goto_location(&file, line, x, buffer) {
+                                        self.notify(Level::Error, err.to_string());
+                                    }
+                                }
+                            }
+                            PickerKind::Themes => {
+                                if let Err(err) = self.switch_theme(item, buffer) {
+                                    self.notify(Level::Error, err.to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Action::Lint => {
+                if self.buffer.file.is_none()
+                    || !self.config.lint.contains_key(&self.buffer.filetype)
+                {
+                    self.notify(
+                        Level::Warning,
+                        format!("no lint command for {}", self.buffer.filetype),
+                    );
+                } else {
+                    self.start_lint();
                 }
             }
         }
@@ -844,30 +7366,78 @@ impl Editor {
     }
 }
 
-fn event_to_key_action(mappings: &HashMap<String, KeyAction>, ev: &Event) -> Option<KeyAction> {
-    match ev {
-        event::Event::Key(KeyEvent {
-            code, modifiers, ..
-        }) => {
-            let key = match code {
-                // KeyCode::Char('q') => return Ok(Some(Action::Quit)),
-                KeyCode::Char(c) => format!("{c}"),
-                _ => format!("{code:?}"),
-            };
+/// The width of the gutter before the sign column: the fold indicator and the
+/// line numbers, as wide as the largest one.
+fn gutter_width(line_numbers: LineNumbers, lines: usize) -> usize {
+    match line_numbers {
+        LineNumbers::None => 1,
+        _ => lines.to_string().len() + 1,
+    }
+}
 
-            let key = match *modifiers {
-                KeyModifiers::CONTROL => format!("Ctrl-{key}"),
-                KeyModifiers::ALT => format!("ALT-{key}"),
-                _ => key,
-            };
+/// `text` cut or padded with blanks to `width` columns.
+fn fit(text: &str, width: usize) -> String {
+    let text: String = text.chars().take(width).collect();
+    format!("{text:width$}")
+}
 
-            mappings.get(&key).cloned()
-        }
-        _ => None,
+/// A path relative to the current directory when it's inside it.
+fn display_path(path: &str) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let path = Path::new(path);
+    path.strip_prefix(&cwd)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Applies a language server's edits to `buffer`, returning the action that
+/// undoes them all. Edits are applied from the last one up, so the positions
+/// of the others still hold.
+fn apply_text_edits(buffer: &mut Buffer, edits: &[TextEdit]) -> Action {
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| edit.start);
+    let last = buffer.len().saturating_sub(1);
+    let mut undo = vec![];
+    for edit in edits.iter().rev() {
+        let position = |(line, character): (usize, usize)| match line > last {
+            true => (buffer.get(last).map_or(0, |l| l.len()), last),
+            false => {
+                let contents = buffer.get(line).unwrap_or_default();
+                (lsp::byte_column(&contents, character), line)
+            }
+        };
+        let (start, end) = (position(edit.start), position(edit.end));
+        let old = buffer.remove_range(start, end);
+        let new_end = buffer.insert_text(start.0, start.1, &edit.text);
+        undo.push(Action::InsertTextAt(start.0, start.1, old));
+        undo.push(Action::RemoveRange(start, new_end));
     }
+    Action::UndoMultiple(undo)
+}
+
+/// The edit putting `lines` in place of lines `range` of a buffer `len`
+/// lines long. Lines going at the end are put after the line break of the
+/// line before them, as there's none after the last line.
+fn replace_lines_edit(range: Range<usize>, lines: &[&str], len: usize) -> TextEdit {
+    let last = len.saturating_sub(1);
+    let (start, end, text) = match (range.start, range.end < len) {
+        (_, true) => (
+            (range.start, 0),
+            (range.end, 0),
+            lines.iter().map(|line| format!("{line}\n")).collect(),
+        ),
+        (0, false) => ((0, 0), (last, usize::MAX), lines.join("\n")),
+        (start, false) => (
+            (start - 1, usize::MAX),
+            (last, usize::MAX),
+            lines.iter().map(|line| format!("\n{line}")).collect(),
+        ),
+    };
+    TextEdit { start, end, text }
 }
 
-fn determine_style_for_position(style_info: &Vec<StyleInfo>, pos: usize) -> Option<Style> {
+fn determine_style_for_position(style_info: &[StyleInfo], pos: usize) -> Option<Style> {
     if let Some(s) = style_info.iter().find(|ci| ci.contains(pos)) {
         return Some(s.style.clone());
     }
@@ -877,6 +7447,8 @@ fn determine_style_for_position(style_info: &Vec<StyleInfo>, pos: usize) -> Opti
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::inlay::Hint;
+    use crossterm::style::Color;
 
     #[test]
     #[should_panic(expected = "out of bounds")]
@@ -918,7 +7490,7 @@ mod test {
                 b: 255
             })
         );
-        assert_eq!(buffer.cells[start].style.italic, true);
+        assert!(buffer.cells[start].style.italic);
         assert_eq!(buffer.cells[start + 1].c, 'e');
         assert_eq!(buffer.cells[start + 2].c, 'l');
         assert_eq!(buffer.cells[start + 3].c, 'l');
@@ -959,13 +7531,120 @@ mod test {
         assert_eq!(diff[0].cell.c, 'a');
     }
 
+    #[test]
+    fn test_changes() {
+        let mut buffer = RenderBuffer::new(3, 2, Style::default());
+        assert_eq!(buffer.changes().len(), 6);
+        buffer.present();
+        assert!(buffer.changes().is_empty());
+
+        buffer.set_char(0, 0, ' ', &Style::default());
+        buffer.set_text(1, 1, "ab", &Style::default());
+        let changes = buffer.changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].x, changes[0].y, changes[0].cell.c), (1, 1, 'a'));
+        buffer.present();
+        assert!(buffer.dirty.iter().all(|dirty| !dirty));
+    }
+
+    #[test]
+    fn test_draw_floats() {
+        let mut buffer = RenderBuffer::new(6, 4, Style::default());
+        let dialog = Float::new(0, 0, 4, 3, Layer::Dialog, Style::default()).with_border();
+        let popup = Float::lines(
+            2,
+            1,
+            4,
+            Layer::Popup,
+            &[("ab".to_string(), Style::default())],
+        );
+        buffer.add_float(dialog);
+        buffer.add_float(popup);
+        buffer.draw_floats();
+        let row = |y: usize| -> String {
+            buffer.cells[y * 6..(y + 1) * 6]
+                .iter()
+                .map(|c| c.c)
+                .collect()
+        };
+        // the dialog goes over the popup, though it was added first
+        assert_eq!(row(0), "┌──┐  ");
+        assert_eq!(row(1), "│  │b ");
+        assert_eq!(row(2), "└──┘  ");
+        assert!(buffer.floats.is_empty());
+    }
+
+    #[test]
+    fn test_inlay_hints() {
+        let config = Config {
+            inlay_hints: true,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(None, "let x = f(1);".to_string());
+        let mut render_buffer = RenderBuffer::new(30, 5, Style::default());
+        let mut editor = Editor::with_size(30, 5, config, Theme::default(), buffer).unwrap();
+        editor.hints.set(vec![
+            Hint {
+                line: 0,
+                x: 5,
+                label: ": u8".to_string(),
+            },
+            Hint {
+                line: 0,
+                x: 10,
+                label: "n: ".to_string(),
+            },
+        ]);
+        editor.draw_viewport(&mut render_buffer).unwrap();
+        let row: String = render_buffer.cells[editor.vx..editor.vx + 20]
+            .iter()
+            .map(|c| c.c)
+            .collect();
+        assert_eq!(row, "let x: u8 = f(n: 1);");
+
+        editor.cx = 10;
+        assert_eq!(editor.cursor_cell(), (17, 0));
+        assert_eq!(editor.position_at((editor.vx + 6) as u16, 0), Some((5, 0)));
+        editor
+            .execute(&Action::ToggleInlayHints, &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.cursor_cell(), (10, 0));
+    }
+
+    #[test]
+    fn test_draw_signature() {
+        let buffer = Buffer::new(None, "\nadd(1, ".to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        editor.mode = Mode::Insert;
+        editor.cursor_to_line(1);
+        editor.cx = 7;
+        editor.signature = Some(Signature {
+            label: "fn add(a: u8, b: u8)".to_string(),
+            active: Some(14..19),
+        });
+        editor.draw_floats(&mut render_buffer);
+        let start = editor.vy * 40 + editor.vx + 7;
+        let row: String = render_buffer.cells[start..start + 22]
+            .iter()
+            .map(|c| c.c)
+            .collect();
+        assert_eq!(row, " fn add(a: u8, b: u8) ");
+
+        editor
+            .execute(&Action::InsertCharAtCursorPos(')'), &mut render_buffer)
+            .unwrap();
+        assert!(editor.signature.is_none());
+    }
+
     #[test]
     fn test_draw_viewport() {
         let contents = "hello\nworld!";
         let config = Config::default();
         let theme = Theme::default();
         let buffer = Buffer::new(None, contents.to_string());
-        // log!("buffer: {buffer:?}");
+        // log!(Debug, "buffer: {buffer:?}");
         let mut render_buffer = RenderBuffer::new(10, 10, Style::default());
         let mut editor = Editor::with_size(10, 10, config, theme, buffer).unwrap();
         editor.draw_viewport(&mut render_buffer).unwrap();
@@ -982,6 +7661,660 @@ mod test {
         assert_eq!(render_buffer.cells[9].c, ' ');
     }
 
+    #[test]
+    fn test_scrolloff() {
+        let contents = (1..=100).map(|n| n.to_string()).collect::<Vec<_>>();
+        let config = Config {
+            scrolloff: 3,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(None, contents.join("\n"));
+        let mut editor = Editor::with_size(10, 10, config, Theme::default(), buffer).unwrap();
+        let last_row = editor.vheight() - 1;
+        editor.cy = last_row;
+        assert!(editor.apply_scrolloff());
+        assert_eq!((editor.vtop, editor.cy), (3, last_row - 3));
+        editor.cy = 1;
+        assert!(editor.apply_scrolloff());
+        assert_eq!((editor.vtop, editor.cy), (1, 3));
+        assert!(!editor.apply_scrolloff());
+    }
+
+    #[test]
+    fn test_wrap() {
+        let config = Config {
+            wrap: true,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(None, "the quick brown fox\nend".to_string());
+        let mut render_buffer = RenderBuffer::new(13, 10, Style::default());
+        let mut editor = Editor::with_size(13, 10, config, Theme::default(), buffer).unwrap();
+        editor.draw_viewport(&mut render_buffer).unwrap();
+        let row = |y: usize| -> String {
+            render_buffer.cells[y * 13..(y + 1) * 13]
+                .iter()
+                .map(|cell| cell.c)
+                .collect()
+        };
+        assert_eq!(row(0), " 1 the quick ");
+        assert_eq!(row(1), "   brown fox ");
+        assert_eq!(row(2), " 2 end       ");
+
+        editor.cx = 4;
+        editor.move_visual(true, &mut render_buffer).unwrap();
+        assert_eq!((editor.cx, editor.cursor_cell()), (14, (4, 1)));
+        editor.move_visual(true, &mut render_buffer).unwrap();
+        assert_eq!((editor.buffer_line(), editor.cursor_cell()), (1, (3, 2)));
+    }
+
+    #[test]
+    fn test_zen() {
+        let config = Config {
+            zen_width: 40,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(None, "hello".to_string());
+        let mut render_buffer = RenderBuffer::new(100, 10, Style::default());
+        let mut editor = Editor::with_size(100, 10, config, Theme::default(), buffer).unwrap();
+        let layout = (editor.vx, editor.vheight());
+        editor
+            .execute(&Action::ToggleZen, &mut render_buffer)
+            .unwrap();
+        assert_eq!((editor.vx, editor.text_right()), (30, 70));
+        assert_eq!(editor.vheight(), layout.1 + 1);
+        editor
+            .execute(&Action::ToggleZen, &mut render_buffer)
+            .unwrap();
+        assert_eq!((editor.vx, editor.vheight()), layout);
+    }
+
+    #[test]
+    fn test_diff_view() {
+        let path = std::env::temp_dir().join("rustik-diff-test.txt");
+        fs::write(&path, "a\nb\nc\nd\ne\n").unwrap();
+        let buffer = Buffer::new(None, "a\nB\nc\ne".to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        let file = path.to_string_lossy().to_string();
+        editor
+            .execute(&Action::DiffWith(file), &mut render_buffer)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        editor.draw_diff(&mut render_buffer);
+        render_buffer.draw_floats();
+        let row = |y: usize| -> String {
+            let cells = &render_buffer.cells[y * 40..(y + 1) * 40];
+            cells
+                .iter()
+                .map(|c| c.c)
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        };
+        assert_eq!(row(2), format!("2▎ b{}│2▎ B", " ".repeat(15)));
+        assert_eq!(
+            row(4),
+            format!("4▎ d{}│   {}", " ".repeat(15), "╱".repeat(17))
+        );
+
+        editor
+            .execute(&Action::NextHunk, &mut render_buffer)
+            .unwrap();
+        editor
+            .execute(&Action::NextHunk, &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.diff.as_ref().unwrap().row, 3);
+        editor
+            .execute(&Action::DiffGoto, &mut render_buffer)
+            .unwrap();
+        assert!(editor.diff.is_none());
+        assert_eq!(editor.buffer_line(), 3);
+    }
+
+    #[test]
+    fn test_replace_lines_edit() {
+        let replace = |text: &str, range: Range<usize>, lines: &[&str]| {
+            let mut buffer = Buffer::new(None, text.to_string());
+            let edit = replace_lines_edit(range, lines, buffer.len());
+            apply_text_edits(&mut buffer, &[edit]);
+            buffer.lines.join("|")
+        };
+        assert_eq!(replace("a\nB\nc", 1..2, &["b", "b2"]), "a|b|b2|c");
+        assert_eq!(replace("a\nc", 1..1, &["b"]), "a|b|c");
+        assert_eq!(replace("a\nb\nx", 2..3, &[]), "a|b");
+        assert_eq!(replace("a", 1..1, &["b", "c"]), "a|b|c");
+        assert_eq!(replace("x\ny", 0..2, &["a"]), "a");
+    }
+
+    #[test]
+    fn test_name_buffer() {
+        let path = std::env::temp_dir().join("rustik-scratch-test.rs");
+        _ = fs::remove_file(&path);
+        let file = path.to_string_lossy().to_string();
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor = Editor::with_size(
+            40,
+            10,
+            Config::default(),
+            Theme::default(),
+            Buffer::new(Some("other.txt".to_string()), String::new()),
+        )
+        .unwrap();
+        editor
+            .execute(&Action::NewBuffer, &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.buffer.file, None);
+        editor.buffer.insert_text(0, 0, "x");
+        assert!(editor.execute(&Action::Save, &mut render_buffer).is_ok());
+        assert!(editor.buffer.modified);
+
+        editor
+            .execute(&Action::SaveAs(file.clone(), false), &mut render_buffer)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x\n");
+        assert_eq!(editor.buffer.file.as_deref(), Some(file.as_str()));
+        assert_eq!(editor.buffer.filetype, "rust");
+        assert!(!editor.buffer.modified);
+        let exists = editor.execute(&Action::SaveAs(file.clone(), false), &mut render_buffer);
+        fs::remove_file(&path).unwrap();
+        assert!(exists.is_err());
+    }
+
+    #[test]
+    fn test_window_title() {
+        let buffer = Buffer::new(Some("notes.txt".to_string()), "a\n".to_string());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        assert_eq!(editor.window_title(), "notes.txt — rustik");
+        editor.buffer.modified = true;
+        assert_eq!(editor.window_title(), "notes.txt [+] — rustik");
+        editor.buffer.file = None;
+        assert_eq!(editor.window_title(), "[No Name] [+] — rustik");
+    }
+
+    /// An editor on `text` after running `actions` as keys would, each of
+    /// which must work.
+    fn run_actions(text: &str, actions: &[Action]) -> Editor {
+        run_actions_with(Config::default(), text, actions)
+    }
+
+    fn run_actions_with(config: Config, text: &str, actions: &[Action]) -> Editor {
+        let buffer = Buffer::new(None, text.to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor = Editor::with_size(40, 10, config, Theme::default(), buffer).unwrap();
+        for action in actions {
+            let action = KeyAction::Single(action.clone());
+            editor
+                .execute_key_action(action, &mut render_buffer)
+                .unwrap();
+        }
+        editor
+    }
+
+    #[test]
+    fn test_toggle_comment_in_empty_buffer() {
+        let toggle = |text: &str, actions: &[Action]| {
+            let config = Config {
+                comments: HashMap::from([("text".to_string(), "#".to_string())]),
+                ..Default::default()
+            };
+            run_actions_with(config, text, actions)
+        };
+        for actions in [
+            vec![Action::ToggleComment],
+            vec![Action::EnterMode(Mode::Visual), Action::ToggleComment],
+        ] {
+            let editor = toggle("", &actions);
+            assert_eq!(editor.buffer.text(), "");
+            assert!(matches!(editor.mode, Mode::Normal));
+            assert!(editor.undo_actions.is_empty());
+        }
+        assert_eq!(toggle("a", &[Action::ToggleComment]).buffer.text(), "# a");
+    }
+
+    #[test]
+    fn test_color_column_zero() {
+        // `:set cc=0` is refused, but the config file can still list 0
+        let editor = run_actions("abc", &[Action::SetColorColumn(vec![0, 2])]);
+        assert_eq!(editor.config.colorcolumn, [0, 2]);
+    }
+
+    #[test]
+    fn test_delete_selection_in_empty_buffer() {
+        let editor = run_actions(
+            "",
+            &[Action::EnterMode(Mode::Visual), Action::DeleteSelection],
+        );
+        assert_eq!(editor.buffer.text(), "");
+        assert!(matches!(editor.mode, Mode::Normal));
+        assert!(editor.undo_actions.is_empty());
+    }
+
+    #[test]
+    fn test_block_past_the_end() {
+        let block = || Action::EnterMode(Mode::VisualBlock);
+        let down = [Action::MoveDown, Action::MoveDown, Action::MoveDown];
+        let delete = [&[block()], &down[..], &[Action::DeleteSelection]].concat();
+        assert_eq!(run_actions("ab\ncd", &delete).buffer.text(), "b\nd");
+        assert_eq!(run_actions("", &delete).buffer.text(), "");
+
+        let insert = [
+            &[block()],
+            &down[..],
+            &[
+                Action::BlockInsert,
+                Action::InsertCharAtCursorPos('x'),
+                Action::EnterMode(Mode::Normal),
+            ],
+        ]
+        .concat();
+        assert_eq!(run_actions("ab\ncd", &insert).buffer.text(), "xab\nxcd");
+    }
+
+    #[test]
+    fn test_change_case_in_empty_buffer() {
+        for case in [Case::Lower, Case::Upper] {
+            let actions = [
+                Action::EnterMode(Mode::Visual),
+                Action::ChangeSelectionCase(case),
+            ];
+            let editor = run_actions("", &actions);
+            assert_eq!(editor.buffer.len(), 0);
+            assert!(editor.undo_actions.is_empty());
+        }
+        let actions = [
+            Action::EnterMode(Mode::Visual),
+            Action::MoveRight,
+            Action::ChangeSelectionCase(Case::Upper),
+        ];
+        assert_eq!(run_actions("ab", &actions).buffer.text(), "AB");
+    }
+
+    #[test]
+    fn test_shell_in_empty_buffer() {
+        let filter = |text: &str, from, to| {
+            let actions = [Action::FilterLines(from, to, "sort".to_string())];
+            run_actions(text, &actions).buffer.text()
+        };
+        assert_eq!(filter("", 0, 2), "");
+        assert_eq!(filter("b\na", 0, 2), "a\nb");
+        assert_eq!(filter("c\nb\na", 1, 5), "c\na\nb");
+
+        let read = |text: &str| {
+            let actions = [Action::ReadShell("echo hi".to_string())];
+            run_actions(text, &actions).buffer.text()
+        };
+        assert_eq!(read(""), "hi");
+        assert_eq!(read("a"), "a\nhi");
+    }
+
+    #[test]
+    fn test_filter_selection() {
+        let mut actions = vec![
+            Action::MoveDown,
+            Action::EnterMode(Mode::Visual),
+            Action::MoveDown,
+            Action::FilterSelection,
+        ];
+        let editor = run_actions("d\nc\nb\na", &actions);
+        assert!(matches!(editor.mode, Mode::Command));
+        assert_eq!(editor.command, "2,3!");
+        assert_eq!(editor.selection_anchor, None);
+
+        actions.extend("sort".chars().map(Action::InsertCommandChar));
+        actions.push(Action::ExecuteCommand);
+        let editor = run_actions("d\nc\nb\na", &actions);
+        assert_eq!(editor.buffer.text(), "d\nb\nc\na");
+        assert!(matches!(editor.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn test_move_visual_in_empty_buffer() {
+        for actions in [
+            vec![Action::MoveVisualDown],
+            vec![Action::MoveVisualUp],
+            vec![Action::Operator(Operator::Delete), Action::MoveVisualUp],
+        ] {
+            let editor = run_actions("", &actions);
+            assert_eq!((editor.cx, editor.buffer_line()), (0, 0));
+        }
+        let editor = run_actions("a\nb", &[Action::MoveVisualDown]);
+        assert_eq!(editor.buffer_line(), 1);
+    }
+
+    #[test]
+    fn test_operate_at_edges() {
+        let delete = || Action::Operator(Operator::Delete);
+        let lower = || Action::Operator(Operator::Case(Case::Lower));
+        let text = |text: &str, actions: &[Action]| run_actions(text, actions).buffer.text();
+
+        // `dj`, `d<Down>` and `guj` on the last line do nothing
+        assert_eq!(
+            text("a\nb", &[Action::MoveDown, delete(), Action::MoveDown]),
+            "a\nb"
+        );
+        assert_eq!(
+            text("A\nB", &[Action::MoveDown, lower(), Action::MoveDown]),
+            "A\nB"
+        );
+        assert_eq!(text("AB", &[lower(), Action::MoveDown]), "AB");
+        // `dl` on an empty line or in an empty buffer
+        assert_eq!(
+            text("a\n\nb", &[Action::MoveDown, delete(), Action::MoveRight]),
+            "a\n\nb"
+        );
+        assert_eq!(text("", &[delete(), Action::MoveRight]), "");
+        // while `dG` on the last line takes it, and `dl` the last character
+        assert_eq!(
+            text("a\nb", &[Action::MoveDown, delete(), Action::MoveToBottom]),
+            "a"
+        );
+        assert_eq!(
+            text("ab", &[Action::MoveRight, delete(), Action::MoveRight]),
+            "a"
+        );
+    }
+
+    #[test]
+    fn test_run_command() {
+        let commands = HashMap::from([
+            (
+                "down2".to_string(),
+                KeyAction::Multiple(vec![Action::MoveDown, Action::MoveDown]),
+            ),
+            (
+                "down3".to_string(),
+                KeyAction::Multiple(vec![Action::Run("down2".to_string()), Action::MoveDown]),
+            ),
+            (
+                "loop".to_string(),
+                KeyAction::Single(Action::Run("loop".to_string())),
+            ),
+        ]);
+        let config = Config {
+            commands,
+            ..Config::default()
+        };
+        let buffer = Buffer::new(None, "a\nb\nc\nd\ne\n".to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor = Editor::with_size(40, 10, config, Theme::default(), buffer).unwrap();
+        editor.command = "down3".to_string();
+        editor
+            .execute(&Action::ExecuteCommand, &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.buffer_line(), 3);
+        let looped = editor.execute(&Action::Run("loop".to_string()), &mut render_buffer);
+        assert_eq!(looped.unwrap_err().to_string(), "Command loop runs itself");
+        assert_eq!(editor.running_commands, 0);
+    }
+
+    #[test]
+    fn test_run_script() {
+        let path = std::env::temp_dir().join("rustik-script-test.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let file = path.to_string_lossy().to_string();
+        let buffer = Buffer::from_file(Some(file.clone()), u64::MAX).unwrap();
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        let actions = serde_json::from_str(r#"[{ "GotoLine": 2 }, "DeleteCurrentLine"]"#).unwrap();
+        editor.run_script(actions).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\nthree\n");
+
+        fs::write(&path, "a\n").unwrap();
+        let buffer = Buffer::from_file(Some(file.clone()), u64::MAX).unwrap();
+        let mut past_the_end =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        let actions =
+            serde_json::from_str(r#"["MoveRight", "MoveRight", "DeleteCharAtCursorPos"]"#).unwrap();
+        past_the_end.run_script(actions).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n");
+
+        let actions = vec![
+            Action::DeleteCurrentLine,
+            Action::SetTheme("none".to_string()),
+        ];
+        let failed = editor.run_script(actions);
+        fs::remove_file(&path).unwrap();
+        assert!(failed.unwrap_err().to_string().starts_with("action 2: "));
+    }
+
+    #[test]
+    fn test_session_buffers_and_tabs() {
+        let dir = std::env::temp_dir().join("rustik-session-test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| {
+            let path = dir.join(name);
+            fs::write(&path, "one\ntwo\nthree\n").unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let (a, b, c) = (file("a.txt"), file("b.txt"), file("c.txt"));
+        let open = |file: &str| {
+            let buffer = Buffer::from_file(Some(file.to_string()), u64::MAX).unwrap();
+            let config = Config {
+                large_file: 1,
+                ..Default::default()
+            };
+            Editor::with_size(40, 10, config, Theme::default(), buffer).unwrap()
+        };
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+
+        let mut editor = open(&a);
+        for action in [
+            Action::MoveDown,
+            Action::TabNew(Some(b.clone())),
+            Action::MoveDown,
+            Action::MoveDown,
+            Action::OpenFile(c.clone()),
+        ] {
+            editor.execute(&action, &mut render_buffer).unwrap();
+        }
+        let session = editor.session().unwrap();
+        let files: Vec<&str> = session.buffers.iter().map(|s| s.file.as_str()).collect();
+        assert_eq!(files, [&a, &b, &c]);
+        assert_eq!(session.current().unwrap().file, c);
+        let tabs: Vec<(&str, usize)> = session
+            .tabs
+            .iter()
+            .map(|s| (s.file.as_str(), s.line))
+            .collect();
+        assert_eq!(tabs, [(a.as_str(), 1), (c.as_str(), 0)]);
+        assert_eq!(session.current_tab, 1);
+
+        let mut restored = open(&c);
+        restored.restore_session(&session);
+        let hidden: Vec<(Option<&str>, usize)> = restored
+            .buffers
+            .hidden()
+            .map(|(_, hidden)| (hidden.buffer.file.as_deref(), hidden.line))
+            .collect();
+        assert_eq!(hidden, [(Some(a.as_str()), 1), (Some(b.as_str()), 2)]);
+        assert_eq!((restored.tabs.len(), restored.tabs.current()), (2, 1));
+        assert_eq!(restored.vy, 1);
+        restored
+            .execute(&Action::NextTab, &mut render_buffer)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(restored.buffer.file.as_deref(), Some(a.as_str()));
+        assert_eq!(restored.buffer_line(), 1);
+    }
+
+    #[test]
+    fn test_lint_after_config_change() {
+        let mut editor = run_actions("a", &[]);
+        let (tx, rx) = std::sync::mpsc::channel();
+        editor.lint = Some(("mylint".to_string(), rx));
+        tx.send(Ok(vec![])).unwrap();
+        // the filetype has no lint config anymore
+        assert!(editor.config.lint.is_empty());
+        assert!(editor.receive_lint());
+        assert!(editor.lint.is_none());
+    }
+
+    #[test]
+    fn test_show_locations() {
+        let path = std::env::temp_dir().join("rustik-locations-test.rs");
+        let contents = "fn a() {}\nfn b() { a(); }\nfn c() { a(); }\n";
+        fs::write(&path, contents).unwrap();
+        let file = path.to_string_lossy().to_string();
+        let buffer = Buffer::new(Some(file.clone()), contents.to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        let locations: Vec<Location> = [(1, 9), (2, 9)]
+            .iter()
+            .map(|&(line, character)| Location {
+                path: file.clone(),
+                line,
+                character,
+            })
+            .collect();
+        let entries = editor.location_entries(&locations);
+        assert_eq!(entries[1].text, "fn c() { a(); }");
+
+        editor.show_locations(entries, "references", &mut render_buffer);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(editor.quickfix.entries().len(), 2);
+        assert_eq!((editor.buffer_line(), editor.cx), (1, 9));
+        let back = editor.jumps.back(editor.current_jump()).unwrap();
+        assert_eq!((back.line, back.x), (0, 0));
+    }
+
+    #[test]
+    fn test_apply_workspace_edit() {
+        let other = std::env::temp_dir().join("rustik-rename-test.rs");
+        fs::write(&other, "use crate::foo;\n").unwrap();
+        let other = other.to_string_lossy().to_string();
+        let buffer = Buffer::new(None, "fn foo() {}\nfoo(); foo();".to_string());
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        let edit = |line, character| TextEdit {
+            start: (line, character),
+            end: (line, character + 3),
+            text: "bar".to_string(),
+        };
+        let changes = vec![(other.clone(), vec![edit(0, 11)])];
+        editor
+            .apply_workspace_edit(changes, &mut render_buffer)
+            .unwrap();
+        fs::remove_file(&other).unwrap();
+
+        let id = editor.buffers.find(&other).unwrap();
+        let hidden = editor.buffers.get_mut(id).unwrap();
+        assert_eq!(hidden.buffer.lines, ["use crate::bar;"]);
+        assert!(hidden.buffer.modified && hidden.undo_actions.len() == 1);
+
+        let edits = [edit(1, 7), edit(0, 3), edit(1, 0)];
+        let undo = apply_text_edits(&mut editor.buffer, &edits);
+        assert_eq!(editor.buffer.lines, ["fn bar() {}", "bar(); bar();"]);
+        editor.execute(&undo, &mut render_buffer).unwrap();
+        assert_eq!(editor.buffer.lines, ["fn foo() {}", "foo(); foo();"]);
+    }
+
+    #[test]
+    fn test_hover_docs() {
+        let docs: String = (1..=20).map(|n| format!("/// line {n}\n")).collect();
+        let mut buffer = Buffer::new(
+            None,
+            format!("{docs}fn answer() -> u8 {{\n    42\n}}\nanswer();"),
+        );
+        buffer.filetype = "rust".to_string();
+        let mut render_buffer = RenderBuffer::new(40, 10, Style::default());
+        let mut editor =
+            Editor::with_size(40, 10, Config::default(), Theme::default(), buffer).unwrap();
+        editor.cursor_to_line(23);
+        editor.execute(&Action::Hover, &mut render_buffer).unwrap();
+        let popup = editor.popup.clone().unwrap();
+        assert_eq!(popup[..3], ["fn answer() -> u8", "", "line 1"]);
+
+        editor
+            .execute(&Action::PopupScrollDown(100), &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.popup_scroll, popup.len() - editor.popup_height());
+        editor
+            .execute(&Action::PopupScrollUp(1), &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.popup_scroll, popup.len() - editor.popup_height() - 1);
+
+        // another popup starts at its top
+        editor
+            .execute(&Action::Shell("seq 3".to_string()), &mut render_buffer)
+            .unwrap();
+        assert_eq!(
+            editor.popup.as_deref(),
+            Some(&["1", "2", "3"].map(String::from)[..])
+        );
+        assert_eq!(editor.popup_scroll, 0);
+    }
+
+    #[test]
+    fn test_yank_flash() {
+        let config = Config {
+            yank_flash: 200,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(None, "hello world".to_string());
+        let mut render_buffer = RenderBuffer::new(20, 10, Style::default());
+        let mut editor = Editor::with_size(20, 10, config, Theme::default(), buffer).unwrap();
+        editor.mode = Mode::Visual;
+        editor.selection_anchor = Some((0, 0));
+        editor.cx = 4;
+        editor.execute(&Action::Yank, &mut render_buffer).unwrap();
+        let (flash, _) = editor.flash.unwrap();
+        assert!(flash.contains(4, 0) && !flash.contains(5, 0));
+        assert!(!editor.expire_flash());
+
+        editor.flash = Some((flash, Instant::now()));
+        assert!(editor.expire_flash());
+        assert!(editor.flash.is_none());
+    }
+
+    #[test]
+    fn test_scroll_viewport() {
+        let contents = (1..=20).map(|n| n.to_string()).collect::<Vec<_>>();
+        let buffer = Buffer::new(None, contents.join("\n"));
+        let config = Config::default();
+        let mut editor = Editor::with_size(10, 10, config, Theme::default(), buffer).unwrap();
+        editor.cy = 2;
+        assert!(editor.scroll_viewport(1, true));
+        assert_eq!((editor.vtop, editor.buffer_line()), (1, 2));
+        assert!(editor.scroll_viewport(5, true));
+        assert_eq!((editor.vtop, editor.buffer_line()), (6, 6));
+        assert!(editor.scroll_viewport(100, true));
+        assert_eq!(editor.vtop, 19);
+        assert!(!editor.scroll_viewport(1, true));
+    }
+
+    #[test]
+    fn test_quit_unsaved() {
+        let buffer = Buffer::new(None, "hello".to_string());
+        let config = Config::default();
+        let mut render_buffer = RenderBuffer::new(10, 10, Style::default());
+        let mut editor = Editor::with_size(10, 10, config, Theme::default(), buffer).unwrap();
+        editor.buffer.modified = true;
+        assert!(editor.execute(&Action::Quit, &mut render_buffer).is_err());
+        assert!(editor
+            .execute(&Action::ForceQuit, &mut render_buffer)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_readonly() {
+        let mut buffer = Buffer::new(None, "one\ntwo".to_string());
+        buffer.readonly = true;
+        let config = Config::default();
+        let mut render_buffer = RenderBuffer::new(10, 10, Style::default());
+        let mut editor = Editor::with_size(10, 10, config, Theme::default(), buffer).unwrap();
+        let delete = KeyAction::Single(Action::DeleteCurrentLine);
+        editor
+            .execute_key_action(delete, &mut render_buffer)
+            .unwrap();
+        assert_eq!(editor.buffer.len(), 2);
+        assert!(!editor.buffer.modified);
+    }
+
     #[test]
     fn test_buffer_diff() {
         let contents1 = vec![" 1:2 ".to_string()];
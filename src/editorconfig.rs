@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::encoding::Encoding;
+
+/// What `.editorconfig` files ask for, where they say anything.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Properties {
+    pub tabstop: Option<usize>,
+    pub expandtab: Option<bool>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub final_newline: Option<bool>,
+    pub charset: Option<Encoding>,
+}
+
+/// Reads the `.editorconfig` files from `file`'s directory up to the one
+/// marked `root = true`. Sections further down a file, and files closer to
+/// `file`, take precedence.
+pub fn properties(file: &Path) -> Properties {
+    let mut properties = Properties::default();
+    let Ok(file) = fs::canonicalize(file) else {
+        return properties;
+    };
+    let mut configs = vec![];
+    for dir in file.ancestors().skip(1) {
+        let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) else {
+            continue;
+        };
+        let root = is_root(&text);
+        configs.push((dir.to_path_buf(), text));
+        if root {
+            break;
+        }
+    }
+    for (dir, text) in configs.iter().rev() {
+        apply(&mut properties, text, dir, &file);
+    }
+    properties
+}
+
+fn is_root(text: &str) -> bool {
+    text.lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| key.trim() == "root" && value.trim().eq_ignore_ascii_case("true"))
+}
+
+/// Applies the properties of the sections in `text` that match `file`.
+fn apply(properties: &mut Properties, text: &str, dir: &Path, file: &Path) {
+    let relative = file
+        .strip_prefix(dir)
+        .map_or(PathBuf::new(), Path::to_path_buf);
+    let relative = relative.to_string_lossy();
+    let name = file
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let mut matching = false;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            // patterns without a slash match the name in any directory
+            matching = match section.strip_prefix('/') {
+                Some(section) => glob_matches(section, &relative),
+                None if section.contains('/') => glob_matches(section, &relative),
+                None => glob_matches(section, &name),
+            };
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if !matching {
+            continue;
+        }
+        let value = value.trim().to_lowercase();
+        let flag = match value.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match key.trim().to_lowercase().as_str() {
+            "indent_style" => match value.as_str() {
+                "space" => properties.expandtab = Some(true),
+                "tab" => properties.expandtab = Some(false),
+                _ => {}
+            },
+            "indent_size" | "tab_width" => {
+                if let Ok(size) = value.parse() {
+                    properties.tabstop = Some(size);
+                }
+            }
+            "trim_trailing_whitespace" => {
+                properties.trim_trailing_whitespace = flag.or(properties.trim_trailing_whitespace)
+            }
+            "insert_final_newline" => properties.final_newline = flag.or(properties.final_newline),
+            "charset" => properties.charset = Encoding::find(&value).or(properties.charset),
+            _ => {}
+        }
+    }
+}
+
+/// Matches an EditorConfig glob: `*` within a directory, `**` across them,
+/// `?` for one character and `{a,b}` for either.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    if let Some(open) = pattern.find('{') {
+        if let Some(close) = pattern[open..].find('}').map(|c| open + c) {
+            let (before, after) = (&pattern[..open], &pattern[close + 1..]);
+            return pattern[open + 1..close]
+                .split(',')
+                .any(|choice| glob_matches(&format!("{before}{choice}{after}"), path));
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches_from(&pattern, &path)
+}
+
+fn matches_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches_from(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| matches_from(rest, &path[i..])),
+        ['?', rest @ ..] => {
+            path.first().is_some_and(|&c| c != '/') && matches_from(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && matches_from(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*", "main.rs"));
+        assert!(glob_matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(!glob_matches("*.rs", "src/main.rs"));
+        assert!(glob_matches("src/**.rs", "src/a/main.rs"));
+        assert!(glob_matches("Makefile", "Makefile"));
+    }
+
+    #[test]
+    fn test_properties() {
+        let dir = std::env::temp_dir().join("rustik-test-editorconfig");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n\n[*]\nindent_style = space\nindent_size = 2\ncharset = latin1\n\n[Makefile]\nindent_style = tab\ninsert_final_newline = false\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/.editorconfig"), "[*.rs]\nindent_size = 4\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(dir.join("Makefile"), "").unwrap();
+
+        let main = properties(&dir.join("src/main.rs"));
+        assert_eq!((main.tabstop, main.expandtab), (Some(4), Some(true)));
+        assert_eq!(main.charset, Some(Encoding::Latin1));
+        let makefile = properties(&dir.join("Makefile"));
+        assert_eq!(
+            (makefile.tabstop, makefile.expandtab),
+            (Some(2), Some(false))
+        );
+        assert_eq!(makefile.final_newline, Some(false));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+/// How a file's text is stored on disk. It's decoded on open and encoded
+/// again on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the character with that code.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    /// Looks up an encoding by name, taking the usual spellings of each.
+    pub fn find(name: &str) -> Option<Self> {
+        let encoding = match name.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8,
+            "utf-8-bom" | "utf8-bom" => Encoding::Utf8Bom,
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" | "ucs-2le" => Encoding::Utf16Le,
+            "utf-16be" | "utf16be" | "ucs-2be" => Encoding::Utf16Be,
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Encoding::Latin1,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    /// Guesses the encoding from a byte order mark, then from whether the
+    /// bytes are valid UTF-8, falling back to Latin-1. Returns `None` for
+    /// binary files, which have NUL bytes outside of UTF-16.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+            return Some(Encoding::Utf8Bom);
+        }
+        if bytes.starts_with(&[0xff, 0xfe]) && bytes.len().is_multiple_of(2) {
+            return Some(Encoding::Utf16Le);
+        }
+        if bytes.starts_with(&[0xfe, 0xff]) && bytes.len().is_multiple_of(2) {
+            return Some(Encoding::Utf16Be);
+        }
+        if bytes.contains(&0) {
+            return None;
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Some(Encoding::Utf8),
+            Err(_) => Some(Encoding::Latin1),
+        }
+    }
+
+    /// Decodes `bytes`, dropping the byte order mark. Invalid sequences
+    /// become U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes);
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|pair| match self {
+                        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                        _ => u16::from_be_bytes([pair[0], pair[1]]),
+                    })
+                    .collect();
+                let units = units.strip_prefix(&[0xfeff]).unwrap_or(&units);
+                String::from_utf16_lossy(units)
+            }
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    /// Encodes `text`, with a byte order mark for the encodings that have
+    /// one. Fails on characters the encoding can't store.
+    pub fn encode(self, text: &str) -> anyhow::Result<Vec<u8>> {
+        let bytes = match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Utf8Bom => [&[0xef, 0xbb, 0xbf], text.as_bytes()].concat(),
+            Encoding::Utf16Le => std::iter::once(0xfeff)
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            Encoding::Utf16Be => std::iter::once(0xfeff)
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_be_bytes)
+                .collect(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| match u8::try_from(c) {
+                    Ok(byte) => Ok(byte),
+                    Err(_) => anyhow::bail!("{c:?} can't be written as {}", self.name()),
+                })
+                .collect::<anyhow::Result<_>>()?,
+        };
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(b"caf\xc3\xa9"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::detect(b"caf\xe9"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::detect(b"\xff\xfea\0"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::detect(b"a\0b"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Latin1,
+        ] {
+            let bytes = encoding.encode("café\n").unwrap();
+            assert_eq!(Encoding::detect(&bytes), Some(encoding));
+            assert_eq!(encoding.decode(&bytes), "café\n");
+        }
+        assert!(Encoding::Latin1.encode("€").is_err());
+        assert_eq!(Encoding::find("UTF_16BE"), Some(Encoding::Utf16Be));
+    }
+}
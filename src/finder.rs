@@ -0,0 +1,189 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, mpsc},
+    thread,
+};
+
+use crate::picker::Source;
+
+const BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    base: PathBuf,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/// The `.gitignore` rules in effect for a directory, including the ones
+/// inherited from its parents. Later rules win, so a `!pattern` can re-include
+/// what an earlier rule ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Gitignore {
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    /// Adds the rules from `contents`, a `.gitignore` file in `base`.
+    pub fn add(&mut self, base: &Path, contents: &str) {
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            // a slash anywhere but the end anchors the pattern to `base`
+            let anchored = line.contains('/');
+            self.rules.push(Rule {
+                pattern: line.trim_start_matches('/').to_string(),
+                base: base.to_path_buf(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&rule.base) else {
+                continue;
+            };
+            let matched = if rule.anchored {
+                glob_match(&rule.pattern, &relative.to_string_lossy())
+            } else {
+                path.file_name()
+                    .is_some_and(|name| glob_match(&rule.pattern, &name.to_string_lossy()))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Matches `text` against a gitignore style glob: `*` matches within a path
+/// segment, `**` across segments and `?` any single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match p {
+            [] => t.is_empty(),
+            ['*', '*', rest @ ..] => {
+                let rest = rest.strip_prefix(&['/']).unwrap_or(rest);
+                (0..=t.len()).any(|i| matches(rest, &t[i..]))
+            }
+            ['*', rest @ ..] => (0..=t.len())
+                .take_while(|i| *i == 0 || t[i - 1] != '/')
+                .any(|i| matches(rest, &t[i..])),
+            ['?', rest @ ..] => t.first().is_some_and(|c| *c != '/') && matches(rest, &t[1..]),
+            [c, rest @ ..] => t.first() == Some(c) && matches(rest, &t[1..]),
+        }
+    }
+
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    matches(&p, &t)
+}
+
+/// Calls `f` with every file under `root`, skipping `.git` and whatever the
+/// `.gitignore` files along the way ignore. The walk stops early once `f`
+/// returns false.
+pub fn walk(root: &Path, f: &mut impl FnMut(&Path) -> bool) {
+    let mut dirs = vec![(root.to_path_buf(), Gitignore::default())];
+    while let Some((dir, mut ignore)) = dirs.pop() {
+        if let Ok(contents) = fs::read_to_string(dir.join(".gitignore")) {
+            ignore.add(&dir, &contents);
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        let mut entries: Vec<_> = entries.flatten().collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if entry.file_name() == ".git" || ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                dirs.push((path, ignore.clone()));
+            } else if file_type.is_file() && !f(&path) {
+                return;
+            }
+        }
+    }
+}
+
+/// Lists the files under `root` on a separate thread, sending their paths
+/// relative to `root` in batches as they're found.
+pub fn scan(root: &Path) -> Source {
+    let (tx, rx) = mpsc::channel();
+    let source = Source::new(rx);
+    let cancelled = source.cancelled.clone();
+    let root = root.to_path_buf();
+
+    thread::spawn(move || {
+        let mut batch = vec![];
+        walk(&root, &mut |path| {
+            let path = path.strip_prefix(&root).unwrap_or(path);
+            batch.push(path.to_string_lossy().to_string());
+            if batch.len() >= BATCH_SIZE {
+                _ = tx.send(std::mem::take(&mut batch));
+            }
+            !cancelled.load(Ordering::Relaxed)
+        });
+        _ = tx.send(batch);
+    });
+
+    source
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/a/b/main.rs"));
+        assert!(glob_match("**/fixtures", "src/fixtures"));
+        assert!(glob_match("ma?n.rs", "main.rs"));
+        assert!(!glob_match("target", "targets"));
+    }
+
+    #[test]
+    fn test_gitignore_rules() {
+        let root = Path::new("/repo");
+        let mut ignore = Gitignore::default();
+        ignore.add(
+            root,
+            "# build output\ntarget/\n*.log\n!keep.log\n/Cargo.lock\n",
+        );
+
+        assert!(ignore.is_ignored(Path::new("/repo/target"), true));
+        assert!(!ignore.is_ignored(Path::new("/repo/target"), false));
+        assert!(ignore.is_ignored(Path::new("/repo/src/debug.log"), false));
+        assert!(!ignore.is_ignored(Path::new("/repo/keep.log"), false));
+        assert!(ignore.is_ignored(Path::new("/repo/Cargo.lock"), false));
+        assert!(!ignore.is_ignored(Path::new("/repo/src/Cargo.lock"), false));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::theme::Style;
+
+/// Which floats go on top of which, from the bottom up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// Panels along the edges of the viewport, like the `:make` output.
+    Panel,
+    /// Popups next to the cursor: hover information and completions.
+    Popup,
+    /// Boxes that take the keys typed, like the picker and the terminal.
+    Dialog,
+}
+
+/// A box drawn over the viewport, holding rows of styled text. The rest of
+/// a row is filled in the style of its last piece of text.
+#[derive(Debug, Clone)]
+pub struct Float {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub layer: Layer,
+    /// The style of the border, and of the rows without any text.
+    pub style: Style,
+    pub border: bool,
+    pub rows: Vec<Vec<(String, Style)>>,
+}
+
+impl Float {
+    pub fn new(
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        layer: Layer,
+        style: Style,
+    ) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            layer,
+            style,
+            border: false,
+            rows: vec![],
+        }
+    }
+
+    /// A float of single style lines, each starting with a blank.
+    pub fn lines(
+        x: usize,
+        y: usize,
+        width: usize,
+        layer: Layer,
+        lines: &[(String, Style)],
+    ) -> Self {
+        let style = lines.first().map(|(_, s)| s.clone()).unwrap_or_default();
+        let mut float = Self::new(x, y, width, lines.len(), layer, style);
+        float.rows = lines
+            .iter()
+            .map(|(line, style)| vec![(format!(" {line}"), style.clone())])
+            .collect();
+        float
+    }
+
+    pub fn with_border(mut self) -> Self {
+        self.border = true;
+        self
+    }
+
+    /// The box the rows go in, inside the border, as `(x, y, width, height)`.
+    pub fn inner(&self) -> (usize, usize, usize, usize) {
+        match self.border {
+            true => (
+                self.x + 1,
+                self.y + 1,
+                self.width.saturating_sub(2),
+                self.height.saturating_sub(2),
+            ),
+            false => (self.x, self.y, self.width, self.height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inner() {
+        let float = Float::new(2, 3, 10, 5, Layer::Dialog, Style::default());
+        assert_eq!(float.inner(), (2, 3, 10, 5));
+        assert_eq!(float.with_border().inner(), (3, 4, 8, 3));
+        assert!(Layer::Panel < Layer::Popup && Layer::Popup < Layer::Dialog);
+    }
+}
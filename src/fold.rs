@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use tree_sitter::{InputEdit, Point};
+
+/// Closed folds as half-open line ranges. The first line of a fold stays
+/// visible as its header while the rest are hidden.
+#[derive(Debug, Default)]
+pub struct Folds {
+    closed: Vec<Range<usize>>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.closed.iter().any(|f| f.start < line && line < f.end)
+    }
+
+    /// Returns the number of lines hidden under `line` if it's a fold header.
+    pub fn folded_at(&self, line: usize) -> Option<usize> {
+        self.closed
+            .iter()
+            .filter(|f| f.start == line)
+            .map(|f| f.end - f.start - 1)
+            .max()
+    }
+
+    pub fn next_visible(&self, line: usize) -> usize {
+        let mut next = line + 1;
+        while let Some(end) = self
+            .closed
+            .iter()
+            .filter(|f| f.start < next && next < f.end)
+            .map(|f| f.end)
+            .max()
+        {
+            next = end;
+        }
+        next
+    }
+
+    pub fn prev_visible(&self, line: usize) -> Option<usize> {
+        let mut prev = line.checked_sub(1)?;
+        while let Some(start) = self
+            .closed
+            .iter()
+            .filter(|f| f.start < prev && prev < f.end)
+            .map(|f| f.start)
+            .min()
+        {
+            prev = start;
+        }
+        Some(prev)
+    }
+
+    /// Returns the line shown `n` visible lines below `line`.
+    pub fn nth_visible(&self, line: usize, n: usize) -> usize {
+        if self.is_empty() {
+            return line + n;
+        }
+        (0..n).fold(line, |line, _| self.next_visible(line))
+    }
+
+    /// Returns the line shown `n` visible lines above `line`, stopping at the top.
+    pub fn nth_visible_back(&self, line: usize, n: usize) -> usize {
+        let mut line = line;
+        for _ in 0..n {
+            match self.prev_visible(line) {
+                Some(prev) => line = prev,
+                None => break,
+            }
+        }
+        line
+    }
+
+    pub fn close(&mut self, range: Range<usize>) {
+        if range.end - range.start > 1 && !self.closed.contains(&range) {
+            self.closed.push(range);
+        }
+    }
+
+    /// Returns `line` if it's visible, or the header of the outermost closed
+    /// fold hiding it.
+    pub fn visible(&self, line: usize) -> usize {
+        if self.is_hidden(line) {
+            self.prev_visible(line).unwrap_or_default()
+        } else {
+            line
+        }
+    }
+
+    /// Opens every closed fold whose lines include `line`, returning whether
+    /// any fold was opened.
+    pub fn open_at(&mut self, line: usize) -> bool {
+        let len = self.closed.len();
+        self.closed.retain(|f| !f.contains(&line));
+        len != self.closed.len()
+    }
+
+    /// Opens just the folds hiding `line`, returning whether any was opened.
+    pub fn reveal(&mut self, line: usize) -> bool {
+        let len = self.closed.len();
+        self.closed.retain(|f| !(f.start < line && line < f.end));
+        len != self.closed.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.closed.clear();
+    }
+
+    /// Keeps folds attached to their lines when the buffer changes.
+    pub fn apply_edit(&mut self, edit: &InputEdit) {
+        if edit.old_end_position.row == edit.new_end_position.row {
+            return;
+        }
+        self.closed.retain_mut(|f| {
+            f.start = shift_row(f.start, edit);
+            f.end = shift_row(f.end, edit);
+            f.end - f.start > 1
+        });
+    }
+}
+
+/// Where `row` ends up after `edit`. Rows inside a removed range move to
+/// the end of the replacement.
+pub fn shift_row(row: usize, edit: &InputEdit) -> usize {
+    let point = Point::new(row, 0);
+    if point >= edit.old_end_position {
+        row + edit.new_end_position.row - edit.old_end_position.row
+    } else if point > edit.start_position {
+        edit.new_end_position.row
+    } else {
+        row
+    }
+}
+
+/// Computes foldable ranges from indentation: a line followed by more deeply
+/// indented lines starts a fold that runs until the indentation drops back.
+pub fn indent_ranges(lines: &[String]) -> Vec<Range<usize>> {
+    (0..lines.len())
+        .filter_map(|start| indent_range_at(lines, start))
+        .collect()
+}
+
+pub fn indent_range_at(lines: &[String], start: usize) -> Option<Range<usize>> {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let line = lines.get(start)?;
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    let level = indent(line);
+    let mut last = start;
+    for (n, next) in lines.iter().enumerate().skip(start + 1) {
+        if next.trim().is_empty() {
+            continue;
+        }
+        if indent(next) <= level {
+            break;
+        }
+        last = n;
+    }
+
+    (last > start).then_some(start..last + 1)
+}
+
+/// Picks the innermost range containing `line`.
+pub fn innermost(ranges: &[Range<usize>], line: usize) -> Option<Range<usize>> {
+    ranges
+        .iter()
+        .filter(|r| r.contains(&line))
+        .min_by_key(|r| r.end - r.start)
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_indent_ranges() {
+        let ranges = indent_ranges(&lines("a\n  b\n\n  c\n    d\ne"));
+        assert_eq!(ranges, vec![0..5, 3..5]);
+    }
+
+    #[test]
+    fn test_visible_lines_skip_folds() {
+        let mut folds = Folds::default();
+        folds.close(2..5);
+        assert!(folds.is_hidden(3));
+        assert!(!folds.is_hidden(2));
+        assert_eq!(folds.folded_at(2), Some(2));
+        assert_eq!(folds.next_visible(2), 5);
+        assert_eq!(folds.prev_visible(5), Some(2));
+        assert_eq!(folds.nth_visible(0, 4), 6);
+        assert_eq!(folds.nth_visible_back(6, 4), 0);
+        assert_eq!(folds.visible(4), 2);
+        assert!(!folds.reveal(2));
+        assert!(folds.open_at(4));
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn test_folds_follow_line_edits() {
+        let mut folds = Folds::default();
+        folds.close(2..5);
+        // a line inserted above the fold
+        folds.apply_edit(&InputEdit {
+            start_byte: 0,
+            old_end_byte: 0,
+            new_end_byte: 2,
+            start_position: Point::new(0, 0),
+            old_end_position: Point::new(0, 0),
+            new_end_position: Point::new(1, 0),
+        });
+        assert_eq!(folds.folded_at(3), Some(2));
+    }
+}
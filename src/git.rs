@@ -0,0 +1,679 @@
+use std::{
+    io::Write,
+    ops::Range,
+    path::Path,
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Past this many edits the diff stops looking for the shortest edit script
+/// and reports the rest as one changed block.
+const MAX_EDITS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl HunkKind {
+    pub fn sign(&self) -> char {
+        match self {
+            HunkKind::Added | HunkKind::Modified => '▎',
+            HunkKind::Deleted => '▁',
+        }
+    }
+}
+
+/// A block of lines that differs from `HEAD`, spanning lines `start..end` of
+/// the buffer. Deleted hunks are empty and start where the lines were.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub kind: HunkKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Hunk {
+    /// The line the hunk starts on in the gutter. Deletions are marked on the
+    /// line above them.
+    pub fn line(&self) -> usize {
+        match self.kind {
+            HunkKind::Deleted => self.start.saturating_sub(1),
+            _ => self.start,
+        }
+    }
+
+    fn contains(&self, line: usize) -> bool {
+        match self.kind {
+            HunkKind::Deleted => line == self.line(),
+            _ => (self.start..self.end).contains(&line),
+        }
+    }
+}
+
+/// The hunks of the buffer, ordered by line.
+#[derive(Debug, Default)]
+pub struct GitSigns {
+    hunks: Vec<Hunk>,
+}
+
+impl GitSigns {
+    pub fn new(hunks: Vec<Hunk>) -> Self {
+        Self { hunks }
+    }
+
+    pub fn on_line(&self, line: usize) -> Option<HunkKind> {
+        self.hunks.iter().find(|h| h.contains(line)).map(|h| h.kind)
+    }
+
+    pub fn next(&self, line: usize) -> Option<&Hunk> {
+        self.hunks
+            .iter()
+            .find(|h| h.line() > line)
+            .or_else(|| self.hunks.first())
+    }
+
+    pub fn prev(&self, line: usize) -> Option<&Hunk> {
+        self.hunks
+            .iter()
+            .rev()
+            .find(|h| h.line() < line)
+            .or_else(|| self.hunks.last())
+    }
+}
+
+/// Who last changed a line, and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    pub author: String,
+    /// Seconds since the epoch.
+    pub time: u64,
+    pub summary: String,
+    pub committed: bool,
+}
+
+impl BlameLine {
+    /// Formats the line's annotation, like `Jane Doe, 3 days ago • Fix typo`.
+    pub fn describe(&self, now: u64) -> String {
+        if !self.committed {
+            return "Not committed yet".to_string();
+        }
+        let ago = relative_time(now.saturating_sub(self.time));
+        format!("{}, {ago} • {}", self.author, self.summary)
+    }
+}
+
+fn relative_time(seconds: u64) -> String {
+    let (n, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        86400..2592000 => (seconds / 86400, "day"),
+        2592000..31536000 => (seconds / 2592000, "month"),
+        _ => (seconds / 31536000, "year"),
+    };
+    let s = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{s} ago")
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Blames `lines`, the buffer's contents, against the history of `file` on a
+/// separate thread. Lines changed in the buffer show up as not committed.
+pub fn blame(file: &str, lines: Vec<String>) -> Receiver<anyhow::Result<Vec<BlameLine>>> {
+    let (tx, rx) = mpsc::channel();
+    let file = file.to_string();
+
+    thread::spawn(move || {
+        let result = run_blame(&file, &lines).map(|output| parse_blame(&output));
+        _ = tx.send(result);
+    });
+
+    rx
+}
+
+fn run_blame(file: &str, lines: &[String]) -> anyhow::Result<String> {
+    let path = Path::new(file);
+    let (dir, name) = split_path(path)?;
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["blame", "--line-porcelain", "--contents", "-", "--"])
+        .arg(name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let contents = lines.join("\n") + "\n";
+    // written on another thread so a full stdout pipe can't block it
+    let writer = thread::spawn(move || stdin.write_all(contents.as_bytes()));
+    let output = child.wait_with_output()?;
+    _ = writer.join();
+
+    if !output.status.success() {
+        anyhow::bail!("{file} is not tracked by git");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses `git blame --line-porcelain` output, which repeats the commit
+/// details for every line, into one entry per line.
+fn parse_blame(output: &str) -> Vec<BlameLine> {
+    let mut lines = vec![];
+    let mut current: Option<BlameLine> = None;
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            lines.extend(current.take());
+            continue;
+        }
+        let Some(blame) = &mut current else {
+            let commit = line.split(' ').next().unwrap_or_default();
+            current = Some(BlameLine {
+                author: String::new(),
+                time: 0,
+                summary: String::new(),
+                committed: commit.chars().any(|c| c != '0'),
+            });
+            continue;
+        };
+        if let Some(author) = line.strip_prefix("author ") {
+            blame.author = author.to_string();
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            blame.time = time.parse().unwrap_or_default();
+        } else if let Some(summary) = line.strip_prefix("summary ") {
+            blame.summary = summary.to_string();
+        }
+    }
+    lines
+}
+
+/// Returns the name of the branch checked out where `file` is, or the short
+/// commit hash when `HEAD` is detached.
+pub fn branch(file: &str) -> Option<String> {
+    let (dir, _) = split_path(Path::new(file)).ok()?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || branch.is_empty() {
+        return None;
+    }
+    if branch != "HEAD" {
+        return Some(branch);
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--short", "HEAD"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Runs git in `dir`, returning what it printed. It fails with the first
+/// line git printed about the error.
+pub fn run(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let line = err.lines().find(|line| !line.trim().is_empty());
+        anyhow::bail!("git {}: {}", args[0], line.unwrap_or("failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Splits `path` into the directory to run git in and the file's name.
+fn split_path(path: &Path) -> anyhow::Result<(&Path, &std::ffi::OsStr)> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("not a file: {}", path.display()))?;
+    Ok((dir, name))
+}
+
+/// Returns the contents of `file` at `HEAD`, failing when it isn't in a git
+/// repository or isn't committed.
+pub fn head_contents(file: &str) -> anyhow::Result<String> {
+    show(file, "HEAD")
+}
+
+/// Returns the contents of `file` in the index, what the next commit takes.
+pub fn index_contents(file: &str) -> anyhow::Result<String> {
+    show(file, "")
+}
+
+/// Returns the contents of `file` at `revision`, or in the index when it's
+/// empty.
+fn show(file: &str, revision: &str) -> anyhow::Result<String> {
+    let (dir, name) = split_path(Path::new(file))?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("{revision}:./{}", name.to_string_lossy()))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!("{file} is not tracked by git");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Stages the changes of `patch`, a diff of `file` against its version in
+/// the index made by `hunk_patch`.
+pub fn stage(file: &str, patch: &str) -> anyhow::Result<()> {
+    let (dir, _) = split_path(Path::new(file))?;
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["apply", "--cached", "--unidiff-zero", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(patch.as_bytes())?;
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "git apply failed: {}",
+            err.lines().next().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Diffs `lines` against the `HEAD` version of `file` on a separate thread.
+pub fn diff_against_head(file: &str, lines: Vec<String>) -> Receiver<anyhow::Result<Vec<Hunk>>> {
+    let (tx, rx) = mpsc::channel();
+    let file = file.to_string();
+
+    thread::spawn(move || {
+        let result = head_contents(&file).map(|head| {
+            let old: Vec<&str> = head.lines().collect();
+            let new: Vec<&str> = lines.iter().map(String::as_str).collect();
+            diff(&old, &new)
+        });
+        _ = tx.send(result);
+    });
+
+    rx
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// The edits turning `old` into `new`, line by line. Files too different
+/// to compare are deleted and inserted whole, past the lines they start and
+/// end with alike.
+fn line_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+    ops.extend(edit_script(old, new).unwrap_or_else(|| {
+        let mut ops = vec![Op::Delete; old.len()];
+        ops.extend(vec![Op::Insert; new.len()]);
+        ops
+    }));
+    ops.extend(vec![Op::Equal; suffix]);
+    ops
+}
+
+/// Compares two versions of a file line by line, returning the blocks of
+/// `new` that were added, changed or deleted.
+pub fn diff(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    let mut line = 0;
+    let mut ops = line_ops(old, new).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == Op::Equal {
+            line += 1;
+            continue;
+        }
+        let mut deleted = usize::from(op == Op::Delete);
+        let mut inserted = usize::from(op == Op::Insert);
+        while let Some(op) = ops.next_if(|op| *op != Op::Equal) {
+            match op {
+                Op::Delete => deleted += 1,
+                _ => inserted += 1,
+            }
+        }
+        let kind = match (deleted, inserted) {
+            (_, 0) => HunkKind::Deleted,
+            (0, _) => HunkKind::Added,
+            _ => HunkKind::Modified,
+        };
+        hunks.push(Hunk {
+            kind,
+            start: line,
+            end: line + inserted,
+        });
+        line += inserted;
+    }
+    hunks
+}
+
+/// A block of lines that differs between two versions of a file: lines
+/// `old` of the one were replaced by lines `new` of the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// The change covering `line` of `new`, counting deletions as on the line
+/// above them like the gutter does.
+pub fn change_at(old: &[&str], new: &[&str], line: usize) -> Option<Change> {
+    let (mut a, mut b) = (0, 0);
+    let mut ops = line_ops(old, new).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == Op::Equal {
+            (a, b) = (a + 1, b + 1);
+            continue;
+        }
+        let mut deleted = usize::from(op == Op::Delete);
+        let mut inserted = usize::from(op == Op::Insert);
+        while let Some(op) = ops.next_if(|op| *op != Op::Equal) {
+            match op {
+                Op::Delete => deleted += 1,
+                _ => inserted += 1,
+            }
+        }
+        let change = Change {
+            old: a..a + deleted,
+            new: b..b + inserted,
+        };
+        let covers = match inserted {
+            0 => b.saturating_sub(1) == line,
+            _ => change.new.contains(&line),
+        };
+        if covers {
+            return Some(change);
+        }
+        (a, b) = (a + deleted, b + inserted);
+    }
+    None
+}
+
+/// The lines of a `change` in unified diff format without context: the
+/// `@@` header, then the lines taken out and the ones put in.
+pub fn hunk_lines(old: &[&str], new: &[&str], change: &Change) -> Vec<String> {
+    // an empty range starts after the line before it
+    let start = |range: &Range<usize>| match range.is_empty() {
+        true => range.start,
+        false => range.start + 1,
+    };
+    let mut lines = vec![format!(
+        "@@ -{},{} +{},{} @@",
+        start(&change.old),
+        change.old.len(),
+        start(&change.new),
+        change.new.len()
+    )];
+    lines.extend(
+        old[change.old.clone()]
+            .iter()
+            .map(|line| format!("-{line}")),
+    );
+    lines.extend(
+        new[change.new.clone()]
+            .iter()
+            .map(|line| format!("+{line}")),
+    );
+    lines
+}
+
+/// A patch for `git apply` making `change` to the file `name`.
+pub fn hunk_patch(name: &str, old: &[&str], new: &[&str], change: &Change) -> String {
+    let mut patch = format!("--- a/{name}\n+++ b/{name}\n");
+    for line in hunk_lines(old, new, change) {
+        patch.push_str(&line);
+        patch.push('\n');
+    }
+    patch
+}
+
+/// Lines up two versions of a file side by side: each row holds a line of
+/// `old`, of `new`, or of both when they're the same or one replaced the
+/// other. Lines only one side has go across from a gap in the other.
+pub fn align(old: &[&str], new: &[&str]) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut rows = vec![];
+    let (mut a, mut b) = (0, 0);
+    let mut ops = line_ops(old, new).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == Op::Equal {
+            rows.push((Some(a), Some(b)));
+            (a, b) = (a + 1, b + 1);
+            continue;
+        }
+        let mut deleted = usize::from(op == Op::Delete);
+        let mut inserted = usize::from(op == Op::Insert);
+        while let Some(op) = ops.next_if(|op| *op != Op::Equal) {
+            match op {
+                Op::Delete => deleted += 1,
+                _ => inserted += 1,
+            }
+        }
+        for i in 0..deleted.max(inserted) {
+            rows.push((
+                (i < deleted).then_some(a + i),
+                (i < inserted).then_some(b + i),
+            ));
+        }
+        (a, b) = (a + deleted, b + inserted);
+    }
+    rows
+}
+
+/// Finds the shortest edit script turning `a` into `b` with Myers' algorithm,
+/// giving up after `MAX_EDITS` edits.
+fn edit_script(a: &[&str], b: &[&str]) -> Option<Vec<Op>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDITS) as isize;
+    let offset = max + 1;
+    // v[k + offset] is the furthest x reached on diagonal k = x - y
+    let mut v = vec![0; 2 * max as usize + 3];
+    // the part of `v` each round started from, for walking back
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Op> {
+    let mut ops = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let k = x - y;
+        let get = |k: isize| v[(k + d) as usize];
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        (x, y) = (prev_x, prev_y);
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, x as usize));
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hunk(kind: HunkKind, start: usize, end: usize) -> Hunk {
+        Hunk { kind, start, end }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = ["a", "b", "c", "d", "e"];
+        assert_eq!(diff(&old, &old), vec![]);
+        assert_eq!(
+            diff(&old, &["a", "x", "b", "c", "e"]),
+            vec![hunk(HunkKind::Added, 1, 2), hunk(HunkKind::Deleted, 4, 4)]
+        );
+        assert_eq!(
+            diff(&old, &["a", "B", "C", "d", "e", "f"]),
+            vec![hunk(HunkKind::Modified, 1, 3), hunk(HunkKind::Added, 5, 6)]
+        );
+        assert_eq!(diff(&old, &[]), vec![hunk(HunkKind::Deleted, 0, 0)]);
+    }
+
+    #[test]
+    fn test_align() {
+        let old = ["a", "b", "c", "d"];
+        assert_eq!(
+            align(&old, &["a", "B", "C", "D", "d", "e"]),
+            [
+                (Some(0), Some(0)),
+                (Some(1), Some(1)),
+                (Some(2), Some(2)),
+                (None, Some(3)),
+                (Some(3), Some(4)),
+                (None, Some(5)),
+            ]
+        );
+        assert_eq!(
+            align(&old, &["b", "d"]),
+            [
+                (Some(0), None),
+                (Some(1), Some(0)),
+                (Some(2), None),
+                (Some(3), Some(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_at() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "B", "c"];
+        let change = |old, new| Some(Change { old, new });
+        assert_eq!(change_at(&old, &new, 1), change(1..2, 1..2));
+        assert_eq!(change_at(&old, &new, 2), change(3..4, 3..3));
+        assert_eq!(change_at(&old, &new, 0), None);
+
+        let patch = hunk_patch("f.txt", &old, &new, &change_at(&old, &new, 2).unwrap());
+        assert_eq!(patch, "--- a/f.txt\n+++ b/f.txt\n@@ -4,1 +3,0 @@\n-d\n");
+        assert_eq!(
+            hunk_lines(
+                &["a"],
+                &["a", "b"],
+                &Change {
+                    old: 1..1,
+                    new: 1..2
+                }
+            ),
+            ["@@ -1,0 +2,1 @@", "+b"]
+        );
+    }
+
+    #[test]
+    fn test_git_signs_navigation() {
+        let signs = GitSigns::new(vec![
+            hunk(HunkKind::Modified, 2, 4),
+            hunk(HunkKind::Deleted, 8, 8),
+        ]);
+        assert_eq!(signs.on_line(3), Some(HunkKind::Modified));
+        assert_eq!(signs.on_line(7), Some(HunkKind::Deleted));
+        assert_eq!(signs.on_line(8), None);
+
+        assert_eq!(signs.next(2).map(Hunk::line), Some(7));
+        assert_eq!(signs.next(7).map(Hunk::line), Some(2));
+        assert_eq!(signs.prev(2).map(Hunk::line), Some(7));
+    }
+
+    #[test]
+    fn test_parse_blame() {
+        let output = "\
+1234abcd 1 1 1
+author Jane Doe
+author-time 1700000000
+summary Add main
+filename src/main.rs
+\tfn main() {
+0000000000 2 2
+author Not Committed Yet
+author-time 1700000500
+summary Version of src/main.rs from -
+\t    todo!()
+";
+        let lines = parse_blame(output);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].describe(1700000000 + 3 * 86400),
+            "Jane Doe, 3 days ago • Add main"
+        );
+        assert!(!lines[1].committed);
+        assert_eq!(lines[1].describe(0), "Not committed yet");
+    }
+}
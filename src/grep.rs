@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{finder, picker::Source};
+
+const MAX_RESULTS: usize = 10_000;
+const MAX_LINE_LENGTH: usize = 200;
+
+/// Builds the regex for `pattern`, ignoring case unless the pattern has
+/// uppercase characters. Patterns that aren't valid regexes are searched for
+/// literally, so a live search never fails halfway through typing.
+pub fn build_regex(pattern: &str) -> Regex {
+    let case_insensitive = !pattern.chars().any(char::is_uppercase);
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .or_else(|_| {
+            RegexBuilder::new(&regex::escape(pattern))
+                .case_insensitive(case_insensitive)
+                .build()
+        })
+        .expect("escaped pattern is a valid regex")
+}
+
+/// Searches the files under `root` for `pattern` on several threads. One
+/// thread walks the directory tree while the others search the files it
+/// finds, each sending `path:line:col: text` results as files are done.
+pub fn search(root: &Path, pattern: &str) -> Source {
+    let (tx, rx) = mpsc::channel();
+    let source = Source::new(rx);
+    let cancelled = source.cancelled.clone();
+    let regex = build_regex(pattern);
+    let root = root.to_path_buf();
+
+    let (paths_tx, paths) = mpsc::channel::<PathBuf>();
+    let paths = Arc::new(Mutex::new(paths));
+    let found = Arc::new(AtomicUsize::new(0));
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+
+    for _ in 0..workers {
+        let paths = paths.clone();
+        let tx = tx.clone();
+        let regex = regex.clone();
+        let root = root.clone();
+        let cancelled = cancelled.clone();
+        let found = found.clone();
+
+        thread::spawn(move || loop {
+            let Ok(path) = paths.lock().expect("lock is not poisoned").recv() else {
+                break;
+            };
+            if cancelled.load(Ordering::Relaxed) || found.load(Ordering::Relaxed) >= MAX_RESULTS {
+                break;
+            }
+            let relative = path.strip_prefix(&root).unwrap_or(&path);
+            let results = search_file(&path, &relative.to_string_lossy(), &regex);
+            if results.is_empty() {
+                continue;
+            }
+            found.fetch_add(results.len(), Ordering::Relaxed);
+            if tx.send(results).is_err() {
+                break;
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        finder::walk(&root, &mut |path| {
+            paths_tx.send(path.to_path_buf()).is_ok() && !cancelled.load(Ordering::Relaxed)
+        });
+    });
+
+    source
+}
+
+/// Returns the matching lines of the file at `path`, shown as `name`.
+/// Binary files and files that aren't UTF-8 are skipped.
+fn search_file(path: &Path, name: &str, regex: &Regex) -> Vec<String> {
+    let Ok(bytes) = fs::read(path) else {
+        return vec![];
+    };
+    if bytes.iter().take(8192).any(|b| *b == 0) {
+        return vec![];
+    }
+    let Ok(contents) = String::from_utf8(bytes) else {
+        return vec![];
+    };
+
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            let m = regex.find(line)?;
+            let text: String = line.trim().chars().take(MAX_LINE_LENGTH).collect();
+            Some(format!("{name}:{}:{}: {text}", n + 1, m.start() + 1))
+        })
+        .collect()
+}
+
+/// Splits a `path:line:col: text` result into the path and the zero based
+/// line and byte column.
+pub fn parse_result(result: &str) -> Option<(&str, usize, usize)> {
+    let mut parts = result.splitn(4, ':');
+    let path = parts.next()?;
+    let line: usize = parts.next()?.parse().ok()?;
+    let col: usize = parts.next()?.parse().ok()?;
+    Some((path, line.checked_sub(1)?, col.saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_file() {
+        let path = std::env::temp_dir().join("rustik-grep-test.rs");
+        fs::write(&path, "use std::fs;\n\npub fn bracket_pair() {}\n").unwrap();
+        let regex = build_regex("fn \\w+_pair");
+        let results = search_file(&path, "test.rs", &regex);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(results, vec!["test.rs:3:5: pub fn bracket_pair() {}"]);
+        let (path, line, col) = parse_result(&results[0]).unwrap();
+        assert_eq!((path, line, col), ("test.rs", 2, 4));
+    }
+
+    #[test]
+    fn test_build_regex() {
+        assert!(build_regex("buffer").is_match("Buffer"));
+        assert!(!build_regex("Buffer").is_match("buffer"));
+        assert!(build_regex("foo(").is_match("foo(bar)"));
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::bail;
+
+/// Bytes shown on each line of a hex dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Shows `bytes` as lines of an offset, the bytes in hex and the printable
+/// ones as ASCII, like `hexdump -C`.
+pub fn dump(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        text.push_str(&format!(
+            "{:08x}  {:<width$}  |{ascii}|\n",
+            i * BYTES_PER_LINE,
+            hex.join(" "),
+            width = BYTES_PER_LINE * 3 - 1
+        ));
+    }
+    text
+}
+
+/// Reads the bytes back from the hex column of a dump, so bytes can be
+/// changed, added and removed there. Offsets and the ASCII panel are
+/// ignored.
+pub fn parse(lines: &[String]) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    for (y, line) in lines.iter().enumerate() {
+        let hex = line.split_once('|').map_or(line.as_str(), |(hex, _)| hex);
+        // the offset comes first
+        for word in hex.split_whitespace().skip(1) {
+            match u8::from_str_radix(word, 16) {
+                Ok(byte) if word.len() == 2 => bytes.push(byte),
+                _ => bail!("line {}: {word:?} isn't a hex byte", y + 1),
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let bytes: Vec<u8> = (0..20).chain([b'h', b'i', 0xff]).collect();
+        let text = dump(&bytes);
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        assert_eq!(
+            lines[1],
+            "00000010  10 11 12 13 68 69 ff                             |....hi.|"
+        );
+        assert_eq!(parse(&lines).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_parse() {
+        let lines = ["00000000  41 4 |A|".to_string()];
+        assert!(parse(&lines).is_err());
+        let lines = ["00000000  41 42 0a".to_string(), String::new()];
+        assert_eq!(parse(&lines).unwrap(), b"AB\n");
+    }
+}
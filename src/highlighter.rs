@@ -1,44 +1,417 @@
-use crate::{editor::StyleInfo, theme::Theme};
-use tree_sitter::{Parser, Query, QueryCursor};
-use tree_sitter_rust::{language, HIGHLIGHT_QUERY};
+use std::{
+    collections::HashSet,
+    mem,
+    ops::Range,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    buffer,
+    editor::StyleInfo,
+    language::{self, Language},
+    log,
+    theme::Theme,
+};
+use tree_sitter::{InputEdit, Parser, Query, QueryCursor, Tree};
+
+pub enum Request {
+    SetLanguage(&'static Language),
+    Edit(InputEdit),
+    Update { version: usize, code: String },
+}
+
+pub struct Highlighted {
+    pub version: usize,
+    pub styles: Vec<StyleInfo>,
+    pub brackets: Vec<(usize, usize)>,
+    /// How long parsing and highlighting took.
+    pub elapsed: Duration,
+}
+
+/// Runs a `Highlighter` on its own thread so parsing never blocks input.
+pub struct HighlightWorker {
+    tx: Sender<Request>,
+    rx: Receiver<Highlighted>,
+}
+
+impl HighlightWorker {
+    pub fn spawn(mut highlighter: Highlighter) -> Self {
+        let (tx, requests) = mpsc::channel::<Request>();
+        let (results, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            while let Ok(request) = requests.recv() {
+                // coalesce everything queued so only the latest text is parsed
+                let mut update = None;
+                for request in std::iter::once(request).chain(requests.try_iter()) {
+                    match request {
+                        Request::SetLanguage(language) => {
+                            if highlighter.set_language(language).is_err() {
+                                _ = highlighter.set_language(language::plain_text());
+                            }
+                        }
+                        Request::Edit(edit) => highlighter.edit(&edit),
+                        Request::Update { version, code } => update = Some((version, code)),
+                    }
+                }
+
+                let Some((version, code)) = update else {
+                    continue;
+                };
+                let start = Instant::now();
+                if highlighter.needs_update() {
+                    // answered all the same, unhighlighted, or the editor
+                    // would keep waiting for this version
+                    if let Err(err) = highlighter.update(&code) {
+                        log!(Warn, "highlighting: {err}");
+                    }
+                }
+                let result = Highlighted {
+                    version,
+                    styles: highlighter.styles().to_vec(),
+                    brackets: highlighter.brackets(),
+                    elapsed: start.elapsed(),
+                };
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { tx, rx }
+    }
+
+    pub fn send(&self, request: Request) -> anyhow::Result<()> {
+        self.tx
+            .send(request)
+            .map_err(|_| anyhow::anyhow!("highlight worker stopped"))
+    }
+
+    /// Returns the most recent result, if any arrived since the last call.
+    pub fn try_recv(&self) -> Option<Highlighted> {
+        self.rx.try_iter().last()
+    }
+}
 
 pub struct Highlighter {
     parser: Parser,
-    query: Query,
+    query: Option<Query>,
     theme: Theme,
+    tree: Option<Tree>,
+    styles: Vec<StyleInfo>,
+    dirty: Vec<Range<usize>>,
 }
 
 impl Highlighter {
-    pub fn new(theme: &Theme) -> anyhow::Result<Self> {
-        let mut parser = Parser::new();
-        let language = language();
-        parser.set_language(language)?;
-        let query = Query::new(language, HIGHLIGHT_QUERY)?;
-        let theme = theme.clone();
-        Ok(Self {
-            parser,
-            query,
-            theme,
-        })
+    pub fn new(theme: &Theme, language: &Language) -> anyhow::Result<Self> {
+        let mut highlighter = Self {
+            parser: Parser::new(),
+            query: None,
+            theme: theme.clone(),
+            tree: None,
+            styles: vec![],
+            dirty: vec![],
+        };
+        highlighter.set_language(language)?;
+        Ok(highlighter)
     }
 
-    pub fn highlight(&mut self, code: &str) -> anyhow::Result<Vec<StyleInfo>> {
-        let tree = self.parser.parse(&code, None).expect("parse works");
-        let mut colors = Vec::new();
+    pub fn set_language(&mut self, language: &Language) -> anyhow::Result<()> {
+        self.query = None;
+        self.tree = None;
+        self.styles.clear();
+        self.dirty.clear();
+        if let Some(grammar) = language.grammar {
+            let grammar = grammar();
+            self.parser.set_language(grammar)?;
+            self.query = Some(Query::new(grammar, language.highlight_query)?);
+        }
+        Ok(())
+    }
+
+    /// Applies a buffer edit to the syntax tree and shifts the cached styles,
+    /// remembering the edited range so it gets recomputed on the next update.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if self.query.is_none() {
+            return;
+        }
+
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+
+        apply_edit(&mut self.styles, edit);
+
+        for range in self.dirty.iter_mut() {
+            range.start = shift(range.start, edit);
+            range.end = shift(range.end, edit);
+        }
+        self.dirty.push(edit.start_byte..edit.new_end_byte);
+    }
+
+    pub fn needs_update(&self) -> bool {
+        self.query.is_some() && (self.tree.is_none() || !self.dirty.is_empty())
+    }
+
+    /// Reparses `code` reusing the previous tree and recomputes styles only
+    /// for the edited ranges and the ranges whose syntax changed. When it
+    /// can't be parsed nothing is highlighted, until the next update parses
+    /// it from scratch.
+    pub fn update(&mut self, code: &str) -> anyhow::Result<()> {
+        let Some(query) = &self.query else {
+            return Ok(());
+        };
+
+        let old_tree = self.tree.take();
+        let Some(tree) = self.parser.parse(code, old_tree.as_ref()) else {
+            self.styles.clear();
+            self.dirty.clear();
+            anyhow::bail!("the text couldn't be parsed");
+        };
+
+        let mut ranges = mem::take(&mut self.dirty);
+        match &old_tree {
+            Some(old_tree) => ranges.extend(
+                old_tree
+                    .changed_ranges(&tree)
+                    .map(|r| r.start_byte..r.end_byte),
+            ),
+            None => {
+                self.styles.clear();
+                ranges.clear();
+                ranges.push(0..code.len());
+            }
+        }
+
+        // widen by one byte on each side so tokens touching an edit are refreshed
+        let ranges: Vec<Range<usize>> = ranges
+            .into_iter()
+            .map(|r| r.start.saturating_sub(1)..(r.end + 1).min(code.len().max(1)))
+            .collect();
+
+        self.styles
+            .retain(|s| !ranges.iter().any(|r| s.start < r.end && r.start < s.end));
+
+        let mut seen = HashSet::new();
         let mut cursor = QueryCursor::new();
-        let matches = cursor.matches(&self.query, tree.root_node(), code.as_bytes());
-        for mat in matches {
-            for cap in mat.captures {
-                let node = cap.node;
-                let start = node.start_byte();
-                let end = node.end_byte();
-                let scope = self.query.capture_names()[cap.index as usize].as_str();
-                let style = self.theme.get_style(scope);
-                if let Some(style) = style {
-                    colors.push(StyleInfo { start, end, style });
+        for range in ranges {
+            cursor.set_byte_range(range);
+            let matches = cursor.matches(query, tree.root_node(), code.as_bytes());
+            for mat in matches {
+                for cap in mat.captures {
+                    let node = cap.node;
+                    let start = node.start_byte();
+                    let end = node.end_byte();
+                    if !seen.insert((start, end, cap.index)) {
+                        continue;
+                    }
+                    let scope = query.capture_names()[cap.index as usize].as_str();
+                    if let Some(style) = self.theme.get_style(scope) {
+                        self.styles.push(StyleInfo { start, end, style });
+                    }
+                }
+            }
+        }
+
+        self.styles.sort_by_key(|s| s.start);
+        self.tree = Some(tree);
+
+        Ok(())
+    }
+
+    pub fn styles(&self) -> &[StyleInfo] {
+        &self.styles
+    }
+
+    /// Returns the byte offsets of every bracket pair in the syntax tree. Pairs
+    /// are matched among siblings, so brackets inside strings, comments or
+    /// char literals never match.
+    pub fn brackets(&self) -> Vec<(usize, usize)> {
+        let Some(tree) = &self.tree else {
+            return vec![];
+        };
+
+        let mut pairs = vec![];
+        let mut nodes = vec![tree.root_node()];
+        while let Some(node) = nodes.pop() {
+            let mut open = vec![];
+            for i in 0..node.child_count() {
+                let child = node.child(i).expect("child within count");
+                nodes.push(child);
+
+                let mut kind = child.kind().chars();
+                let (Some(c), None) = (kind.next(), kind.next()) else {
+                    continue;
+                };
+                let Some((opening, _)) = buffer::bracket_pair(c) else {
+                    continue;
+                };
+                if c == opening {
+                    open.push((c, child.start_byte()));
+                } else if !child.is_missing() {
+                    if let Some((o, start)) = open.pop() {
+                        if o == opening {
+                            pairs.push((start, child.start_byte()));
+                        }
+                    }
                 }
             }
         }
-        Ok(colors)
+
+        pairs.sort();
+        pairs
+    }
+}
+
+/// Shifts `styles` past an edit, dropping the ones the edit touched.
+pub fn apply_edit(styles: &mut Vec<StyleInfo>, edit: &InputEdit) {
+    styles.retain_mut(|s| {
+        if s.start < edit.old_end_byte && s.end > edit.start_byte {
+            return false;
+        }
+        s.start = shift(s.start, edit);
+        s.end = shift(s.end, edit);
+        true
+    });
+}
+
+/// Shifts bracket pairs past an edit, dropping pairs the edit touched.
+pub fn apply_edit_to_brackets(brackets: &mut Vec<(usize, usize)>, edit: &InputEdit) {
+    let touched = |pos: usize| pos >= edit.start_byte && pos < edit.old_end_byte;
+    brackets.retain_mut(|(open, close)| {
+        if touched(*open) || touched(*close) {
+            return false;
+        }
+        *open = shift(*open, edit);
+        *close = shift(*close, edit);
+        true
+    });
+}
+
+/// Returns the styles overlapping `start..end`, relative to `start`.
+pub fn styles_in(styles: &[StyleInfo], start: usize, end: usize) -> Vec<StyleInfo> {
+    styles
+        .iter()
+        .take_while(|s| s.start < end)
+        .filter(|s| s.end > start)
+        .map(|s| StyleInfo {
+            start: s.start.saturating_sub(start),
+            end: s.end - start,
+            style: s.style.clone(),
+        })
+        .collect()
+}
+
+fn shift(pos: usize, edit: &InputEdit) -> usize {
+    if pos >= edit.old_end_byte {
+        pos - edit.old_end_byte + edit.new_end_byte
+    } else if pos > edit.start_byte {
+        edit.new_end_byte
+    } else {
+        pos
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{buffer::Buffer, language, theme::parse_vscode_theme};
+
+    fn full_highlight(theme: &Theme, code: &str) -> Vec<(usize, usize)> {
+        let mut highlighter = Highlighter::new(theme, language::find("rust").unwrap()).unwrap();
+        highlighter.update(code).unwrap();
+        styles_in(highlighter.styles(), 0, code.len())
+            .iter()
+            .map(|s| (s.start, s.end))
+            .collect()
+    }
+
+    #[test]
+    fn test_incremental_matches_full_parse() {
+        let theme = parse_vscode_theme("./src/fixtures/mocha.json").unwrap();
+        let mut buffer = Buffer::new(None, "fn main() {\n    let x = 1;\n}".to_string());
+        let mut highlighter = Highlighter::new(&theme, language::find("rust").unwrap()).unwrap();
+        highlighter.update(&buffer.text()).unwrap();
+
+        for (x, c) in "// ".chars().enumerate() {
+            buffer.insert(4 + x, 1, c);
+        }
+        buffer.insert_line(2, "    \"s\";".to_string());
+        buffer.remove(0, 0);
+        for edit in buffer.take_edits() {
+            highlighter.edit(&edit);
+        }
+        assert!(highlighter.needs_update());
+        highlighter.update(&buffer.text()).unwrap();
+
+        let code = buffer.text();
+        let mut incremental: Vec<(usize, usize)> = styles_in(highlighter.styles(), 0, code.len())
+            .iter()
+            .map(|s| (s.start, s.end))
+            .collect();
+        let mut full = full_highlight(&theme, &code);
+        incremental.sort();
+        full.sort();
+        assert_eq!(incremental, full);
+    }
+
+    #[test]
+    fn test_worker_returns_latest_version() {
+        let theme = parse_vscode_theme("./src/fixtures/mocha.json").unwrap();
+        let highlighter = Highlighter::new(&theme, language::plain_text()).unwrap();
+        let worker = HighlightWorker::spawn(highlighter);
+        worker
+            .send(Request::SetLanguage(language::find("rust").unwrap()))
+            .unwrap();
+        worker
+            .send(Request::Update {
+                version: 1,
+                code: "fn main() {}".to_string(),
+            })
+            .unwrap();
+
+        let result = loop {
+            if let Some(result) = worker.try_recv() {
+                break result;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(result.version, 1);
+        assert!(!result.styles.is_empty());
+    }
+
+    #[test]
+    fn test_worker_answers_failed_parse() {
+        let theme = parse_vscode_theme("./src/fixtures/mocha.json").unwrap();
+        let mut highlighter = Highlighter::new(&theme, language::find("rust").unwrap()).unwrap();
+        // a parser without a language parses nothing
+        highlighter.parser = Parser::new();
+        let worker = HighlightWorker::spawn(highlighter);
+        worker
+            .send(Request::Update {
+                version: 2,
+                code: "fn main() {}".to_string(),
+            })
+            .unwrap();
+
+        let result = loop {
+            if let Some(result) = worker.try_recv() {
+                break result;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(result.version, 2);
+        assert!(result.styles.is_empty());
+    }
+
+    #[test]
+    fn test_brackets_skip_strings() {
+        let theme = Theme::default();
+        let code = "fn a() { let s = \"(\"; }";
+        let mut highlighter = Highlighter::new(&theme, language::find("rust").unwrap()).unwrap();
+        highlighter.update(code).unwrap();
+        assert_eq!(highlighter.brackets(), vec![(4, 5), (7, 22)]);
     }
 }
@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config;
+
+/// How many commands are remembered.
+const MAX_HISTORY: usize = 200;
+
+/// Where the command history is kept between runs.
+pub fn path() -> Option<PathBuf> {
+    Some(config::state_dir()?.join("command_history.json"))
+}
+
+/// The commands run from the `:` prompt, oldest first, and where `Up` and
+/// `Down` are in them.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// The entry being shown, `None` while the typed command is.
+    index: Option<usize>,
+    /// What was typed before browsing, which entries have to start with.
+    prefix: String,
+}
+
+impl History {
+    /// Loads the history saved at `path`, starting empty without one.
+    pub fn load(path: &Path) -> Self {
+        let entries = fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&self.entries)?)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Remembers a command that was run, moving it to the end when it was
+    /// run before.
+    pub fn add(&mut self, command: &str) {
+        self.reset();
+        if command.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| entry != command);
+        self.entries.push(command.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+    }
+
+    /// Stops browsing, after the command was edited.
+    pub fn reset(&mut self) {
+        self.index = None;
+    }
+
+    /// The previous command starting with what was typed, `typed` when
+    /// browsing starts.
+    pub fn prev(&mut self, typed: &str) -> Option<&str> {
+        if self.index.is_none() {
+            self.prefix = typed.to_string();
+        }
+        let end = self.index.unwrap_or(self.entries.len());
+        let index = self.entries[..end]
+            .iter()
+            .rposition(|entry| entry.starts_with(&self.prefix))?;
+        self.index = Some(index);
+        Some(&self.entries[index])
+    }
+
+    /// The next command starting with what was typed, or what was typed
+    /// after the last one.
+    pub fn next(&mut self) -> Option<&str> {
+        let start = self.index? + 1;
+        let found = self.entries[start..]
+            .iter()
+            .position(|entry| entry.starts_with(&self.prefix));
+        match found {
+            Some(offset) => {
+                self.index = Some(start + offset);
+                Some(&self.entries[start + offset])
+            }
+            None => {
+                self.index = None;
+                Some(&self.prefix)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_browse() {
+        let mut history = History::default();
+        for command in ["set nu", "w", "s/a/b/", "set list", "w"] {
+            history.add(command);
+        }
+        assert_eq!(history.entries(), ["set nu", "s/a/b/", "set list", "w"]);
+
+        assert_eq!(history.prev("set"), Some("set list"));
+        assert_eq!(history.prev("set list"), Some("set nu"));
+        assert_eq!(history.prev("set nu"), None);
+        assert_eq!(history.next(), Some("set list"));
+        assert_eq!(history.next(), Some("set"));
+        assert_eq!(history.next(), None);
+    }
+}
@@ -0,0 +1,100 @@
+/// A number found on a line: where it starts and ends and its text after
+/// adding to it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Incremented {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Adds `delta` to the number under or after `x` on `line`, like vim's
+/// `Ctrl-a`. Decimal numbers may be negative, hexadecimal ones start with
+/// `0x`, keep their width and letter case and wrap around.
+pub fn increment(line: &str, x: usize, delta: i64) -> Option<Incremented> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex_digits = bytes[i] == b'0'
+            && matches!(bytes.get(i + 1), Some(b'x' | b'X'))
+            && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+        if hex_digits {
+            let end = digits_end(bytes, i + 2, u8::is_ascii_hexdigit);
+            if end > x {
+                return Some(increment_hex(line, i, end, delta));
+            }
+            i = end;
+        } else if bytes[i].is_ascii_digit() {
+            let end = digits_end(bytes, i, u8::is_ascii_digit);
+            if end > x {
+                let start = match i > 0 && bytes[i - 1] == b'-' {
+                    true => i - 1,
+                    false => i,
+                };
+                return increment_decimal(line, start, end, delta);
+            }
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+fn digits_end(bytes: &[u8], start: usize, is_digit: fn(&u8) -> bool) -> usize {
+    start + bytes[start..].iter().take_while(|b| is_digit(b)).count()
+}
+
+fn increment_decimal(line: &str, start: usize, end: usize, delta: i64) -> Option<Incremented> {
+    let value: i64 = line[start..end].parse().ok()?;
+    Some(Incremented {
+        start,
+        end,
+        text: value.saturating_add(delta).to_string(),
+    })
+}
+
+fn increment_hex(line: &str, start: usize, end: usize, delta: i64) -> Incremented {
+    let digits = &line[start + 2..end];
+    let value = u64::from_str_radix(digits, 16).unwrap_or(u64::MAX);
+    let value = value.wrapping_add_signed(delta);
+    let width = digits.len();
+    let hex = match digits.chars().any(|c| c.is_ascii_uppercase()) {
+        true => format!("{value:0width$X}"),
+        false => format!("{value:0width$x}"),
+    };
+    Incremented {
+        start,
+        end,
+        text: format!("{}{hex}", &line[start..start + 2]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn incremented(line: &str, x: usize, delta: i64) -> Option<String> {
+        let found = increment(line, x, delta)?;
+        Some(format!(
+            "{}{}{}",
+            &line[..found.start],
+            found.text,
+            &line[found.end..]
+        ))
+    }
+
+    #[test]
+    fn test_increment() {
+        assert_eq!(incremented("x = 9;", 0, 1).unwrap(), "x = 10;");
+        assert_eq!(incremented("a1 b2", 2, 5).unwrap(), "a1 b7");
+        assert_eq!(incremented("a1 b2", 1, 5).unwrap(), "a6 b2");
+        assert_eq!(incremented("at -3", 0, 1).unwrap(), "at -2");
+        assert_eq!(incremented("at 2", 0, -5).unwrap(), "at -3");
+        assert_eq!(incremented("0x0f", 0, 1).unwrap(), "0x10");
+        assert_eq!(incremented("0xFF", 3, 1).unwrap(), "0x100");
+        assert_eq!(incremented("0x00", 0, -1).unwrap(), "0xffffffffffffffff");
+        assert_eq!(incremented("0xAb", 0, 1).unwrap(), "0xAC");
+        assert_eq!(incremented("no numbers", 0, 1), None);
+        assert_eq!(incremented("1 then", 2, 1), None);
+    }
+}
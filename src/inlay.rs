@@ -0,0 +1,109 @@
+use std::ops::Range;
+
+use tree_sitter::{InputEdit, Point};
+
+/// Text the language server shows between the characters of a line, like a
+/// variable's type or the name of the parameter an argument goes to. `x` is
+/// the byte the label goes before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    pub line: usize,
+    pub x: usize,
+    pub label: String,
+}
+
+impl Hint {
+    /// How many columns the label takes.
+    pub fn width(&self) -> usize {
+        self.label.chars().count()
+    }
+}
+
+/// The hints for the buffer, kept where they belong as it's edited until
+/// the server sends new ones.
+#[derive(Debug, Default)]
+pub struct Hints {
+    items: Vec<Hint>,
+}
+
+impl Hints {
+    pub fn set(&mut self, mut items: Vec<Hint>) {
+        items.sort_by_key(|hint| (hint.line, hint.x));
+        self.items = items;
+    }
+
+    /// The hints on `line` that go before one of the bytes in `range`.
+    pub fn in_range(&self, line: usize, range: Range<usize>) -> impl Iterator<Item = &Hint> {
+        let start = self.items.partition_point(|hint| hint.line < line);
+        self.items[start..]
+            .iter()
+            .take_while(move |hint| hint.line == line)
+            .filter(move |hint| range.contains(&hint.x))
+    }
+
+    /// How many columns the hints in `range` of `line` take.
+    pub fn width(&self, line: usize, range: Range<usize>) -> usize {
+        self.in_range(line, range).map(Hint::width).sum()
+    }
+
+    /// Keeps hints next to their text as it moves. Hints in text that was
+    /// replaced are dropped.
+    pub fn apply_edit(&mut self, edit: &InputEdit) {
+        let (start, old_end, new_end) = (
+            edit.start_position,
+            edit.old_end_position,
+            edit.new_end_position,
+        );
+        self.items.retain_mut(|hint| {
+            let point = Point::new(hint.line, hint.x);
+            if point < old_end {
+                return point <= start;
+            }
+            if hint.line == old_end.row {
+                hint.x = hint.x - old_end.column + new_end.column;
+            }
+            hint.line = hint.line + new_end.row - old_end.row;
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_edit() {
+        let hint = |line, x| Hint {
+            line,
+            x,
+            label: ": u8".to_string(),
+        };
+        let mut hints = Hints::default();
+        hints.set(vec![hint(1, 5), hint(0, 5), hint(0, 9)]);
+        assert_eq!(hints.width(0, 0..9), 4);
+        assert_eq!(hints.width(0, 0..usize::MAX), 8);
+
+        // "ab" typed at the start of the first line, over the third byte
+        hints.apply_edit(&InputEdit {
+            start_byte: 0,
+            old_end_byte: 3,
+            new_end_byte: 2,
+            start_position: Point::new(0, 0),
+            old_end_position: Point::new(0, 3),
+            new_end_position: Point::new(0, 2),
+        });
+        assert_eq!(hints.items, [hint(0, 4), hint(0, 8), hint(1, 5)]);
+
+        // a line break before the first hint
+        hints.apply_edit(&InputEdit {
+            start_byte: 2,
+            old_end_byte: 2,
+            new_end_byte: 3,
+            start_position: Point::new(0, 2),
+            old_end_position: Point::new(0, 2),
+            new_end_position: Point::new(1, 0),
+        });
+        assert_eq!(hints.items, [hint(1, 2), hint(1, 6), hint(2, 5)]);
+    }
+}
@@ -0,0 +1,115 @@
+const MAX_JUMPS: usize = 100;
+
+/// A place the cursor jumped from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub file: Option<String>,
+    pub x: usize,
+    pub line: usize,
+}
+
+/// Recently visited locations, walked with `JumpBack` and `JumpForward`.
+/// `index` is where the next jump is recorded; it's below the end while
+/// walking back through the list.
+#[derive(Debug, Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    index: usize,
+}
+
+impl JumpList {
+    /// Records the location a jump starts from, dropping the ones that were
+    /// walked back over and an earlier visit of the same line.
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps.truncate(self.index);
+        self.jumps
+            .retain(|j| (j.file.as_ref(), j.line) != (jump.file.as_ref(), jump.line));
+        self.jumps.push(jump);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+
+    /// Returns the location before the current one. The first step back
+    /// records `current` so it can be returned to with `forward`.
+    pub fn back(&mut self, current: Jump) -> Option<Jump> {
+        if self.index == 0 {
+            return None;
+        }
+        let index = if self.index == self.jumps.len() {
+            // recording `current` drops an earlier visit of its line, which
+            // may be the only jump there was
+            self.push(current);
+            self.jumps.len() - 1
+        } else {
+            self.index
+        };
+        let Some(index) = index.checked_sub(1) else {
+            self.index = self.jumps.len();
+            return None;
+        };
+        self.index = index;
+        self.jumps.get(self.index).cloned()
+    }
+
+    pub fn forward(&mut self) -> Option<Jump> {
+        if self.index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.index += 1;
+        self.jumps.get(self.index).cloned()
+    }
+
+    /// Renames the files of the jumps, after `:cd`.
+    pub fn rebase(&mut self, rename: impl Fn(&str) -> String) {
+        for jump in &mut self.jumps {
+            jump.file = jump.file.as_deref().map(&rename);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jump(line: usize) -> Jump {
+        Jump {
+            file: Some("a.rs".to_string()),
+            x: 0,
+            line,
+        }
+    }
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut jumps = JumpList::default();
+        jumps.push(jump(1));
+        jumps.push(jump(10));
+
+        assert_eq!(jumps.back(jump(20)), Some(jump(10)));
+        assert_eq!(jumps.back(jump(10)), Some(jump(1)));
+        assert_eq!(jumps.back(jump(1)), None);
+        assert_eq!(jumps.forward(), Some(jump(10)));
+        assert_eq!(jumps.forward(), Some(jump(20)));
+        assert_eq!(jumps.forward(), None);
+
+        // jumping after going back forgets the locations ahead
+        jumps.back(jump(20));
+        jumps.push(jump(10));
+        assert_eq!(jumps.forward(), None);
+        assert_eq!(jumps.back(jump(5)), Some(jump(10)));
+    }
+
+    #[test]
+    fn test_back_from_only_jump() {
+        // `gg` then `Ctrl-o` with the cursor still on the line jumped from
+        let mut jumps = JumpList::default();
+        jumps.push(jump(0));
+        assert_eq!(jumps.back(jump(0)), None);
+        assert_eq!(jumps.forward(), None);
+        jumps.push(jump(5));
+        assert_eq!(jumps.back(jump(9)), Some(jump(5)));
+        assert_eq!(jumps.back(jump(5)), Some(jump(0)));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{collections::HashMap, time::Duration};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{
+    config::{KeyAction, Keys},
+    editor::Action,
+};
+
+const DEFAULT_LEADER: &str = "\\";
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Returns the name a key is mapped by: the character itself, `Ctrl-x` and
+/// `ALT-x` with modifiers, or the key code's name, like `Enter` or `Esc`.
+pub fn key_name(event: &KeyEvent) -> String {
+    let key = match event.code {
+        KeyCode::Char(c) => format!("{c}"),
+        code => format!("{code:?}"),
+    };
+
+    match event.modifiers {
+        KeyModifiers::CONTROL => format!("Ctrl-{key}"),
+        KeyModifiers::ALT => format!("ALT-{key}"),
+        _ => key,
+    }
+}
+
+fn normalize(name: &str) -> String {
+    match name {
+        "Space" | "<space>" => " ".to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// Splits a mapping like `"g g"` or `"<leader>w"` into key names. Keys are
+/// separated by spaces, except right after `<leader>`, where each character
+/// is a key of its own.
+pub fn parse_sequence(spec: &str, leader: &str) -> Vec<String> {
+    if spec.trim().is_empty() {
+        return vec![normalize(spec)];
+    }
+
+    spec.split_whitespace()
+        .flat_map(|token| match token.strip_prefix("<leader>") {
+            Some(rest) => std::iter::once(normalize(leader))
+                .chain(rest.chars().map(String::from))
+                .collect(),
+            None => vec![normalize(token)],
+        })
+        .collect()
+}
+
+/// The actions of a key action, in order.
+pub fn into_actions(ka: KeyAction) -> Vec<Action> {
+    match ka {
+        KeyAction::Single(action) => vec![action],
+        KeyAction::Multiple(actions) => actions,
+        KeyAction::Nested(_) => vec![],
+    }
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    action: Option<KeyAction>,
+    children: HashMap<String, Node>,
+}
+
+pub enum Lookup {
+    /// The keys are mapped and no longer sequence starts with them.
+    Action(KeyAction),
+    /// The keys start a longer sequence. The action they're mapped to on
+    /// their own, if any, runs when the sequence isn't completed in time.
+    Pending(Option<KeyAction>),
+    Unmapped,
+}
+
+/// A mode's mappings as a prefix tree of key names.
+#[derive(Debug, Default)]
+pub struct Keymap {
+    root: Node,
+    timeout: Option<Duration>,
+}
+
+impl Keymap {
+    /// Builds the tree from configured mappings. Nested tables are the same
+    /// as sequences: `"g" = { "g" = ... }` maps `"g g"`.
+    pub fn new(
+        mappings: &HashMap<String, KeyAction>,
+        leader: &str,
+        timeout: Option<Duration>,
+    ) -> Self {
+        let mut root = Node::default();
+        for (spec, ka) in mappings {
+            insert(&mut root, parse_sequence(spec, leader), ka, leader);
+        }
+        Self { root, timeout }
+    }
+
+    pub fn lookup(&self, keys: &[String]) -> Lookup {
+        let mut node = &self.root;
+        for key in keys {
+            let Some(child) = node.children.get(key) else {
+                return Lookup::Unmapped;
+            };
+            node = child;
+        }
+
+        match (&node.action, node.children.is_empty()) {
+            (_, false) => Lookup::Pending(node.action.clone()),
+            (Some(ka), true) => Lookup::Action(ka.clone()),
+            (None, true) => Lookup::Unmapped,
+        }
+    }
+
+    /// Maps `spec` on top of the configured mappings.
+    pub fn map(&mut self, spec: &str, ka: &KeyAction, leader: &str) {
+        insert(&mut self.root, parse_sequence(spec, leader), ka, leader);
+    }
+
+    /// How long to wait for the rest of a sequence, or `None` to wait
+    /// indefinitely.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+fn insert(node: &mut Node, keys: Vec<String>, ka: &KeyAction, leader: &str) {
+    if let KeyAction::Nested(mappings) = ka {
+        for (spec, ka) in mappings {
+            let mut sequence = keys.clone();
+            sequence.extend(parse_sequence(spec, leader));
+            insert(node, sequence, ka, leader);
+        }
+        return;
+    }
+
+    let node = keys
+        .into_iter()
+        .fold(node, |node, key| node.children.entry(key).or_default());
+    node.action = Some(ka.clone());
+}
+
+/// The keymaps of every mode.
+#[derive(Debug, Default)]
+pub struct Keymaps {
+    pub normal: Keymap,
+    pub insert: Keymap,
+    pub command: Keymap,
+    pub completion: Keymap,
+    pub popup: Keymap,
+    pub diff: Keymap,
+    pub status: Keymap,
+    pub picker: Keymap,
+    pub terminal: Keymap,
+    pub visual: Keymap,
+    pub replace: Keymap,
+    pub visual_block: Keymap,
+    /// The leader key, for mappings added later.
+    pub leader: String,
+}
+
+impl Keymaps {
+    pub fn new(keys: &Keys) -> Self {
+        let leader = keys.leader.as_deref().unwrap_or(DEFAULT_LEADER);
+        let keymap = |mode: &str, mappings: &HashMap<String, KeyAction>| {
+            let ms = keys
+                .timeouts
+                .get(mode)
+                .copied()
+                .unwrap_or(DEFAULT_TIMEOUT_MS);
+            let timeout = (ms > 0).then(|| Duration::from_millis(ms));
+            Keymap::new(mappings, leader, timeout)
+        };
+
+        Self {
+            normal: keymap("normal", &keys.normal),
+            insert: keymap("insert", &keys.insert),
+            command: keymap("command", &keys.command),
+            completion: keymap("completion", &keys.completion),
+            popup: keymap("popup", &keys.popup),
+            diff: keymap("diff", &keys.diff),
+            status: keymap("status", &keys.status),
+            picker: keymap("picker", &keys.picker),
+            terminal: keymap("terminal", &keys.terminal),
+            visual: keymap("visual", &keys.visual),
+            replace: keymap("replace", &keys.replace),
+            visual_block: keymap("visual_block", &keys.visual_block),
+            leader: leader.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        assert_eq!(parse_sequence("g g", "\\"), keys(&["g", "g"]));
+        assert_eq!(
+            parse_sequence("<leader>fg", "Space"),
+            keys(&[" ", "f", "g"])
+        );
+        assert_eq!(
+            parse_sequence("<leader> Ctrl-p", ","),
+            keys(&[",", "Ctrl-p"])
+        );
+        assert_eq!(parse_sequence(" ", "\\"), keys(&[" "]));
+        assert_eq!(parse_sequence("Enter", "\\"), keys(&["Enter"]));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let toml = r#"
+            "d" = "DeleteCharAtCursorPos"
+            "d d" = "DeleteCurrentLine"
+            "g" = { "g" = "MoveToTop" }
+            "<leader>w" = "Undo"
+        "#;
+        let mappings: HashMap<String, KeyAction> = toml::from_str(toml).unwrap();
+        let keymap = Keymap::new(&mappings, ",", None);
+
+        assert!(matches!(
+            keymap.lookup(&keys(&["d"])),
+            Lookup::Pending(Some(KeyAction::Single(Action::DeleteCharAtCursorPos)))
+        ));
+        assert!(matches!(
+            keymap.lookup(&keys(&["d", "d"])),
+            Lookup::Action(KeyAction::Single(Action::DeleteCurrentLine))
+        ));
+        assert!(matches!(
+            keymap.lookup(&keys(&["g"])),
+            Lookup::Pending(None)
+        ));
+        assert!(matches!(
+            keymap.lookup(&keys(&["g", "g"])),
+            Lookup::Action(KeyAction::Single(Action::MoveToTop))
+        ));
+        assert!(matches!(
+            keymap.lookup(&keys(&[",", "w"])),
+            Lookup::Action(KeyAction::Single(Action::Undo))
+        ));
+        assert!(matches!(
+            keymap.lookup(&keys(&["g", "x"])),
+            Lookup::Unmapped
+        ));
+    }
+}
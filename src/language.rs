@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use tree_sitter::Language as Grammar;
+
+pub const PLAIN_TEXT: &str = "text";
+
+#[derive(Debug)]
+pub struct Language {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub grammar: Option<fn() -> Grammar>,
+    pub highlight_query: &'static str,
+}
+
+// Plain text has no tree-sitter grammar, so nothing in it is highlighted.
+static LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        grammar: Some(tree_sitter_rust::language),
+        highlight_query: tree_sitter_rust::HIGHLIGHT_QUERY,
+    },
+    Language {
+        name: "toml",
+        extensions: &["toml"],
+        grammar: Some(tree_sitter_toml::language),
+        highlight_query: tree_sitter_toml::HIGHLIGHT_QUERY,
+    },
+    Language {
+        name: "json",
+        extensions: &["json"],
+        grammar: Some(tree_sitter_json::language),
+        highlight_query: tree_sitter_json::HIGHLIGHT_QUERY,
+    },
+    Language {
+        name: "markdown",
+        extensions: &["md", "markdown"],
+        grammar: Some(tree_sitter_md::language),
+        highlight_query: tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+    },
+    Language {
+        name: "python",
+        extensions: &["py", "pyi"],
+        grammar: Some(tree_sitter_python::language),
+        highlight_query: tree_sitter_python::HIGHLIGHT_QUERY,
+    },
+    Language {
+        name: "javascript",
+        extensions: &["js", "mjs", "cjs"],
+        grammar: Some(tree_sitter_javascript::language),
+        highlight_query: tree_sitter_javascript::HIGHLIGHT_QUERY,
+    },
+    Language {
+        name: PLAIN_TEXT,
+        extensions: &["txt"],
+        grammar: None,
+        highlight_query: "",
+    },
+];
+
+pub fn plain_text() -> &'static Language {
+    find(PLAIN_TEXT).expect("plain text language is registered")
+}
+
+/// Looks up a language by name, falling back to matching it as an extension
+/// so that both `:set filetype=rust` and `:set filetype=rs` work.
+pub fn find(name: &str) -> Option<&'static Language> {
+    LANGUAGES
+        .iter()
+        .find(|l| l.name == name)
+        .or_else(|| LANGUAGES.iter().find(|l| l.extensions.contains(&name)))
+}
+
+pub fn detect(file: Option<&str>) -> &'static Language {
+    file.and_then(|f| Path::new(f).extension())
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| LANGUAGES.iter().find(|l| l.extensions.contains(&ext)))
+        .unwrap_or_else(plain_text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(Some("src/main.rs")).name, "rust");
+        assert_eq!(detect(Some("Cargo.toml")).name, "toml");
+        assert_eq!(detect(Some("README.md")).name, "markdown");
+        assert_eq!(detect(Some("script.py")).name, "python");
+        assert_eq!(detect(Some("index.js")).name, "javascript");
+        assert_eq!(detect(Some("Makefile")).name, PLAIN_TEXT);
+        assert_eq!(detect(None).name, PLAIN_TEXT);
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("rust").unwrap().name, "rust");
+        assert_eq!(find("rs").unwrap().name, "rust");
+        assert!(find("cobol").is_none());
+    }
+
+    #[test]
+    fn test_highlight_queries() {
+        for language in LANGUAGES {
+            let Some(grammar) = language.grammar else {
+                continue;
+            };
+            let query = tree_sitter::Query::new(grammar(), language.highlight_query);
+            assert!(query.is_ok(), "{}: {:?}", language.name, query.err());
+        }
+    }
+}
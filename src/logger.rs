@@ -1,29 +1,107 @@
 use std::{
     fs::{File, OpenOptions},
     io::Write,
+    path::{Path, PathBuf},
     sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+/// How much goes to the log, each level including the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    /// Every action run.
+    Debug,
+    /// How long every frame took to draw.
+    Trace,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Self> {
+        let level = match name.to_lowercase().as_str() {
+            "error" => Level::Error,
+            "warn" | "warning" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            "trace" => Level::Trace,
+            _ => return None,
+        };
+        Some(level)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// The log `--log-file` turns on, one line per record: the time in seconds
+/// since the epoch, the level and the message.
 pub(crate) struct Logger {
     file: Mutex<File>,
+    path: PathBuf,
+    level: Level,
 }
 
 impl Logger {
-    pub fn new(file: &str) -> Self {
+    pub fn new(path: &Path, level: Level) -> anyhow::Result<Self> {
         let file = OpenOptions::new()
             .create(true)
-            .write(true)
             .append(true)
-            .open(file)
-            .expect("log file opens fine");
+            .open(path)
+            .map_err(|err| anyhow::anyhow!("log file {}: {err}", path.display()))?;
 
-        Logger {
+        Ok(Logger {
             file: Mutex::new(file),
-        }
+            path: path.to_path_buf(),
+            level,
+        })
     }
 
-    pub fn log(&self, message: &str) {
-        let mut file = self.file.lock().unwrap();
-        writeln!(file, "{}", message).expect("write to file works");
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, level: Level, message: &str) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format_line(time.as_secs_f64(), level, message);
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        // nowhere to report a log that can't be written
+        _ = writeln!(file, "{line}");
+    }
+}
+
+fn format_line(time: f64, level: Level, message: &str) -> String {
+    // records stay on one line each, whatever the message holds
+    let message = message.replace('\n', "\\n");
+    format!("{time:.3} {:<5} {message}", level.name())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(1.5, Level::Warn, "git diff:\nfailed"),
+            "1.500 WARN  git diff:\\nfailed"
+        );
+        assert_eq!(Level::parse("Debug"), Some(Level::Debug));
+        assert_eq!(Level::parse("verbose"), None);
+        assert!(Level::Error < Level::Trace);
     }
 }
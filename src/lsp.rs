@@ -0,0 +1,710 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    ops::Range,
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    completion::Completion,
+    diagnostics::{Diagnostic, Severity},
+    inlay::Hint,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// A position in a file as reported by the server. `character` counts UTF-16
+/// code units, see `byte_column` to convert it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub character: usize,
+}
+
+/// A change the server asks for: the text from `start` to `end`, both
+/// `(line, character)` positions, replaced with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub text: String,
+}
+
+/// The signature of the function whose call is being typed, with the byte
+/// range of the parameter the cursor is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub label: String,
+    pub active: Option<Range<usize>>,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Hover(String),
+    Definition(Vec<Location>),
+    References(Vec<Location>),
+    /// The edits renaming a symbol, by file.
+    Rename(Vec<(String, Vec<TextEdit>)>),
+    SignatureHelp(Option<Signature>),
+    /// Inlay hints for the open file, with `x` in UTF-16 units.
+    InlayHints(Vec<Hint>),
+    Completion(Vec<Completion>),
+    /// Diagnostics published for the open file, with `x` in UTF-16 units.
+    Diagnostics(Vec<Diagnostic>),
+    Error(String),
+    Exited(String),
+}
+
+enum Pending {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    SignatureHelp,
+    InlayHints,
+    Completion,
+}
+
+/// A language server spawned as a child process, speaking JSON-RPC over its
+/// stdin/stdout. Responses are read on a separate thread and collected with
+/// `poll`, so requests never block the editor.
+pub struct LspClient {
+    child: Child,
+    stdin: ChildStdin,
+    rx: Receiver<Value>,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    initialized: bool,
+    queued: Vec<Value>,
+    uri: String,
+    version: i32,
+}
+
+impl LspClient {
+    pub fn start(
+        config: &ServerConfig,
+        file: &str,
+        language_id: &str,
+        text: String,
+    ) -> anyhow::Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let root = std::env::current_dir()?;
+        let mut client = Self {
+            child,
+            stdin,
+            rx,
+            next_id: 0,
+            pending: HashMap::new(),
+            initialized: false,
+            queued: vec![],
+            uri: path_to_uri(file)?,
+            version: 0,
+        };
+
+        client.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": path_to_uri(&root.to_string_lossy())?,
+                "capabilities": {
+                    "textDocument": {
+                        "hover": { "contentFormat": ["plaintext", "markdown"] },
+                        "completion": { "completionItem": { "snippetSupport": false } },
+                        "inlayHint": {},
+                        "signatureHelp": {
+                            "signatureInformation": {
+                                "parameterInformation": { "labelOffsetSupport": true },
+                            },
+                        },
+                    },
+                },
+            }),
+            Pending::Initialize,
+        )?;
+        client.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": client.uri,
+                    "languageId": language_id,
+                    "version": client.version,
+                    "text": text,
+                },
+            }),
+        )?;
+
+        Ok(client)
+    }
+
+    pub fn did_change(&mut self, text: String) -> anyhow::Result<()> {
+        self.version += 1;
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": self.uri, "version": self.version },
+                "contentChanges": [{ "text": text }],
+            }),
+        )
+    }
+
+    pub fn hover(&mut self, line: usize, character: usize) -> anyhow::Result<()> {
+        let params = self.position_params(line, character);
+        self.request("textDocument/hover", params, Pending::Hover)
+    }
+
+    pub fn definition(&mut self, line: usize, character: usize) -> anyhow::Result<()> {
+        let params = self.position_params(line, character);
+        self.request("textDocument/definition", params, Pending::Definition)
+    }
+
+    pub fn references(&mut self, line: usize, character: usize) -> anyhow::Result<()> {
+        let mut params = self.position_params(line, character);
+        params["context"] = json!({ "includeDeclaration": true });
+        self.request("textDocument/references", params, Pending::References)
+    }
+
+    pub fn rename(&mut self, line: usize, character: usize, name: &str) -> anyhow::Result<()> {
+        let mut params = self.position_params(line, character);
+        params["newName"] = json!(name);
+        self.request("textDocument/rename", params, Pending::Rename)
+    }
+
+    pub fn signature_help(&mut self, line: usize, character: usize) -> anyhow::Result<()> {
+        let params = self.position_params(line, character);
+        self.request("textDocument/signatureHelp", params, Pending::SignatureHelp)
+    }
+
+    /// Asks for the hints of the first `lines` lines of the file.
+    pub fn inlay_hints(&mut self, lines: usize) -> anyhow::Result<()> {
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": lines, "character": 0 },
+            },
+        });
+        self.request("textDocument/inlayHint", params, Pending::InlayHints)
+    }
+
+    pub fn completion(&mut self, line: usize, character: usize) -> anyhow::Result<()> {
+        let params = self.position_params(line, character);
+        self.request("textDocument/completion", params, Pending::Completion)
+    }
+
+    /// Collects the results of requests answered since the last call.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let messages: Vec<Value> = self.rx.try_iter().collect();
+        let mut events = vec![];
+
+        for message in messages {
+            let id = message.get("id").cloned();
+            if let Some(method) = message["method"].as_str() {
+                // requests from the server get an empty reply so it doesn't stall
+                if let Some(id) = id {
+                    _ = self.send(json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+                } else if method == "textDocument/publishDiagnostics"
+                    && message["params"]["uri"] == self.uri
+                {
+                    let diagnostics = parse_diagnostics(&message["params"]["diagnostics"]);
+                    events.push(Event::Diagnostics(diagnostics));
+                }
+                continue;
+            }
+
+            let Some(pending) = id
+                .and_then(|id| id.as_u64())
+                .and_then(|id| self.pending.remove(&id))
+            else {
+                continue;
+            };
+
+            if let Some(error) = message.get("error") {
+                let error = error["message"].as_str().unwrap_or("request failed");
+                events.push(Event::Error(error.to_string()));
+                continue;
+            }
+
+            let result = &message["result"];
+            match pending {
+                Pending::Initialize => {
+                    self.initialized = true;
+                    _ = self.notify("initialized", json!({}));
+                    for message in std::mem::take(&mut self.queued) {
+                        _ = self.send(message);
+                    }
+                }
+                Pending::Hover => events.push(Event::Hover(parse_hover(result))),
+                Pending::Definition => events.push(Event::Definition(parse_locations(result))),
+                Pending::References => events.push(Event::References(parse_locations(result))),
+                Pending::Rename => events.push(Event::Rename(parse_workspace_edit(result))),
+                Pending::SignatureHelp => {
+                    events.push(Event::SignatureHelp(parse_signature_help(result)))
+                }
+                Pending::InlayHints => events.push(Event::InlayHints(parse_inlay_hints(result))),
+                Pending::Completion => events.push(Event::Completion(parse_completions(result))),
+            }
+        }
+
+        if let Ok(Some(status)) = self.child.try_wait() {
+            events.push(Event::Exited(status.to_string()));
+        }
+
+        events
+    }
+
+    fn position_params(&self, line: usize, character: usize) -> Value {
+        json!({
+            "textDocument": { "uri": self.uri },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    fn request(&mut self, method: &str, params: Value, pending: Pending) -> anyhow::Result<()> {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, pending);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        }))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> anyhow::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&mut self, message: Value) -> anyhow::Result<()> {
+        // nothing but the initialize request may be sent before it's answered
+        if !self.initialized && message["method"] != "initialize" {
+            self.queued.push(message);
+            return Ok(());
+        }
+        self.stdin.write_all(&encode(&message))?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+fn encode(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+fn read_message(reader: &mut impl BufRead) -> anyhow::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let Some(len) = len else {
+        anyhow::bail!("message without Content-Length");
+    };
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn parse_hover(result: &Value) -> String {
+    fn marked_string(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Object(o) => o.get("value")?.as_str().map(|s| s.to_string()),
+            _ => None,
+        }
+    }
+
+    match &result["contents"] {
+        Value::Array(values) => values
+            .iter()
+            .filter_map(marked_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        contents => marked_string(contents).unwrap_or_default(),
+    }
+}
+
+fn parse_locations(result: &Value) -> Vec<Location> {
+    let parse = |value: &Value| {
+        // either a Location or a LocationLink
+        let uri = value
+            .get("uri")
+            .or_else(|| value.get("targetUri"))?
+            .as_str()?;
+        let range = value
+            .get("range")
+            .or_else(|| value.get("targetSelectionRange"))?;
+        Some(Location {
+            path: uri_to_path(uri),
+            line: range["start"]["line"].as_u64()? as usize,
+            character: range["start"]["character"].as_u64()? as usize,
+        })
+    };
+
+    match result {
+        Value::Array(values) => values.iter().filter_map(parse).collect(),
+        value => parse(value).into_iter().collect(),
+    }
+}
+
+/// The edits of a `WorkspaceEdit` by file, from either its `changes` or its
+/// `documentChanges`.
+fn parse_workspace_edit(result: &Value) -> Vec<(String, Vec<TextEdit>)> {
+    fn position(value: &Value) -> Option<(usize, usize)> {
+        Some((
+            value["line"].as_u64()? as usize,
+            value["character"].as_u64()? as usize,
+        ))
+    }
+    fn text_edits(edits: &Value) -> Vec<TextEdit> {
+        let Some(edits) = edits.as_array() else {
+            return vec![];
+        };
+        edits
+            .iter()
+            .filter_map(|edit| {
+                Some(TextEdit {
+                    start: position(&edit["range"]["start"])?,
+                    end: position(&edit["range"]["end"])?,
+                    text: edit["newText"].as_str()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    if let Some(changes) = result["documentChanges"].as_array() {
+        return changes
+            .iter()
+            .filter_map(|change| {
+                let uri = change["textDocument"]["uri"].as_str()?;
+                Some((uri_to_path(uri), text_edits(&change["edits"])))
+            })
+            .collect();
+    }
+    let Some(changes) = result["changes"].as_object() else {
+        return vec![];
+    };
+    changes
+        .iter()
+        .map(|(uri, edits)| (uri_to_path(uri), text_edits(edits)))
+        .collect()
+}
+
+/// The active signature, with its active parameter found by name or by
+/// UTF-16 offsets into the label.
+fn parse_signature_help(result: &Value) -> Option<Signature> {
+    let signatures = result["signatures"].as_array()?;
+    let index = result["activeSignature"].as_u64().unwrap_or(0) as usize;
+    let signature = signatures.get(index).or(signatures.first())?;
+    let label = signature["label"].as_str()?.to_string();
+    let parameter = signature["activeParameter"]
+        .as_u64()
+        .or(result["activeParameter"].as_u64())
+        .unwrap_or(0) as usize;
+    let active = signature["parameters"]
+        .get(parameter)
+        .and_then(|parameter| match &parameter["label"] {
+            Value::String(name) => {
+                let start = label.find(name.as_str())?;
+                Some(start..start + name.len())
+            }
+            Value::Array(offsets) => {
+                let offset =
+                    |i: usize| Some(byte_column(&label, offsets.get(i)?.as_u64()? as usize));
+                Some(offset(0)?..offset(1)?)
+            }
+            _ => None,
+        })
+        .filter(|active| active.start <= active.end);
+    Some(Signature { label, active })
+}
+
+/// Inlay hints, with their padding as spaces around the label. Labels can
+/// come in parts, which are joined.
+fn parse_inlay_hints(result: &Value) -> Vec<Hint> {
+    let Some(hints) = result.as_array() else {
+        return vec![];
+    };
+    hints
+        .iter()
+        .filter_map(|hint| {
+            let label = match &hint["label"] {
+                Value::String(label) => label.clone(),
+                Value::Array(parts) => parts
+                    .iter()
+                    .filter_map(|part| part["value"].as_str())
+                    .collect(),
+                _ => return None,
+            };
+            let pad = |key: &str| if hint[key] == true { " " } else { "" };
+            Some(Hint {
+                line: hint["position"]["line"].as_u64()? as usize,
+                x: hint["position"]["character"].as_u64()? as usize,
+                label: format!("{}{label}{}", pad("paddingLeft"), pad("paddingRight")),
+            })
+        })
+        .collect()
+}
+
+fn parse_completions(result: &Value) -> Vec<Completion> {
+    let items = match result {
+        Value::Array(items) => items,
+        value => match value["items"].as_array() {
+            Some(items) => items,
+            None => return vec![],
+        },
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let label = item["label"].as_str()?.to_string();
+            let text = item["textEdit"]["newText"]
+                .as_str()
+                .or_else(|| item["insertText"].as_str())
+                .unwrap_or(&label)
+                .to_string();
+            Some(Completion { label, text })
+        })
+        .collect()
+}
+
+fn parse_diagnostics(diagnostics: &Value) -> Vec<Diagnostic> {
+    let Some(diagnostics) = diagnostics.as_array() else {
+        return vec![];
+    };
+
+    diagnostics
+        .iter()
+        .filter_map(|d| {
+            let start = &d["range"]["start"];
+            let severity = match d["severity"].as_u64() {
+                Some(2) => Severity::Warning,
+                Some(3) => Severity::Info,
+                Some(4) => Severity::Hint,
+                _ => Severity::Error,
+            };
+            Some(Diagnostic {
+                line: start["line"].as_u64()? as usize,
+                x: start["character"].as_u64()? as usize,
+                severity,
+                message: d["message"].as_str()?.to_string(),
+                source: "lsp".to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn path_to_uri(path: &str) -> anyhow::Result<String> {
+    let path = Path::new(path);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    Ok(format!(
+        "file://{}",
+        path.to_string_lossy()
+            .replace('%', "%25")
+            .replace(' ', "%20")
+    ))
+}
+
+pub fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = vec![];
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = iter.by_ref().take(2).collect();
+            if let Some(decoded) = std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                bytes.push(decoded);
+                continue;
+            }
+            bytes.push(b);
+            bytes.extend(hex);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Converts a byte column in `line` to the UTF-16 column LSP expects.
+pub fn utf16_column(line: &str, byte: usize) -> usize {
+    line.get(..byte)
+        .unwrap_or(line)
+        .chars()
+        .map(char::len_utf16)
+        .sum()
+}
+
+/// Converts an LSP UTF-16 column in `line` back to a byte column.
+pub fn byte_column(line: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= utf16 {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "result": null });
+        let encoded = encode(&message);
+        let mut reader = BufReader::new(&encoded[..]);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_results() {
+        let hover = json!({ "contents": { "kind": "markdown", "value": "fn main()" } });
+        assert_eq!(parse_hover(&hover), "fn main()");
+
+        let locations = json!([{
+            "targetUri": "file:///tmp/my%20file.rs",
+            "targetRange": {},
+            "targetSelectionRange": { "start": { "line": 3, "character": 4 } },
+        }]);
+        assert_eq!(
+            parse_locations(&locations),
+            vec![Location {
+                path: "/tmp/my file.rs".to_string(),
+                line: 3,
+                character: 4,
+            }]
+        );
+
+        let completions = json!({ "items": [{ "label": "len()", "insertText": "len" }] });
+        assert_eq!(
+            parse_completions(&completions),
+            vec![Completion {
+                label: "len()".to_string(),
+                text: "len".to_string(),
+            }]
+        );
+
+        let edit = json!({ "changes": { "file:///tmp/a.rs": [{
+            "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 7 } },
+            "newText": "bar",
+        }] } });
+        assert_eq!(
+            parse_workspace_edit(&edit),
+            vec![(
+                "/tmp/a.rs".to_string(),
+                vec![TextEdit {
+                    start: (1, 4),
+                    end: (1, 7),
+                    text: "bar".to_string(),
+                }]
+            )]
+        );
+
+        let help = json!({
+            "signatures": [{
+                "label": "fn add(a: u8, b: u8) -> u8",
+                "parameters": [{ "label": [7, 12] }, { "label": "b: u8" }],
+            }],
+            "activeParameter": 1,
+        });
+        assert_eq!(
+            parse_signature_help(&help),
+            Some(Signature {
+                label: "fn add(a: u8, b: u8) -> u8".to_string(),
+                active: Some(14..19),
+            })
+        );
+        assert_eq!(parse_signature_help(&Value::Null), None);
+
+        let hints = json!([{
+            "position": { "line": 0, "character": 5 },
+            "label": [{ "value": ": " }, { "value": "u8" }],
+            "paddingRight": true,
+        }]);
+        assert_eq!(
+            parse_inlay_hints(&hints),
+            vec![Hint {
+                line: 0,
+                x: 5,
+                label: ": u8 ".to_string(),
+            }]
+        );
+
+        let diagnostics = json!([{
+            "range": { "start": { "line": 2, "character": 1 }, "end": {} },
+            "severity": 2,
+            "message": "unused",
+        }]);
+        let diagnostics = parse_diagnostics(&diagnostics);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_utf16_columns() {
+        let line = "let é = '😀';";
+        assert_eq!(utf16_column(line, 9), 8);
+        assert_eq!(byte_column(line, 8), 9);
+        assert_eq!(byte_column(line, 100), line.len());
+    }
+}
@@ -1,45 +1,193 @@
-use std::{fs, io::stdout, panic};
+use std::{fs, path::Path};
 
 use buffer::Buffer;
+use cli::Args;
 use config::Config;
-use crossterm::{terminal, ExecutableCommand};
-use editor::Editor;
+use editor::{Action, Editor};
 use logger::Logger;
 use once_cell::sync::OnceCell;
+use session::Session;
 
+mod abbrev;
+mod autopairs;
+mod block;
 mod buffer;
+mod buffers;
+mod cli;
+mod command;
+mod comment;
+mod completion;
 mod config;
+mod crash;
+mod cursors;
+mod diagnostics;
+mod diffview;
+mod dirlist;
+mod docs;
 mod editor;
+mod editorconfig;
+mod encoding;
+mod finder;
+mod float;
+mod fold;
+mod git;
+mod grep;
+mod hex;
 mod highlighter;
+mod history;
+mod increment;
+mod inlay;
+mod jumplist;
+mod keymap;
+mod language;
 mod logger;
+mod lsp;
+mod make;
+mod markdown;
+mod marks;
+mod message;
+mod motion;
+mod picker;
+mod plugin;
+mod profile;
+mod project;
+mod quickfix;
+mod recent;
+mod session;
+mod shell;
+mod signals;
+mod status;
+mod statusline;
+mod stdin;
+mod surround;
+mod swap;
+mod tabs;
+mod tags;
+mod term;
 mod theme;
+mod undofile;
+mod wrap;
 
 static LOGGER: OnceCell<Logger> = OnceCell::new();
 
+/// Writes a record at a `logger::Level` to the log, when `--log-file`
+/// turned it on for that level.
 #[macro_export]
 macro_rules! log {
-    ($( $arg:tt )*) => {
-        let log_message = format!($( $arg )*);
-        $crate::LOGGER.get_or_init(|| $crate::Logger::new("rustik.log")).log(&log_message);
+    ($level:ident, $( $arg:tt )*) => {
+        if let Some(logger) = $crate::LOGGER.get() {
+            if logger.enabled($crate::logger::Level::$level) {
+                logger.log($crate::logger::Level::$level, &format!($( $arg )*));
+            }
+        }
     };
 }
 
+/// `--headless`: runs the actions in the script, a JSON array of them written
+/// like the ones in `[keys]`, on the file and writes it.
+fn run_headless(args: &Args, config: Config) -> anyhow::Result<()> {
+    let script = args
+        .script
+        .as_ref()
+        .expect("--headless comes with --script");
+    let actions: Vec<Action> = serde_json::from_str(&fs::read_to_string(script)?)
+        .map_err(|err| anyhow::anyhow!("{}: {err}", script.display()))?;
+    let file = args.files[0].clone();
+    let buffer = match Path::new(&file).exists() {
+        true => Buffer::from_file(Some(file), u64::MAX)?,
+        false => Buffer::new(Some(file), String::new()),
+    };
+    let mut editor = Editor::with_size(80, 24, config, theme::Theme::default(), buffer)?;
+    editor.run_script(actions)
+}
+
 fn main() -> anyhow::Result<()> {
-    let toml = fs::read_to_string("src/fixtures/config.toml")?;
-    let config: Config = toml::from_str(&toml)?;
-    let file = std::env::args().nth(1);
-    let buffer = Buffer::from_file(file.clone());
+    let args = Args::parse(std::env::args().skip(1))?;
+    if let Some(path) = &args.log_file {
+        let level = args.log_level.unwrap_or(logger::Level::Info);
+        _ = LOGGER.set(Logger::new(path, level)?);
+        log!(Info, "rustik {} started", env!("CARGO_PKG_VERSION"));
+    }
+    crash::install();
+    let cwd = std::env::current_dir()?;
+    let config_path = args.config.clone().or_else(config::find_path);
+    let project_config = config::find_project_path(&cwd);
+    if let Some(path) = &project_config {
+        log!(Info, "project config {}", path.display());
+    }
+    let config = Config::load(config_path.as_deref(), project_config.as_deref())?;
+    if args.headless {
+        return run_headless(&args, config);
+    }
+    // `-` reads the text from stdin, as does piping it in without a file
+    let from_stdin = args.files.first().is_some_and(|file| file == "-")
+        || (args.files.is_empty() && args.session.is_none() && stdin::is_piped());
+    let auto_session = config.session.auto && !from_stdin;
+    let session = match &args.session {
+        Some(path) => Some(Session::load(path)?),
+        None if auto_session && args.files.is_empty() => session::auto_path(&cwd)
+            .filter(|path| path.is_file())
+            .and_then(|path| Session::load(&path).ok()),
+        None => None,
+    };
+    if let Some(session) = &session {
+        std::env::set_current_dir(&session.cwd)?;
+    }
 
-    let theme = theme::parse_vscode_theme(&config.theme)?;
-    let mut editor = Editor::new(config, theme, buffer?)?;
+    let state = session.as_ref().and_then(Session::current);
+    let file = args
+        .files
+        .first()
+        .cloned()
+        .or_else(|| state.map(|s| s.file.clone()));
+    let (mut buffer, error) = if from_stdin {
+        (Buffer::new(None, stdin::read()?), None)
+    } else {
+        let opened = match (&file, args.hex) {
+            (Some(file), true) => Buffer::from_hex_file(file),
+            _ => Buffer::from_file(file.clone(), config.large_file_size()),
+        };
+        match opened {
+            Ok(buffer) => (buffer, None),
+            Err(err) => (Buffer::new(file, String::new()), Some(err)),
+        }
+    };
 
-    panic::set_hook(Box::new(|info| {
-        _ = stdout().execute(terminal::LeaveAlternateScreen);
-        _ = terminal::disable_raw_mode();
+    buffer.readonly |= args.readonly;
 
-        eprintln!("{}", info);
-    }));
+    let theme = theme::load(&config.theme, config.color_depth())?;
+    let mut editor = Editor::new(config, theme, buffer)?;
+    if let Some(path) = recent::path() {
+        editor.load_recent(&path);
+    }
+    if let Some(session) = &session {
+        editor.restore_session(session);
+    }
+    editor.set_arglist(args.files, args.readonly, args.hex);
+    if let Some(line) = args.line {
+        editor.start_at(line, args.column.unwrap_or(1));
+    }
+    editor.watch_config(config_path, project_config);
+    if let Some(dir) = config::plugin_dir() {
+        editor.load_plugins(&dir);
+    }
+    if let Some(err) = error {
+        editor.notify(message::Level::Error, err.to_string());
+    }
 
     editor.run()?;
-    editor.cleanup()
+    editor.cleanup()?;
+    // on stderr, as stdout is the screen: `2>` takes it without the editor
+    if args.print_position {
+        if let Some(position) = editor.position() {
+            eprintln!("{position}");
+        }
+    }
+
+    if auto_session {
+        if let Some(path) = session::auto_path(&cwd) {
+            editor.session()?.save(&path)?;
+        }
+    }
+    Ok(())
 }
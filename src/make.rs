@@ -0,0 +1,112 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// What a running build sends back.
+#[derive(Debug)]
+enum Event {
+    Line(String),
+    /// The build exited, with its exit code unless it was killed.
+    Done(Option<i32>),
+}
+
+/// A build started by `:make`, running in the background while its output
+/// comes in line by line.
+#[derive(Debug)]
+pub struct Make {
+    pub command: String,
+    pub lines: Vec<String>,
+    /// `None` while the build runs, then its exit code.
+    pub status: Option<Option<i32>>,
+    events: Receiver<Event>,
+}
+
+impl Make {
+    /// Starts `command` with `sh -c`. Its stdout and stderr both go to the
+    /// output, as they're printed.
+    pub fn start(command: &str) -> anyhow::Result<Make> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        let (tx, events) = mpsc::channel();
+        thread::spawn(move || {
+            let errors = {
+                let tx = tx.clone();
+                thread::spawn(move || send_lines(stderr, &tx))
+            };
+            send_lines(stdout, &tx);
+            _ = errors.join();
+            let code = child.wait().ok().and_then(|status| status.code());
+            _ = tx.send(Event::Done(code));
+        });
+
+        Ok(Make {
+            command: command.to_string(),
+            lines: vec![],
+            status: None,
+            events,
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status.is_none()
+    }
+
+    /// Takes the output printed since the last call, returning whether there
+    /// was any or the build finished.
+    pub fn receive(&mut self) -> bool {
+        let mut received = false;
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Event::Line(line) => self.lines.push(line),
+                Event::Done(code) => self.status = Some(code),
+            }
+            received = true;
+        }
+        received
+    }
+
+    /// Whether the build finished successfully.
+    pub fn succeeded(&self) -> bool {
+        self.status == Some(Some(0))
+    }
+}
+
+fn send_lines(output: impl Read, tx: &Sender<Event>) {
+    for line in BufReader::new(output).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if tx.send(Event::Line(line)).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_make() {
+        let mut make = Make::start("echo one; echo two >&2; exit 2").unwrap();
+        while make.is_running() {
+            make.receive();
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        make.lines.sort();
+        assert_eq!(make.lines, ["one", "two"]);
+        assert_eq!(make.status, Some(Some(2)));
+        assert!(!make.succeeded());
+    }
+}
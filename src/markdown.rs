@@ -0,0 +1,218 @@
+use std::mem;
+
+use crate::wrap;
+
+/// What a piece of rendered Markdown is, which picks its style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Heading,
+    Emphasis,
+    Strong,
+    Code,
+    Bullet,
+    Quote,
+    Link,
+    Rule,
+}
+
+/// A rendered row: pieces of text and what they are.
+pub type Line = Vec<(String, Kind)>;
+
+/// Renders Markdown `lines` for a terminal `width` columns wide, returning
+/// each row with the source line it comes from. Markers like `#` and `**`
+/// are dropped, and long lines wrap.
+pub fn render(lines: &[String], width: usize) -> Vec<(usize, Line)> {
+    let mut rows = vec![];
+    let mut in_code = false;
+    for (n, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        // code is cut off rather than wrapped, like in the buffer
+        if in_code {
+            rows.push((n, vec![(format!("  {line}"), Kind::Code)]));
+            continue;
+        }
+        let rendered = wrap_line(render_line(line, width), width);
+        rows.extend(rendered.into_iter().map(|row| (n, row)));
+    }
+    rows
+}
+
+/// Renders a line outside of code blocks.
+fn render_line(line: &str, width: usize) -> Line {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+        return inline(trimmed[hashes..].trim(), Kind::Heading)
+            .into_iter()
+            .map(|(text, _)| (text, Kind::Heading))
+            .collect();
+    }
+    if is_rule(trimmed) {
+        return vec![("─".repeat(width), Kind::Rule)];
+    }
+    if let Some(rest) = trimmed.strip_prefix('>') {
+        let mut rendered = vec![("│ ".to_string(), Kind::Quote)];
+        rendered.extend(inline(rest.trim_start(), Kind::Quote));
+        return rendered;
+    }
+    if let Some((bullet, rest)) = list_item(trimmed) {
+        let mut rendered = vec![(format!("{indent}{bullet} "), Kind::Bullet)];
+        rendered.extend(inline(rest, Kind::Text));
+        return rendered;
+    }
+    inline(line, Kind::Text)
+}
+
+/// `---`, `***` or `___`, maybe with spaces in between.
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|mark| marks.chars().all(|c| c.to_string() == *mark))
+}
+
+/// The bullet to draw for a list item and the text after its marker.
+fn list_item(line: &str) -> Option<(String, &str)> {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return Some(("•".to_string(), rest));
+        }
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = line[digits..].strip_prefix(". ")?;
+    (digits > 0).then(|| (line[..digits + 1].to_string(), rest))
+}
+
+/// Splits text into styled pieces for `**strong**`, `*emphasis*` (or with
+/// underscores), `` `code` `` and `[links](url)`, the rest being `base`.
+fn inline(text: &str, base: Kind) -> Line {
+    let mut pieces = vec![];
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // underscores inside words are just underscores
+        let in_word = c == '_' && plain.ends_with(|c: char| c.is_alphanumeric());
+        let found = match c {
+            '`' => delimited(rest, "`").map(|(inner, after)| (inner, Kind::Code, after)),
+            '*' | '_' if !in_word => {
+                let strong = if c == '*' { "**" } else { "__" };
+                let emphasis = if c == '*' { "*" } else { "_" };
+                delimited(rest, strong)
+                    .map(|(inner, after)| (inner, Kind::Strong, after))
+                    .or_else(|| {
+                        delimited(rest, emphasis)
+                            .map(|(inner, after)| (inner, Kind::Emphasis, after))
+                    })
+            }
+            '[' => link(rest).map(|(inner, after)| (inner, Kind::Link, after)),
+            _ => None,
+        };
+        match found {
+            Some((inner, kind, after)) => {
+                if !plain.is_empty() {
+                    pieces.push((mem::take(&mut plain), base));
+                }
+                pieces.push((inner.to_string(), kind));
+                rest = after;
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        pieces.push((plain, base));
+    }
+    pieces
+}
+
+/// The text between `delimiter` at the start of `text` and the next one, and
+/// what follows that.
+fn delimited<'a>(text: &'a str, delimiter: &str) -> Option<(&'a str, &'a str)> {
+    let inner = text.strip_prefix(delimiter)?;
+    let end = inner.find(delimiter)?;
+    (end > 0).then(|| (&inner[..end], &inner[end + delimiter.len()..]))
+}
+
+/// The text of a `[text](url)` link at the start of `text`, and what follows
+/// it.
+fn link(text: &str) -> Option<(&str, &str)> {
+    let close = text.find("](")?;
+    let end = close + text[close..].find(')')?;
+    Some((&text[1..close], &text[end + 1..]))
+}
+
+/// Wraps a rendered line to `width` columns, breaking where the buffer's
+/// soft wrapping would.
+fn wrap_line(line: Line, width: usize) -> Vec<Line> {
+    let plain: String = line.iter().map(|(text, _)| text.as_str()).collect();
+    let starts = wrap::row_starts(&plain, width);
+    let mut rows: Vec<Line> = vec![vec![]; starts.len()];
+    let mut pos = 0;
+    for (text, kind) in line {
+        for (i, c) in text.char_indices() {
+            let row = wrap::row_of(&starts, pos + i);
+            match rows[row].last_mut() {
+                Some((piece, k)) if *k == kind => piece.push(c),
+                _ => rows[row].push((c.to_string(), kind)),
+            }
+        }
+        pos += text.len();
+    }
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            inline("a **b** *c* `d` [e](f) snake_case", Kind::Text),
+            [
+                ("a ".to_string(), Kind::Text),
+                ("b".to_string(), Kind::Strong),
+                (" ".to_string(), Kind::Text),
+                ("c".to_string(), Kind::Emphasis),
+                (" ".to_string(), Kind::Text),
+                ("d".to_string(), Kind::Code),
+                (" ".to_string(), Kind::Text),
+                ("e".to_string(), Kind::Link),
+                (" snake_case".to_string(), Kind::Text),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let lines: Vec<String> = ["# Title", "- one", "```", "let x = 1;", "```", "> quoted"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let rows = render(&lines, 20);
+        assert_eq!(rows[0], (0, vec![("Title".to_string(), Kind::Heading)]));
+        assert_eq!(
+            rows[1],
+            (
+                1,
+                vec![
+                    ("• ".to_string(), Kind::Bullet),
+                    ("one".to_string(), Kind::Text)
+                ]
+            )
+        );
+        assert_eq!(rows[2], (3, vec![("  let x = 1;".to_string(), Kind::Code)]));
+        assert_eq!(rows[3].1[0], ("│ quoted".to_string(), Kind::Quote));
+        assert_eq!(render(&["a b c d".to_string()], 4).len(), 2);
+    }
+}
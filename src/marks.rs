@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use tree_sitter::InputEdit;
+
+use crate::fold;
+
+/// A marked position: the file (`None` for a buffer without one) and the
+/// `(x, line)` in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub file: Option<String>,
+    pub x: usize,
+    pub line: usize,
+}
+
+/// Marks set with `m`. Lowercase marks belong to the file they're set in,
+/// uppercase ones are global and lead back to their file from anywhere. `'`
+/// is set automatically to where the cursor last jumped from.
+#[derive(Debug, Default)]
+pub struct Marks {
+    local: HashMap<Option<String>, HashMap<char, (usize, usize)>>,
+    global: HashMap<char, Mark>,
+}
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphabetic() || name == '\''
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, mark: Mark) {
+        if name.is_ascii_uppercase() {
+            self.global.insert(name, mark);
+        } else {
+            self.local
+                .entry(mark.file)
+                .or_default()
+                .insert(name, (mark.x, mark.line));
+        }
+    }
+
+    /// Looks a mark up from `file`.
+    pub fn get(&self, name: char, file: Option<&String>) -> Option<Mark> {
+        if name.is_ascii_uppercase() {
+            return self.global.get(&name).cloned();
+        }
+        let (x, line) = *self.local.get(&file.cloned())?.get(&name)?;
+        Some(Mark {
+            file: file.cloned(),
+            x,
+            line,
+        })
+    }
+
+    /// Keeps the marks of `file` on the same text when lines are inserted or
+    /// removed above them.
+    pub fn apply_edit(&mut self, file: Option<&String>, edit: &InputEdit) {
+        if edit.old_end_position.row == edit.new_end_position.row {
+            return;
+        }
+        if let Some(marks) = self.local.get_mut(&file.cloned()) {
+            for (_, line) in marks.values_mut() {
+                *line = fold::shift_row(*line, edit);
+            }
+        }
+        for mark in self.global.values_mut().filter(|m| m.file.as_ref() == file) {
+            mark.line = fold::shift_row(mark.line, edit);
+        }
+    }
+
+    /// Renames the files of the marks, after `:cd`.
+    pub fn rebase(&mut self, rename: impl Fn(&str) -> String) {
+        self.local = std::mem::take(&mut self.local)
+            .into_iter()
+            .map(|(file, marks)| (file.as_deref().map(&rename), marks))
+            .collect();
+        for mark in self.global.values_mut() {
+            mark.file = mark.file.as_deref().map(&rename);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree_sitter::Point;
+
+    use super::*;
+
+    fn mark(file: &str, line: usize) -> Mark {
+        Mark {
+            file: Some(file.to_string()),
+            x: 2,
+            line,
+        }
+    }
+
+    #[test]
+    fn test_local_and_global_marks() {
+        let (a, b) = ("a.rs".to_string(), "b.rs".to_string());
+        let mut marks = Marks::default();
+        marks.set('a', mark("a.rs", 3));
+        marks.set('A', mark("a.rs", 5));
+
+        assert_eq!(marks.get('a', Some(&a)), Some(mark("a.rs", 3)));
+        assert_eq!(marks.get('a', Some(&b)), None);
+        assert_eq!(marks.get('A', Some(&b)), Some(mark("a.rs", 5)));
+
+        // a line inserted above both marks
+        marks.apply_edit(
+            Some(&a),
+            &InputEdit {
+                start_byte: 0,
+                old_end_byte: 0,
+                new_end_byte: 1,
+                start_position: Point::new(0, 0),
+                old_end_position: Point::new(0, 0),
+                new_end_position: Point::new(1, 0),
+            },
+        );
+        assert_eq!(marks.get('a', Some(&a)), Some(mark("a.rs", 4)));
+        assert_eq!(marks.get('A', None), Some(mark("a.rs", 6)));
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+/// How long a message stays on the message line.
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HISTORY: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    shown_at: Instant,
+}
+
+/// The message shown below the statusline, and the ones shown before it.
+#[derive(Debug, Default)]
+pub struct Messages {
+    current: Option<Message>,
+    history: Vec<Message>,
+}
+
+impl Messages {
+    pub fn push(&mut self, level: Level, text: String) {
+        let message = Message {
+            level,
+            text,
+            shown_at: Instant::now(),
+        };
+        if self.history.len() >= MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(message.clone());
+        self.current = Some(message);
+    }
+
+    pub fn current(&self) -> Option<&Message> {
+        self.current.as_ref()
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+    }
+
+    /// How long until the current message times out.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.current
+            .as_ref()
+            .map(|m| TIMEOUT.saturating_sub(m.shown_at.elapsed()))
+    }
+
+    /// Clears the current message once it timed out, returning whether it did.
+    pub fn expire(&mut self) -> bool {
+        if self.remaining() == Some(Duration::ZERO) {
+            self.current = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_messages_expire_but_stay_in_history() {
+        let mut messages = Messages::default();
+        messages.push(Level::Error, "file not found".to_string());
+        assert_eq!(messages.current().map(|m| m.level), Some(Level::Error));
+        assert!(!messages.expire());
+
+        messages.current.as_mut().unwrap().shown_at -= TIMEOUT;
+        assert!(messages.expire());
+        assert!(messages.current().is_none());
+        assert_eq!(messages.history()[0].text, "file not found");
+    }
+}
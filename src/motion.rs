@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+
+/// Motions over the text that need more than the cursor's line to find
+/// their target. They move the cursor in normal mode and give the range an
+/// operator like `d` applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Motion {
+    /// `}`: to the blank line after the paragraph.
+    ParagraphForward,
+    /// `{`: to the blank line before the paragraph.
+    ParagraphBackward,
+    /// `)`: to the start of the next sentence.
+    SentenceForward,
+    /// `(`: to the start of the sentence, or the one before at its start.
+    SentenceBackward,
+}
+
+/// The in-line character searches: `f`, `F`, `t` and `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Find {
+    /// `f`: to the next occurrence of the character.
+    Forward,
+    /// `F`: to the previous occurrence.
+    Backward,
+    /// `t`: to just before the next occurrence.
+    TillForward,
+    /// `T`: to just after the previous occurrence.
+    TillBackward,
+}
+
+impl Find {
+    pub fn is_forward(self) -> bool {
+        matches!(self, Find::Forward | Find::TillForward)
+    }
+
+    /// The search `,` runs after this one.
+    pub fn reversed(self) -> Find {
+        match self {
+            Find::Forward => Find::Backward,
+            Find::Backward => Find::Forward,
+            Find::TillForward => Find::TillBackward,
+            Find::TillBackward => Find::TillForward,
+        }
+    }
+}
+
+/// Returns where searching `line` for `c` from `x` leads, if it's there. A
+/// `repeat` of `t` or `T` skips an occurrence right next to the cursor, so
+/// `;` doesn't get stuck in front of it.
+pub fn find_char(find: Find, c: char, line: &str, x: usize, repeat: bool) -> Option<usize> {
+    let chars = line.char_indices();
+    match find {
+        Find::Forward | Find::TillForward => {
+            let mut after = chars.skip_while(|&(i, _)| i <= x).peekable();
+            let mut prev = x;
+            if repeat && find == Find::TillForward {
+                if let Some((i, _)) = after.next_if(|&(_, ch)| ch == c) {
+                    prev = i;
+                }
+            }
+            for (i, ch) in after {
+                if ch == c {
+                    return Some(if find == Find::Forward { i } else { prev });
+                }
+                prev = i;
+            }
+            None
+        }
+        Find::Backward | Find::TillBackward => {
+            let mut before = chars.take_while(|&(i, _)| i < x).collect::<Vec<_>>();
+            let mut next = x;
+            if repeat && find == Find::TillBackward {
+                if let Some(&(i, ch)) = before.last() {
+                    if ch == c {
+                        before.pop();
+                        next = i;
+                    }
+                }
+            }
+            for &(i, ch) in before.iter().rev() {
+                if ch == c {
+                    return Some(if find == Find::Backward { i } else { next });
+                }
+                next = i;
+            }
+            None
+        }
+    }
+}
+
+/// Returns the `(x, line)` the motion leads to from `(x, line)`.
+pub fn target(motion: Motion, lines: &[String], from: (usize, usize)) -> (usize, usize) {
+    match motion {
+        Motion::ParagraphForward => paragraph_forward(lines, from.1),
+        Motion::ParagraphBackward => paragraph_backward(lines, from.1),
+        Motion::SentenceForward => {
+            let starts = sentence_starts(lines);
+            let pos = (from.1, from.0);
+            starts
+                .into_iter()
+                .find(|&(x, y)| (y, x) > pos)
+                .unwrap_or_else(|| end(lines))
+        }
+        Motion::SentenceBackward => {
+            let starts = sentence_starts(lines);
+            let pos = (from.1, from.0);
+            starts
+                .into_iter()
+                .rev()
+                .find(|&(x, y)| (y, x) < pos)
+                .unwrap_or((0, 0))
+        }
+    }
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+/// The position after the last character.
+fn end(lines: &[String]) -> (usize, usize) {
+    let last = lines.len().saturating_sub(1);
+    (lines.get(last).map_or(0, |l| l.len()), last)
+}
+
+/// Moves to the first blank line after some text, starting with the
+/// cursor's line.
+fn paragraph_forward(lines: &[String], line: usize) -> (usize, usize) {
+    let mut seen_text = lines.get(line).is_some_and(|l| !is_blank(l));
+    for (y, text) in lines.iter().enumerate().skip(line + 1) {
+        if is_blank(text) && seen_text {
+            return (0, y);
+        }
+        seen_text |= !is_blank(text);
+    }
+    end(lines)
+}
+
+fn paragraph_backward(lines: &[String], line: usize) -> (usize, usize) {
+    let mut seen_text = lines.get(line).is_some_and(|l| !is_blank(l));
+    for y in (0..line.min(lines.len())).rev() {
+        if is_blank(&lines[y]) && seen_text {
+            return (0, y);
+        }
+        seen_text |= !is_blank(&lines[y]);
+    }
+    (0, 0)
+}
+
+/// Where sentences start: the first character after a `.`, `!` or `?`
+/// followed by whitespace (closing brackets and quotes may come in between),
+/// the first character after a blank line, and the first blank line of a
+/// run, which separates paragraphs.
+fn sentence_starts(lines: &[String]) -> Vec<(usize, usize)> {
+    let mut starts = vec![];
+    let mut at_start = true;
+    let mut ended = false;
+    let mut after_blank = false;
+
+    for (y, line) in lines.iter().enumerate() {
+        if is_blank(line) {
+            if !after_blank {
+                starts.push((0, y));
+            }
+            after_blank = true;
+            at_start = true;
+            ended = false;
+            continue;
+        }
+        after_blank = false;
+
+        for (x, c) in line.char_indices() {
+            if c.is_whitespace() {
+                at_start |= ended;
+                ended = false;
+                continue;
+            }
+            if at_start {
+                starts.push((x, y));
+                at_start = false;
+            }
+            ended = match c {
+                '.' | '!' | '?' => true,
+                ')' | ']' | '"' | '\'' => ended,
+                _ => false,
+            };
+        }
+        // the line break counts as whitespace
+        at_start |= ended;
+        ended = false;
+    }
+    starts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_paragraph_motions() {
+        let lines = lines("a\nb\n\n\nc\nd\n\ne");
+        let forward = |pos| target(Motion::ParagraphForward, &lines, pos);
+        let backward = |pos| target(Motion::ParagraphBackward, &lines, pos);
+
+        assert_eq!(forward((0, 0)), (0, 2));
+        assert_eq!(forward((0, 2)), (0, 6));
+        assert_eq!(forward((0, 7)), (1, 7));
+        assert_eq!(backward((0, 5)), (0, 3));
+        assert_eq!(backward((0, 4)), (0, 3));
+        assert_eq!(backward((0, 3)), (0, 0));
+    }
+
+    #[test]
+    fn test_find_char() {
+        let line = "a(b, c), d";
+        assert_eq!(find_char(Find::Forward, ',', line, 0, false), Some(3));
+        assert_eq!(find_char(Find::Forward, ',', line, 3, false), Some(7));
+        assert_eq!(find_char(Find::TillForward, ')', line, 0, false), Some(5));
+        assert_eq!(find_char(Find::TillForward, ',', line, 2, false), Some(2));
+        assert_eq!(find_char(Find::TillForward, ',', line, 2, true), Some(6));
+        assert_eq!(find_char(Find::TillForward, ',', "a,,", 0, true), Some(1));
+        assert_eq!(find_char(Find::Backward, '(', line, 9, false), Some(1));
+        assert_eq!(find_char(Find::TillBackward, ',', line, 9, false), Some(8));
+        assert_eq!(find_char(Find::TillBackward, ',', line, 8, true), Some(4));
+        assert_eq!(find_char(Find::Forward, 'x', line, 0, false), None);
+        assert_eq!(find_char(Find::Backward, 'a', line, 0, false), None);
+    }
+
+    #[test]
+    fn test_sentence_motions() {
+        let lines = lines("One. Two (three).  Four?\nfive\n\nSix");
+        let forward = |pos| target(Motion::SentenceForward, &lines, pos);
+        let backward = |pos| target(Motion::SentenceBackward, &lines, pos);
+
+        assert_eq!(forward((0, 0)), (5, 0));
+        assert_eq!(forward((5, 0)), (19, 0));
+        assert_eq!(forward((19, 0)), (0, 1));
+        assert_eq!(forward((2, 1)), (0, 2));
+        assert_eq!(forward((0, 2)), (0, 3));
+        assert_eq!(backward((7, 0)), (5, 0));
+        assert_eq!(backward((5, 0)), (0, 0));
+    }
+}
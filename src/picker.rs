@@ -0,0 +1,293 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, TryRecvError},
+    Arc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerKind {
+    Files,
+    Grep,
+    Themes,
+    Quickfix,
+    Buffers,
+    CommandHistory,
+    Recent,
+}
+
+/// Items sent in batches by a background job. The job should stop once
+/// `cancelled` is set, which happens when the source is dropped.
+#[derive(Debug)]
+pub struct Source {
+    items: Receiver<Vec<String>>,
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl Source {
+    pub fn new(items: Receiver<Vec<String>>) -> Self {
+        Self {
+            items,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A list of items narrowed down by fuzzy matching a query, optionally fed
+/// in batches from a background source while it's open. A live picker
+/// doesn't filter: its source is replaced whenever the query changes.
+#[derive(Debug)]
+pub struct Picker {
+    pub kind: PickerKind,
+    pub title: String,
+    pub query: String,
+    pub selected: usize,
+    pub live: bool,
+    items: Vec<String>,
+    matches: Vec<usize>,
+    source: Option<Source>,
+}
+
+impl Picker {
+    pub fn new(kind: PickerKind, title: &str) -> Self {
+        Self {
+            kind,
+            title: title.to_string(),
+            query: String::new(),
+            selected: 0,
+            live: false,
+            items: vec![],
+            matches: vec![],
+            source: None,
+        }
+    }
+
+    pub fn live(mut self) -> Self {
+        self.live = true;
+        self
+    }
+
+    pub fn with_items(mut self, items: Vec<String>) -> Self {
+        self.items = items;
+        self.refilter();
+        self
+    }
+
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.set_source(source);
+        self
+    }
+
+    /// Replaces the items with the ones `source` sends, cancelling the
+    /// previous source.
+    pub fn set_source(&mut self, source: Source) {
+        self.source = Some(source);
+        self.items.clear();
+        self.refilter();
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Takes the items the source sent since the last call, returning whether
+    /// there were any.
+    pub fn receive(&mut self) -> bool {
+        let Some(source) = &self.source else {
+            return false;
+        };
+
+        let mut received = false;
+        loop {
+            match source.items.try_recv() {
+                Ok(items) => {
+                    self.items.extend(items);
+                    received = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.source = None;
+                    received = true;
+                    break;
+                }
+            }
+        }
+
+        if received {
+            let selected = self.selected;
+            self.refilter();
+            self.selected = selected.min(self.matches.len().saturating_sub(1));
+        }
+        received
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.query.push(c);
+        self.refilter();
+    }
+
+    pub fn pop_char(&mut self) {
+        if self.query.pop().is_some() {
+            self.refilter();
+        }
+    }
+
+    fn refilter(&mut self) {
+        self.selected = 0;
+        if self.live {
+            self.matches = (0..self.items.len()).collect();
+            return;
+        }
+
+        let mut scored: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| Some((fuzzy_score(&self.query, item)?, i)))
+            .collect();
+        scored.sort_by_key(|(score, i)| (-score, self.items[*i].len(), *i));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = self
+                .selected
+                .checked_sub(1)
+                .unwrap_or(self.matches.len() - 1);
+        }
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.matches
+            .get(self.selected)
+            .map(|i| self.items[*i].as_str())
+    }
+
+    pub fn matches(&self) -> impl Iterator<Item = &str> {
+        self.matches.iter().map(|i| self.items[*i].as_str())
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+/// Scores how well `candidate` matches `pattern` when the pattern's
+/// characters appear in it in order, or returns `None` when they don't. The
+/// best scoring alignment wins: matches at word boundaries (especially for
+/// the first character) and runs of consecutive matches score higher, gaps
+/// between matches lower. Matching is case-insensitive unless the pattern has
+/// uppercase characters.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    const BOUNDARY: i64 = 8;
+    const CONSECUTIVE: i64 = 6;
+
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let normalize = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_ascii_lowercase()
+        }
+    };
+    let pattern: Vec<char> = pattern.chars().map(normalize).collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+
+    let bonus = |j: usize| match j.checked_sub(1).map(|k| chars[k]) {
+        None => BOUNDARY,
+        Some('/' | '_' | '-' | '.' | ' ') => BOUNDARY,
+        Some(prev) if prev.is_lowercase() && chars[j].is_uppercase() => BOUNDARY,
+        _ => 0,
+    };
+
+    // best[j]: the best score with the current pattern char matched at j
+    let mut best: Vec<Option<i64>> = chars
+        .iter()
+        .enumerate()
+        .map(|(j, c)| (normalize(*c) == pattern[0]).then(|| 1 + 2 * bonus(j)))
+        .collect();
+
+    for p in &pattern[1..] {
+        let mut next = vec![None; chars.len()];
+        // the best of `best[k] + k` over k < j - 1, for a gap penalty of 1 per char
+        let mut gapped: Option<i64> = None;
+        for j in 1..chars.len() {
+            if j >= 2 {
+                if let Some(score) = best[j - 2] {
+                    gapped = gapped.max(Some(score + (j - 2) as i64));
+                }
+            }
+            if normalize(chars[j]) != *p {
+                continue;
+            }
+            let consecutive = best[j - 1].map(|score| score + CONSECUTIVE);
+            let after_gap = gapped.map(|score| score - j as i64 + 1);
+            next[j] = consecutive.max(after_gap).map(|score| score + 1 + bonus(j));
+        }
+        best = next;
+    }
+
+    best.into_iter().flatten().max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("edr", "src/editor.rs").is_some());
+        assert!(fuzzy_score("rde", "src/editor.rs").is_none());
+        assert!(fuzzy_score("Ed", "src/editor.rs").is_none());
+        assert!(fuzzy_score("", "anything").is_some());
+        assert!(
+            fuzzy_score("buf", "src/buffer.rs").unwrap()
+                > fuzzy_score("buf", "src/bin/ruf.rs").unwrap()
+        );
+        assert!(
+            fuzzy_score("edr", "src/editor.rs").unwrap()
+                > fuzzy_score("edr", "themes/dracula.json").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_picker_receives_and_filters() {
+        let (tx, rx) = mpsc::channel();
+        let mut picker = Picker::new(PickerKind::Files, "Files").with_source(Source::new(rx));
+        tx.send(vec!["src/main.rs".to_string(), "README.md".to_string()])
+            .unwrap();
+        drop(tx);
+
+        assert!(picker.receive());
+        assert!(!picker.is_loading());
+        assert_eq!(picker.item_count(), 2);
+
+        picker.push_char('m');
+        picker.push_char('a');
+        assert_eq!(picker.matches().collect::<Vec<_>>(), vec!["src/main.rs"]);
+        picker.pop_char();
+        picker.pop_char();
+        picker.select_prev();
+        assert_eq!(picker.selected(), Some("src/main.rs"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::editor::Action;
+
+/// What a plugin asks of the editor, one JSON object per line of its
+/// stdout.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Adds `:name`, which sends the plugin a `command` event.
+    Command {
+        name: String,
+    },
+    /// Maps `key` in normal mode to one of the plugin's commands.
+    Map {
+        key: String,
+        command: String,
+    },
+    /// Sets the plugin's text in the `plugins` statusline segment.
+    Status {
+        text: String,
+    },
+    /// Replaces lines `start..end` of the buffer, undoable in one step.
+    SetLines {
+        start: usize,
+        end: usize,
+        lines: Vec<String>,
+    },
+    /// Runs built-in actions, written like the ones in `[keys]`.
+    Actions {
+        actions: Vec<Action>,
+    },
+    Notify {
+        message: String,
+    },
+}
+
+/// What the editor tells a plugin, one JSON object per line of its stdin.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// One of its commands was run, in the buffer given. `line` and `column`
+    /// are the zero based cursor position.
+    Command {
+        name: &'a str,
+        args: &'a str,
+        file: Option<&'a str>,
+        filetype: &'a str,
+        line: usize,
+        column: usize,
+        lines: &'a [String],
+    },
+}
+
+/// A plugin's process, started from an executable in the plugins directory.
+struct Plugin {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    status: Option<String>,
+}
+
+/// The running plugins, with the commands and mappings they added.
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+    /// Which plugin each command goes to.
+    commands: HashMap<String, usize>,
+    /// The keys mapped to commands, to map again when the config is
+    /// reloaded.
+    pub mappings: Vec<(String, String)>,
+    tx: Sender<(usize, Result<Request, String>)>,
+    rx: Receiver<(usize, Result<Request, String>)>,
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            plugins: vec![],
+            commands: HashMap::new(),
+            mappings: vec![],
+            tx,
+            rx,
+        }
+    }
+}
+
+impl Plugins {
+    /// Starts every executable in `dir`, in the order of their names,
+    /// returning the errors of the ones that couldn't be started.
+    pub fn load(&mut self, dir: &Path) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return vec![];
+        };
+        let mut paths: Vec<_> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            })
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .filter_map(|path| self.start(path).err())
+            .collect()
+    }
+
+    fn start(&mut self, path: &Path) -> Result<(), String> {
+        let name = path
+            .file_stem()
+            .map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("plugin {name}: {err}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let index = self.plugins.len();
+        let tx = self.tx.clone();
+        let plugin = name.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let request =
+                    serde_json::from_str(&line).map_err(|err| format!("plugin {plugin}: {err}"));
+                if tx.send((index, request)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.plugins.push(Plugin {
+            name,
+            child,
+            stdin,
+            status: None,
+        });
+        Ok(())
+    }
+
+    /// Takes the requests the plugins sent since the last call, keeping
+    /// track of the commands, mappings and statusline texts they add.
+    pub fn receive(&mut self) -> Vec<Result<Request, String>> {
+        let mut requests = vec![];
+        while let Ok((index, request)) = self.rx.try_recv() {
+            match &request {
+                Ok(Request::Command { name }) => {
+                    self.commands.insert(name.clone(), index);
+                }
+                Ok(Request::Map { key, command }) => {
+                    self.mappings.push((key.clone(), command.clone()));
+                }
+                Ok(Request::Status { text }) => {
+                    self.plugins[index].status = Some(text.clone()).filter(|t| !t.is_empty());
+                }
+                _ => {}
+            }
+            requests.push(request);
+        }
+        requests
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    /// Sends `event` to the plugin that added the command `name`.
+    pub fn send(&mut self, name: &str, event: &Event) -> anyhow::Result<()> {
+        let Some(&index) = self.commands.get(name) else {
+            anyhow::bail!("Unknown command: {name}");
+        };
+        let plugin = &mut self.plugins[index];
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        plugin
+            .stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| plugin.stdin.flush())
+            .map_err(|err| anyhow::anyhow!("plugin {}: {err}", plugin.name))
+    }
+
+    /// The plugins' statusline texts, in the order they were loaded.
+    pub fn status(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .plugins
+            .iter()
+            .filter_map(|plugin| plugin.status.as_deref())
+            .collect();
+        (!texts.is_empty()).then(|| texts.join(" "))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+}
+
+impl Drop for Plugins {
+    fn drop(&mut self) {
+        for plugin in &mut self.plugins {
+            _ = plugin.child.kill();
+            _ = plugin.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_requests() {
+        let parse = |line: &str| serde_json::from_str::<Request>(line).unwrap();
+        assert!(matches!(
+            parse(r#"{"type": "map", "key": "<leader>w", "command": "count"}"#),
+            Request::Map { key, command } if key == "<leader>w" && command == "count"
+        ));
+        assert!(matches!(
+            parse(r#"{"type": "set_lines", "start": 0, "end": 1, "lines": ["a"]}"#),
+            Request::SetLines { start: 0, end: 1, lines } if lines == ["a"]
+        ));
+        assert!(serde_json::from_str::<Request>(r#"{"type": "exit"}"#).is_err());
+        let Request::Actions { actions } =
+            parse(r#"{"type": "actions", "actions": ["MoveDown", { "GotoLine": 3 }]}"#)
+        else {
+            panic!("not actions");
+        };
+        assert!(matches!(
+            actions[..],
+            [Action::MoveDown, Action::GotoLine(3)]
+        ));
+    }
+}
@@ -0,0 +1,93 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// How many frames the overlay sums up.
+const FRAMES: usize = 60;
+
+/// What it took to show one frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Frame {
+    /// From the first draw call to the frame written out.
+    pub render: Duration,
+    /// From the key or event read to the frame written out.
+    pub latency: Duration,
+    /// The cells that differed from the frame before, and were written.
+    pub cells: usize,
+}
+
+/// The timings `:profile` shows over the viewport, to see where the time
+/// goes when drawing.
+#[derive(Debug, Default)]
+pub struct Profile {
+    frames: VecDeque<Frame>,
+    /// How long the highlighter last took to parse and highlight the
+    /// buffer, on its own thread.
+    pub highlight: Option<Duration>,
+}
+
+impl Profile {
+    pub fn record(&mut self, frame: Frame) {
+        if self.frames.len() == FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// The overlay's rows: the last frame's numbers and the most of the
+    /// recent ones.
+    pub fn lines(&self) -> Vec<String> {
+        let last = self.frames.back().copied().unwrap_or_default();
+        let max =
+            |value: fn(&Frame) -> Duration| self.frames.iter().map(value).max().unwrap_or_default();
+        let max_cells = self.frames.iter().map(|f| f.cells).max().unwrap_or(0);
+        let highlight = self.highlight.map_or("-".to_string(), millis);
+        vec![
+            format!(
+                "render    {:>8}  max {:>8}",
+                millis(last.render),
+                millis(max(|f| f.render))
+            ),
+            format!(
+                "latency   {:>8}  max {:>8}",
+                millis(last.latency),
+                millis(max(|f| f.latency))
+            ),
+            format!("cells     {:>8}  max {:>8}", last.cells, max_cells),
+            format!("highlight {highlight:>8}"),
+        ]
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let mut profile = Profile::default();
+        for (ms, cells) in [(4, 10), (1, 3)] {
+            profile.record(Frame {
+                render: Duration::from_millis(ms),
+                latency: Duration::from_millis(ms + 1),
+                cells,
+            });
+        }
+        assert_eq!(
+            profile.lines(),
+            [
+                "render      1.00ms  max   4.00ms",
+                "latency     2.00ms  max   5.00ms",
+                "cells            3  max       10",
+                "highlight        -",
+            ]
+        );
+        for _ in 0..FRAMES {
+            profile.record(Frame::default());
+        }
+        assert_eq!(profile.frames.len(), FRAMES);
+        assert!(profile.lines()[0].ends_with("max   0.00ms"));
+    }
+}
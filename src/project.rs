@@ -0,0 +1,83 @@
+use std::path::{Component, Path, PathBuf};
+
+/// What marks the top of a project outside a git repository.
+const MARKERS: &[&str] = &[
+    ".rustik.toml",
+    "Cargo.toml",
+    "package.json",
+    "go.mod",
+    "pyproject.toml",
+    "Makefile",
+];
+
+/// The root of the project `dir` is in: the top of its git repository, or
+/// else the closest directory above it with one of the `MARKERS`.
+pub fn root(dir: &Path) -> Option<PathBuf> {
+    let git = dir.ancestors().find(|dir| dir.join(".git").exists());
+    git.or_else(|| {
+        dir.ancestors()
+            .find(|dir| MARKERS.iter().any(|marker| dir.join(marker).exists()))
+    })
+    .map(Path::to_path_buf)
+}
+
+/// `file`, a path relative to `from`, as one relative to `to`, or the
+/// whole path when it's not under `to`.
+pub fn rebase(file: &str, from: &Path, to: &Path) -> String {
+    let path = normalize(&from.join(file));
+    match path.strip_prefix(to) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Ok(relative) => relative.display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Drops the `.` in a path and the directories a `..` goes back out of,
+/// without looking at the disk.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normal.file_name().is_some() => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_root() {
+        let dir = std::env::temp_dir().join(format!("rustik-project-{}", std::process::id()));
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("repo/crates/a/src")).unwrap();
+        fs::write(dir.join("repo/crates/a/Cargo.toml"), "").unwrap();
+        fs::create_dir_all(dir.join("plain/src")).unwrap();
+        fs::write(dir.join("plain/go.mod"), "").unwrap();
+
+        let git = root(&dir.join("repo/crates/a/src"));
+        let marked = root(&dir.join("plain/src"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(git, Some(dir.join("repo")));
+        assert_eq!(marked, Some(dir.join("plain")));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let (root, sub) = (Path::new("/r"), Path::new("/r/src"));
+        assert_eq!(rebase("src/main.rs", root, sub), "main.rs");
+        assert_eq!(rebase("README.md", root, sub), "/r/README.md");
+        assert_eq!(rebase("main.rs", sub, root), "src/main.rs");
+        assert_eq!(rebase("../lib.rs", sub, root), "lib.rs");
+        assert_eq!(rebase("/etc/hosts", sub, root), "/etc/hosts");
+        assert_eq!(rebase("./src", root, sub), ".");
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static LOCATION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*([^:\s][^:]*):(\d+):(?:(\d+):)?\s*(.*)$").unwrap());
+
+/// A location in the quickfix list, with a zero based line and byte column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub file: String,
+    pub line: usize,
+    pub x: usize,
+    pub text: String,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file,
+            self.line + 1,
+            self.x + 1,
+            self.text
+        )
+    }
+}
+
+/// Picks the `file:line:col: message` lines out of a command's output, like
+/// the ones compilers and grep print. The column is optional, and lines
+/// without a location are skipped.
+pub fn parse(output: &str) -> Vec<Entry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let caps = LOCATION.captures(line)?;
+            let line_number: usize = caps[2].parse().ok()?;
+            let col = caps.get(3).and_then(|c| c.as_str().parse().ok());
+            Some(Entry {
+                file: caps[1].to_string(),
+                line: line_number.checked_sub(1)?,
+                x: col.map_or(0, |c: usize| c.saturating_sub(1)),
+                text: caps[4].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The locations `:make`, grep and `:cdiagnostics` found, stepped through
+/// with `]q` and `[q`.
+#[derive(Debug, Default)]
+pub struct Quickfix {
+    entries: Vec<Entry>,
+    current: Option<usize>,
+}
+
+impl Quickfix {
+    pub fn set(&mut self, entries: Vec<Entry>) {
+        self.entries = entries;
+        self.current = None;
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Makes entry `index` the current one, returning it.
+    pub fn select(&mut self, index: usize) -> Option<&Entry> {
+        let entry = self.entries.get(index)?;
+        self.current = Some(index);
+        Some(entry)
+    }
+
+    /// Moves to the next entry, or the first one before any was visited. It
+    /// stays on the last one.
+    pub fn next(&mut self) -> Option<&Entry> {
+        let index = self
+            .current
+            .map_or(0, |i| (i + 1).min(self.entries.len().saturating_sub(1)));
+        self.select(index)
+    }
+
+    pub fn prev(&mut self) -> Option<&Entry> {
+        let index = self.current.map_or(0, |i| i.saturating_sub(1));
+        self.select(index)
+    }
+
+    /// The current entry's position, counted from 1, for `(2 of 5)`.
+    pub fn position(&self) -> usize {
+        self.current.map_or(0, |i| i + 1)
+    }
+
+    /// Renames the files of the entries, after `:cd`.
+    pub fn rebase(&mut self, rename: impl Fn(&str) -> String) {
+        for entry in &mut self.entries {
+            entry.file = rename(&entry.file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let output = "   Compiling rustik\n\
+            src/main.rs:3:5: error: expected `;`\n\
+            warning: unused import\n\
+            Makefile:12: recipe failed\n";
+        let entries = parse(output);
+        assert_eq!(
+            entries,
+            [
+                Entry {
+                    file: "src/main.rs".to_string(),
+                    line: 2,
+                    x: 4,
+                    text: "error: expected `;`".to_string()
+                },
+                Entry {
+                    file: "Makefile".to_string(),
+                    line: 11,
+                    x: 0,
+                    text: "recipe failed".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            entries[0].to_string(),
+            "src/main.rs:3:5: error: expected `;`"
+        );
+    }
+
+    #[test]
+    fn test_next_prev() {
+        let mut quickfix = Quickfix::default();
+        quickfix.set(parse("a:1: x\nb:2: y\n"));
+        assert_eq!(quickfix.prev().unwrap().file, "a");
+        assert_eq!(quickfix.next().unwrap().file, "b");
+        assert_eq!(quickfix.next().unwrap().file, "b");
+        assert_eq!(quickfix.position(), 2);
+        assert_eq!(quickfix.prev().unwrap().file, "a");
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs,
+    path::{self, Path, PathBuf},
+};
+
+use crate::{config, session::BufferState};
+
+/// How many files are remembered.
+const MAX_RECENT: usize = 100;
+
+/// Where the recent files are kept between runs.
+pub fn path() -> Option<PathBuf> {
+    Some(config::state_dir()?.join("recent_files.json"))
+}
+
+/// The files opened lately, the last one left first, and where the cursor
+/// was in each. Files are kept as whole paths, as the next run may well be
+/// in another directory.
+#[derive(Debug, Default)]
+pub struct Recent {
+    entries: Vec<BufferState>,
+}
+
+impl Recent {
+    /// Loads the files saved at `path`, starting empty without them.
+    pub fn load(path: &Path) -> Self {
+        let entries = fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { entries }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&self.entries)?)?;
+        Ok(())
+    }
+
+    /// Remembers where the cursor was left in a file, moving it to the top.
+    pub fn add(&mut self, state: BufferState) {
+        let Some(file) = absolute(&state.file) else {
+            return;
+        };
+        self.entries.retain(|entry| entry.file != file);
+        self.entries.insert(0, BufferState { file, ..state });
+        self.entries.truncate(MAX_RECENT);
+    }
+
+    /// Where the cursor was left in `file`, when it was opened before.
+    pub fn get(&self, file: &str) -> Option<&BufferState> {
+        let file = absolute(file)?;
+        self.entries.iter().find(|entry| entry.file == file)
+    }
+
+    /// The files, the last one left first.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.file.as_str())
+    }
+}
+
+/// The whole path of `file`, with links resolved when it exists.
+fn absolute(file: &str) -> Option<String> {
+    let path = fs::canonicalize(file)
+        .or_else(|_| path::absolute(file))
+        .ok()?;
+    Some(path.display().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(file: &str, line: usize) -> BufferState {
+        BufferState {
+            file: file.to_string(),
+            x: 2,
+            line,
+            vtop: 0,
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut recent = Recent::default();
+        recent.add(state("/tmp/a.rs", 4));
+        recent.add(state("/tmp/b.rs", 8));
+        recent.add(state("/tmp/a.rs", 12));
+        assert_eq!(
+            recent.files().collect::<Vec<_>>(),
+            ["/tmp/a.rs", "/tmp/b.rs"]
+        );
+        assert_eq!(recent.get("/tmp/a.rs").map(|s| s.line), Some(12));
+        assert_eq!(recent.get("/tmp/c.rs"), None);
+
+        for line in 0..MAX_RECENT {
+            recent.add(state(&format!("/tmp/{line}.rs"), line));
+        }
+        assert_eq!(recent.files().count(), MAX_RECENT);
+        assert_eq!(recent.get("/tmp/a.rs"), None);
+    }
+}
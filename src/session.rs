@@ -0,0 +1,96 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// Where `:mksession` writes without a path.
+pub const DEFAULT_FILE: &str = "session.json";
+
+/// The state of the editor that's restored with `--session`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub cwd: PathBuf,
+    /// The open buffers with a file, in the order they were opened.
+    pub buffers: Vec<BufferState>,
+    /// The index of the buffer being edited, past the end when it has no
+    /// file.
+    pub current: usize,
+    /// The tab pages, by the file each shows and where its view was left.
+    #[serde(default)]
+    pub tabs: Vec<BufferState>,
+    /// The index of the current tab page.
+    #[serde(default)]
+    pub current_tab: usize,
+}
+
+/// A file and where it was being viewed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferState {
+    pub file: String,
+    pub x: usize,
+    pub line: usize,
+    /// The first line in the viewport.
+    pub vtop: usize,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> anyhow::Result<Session> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("can't read session {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("invalid session {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn current(&self) -> Option<&BufferState> {
+        self.buffers.get(self.current)
+    }
+}
+
+/// Where the automatic session of `cwd` is kept.
+pub fn auto_path(cwd: &Path) -> Option<PathBuf> {
+    config::state_file("sessions", cwd)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let session = Session {
+            cwd: PathBuf::from("/home/me/project"),
+            buffers: vec![BufferState {
+                file: "src/main.rs".to_string(),
+                x: 4,
+                line: 12,
+                vtop: 3,
+            }],
+            current: 0,
+            tabs: vec![],
+            current_tab: 0,
+        };
+        let path = std::env::temp_dir().join("rustik-test-session.json");
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.current().unwrap().line, 12);
+
+        // sessions saved before tab pages were kept still load
+        let json = r#"{"cwd": "/", "buffers": [], "current": 0}"#;
+        let old: Session = serde_json::from_str(json).unwrap();
+        assert!(old.tabs.is_empty() && old.current().is_none());
+    }
+}
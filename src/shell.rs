@@ -0,0 +1,69 @@
+use std::{
+    io::Write,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+};
+
+/// What a shell command printed and how it exited.
+#[derive(Debug)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+}
+
+impl Output {
+    /// The lines of stdout, without the newline that ends the last one.
+    pub fn lines(&self) -> &str {
+        self.stdout.strip_suffix('\n').unwrap_or(&self.stdout)
+    }
+
+    /// Why the command failed, if it did.
+    pub fn error(&self) -> Option<String> {
+        match self.status.code() {
+            Some(0) => None,
+            Some(code) => Some(format!("shell returned {code}")),
+            None => Some("shell command was killed".to_string()),
+        }
+    }
+}
+
+/// Runs `command` with `sh -c`, feeding it `input` on stdin.
+pub fn run(command: &str, input: &str) -> anyhow::Result<Output> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    // written on another thread so a full stdout pipe can't block it
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    _ = writer.join();
+
+    Ok(Output {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let output = run("sort", "b\na\n").unwrap();
+        assert_eq!(output.lines(), "a\nb");
+        assert_eq!(output.error(), None);
+
+        let output = run("echo oops >&2; exit 3", "").unwrap();
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.error().unwrap(), "shell returned 3");
+    }
+}
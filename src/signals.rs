@@ -0,0 +1,60 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use signal_hook::{
+    consts::{SIGCONT, SIGTSTP, SIGWINCH},
+    flag, low_level,
+};
+
+/// A signal the editor handles between events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// `SIGTSTP`: stop until the shell brings the editor back. In raw mode
+    /// `Ctrl-z` is a key instead, so this only comes from another process.
+    Suspend,
+    /// `SIGCONT`: the editor was stopped and is running again, with the
+    /// terminal in whatever state the shell left it.
+    Resume,
+    /// `SIGWINCH`: the terminal changed size. Noted even while the editor is
+    /// busy, so no size change is missed.
+    Resize,
+}
+
+/// The signals that came since they were last checked.
+pub struct Signals {
+    flags: Vec<(Signal, Arc<AtomicBool>)>,
+}
+
+impl Signals {
+    pub fn register() -> anyhow::Result<Self> {
+        let mut flags = vec![];
+        for (signal, number) in [
+            (Signal::Suspend, SIGTSTP),
+            (Signal::Resume, SIGCONT),
+            (Signal::Resize, SIGWINCH),
+        ] {
+            let raised = Arc::new(AtomicBool::new(false));
+            flag::register(number, Arc::clone(&raised))?;
+            flags.push((signal, raised));
+        }
+        Ok(Self { flags })
+    }
+
+    /// Takes the signals raised since the last call, in the order above.
+    pub fn pending(&self) -> Vec<Signal> {
+        self.flags
+            .iter()
+            .filter(|(_, raised)| raised.swap(false, Ordering::Relaxed))
+            .map(|(signal, _)| *signal)
+            .collect()
+    }
+}
+
+/// Stops the process as the default `SIGTSTP` action would, returning once
+/// the shell continues it.
+pub fn suspend() -> anyhow::Result<()> {
+    low_level::emulate_default_handler(SIGTSTP)?;
+    Ok(())
+}
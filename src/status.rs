@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+/// A file with changes git reports, `code` being the letter `git status`
+/// gives them: `M`odified, `A`dded, `D`eleted, `R`enamed, `?` untracked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStatus {
+    pub path: String,
+    pub code: char,
+    /// Whether the changes are in the index rather than the work tree.
+    pub staged: bool,
+}
+
+/// The `:status` panel: the changed files of the repository, the ones left
+/// to stage before the staged ones.
+#[derive(Debug)]
+pub struct GitStatus {
+    /// The top directory of the repository, which the paths start from.
+    pub root: PathBuf,
+    pub branch: Option<String>,
+    pub files: Vec<FileStatus>,
+    /// The file the cursor is on.
+    pub selected: usize,
+}
+
+impl GitStatus {
+    pub fn new(root: PathBuf, branch: Option<String>, porcelain: &str) -> Self {
+        Self {
+            root,
+            branch,
+            files: parse(porcelain),
+            selected: 0,
+        }
+    }
+
+    /// Takes the files from a new `git status`, keeping the cursor where it
+    /// was as far as the list allows.
+    pub fn update(&mut self, porcelain: &str) {
+        self.files = parse(porcelain);
+        self.selected = self.selected.min(self.files.len().saturating_sub(1));
+    }
+
+    pub fn current(&self) -> Option<&FileStatus> {
+        self.files.get(self.selected)
+    }
+
+    /// The rows of the panel, each with the index of the file it shows.
+    pub fn lines(&self) -> Vec<(String, Option<usize>)> {
+        let head = self.branch.as_deref().unwrap_or("(no branch)");
+        let mut lines = vec![(format!("Head: {head}"), None)];
+        for (staged, title) in [(false, "Unstaged changes"), (true, "Staged changes")] {
+            let files: Vec<usize> = (0..self.files.len())
+                .filter(|i| self.files[*i].staged == staged)
+                .collect();
+            if files.is_empty() {
+                continue;
+            }
+            lines.push((String::new(), None));
+            lines.push((format!("{title} ({})", files.len()), None));
+            for i in files {
+                let file = &self.files[i];
+                lines.push((format!("  {} {}", file.code, file.path), Some(i)));
+            }
+        }
+        if self.files.is_empty() {
+            lines.push((String::new(), None));
+            lines.push(("Nothing to commit, working tree clean".to_string(), None));
+        }
+        lines
+    }
+}
+
+/// Parses `git status --porcelain -z` output. A file changed both in the
+/// index and the work tree is listed twice.
+fn parse(porcelain: &str) -> Vec<FileStatus> {
+    let mut files = vec![];
+    let mut entries = porcelain.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let mut codes = entry.chars();
+        let (index, tree) = (codes.next().unwrap_or(' '), codes.next().unwrap_or(' '));
+        let path = entry.get(3..).unwrap_or_default().to_string();
+        // renames and copies are followed by the path they came from
+        if matches!(index, 'R' | 'C') {
+            entries.next();
+        }
+        if !matches!(index, ' ' | '?') {
+            files.push(FileStatus {
+                path: path.clone(),
+                code: index,
+                staged: true,
+            });
+        }
+        if tree != ' ' {
+            files.push(FileStatus {
+                path,
+                code: tree,
+                staged: false,
+            });
+        }
+    }
+    files.sort_by_key(|file| file.staged);
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let porcelain = "MM src/a.rs\0A  b.rs\0R  new.rs\0old.rs\0?? notes.txt\0";
+        let status = GitStatus::new(PathBuf::from("."), Some("main".to_string()), porcelain);
+        let file = |path: &str, code, staged| FileStatus {
+            path: path.to_string(),
+            code,
+            staged,
+        };
+        assert_eq!(
+            status.files,
+            [
+                file("src/a.rs", 'M', false),
+                file("notes.txt", '?', false),
+                file("src/a.rs", 'M', true),
+                file("b.rs", 'A', true),
+                file("new.rs", 'R', true),
+            ]
+        );
+        let lines = status.lines();
+        assert_eq!(lines[0].0, "Head: main");
+        assert_eq!(lines[2].0, "Unstaged changes (2)");
+        assert_eq!(lines[4], ("  ? notes.txt".to_string(), Some(1)));
+        assert_eq!(lines[6].0, "Staged changes (3)");
+        assert_eq!(lines.len(), 10);
+    }
+}
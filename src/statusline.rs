@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Segment {
+    Mode,
+    File,
+    Modified,
+    Filetype,
+    Encoding,
+    Fileformat,
+    Position,
+    Percentage,
+    Branch,
+    /// The texts plugins set.
+    Plugins,
+}
+
+/// Which segments the statusline shows, in order. The first segment on the
+/// left and the last on the right are drawn highlighted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatuslineConfig {
+    #[serde(default)]
+    pub left: Vec<Segment>,
+    #[serde(default)]
+    pub center: Vec<Segment>,
+    #[serde(default)]
+    pub right: Vec<Segment>,
+}
+
+impl Default for StatuslineConfig {
+    fn default() -> Self {
+        Self {
+            left: vec![Segment::Mode, Segment::File],
+            center: vec![],
+            right: vec![Segment::Position],
+        }
+    }
+}
+
+/// What the segments are filled in from.
+pub struct Context<'a> {
+    pub mode: &'a str,
+    pub file: Option<&'a str>,
+    pub modified: bool,
+    pub readonly: bool,
+    pub filetype: &'a str,
+    pub encoding: &'a str,
+    pub fileformat: &'a str,
+    /// One based cursor column and line.
+    pub position: (usize, usize),
+    pub lines: usize,
+    pub branch: Option<&'a str>,
+    pub plugins: Option<&'a str>,
+}
+
+impl Segment {
+    /// Returns the segment's text, or `None` when there's nothing to show.
+    fn text(&self, cx: &Context) -> Option<String> {
+        let text = match self {
+            Segment::Mode => cx.mode.to_string(),
+            Segment::File => cx.file.unwrap_or("No Name").to_string(),
+            // read-only buffers are flagged along with modified ones
+            Segment::Modified => {
+                let flags = match (cx.readonly, cx.modified) {
+                    (true, true) => "[RO][+]",
+                    (true, false) => "[RO]",
+                    (false, true) => "[+]",
+                    (false, false) => return None,
+                };
+                flags.to_string()
+            }
+            Segment::Filetype => cx.filetype.to_string(),
+            Segment::Encoding => cx.encoding.to_string(),
+            Segment::Fileformat => cx.fileformat.to_string(),
+            Segment::Position => format!("{}:{}", cx.position.0, cx.position.1),
+            Segment::Percentage => {
+                format!("{}%", cx.position.1 * 100 / cx.lines.max(1))
+            }
+            Segment::Branch => cx.branch?.to_string(),
+            Segment::Plugins => cx.plugins?.to_string(),
+        };
+        Some(text)
+    }
+}
+
+/// The statusline's text: the highlighted edges and the part between them,
+/// padded to fill the rest of the width.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub left: String,
+    pub middle: String,
+    pub right: String,
+}
+
+/// Lays the segments out for a statusline `width` columns wide, leaving a
+/// column for the separator after a left edge and before a right one.
+pub fn layout(config: &StatuslineConfig, cx: &Context, width: usize) -> Layout {
+    let texts = |segments: &[Segment]| -> Vec<String> {
+        segments.iter().filter_map(|s| s.text(cx)).collect()
+    };
+    let mut left = texts(&config.left);
+    let center = texts(&config.center);
+    let mut right = texts(&config.right);
+
+    let edge = |text: Option<String>| text.map_or(String::new(), |t| format!(" {t} "));
+    let left_edge = edge((!left.is_empty()).then(|| left.remove(0)));
+    let right_edge = edge(right.pop());
+
+    let separators = usize::from(!left_edge.is_empty()) + usize::from(!right_edge.is_empty());
+    let middle_width =
+        width.saturating_sub(left_edge.chars().count() + right_edge.chars().count() + separators);
+
+    let left_text = left.iter().map(|t| format!(" {t}")).collect::<String>();
+    let right_text = right.iter().map(|t| format!("{t} ")).collect::<String>();
+    let center_text = center.join(" ");
+
+    let used = left_text.chars().count() + right_text.chars().count();
+    let free = middle_width.saturating_sub(used);
+    let center_width = center_text.chars().count().min(free);
+    // centered in the whole statusline as long as it fits between the sides
+    let left_len = left_text.chars().count();
+    let center_start = (width.saturating_sub(center_width) / 2)
+        .saturating_sub(left_edge.chars().count() + usize::from(!left_edge.is_empty()))
+        .min(middle_width.saturating_sub(right_text.chars().count() + center_width))
+        .max(left_len + usize::from(left_len > 0));
+
+    let mut middle: String = left_text.chars().take(middle_width).collect();
+    let pad = |middle: &mut String, to: usize| {
+        let len = middle.chars().count();
+        middle.extend(std::iter::repeat_n(' ', to.saturating_sub(len)));
+    };
+    pad(&mut middle, center_start);
+    middle.extend(center_text.chars().take(center_width));
+    pad(
+        &mut middle,
+        middle_width.saturating_sub(right_text.chars().count()),
+    );
+    middle.push_str(&right_text);
+    let middle = middle.chars().take(middle_width).collect();
+
+    Layout {
+        left: left_edge,
+        middle,
+        right: right_edge,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context() -> Context<'static> {
+        Context {
+            mode: "NORMAL",
+            file: Some("src/main.rs"),
+            modified: true,
+            readonly: false,
+            filetype: "rust",
+            encoding: "utf-8",
+            fileformat: "unix",
+            position: (3, 50),
+            lines: 200,
+            branch: None,
+            plugins: None,
+        }
+    }
+
+    #[test]
+    fn test_default_layout() {
+        let layout = layout(&StatuslineConfig::default(), &context(), 40);
+        assert_eq!(layout.left, " NORMAL ");
+        assert_eq!(layout.middle, format!("{:<24}", " src/main.rs"));
+        assert_eq!(layout.right, " 3:50 ");
+    }
+
+    #[test]
+    fn test_layout_segments() {
+        let config = StatuslineConfig {
+            left: vec![Segment::Mode, Segment::File, Segment::Modified],
+            center: vec![Segment::Branch, Segment::Filetype],
+            right: vec![Segment::Percentage, Segment::Position],
+        };
+        let layout = layout(&config, &context(), 50);
+        assert_eq!(layout.left, " NORMAL ");
+        assert_eq!(layout.right, " 3:50 ");
+        assert_eq!(layout.middle, " src/main.rs [+] rust         25% ");
+        assert_eq!(layout.middle.chars().count(), 50 - 8 - 6 - 2);
+    }
+}
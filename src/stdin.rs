@@ -0,0 +1,23 @@
+use std::{
+    fs::File,
+    io::{self, IsTerminal, Read},
+    os::fd::AsRawFd,
+};
+
+/// Whether text is being piped in rather than typed.
+pub fn is_piped() -> bool {
+    !io::stdin().is_terminal()
+}
+
+/// Reads everything piped in, for `rustik -` and `cmd | rustik`, then puts
+/// the terminal in place of stdin so keys can be read from it again.
+pub fn read() -> anyhow::Result<String> {
+    let mut bytes = vec![];
+    io::stdin().lock().read_to_end(&mut bytes)?;
+
+    let tty = File::options().read(true).write(true).open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,184 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^<>]*?(/?)>").unwrap());
+
+/// A byte range of the buffer text, with lines joined by `\n`.
+type Span = (usize, usize);
+
+/// The delimiters found around the cursor, as byte ranges of the buffer
+/// text.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Surrounding {
+    pub open: Span,
+    pub close: Span,
+}
+
+/// The text `ys` and `cs` add for `c`. Opening brackets add a space inside,
+/// closing ones don't, and `b`, `B`, `r` and `a` stand for `()`, `{}`, `[]`
+/// and `<>`, like vim-surround.
+pub fn delimiters(c: char) -> Option<(String, String)> {
+    let (open, close, padded) = match c {
+        '(' => ('(', ')', true),
+        ')' | 'b' => ('(', ')', false),
+        '{' => ('{', '}', true),
+        '}' | 'B' => ('{', '}', false),
+        '[' => ('[', ']', true),
+        ']' | 'r' => ('[', ']', false),
+        '<' => ('<', '>', true),
+        '>' | 'a' => ('<', '>', false),
+        '"' | '\'' | '`' => (c, c, false),
+        _ => return None,
+    };
+    Some(match padded {
+        true => (format!("{open} "), format!(" {close}")),
+        false => (open.to_string(), close.to_string()),
+    })
+}
+
+/// Finds the delimiters named by `c` around `cursor`, a byte offset into
+/// `text`: the innermost bracket pair, the quotes on the cursor's line or,
+/// for `t`, the innermost tag.
+pub fn find(text: &str, cursor: usize, c: char) -> Option<Surrounding> {
+    let (open, close) = match c {
+        '(' | ')' | 'b' => ('(', ')'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '[' | ']' | 'r' => ('[', ']'),
+        '<' | '>' | 'a' => ('<', '>'),
+        '"' | '\'' | '`' => return find_quotes(text, cursor, c),
+        't' => return find_tag(text, cursor),
+        _ => return None,
+    };
+
+    let mut stack = vec![];
+    let mut found: Option<Surrounding> = None;
+    for (i, ch) in text.char_indices() {
+        if ch == open {
+            stack.push(i);
+        } else if ch == close {
+            let Some(start) = stack.pop() else {
+                continue;
+            };
+            let inner = found.as_ref().is_none_or(|f| start > f.open.0);
+            if start <= cursor && cursor <= i && inner {
+                found = Some(Surrounding {
+                    open: (start, start + open.len_utf8()),
+                    close: (i, i + close.len_utf8()),
+                });
+            }
+        }
+    }
+    found
+}
+
+/// Quotes pair up from the start of the line. Outside of a pair, the next
+/// one on the line is used.
+fn find_quotes(text: &str, cursor: usize, quote: char) -> Option<Surrounding> {
+    let line_start = text[..cursor].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[cursor..].find('\n').map_or(text.len(), |i| cursor + i);
+    let line = &text[line_start..line_end];
+    let quotes: Vec<usize> = line
+        .char_indices()
+        .filter(|&(i, c)| c == quote && !line[..i].ends_with('\\'))
+        .map(|(i, _)| line_start + i)
+        .collect();
+    let before = quotes.iter().filter(|&&q| q < cursor).count();
+    let first = match quotes.get(before) == Some(&cursor) {
+        // on a quote: the pair it belongs to
+        true => before - before % 2,
+        false if before % 2 == 1 => before - 1,
+        false => before,
+    };
+    let (open, close) = (*quotes.get(first)?, *quotes.get(first + 1)?);
+    let len = quote.len_utf8();
+    Some(Surrounding {
+        open: (open, open + len),
+        close: (close, close + len),
+    })
+}
+
+fn find_tag(text: &str, cursor: usize) -> Option<Surrounding> {
+    let mut stack: Vec<(&str, Span)> = vec![];
+    let mut found: Option<Surrounding> = None;
+    for caps in TAG.captures_iter(text) {
+        let tag = caps.get(0)?;
+        let name = caps.get(2)?.as_str();
+        let span = (tag.start(), tag.end());
+        if !caps[3].is_empty() {
+            continue;
+        }
+        if caps[1].is_empty() {
+            stack.push((name, span));
+            continue;
+        }
+        // a closing tag closes the last one opened with its name
+        let Some(pos) = stack.iter().rposition(|(open, _)| *open == name) else {
+            continue;
+        };
+        let (_, open) = stack[pos];
+        stack.truncate(pos);
+        let inner = found.as_ref().is_none_or(|f| open.0 > f.open.0);
+        if open.0 <= cursor && cursor < span.1 && inner {
+            found = Some(Surrounding { open, close: span });
+        }
+    }
+    found
+}
+
+/// Widens the delimiters to take the whitespace inside them along, for
+/// `ds(` and `cs(` which remove the padding `ys(` adds.
+pub fn with_padding(text: &str, surrounding: Surrounding) -> Surrounding {
+    let inner = &text[surrounding.open.1..surrounding.close.0];
+    let leading = inner.len() - inner.trim_start_matches([' ', '\t']).len();
+    let trailing = inner.len() - inner.trim_end_matches([' ', '\t']).len();
+    // all whitespace: it goes with the opener
+    let trailing = trailing.min(inner.len() - leading);
+    Surrounding {
+        open: (surrounding.open.0, surrounding.open.1 + leading),
+        close: (surrounding.close.0 - trailing, surrounding.close.1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spans(text: &str, cursor: usize, c: char) -> Option<(&str, &str)> {
+        let found = find(text, cursor, c)?;
+        Some((
+            &text[found.open.0..found.open.1],
+            &text[found.close.0..found.close.1],
+        ))
+    }
+
+    #[test]
+    fn test_find() {
+        let text = "f(a, (b), c)\nx = \"one\" + \"two\";";
+        assert_eq!(find(text, 3, ')').unwrap().open, (1, 2));
+        assert_eq!(find(text, 6, 'b').unwrap().open, (5, 6));
+        assert_eq!(find(text, 11, '(').unwrap().close, (11, 12));
+        assert_eq!(find(text, 0, '('), None);
+        assert_eq!(find(text, 19, '"').unwrap().open, (17, 18));
+        assert_eq!(find(text, 23, '"').unwrap().open, (25, 26));
+        assert_eq!(find(text, 21, '"').unwrap().open, (17, 18));
+
+        let html = "<div class=\"a\">\n  <p>hi</p><br/>\n</div>";
+        assert_eq!(spans(html, 22, 't'), Some(("<p>", "</p>")));
+        assert_eq!(spans(html, 30, 't'), Some(("<div class=\"a\">", "</div>")));
+    }
+
+    #[test]
+    fn test_with_padding() {
+        let text = "( a b )";
+        let found = with_padding(text, find(text, 2, '(').unwrap());
+        assert_eq!(
+            found,
+            Surrounding {
+                open: (0, 2),
+                close: (5, 7)
+            }
+        );
+        assert_eq!(delimiters('('), Some(("( ".to_string(), " )".to_string())));
+        assert_eq!(delimiters('B'), Some(("{".to_string(), "}".to_string())));
+    }
+}
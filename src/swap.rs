@@ -0,0 +1,50 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where the swap file of `file` goes: `.name.swp` next to it, like vim.
+pub fn path(file: &str) -> PathBuf {
+    let path = Path::new(file);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.swp"))
+}
+
+/// Saves the unsaved text of `file`. It's written to a temporary file first
+/// so a crash while writing leaves the previous swap file intact.
+pub fn write(file: &str, text: &str) -> io::Result<()> {
+    let path = path(file);
+    let tmp = path.with_extension("swp.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(tmp, path)
+}
+
+pub fn read(file: &str) -> Option<String> {
+    fs::read_to_string(path(file)).ok()
+}
+
+pub fn remove(file: &str) {
+    _ = fs::remove_file(path(file));
+}
+
+/// Copies `file` to `file~` before it's overwritten.
+pub fn backup(file: &str) -> io::Result<()> {
+    if Path::new(file).exists() {
+        fs::copy(file, format!("{file}~"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path("src/main.rs"), PathBuf::from("src/.main.rs.swp"));
+        assert_eq!(path("notes"), PathBuf::from(".notes.swp"));
+    }
+}
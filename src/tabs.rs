@@ -0,0 +1,120 @@
+/// A tab page: the buffer it shows and where its view was left, kept while
+/// another tab is current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tab {
+    pub buffer: usize,
+    pub x: usize,
+    pub line: usize,
+    pub vtop: usize,
+}
+
+impl Tab {
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            buffer,
+            x: 0,
+            line: 0,
+            vtop: 0,
+        }
+    }
+}
+
+/// The open tab pages. The current one's entry is only brought up to date
+/// when another tab is opened or switched to.
+#[derive(Debug)]
+pub struct Tabs {
+    tabs: Vec<Tab>,
+    current: usize,
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            tabs: vec![Tab::new(1)],
+            current: 0,
+        }
+    }
+}
+
+impl Tabs {
+    /// The tab pages of a session, the current one at index `current`.
+    pub fn restore(tabs: Vec<Tab>, current: usize) -> Self {
+        if tabs.is_empty() {
+            return Self::default();
+        }
+        let current = current.min(tabs.len() - 1);
+        Self { tabs, current }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tabs.len()
+    }
+
+    /// The current tab's index.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tab> {
+        self.tabs.iter()
+    }
+
+    /// Saves the view `left` in the current tab and adds a tab after it,
+    /// which becomes current.
+    pub fn open(&mut self, left: Tab, tab: Tab) {
+        self.tabs[self.current] = left;
+        self.current += 1;
+        self.tabs.insert(self.current, tab);
+    }
+
+    /// Saves the view `left` in the current tab and makes the tab `offset`
+    /// places away current, wrapping around. Returns the tab to show.
+    pub fn go(&mut self, left: Tab, offset: isize) -> Tab {
+        self.tabs[self.current] = left;
+        let len = self.tabs.len() as isize;
+        self.current = (self.current as isize + offset).rem_euclid(len) as usize;
+        self.tabs[self.current]
+    }
+
+    /// Closes the current tab, returning the one to show instead, or `None`
+    /// when it's the last one.
+    pub fn close(&mut self) -> Option<Tab> {
+        if self.tabs.len() == 1 {
+            return None;
+        }
+        self.tabs.remove(self.current);
+        self.current = self.current.min(self.tabs.len() - 1);
+        Some(self.tabs[self.current])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tabs() {
+        let mut tabs = Tabs::default();
+        assert_eq!(tabs.close(), None);
+        tabs.open(
+            Tab {
+                x: 3,
+                ..Tab::new(1)
+            },
+            Tab::new(2),
+        );
+        tabs.open(Tab::new(2), Tab::new(3));
+        assert_eq!((tabs.len(), tabs.current()), (3, 2));
+
+        assert_eq!(
+            tabs.go(Tab::new(3), 1),
+            Tab {
+                x: 3,
+                ..Tab::new(1)
+            }
+        );
+        assert_eq!(tabs.go(Tab::new(1), -1).buffer, 3);
+        assert_eq!(tabs.close().map(|tab| tab.buffer), Some(2));
+        assert_eq!(tabs.current(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::quickfix::Entry;
+
+/// The `tags` file ctags wrote for `dir` or the closest directory above it.
+pub fn find_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join("tags"))
+        .find(|path| path.is_file())
+}
+
+/// The definitions of `name` in the `tags` file at `path`. Files are
+/// relative to the directory of the tags file, and the locations given as
+/// search patterns are looked up in them.
+pub fn lookup(path: &Path, name: &str) -> anyhow::Result<Vec<Entry>> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let entries = fs::read_to_string(path)?
+        .lines()
+        .filter_map(parse_line)
+        .filter(|(tag, _, _)| *tag == name)
+        .filter_map(|(_, file, address)| {
+            let path = dir.join(file);
+            let contents = fs::read_to_string(&path).ok()?;
+            let lines: Vec<&str> = contents.lines().collect();
+            let line = resolve(&lines, address)?;
+            let text = lines.get(line).copied().unwrap_or_default();
+            Some(Entry {
+                file: path.to_string_lossy().into_owned(),
+                line,
+                x: text.find(name).unwrap_or_default(),
+                text: text.trim().to_string(),
+            })
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Splits a `name<Tab>file<Tab>address;"<Tab>fields` line of a tags file.
+/// The `!_TAG_` lines describing the file are skipped.
+fn parse_line(line: &str) -> Option<(&str, &str, &str)> {
+    if line.starts_with("!_TAG_") {
+        return None;
+    }
+    let mut parts = line.splitn(3, '\t');
+    let name = parts.next()?;
+    let file = parts.next()?;
+    let rest = parts.next()?;
+    let address = rest.rsplit_once(";\"").map_or(rest, |(address, _)| address);
+    Some((name, file, address))
+}
+
+/// The zero based line an address points at: a line number or a
+/// `/^pattern$/` search, which matches the line literally.
+fn resolve(lines: &[&str], address: &str) -> Option<usize> {
+    if let Ok(number) = address.parse::<usize>() {
+        return number.checked_sub(1);
+    }
+    let pattern = address
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix('/'))
+        .or_else(|| address.strip_prefix('?')?.strip_suffix('?'))?;
+    let start = pattern.starts_with('^');
+    let end = pattern.ends_with('$') && !pattern.ends_with("\\$");
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = match end {
+        true => &pattern[..pattern.len() - 1],
+        false => pattern,
+    };
+    let pattern = pattern.replace("\\/", "/").replace("\\\\", "\\");
+    lines.iter().position(|line| match (start, end) {
+        (true, true) => *line == pattern,
+        (true, false) => line.starts_with(&pattern),
+        (false, true) => line.ends_with(&pattern),
+        (false, false) => line.contains(&pattern),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("main\tsrc/main.rs\t/^fn main() {$/;\"\tf"),
+            Some(("main", "src/main.rs", "/^fn main() {$/"))
+        );
+        assert_eq!(
+            parse_line("VERSION\tlib.c\t12"),
+            Some(("VERSION", "lib.c", "12"))
+        );
+        assert_eq!(parse_line("!_TAG_FILE_SORTED\t1\t/0=unsorted/"), None);
+
+        let lines = ["use std::fs;", "", "fn main() {", "    // a/b", "}"];
+        assert_eq!(resolve(&lines, "/^fn main() {$/"), Some(2));
+        assert_eq!(resolve(&lines, "/^    \\/\\/ a\\/b$/"), Some(3));
+        assert_eq!(resolve(&lines, "5"), Some(4));
+        assert_eq!(resolve(&lines, "/^fn other() {$/"), None);
+    }
+}
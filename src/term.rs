@@ -0,0 +1,480 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crossterm::{
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    style::Color,
+};
+
+use crate::theme::Style;
+
+/// A shell running in a pseudo terminal, with its output interpreted into a
+/// grid of styled cells.
+pub struct Terminal {
+    master: File,
+    child: Child,
+    output: Receiver<Vec<u8>>,
+    pub screen: Screen,
+}
+
+impl Terminal {
+    pub fn spawn(shell: &str, cols: usize, rows: usize) -> anyhow::Result<Self> {
+        let mut master = 0;
+        let mut slave = 0;
+        let size = window_size(cols, rows);
+        // SAFETY: openpty only writes the two descriptors it's given
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // SAFETY: openpty succeeded, so both are open descriptors we own
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        let mut command = Command::new(shell);
+        command
+            .env("TERM", "xterm")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                // make the pty the controlling terminal of a new session
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+
+        let mut reader = master.try_clone()?;
+        let (tx, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // reading fails with EIO once the shell exits
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                if tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            master,
+            child,
+            output,
+            screen: Screen::new(cols, rows),
+        })
+    }
+
+    /// Feeds the output the shell wrote since the last call to the screen,
+    /// returning whether there was any.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for bytes in self.output.try_iter() {
+            self.screen.feed(&bytes);
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn write(&mut self, input: &str) -> anyhow::Result<()> {
+        self.master.write_all(input.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        if (cols, rows) == (self.screen.cols, self.screen.rows) {
+            return;
+        }
+        self.screen.resize(cols, rows);
+        let size = window_size(cols, rows);
+        // SAFETY: TIOCSWINSZ reads a winsize from the pointer
+        unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+fn window_size(cols: usize, rows: usize) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows as u16,
+        ws_col: cols as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Encodes a key press the way a terminal sends it to the program running
+/// in it.
+pub fn key_input(event: &KeyEvent) -> Option<String> {
+    let input = match event.code {
+        KeyCode::Char(c) if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            let c = c.to_ascii_lowercase();
+            if !c.is_ascii_lowercase() {
+                return None;
+            }
+            ((c as u8 & 0x1f) as char).to_string()
+        }
+        KeyCode::Char(c) if event.modifiers.contains(KeyModifiers::ALT) => format!("\x1b{c}"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Enter => "\r".to_string(),
+        KeyCode::Backspace => "\x7f".to_string(),
+        KeyCode::Tab => "\t".to_string(),
+        KeyCode::BackTab => "\x1b[Z".to_string(),
+        KeyCode::Esc => "\x1b".to_string(),
+        KeyCode::Up => "\x1b[A".to_string(),
+        KeyCode::Down => "\x1b[B".to_string(),
+        KeyCode::Right => "\x1b[C".to_string(),
+        KeyCode::Left => "\x1b[D".to_string(),
+        KeyCode::Home => "\x1b[H".to_string(),
+        KeyCode::End => "\x1b[F".to_string(),
+        KeyCode::Delete => "\x1b[3~".to_string(),
+        KeyCode::PageUp => "\x1b[5~".to_string(),
+        KeyCode::PageDown => "\x1b[6~".to_string(),
+        _ => return None,
+    };
+    Some(input)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// After `ESC (` and friends, which take one more byte.
+    Charset,
+    Csi,
+    Osc,
+    OscEscape,
+}
+
+/// A minimal VT100/xterm screen: enough of the control sequences for shell
+/// prompts, colored output and line editing. Unsupported sequences are
+/// ignored. Cell styles leave colors unset where the default should be used.
+#[derive(Debug)]
+pub struct Screen {
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor: (usize, usize),
+    cells: Vec<Vec<(char, Style)>>,
+    style: Style,
+    state: State,
+    params: String,
+    utf8: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            cursor: (0, 0),
+            cells: vec![vec![(' ', Style::default()); cols]; rows],
+            style: Style::default(),
+            state: State::Ground,
+            params: String::new(),
+            utf8: vec![],
+        }
+    }
+
+    pub fn line(&self, row: usize) -> &[(char, Style)] {
+        &self.cells[row]
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        // keep the bottom of the screen, where the prompt is
+        if rows < self.rows {
+            let scroll = (self.rows - rows).min(self.cursor.1);
+            self.cells.drain(..scroll);
+            self.cursor.1 -= scroll;
+        }
+        self.cells.resize(rows, vec![(' ', Style::default()); cols]);
+        for line in self.cells.iter_mut() {
+            line.resize(cols, (' ', Style::default()));
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.cursor = (
+            self.cursor.0.min(cols.saturating_sub(1)),
+            self.cursor.1.min(rows.saturating_sub(1)),
+        );
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.byte(*b);
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        match self.state {
+            State::Ground => self.ground(b),
+            State::Escape => {
+                self.state = match b {
+                    b'[' => {
+                        self.params.clear();
+                        State::Csi
+                    }
+                    b']' => State::Osc,
+                    b'(' | b')' | b'*' | b'+' | b'#' => State::Charset,
+                    b'M' => {
+                        // reverse index
+                        if self.cursor.1 == 0 {
+                            self.cells.insert(0, self.blank_line());
+                            self.cells.pop();
+                        } else {
+                            self.cursor.1 -= 1;
+                        }
+                        State::Ground
+                    }
+                    _ => State::Ground,
+                }
+            }
+            State::Charset => self.state = State::Ground,
+            State::Csi => {
+                if (0x40..=0x7e).contains(&b) {
+                    self.state = State::Ground;
+                    let params = std::mem::take(&mut self.params);
+                    self.csi(&params, b as char);
+                } else {
+                    self.params.push(b as char);
+                }
+            }
+            State::Osc => match b {
+                0x07 => self.state = State::Ground,
+                0x1b => self.state = State::OscEscape,
+                _ => {}
+            },
+            State::OscEscape => self.state = State::Ground,
+        }
+    }
+
+    fn ground(&mut self, b: u8) {
+        match b {
+            0x1b => self.state = State::Escape,
+            b'\r' => self.cursor.0 = 0,
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            0x08 => self.cursor.0 = self.cursor.0.saturating_sub(1),
+            b'\t' => self.cursor.0 = ((self.cursor.0 / 8 + 1) * 8).min(self.cols - 1),
+            0x00..=0x1f | 0x7f => {}
+            _ => {
+                self.utf8.push(b);
+                match std::str::from_utf8(&self.utf8) {
+                    Ok(s) => {
+                        let c = s.chars().next().expect("not empty");
+                        self.utf8.clear();
+                        self.put(c);
+                    }
+                    Err(err) if err.error_len().is_some() || self.utf8.len() >= 4 => {
+                        self.utf8.clear();
+                        self.put(char::REPLACEMENT_CHARACTER);
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        if self.cursor.0 >= self.cols {
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+        let (x, y) = self.cursor;
+        self.cells[y][x] = (c, self.style.clone());
+        self.cursor.0 += 1;
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.1 + 1 < self.rows {
+            self.cursor.1 += 1;
+        } else {
+            self.cells.remove(0);
+            self.cells.push(self.blank_line());
+        }
+    }
+
+    fn blank_line(&self) -> Vec<(char, Style)> {
+        vec![(' ', Style::default()); self.cols]
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        // private sequences like `?25h` only toggle modes we don't support
+        if params.starts_with(['?', '>', '=']) {
+            return;
+        }
+        let args: Vec<usize> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let arg = |i: usize, default: usize| match args.get(i) {
+            Some(0) | None => default,
+            Some(n) => *n,
+        };
+        let (x, y) = self.cursor;
+
+        match action {
+            'A' => self.cursor.1 = y.saturating_sub(arg(0, 1)),
+            'B' => self.cursor.1 = (y + arg(0, 1)).min(self.rows - 1),
+            'C' => self.cursor.0 = (x + arg(0, 1)).min(self.cols - 1),
+            'D' => self.cursor.0 = x.saturating_sub(arg(0, 1)),
+            'G' => self.cursor.0 = (arg(0, 1) - 1).min(self.cols - 1),
+            'd' => self.cursor.1 = (arg(0, 1) - 1).min(self.rows - 1),
+            'H' | 'f' => {
+                self.cursor = (
+                    (arg(1, 1) - 1).min(self.cols - 1),
+                    (arg(0, 1) - 1).min(self.rows - 1),
+                )
+            }
+            'J' => {
+                let (from, to) = match args[0] {
+                    0 => ((x, y), (self.cols, self.rows - 1)),
+                    1 => ((0, 0), (x + 1, y)),
+                    _ => ((0, 0), (self.cols, self.rows - 1)),
+                };
+                for row in from.1..=to.1 {
+                    let start = if row == from.1 { from.0 } else { 0 };
+                    let end = if row == to.1 { to.0 } else { self.cols };
+                    self.erase(row, start, end);
+                }
+            }
+            'K' => match args[0] {
+                0 => self.erase(y, x, self.cols),
+                1 => self.erase(y, 0, x + 1),
+                _ => self.erase(y, 0, self.cols),
+            },
+            'X' => self.erase(y, x, x + arg(0, 1)),
+            'P' => {
+                let line = &mut self.cells[y];
+                let n = arg(0, 1).min(self.cols - x.min(self.cols));
+                line.drain(x.min(self.cols)..x.min(self.cols) + n);
+                line.resize(self.cols, (' ', Style::default()));
+            }
+            '@' => {
+                let line = &mut self.cells[y];
+                for _ in 0..arg(0, 1) {
+                    line.insert(x.min(self.cols), (' ', Style::default()));
+                }
+                line.truncate(self.cols);
+            }
+            'L' | 'M' => {
+                for _ in 0..arg(0, 1).min(self.rows - y) {
+                    if action == 'L' {
+                        self.cells.pop();
+                        self.cells.insert(y, self.blank_line());
+                    } else {
+                        self.cells.remove(y);
+                        self.cells.push(self.blank_line());
+                    }
+                }
+            }
+            'm' => self.sgr(&args),
+            _ => {}
+        }
+    }
+
+    fn erase(&mut self, row: usize, start: usize, end: usize) {
+        let end = end.min(self.cols);
+        for cell in self.cells[row].iter_mut().take(end).skip(start) {
+            *cell = (' ', Style::default());
+        }
+    }
+
+    /// Applies "select graphic rendition" parameters to the current style.
+    fn sgr(&mut self, args: &[usize]) {
+        let mut args = args.iter().copied();
+        while let Some(arg) = args.next() {
+            match arg {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                3 => self.style.italic = true,
+                22 => self.style.bold = false,
+                23 => self.style.italic = false,
+                30..=37 => self.style.fg = Some(Color::AnsiValue(arg as u8 - 30)),
+                90..=97 => self.style.fg = Some(Color::AnsiValue(arg as u8 - 90 + 8)),
+                40..=47 => self.style.bg = Some(Color::AnsiValue(arg as u8 - 40)),
+                100..=107 => self.style.bg = Some(Color::AnsiValue(arg as u8 - 100 + 8)),
+                39 => self.style.fg = None,
+                49 => self.style.bg = None,
+                38 | 48 => {
+                    let color = match args.next() {
+                        Some(5) => args.next().map(|n| Color::AnsiValue(n as u8)),
+                        Some(2) => match (args.next(), args.next(), args.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::Rgb {
+                                r: r as u8,
+                                g: g as u8,
+                                b: b as u8,
+                            }),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if arg == 38 {
+                        self.style.fg = color;
+                    } else {
+                        self.style.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(screen: &Screen, row: usize) -> String {
+        let line: String = screen.line(row).iter().map(|(c, _)| c).collect();
+        line.trim_end().to_string()
+    }
+
+    #[test]
+    fn test_screen_text_and_scrolling() {
+        let mut screen = Screen::new(10, 2);
+        screen.feed(b"one\r\ntwo\r\nthr\xc3\xa9e");
+        assert_eq!(text(&screen, 0), "two");
+        assert_eq!(text(&screen, 1), "thr\u{e9}e");
+        assert_eq!(screen.cursor, (5, 1));
+
+        screen.feed(b"\x1b[2J\x1b[1;3Hx\x1b[K");
+        assert_eq!(text(&screen, 0), "  x");
+        assert_eq!(text(&screen, 1), "");
+    }
+
+    #[test]
+    fn test_screen_colors() {
+        let mut screen = Screen::new(10, 1);
+        screen.feed(b"\x1b[1;31ma\x1b[38;5;200mb\x1b]0;title\x07\x1b[0mc");
+        let line = screen.line(0);
+        assert_eq!(line[0].1.fg, Some(Color::AnsiValue(1)));
+        assert!(line[0].1.bold);
+        assert_eq!(line[1].1.fg, Some(Color::AnsiValue(200)));
+        assert_eq!(line[2], ('c', Style::default()));
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashMap, fs};
+
+use super::{
+    parse_rgb, DiagnosticStyles, GitStyles, StatuslineStyle, Style, Theme, TokenStyle,
+    POWERLINE_CHARS,
+};
+
+/// Syntax scopes and the base16 color they're drawn in, following the base16
+/// styling guidelines.
+const SYNTAX_COLORS: &[(&str, &str)] = &[
+    ("comment", "base03"),
+    ("variable.builtin", "base08"),
+    ("label", "base08"),
+    ("constant", "base09"),
+    ("constant.builtin", "base09"),
+    ("type", "base0A"),
+    ("type.builtin", "base0A"),
+    ("attribute", "base0A"),
+    ("string", "base0B"),
+    ("escape", "base0C"),
+    ("constructor", "base0C"),
+    ("function", "base0D"),
+    ("function.method", "base0D"),
+    ("property", "base0D"),
+    ("keyword", "base0E"),
+    ("function.macro", "base0E"),
+    ("operator", "base05"),
+    ("variable.parameter", "base08"),
+    ("punctuation.bracket", "base05"),
+    ("punctuation.delimiter", "base05"),
+];
+
+/// Loads a base16 scheme: a YAML file with the sixteen colors `base00` to
+/// `base0F`, flat or under a `palette` key as in the newer scheme format.
+pub fn parse_base16_theme(file: &str) -> anyhow::Result<Theme> {
+    let contents = fs::read_to_string(file)?;
+    let values = parse_yaml(&contents);
+
+    let mut palette = HashMap::new();
+    for i in 0..16 {
+        let key = format!("base{i:02X}");
+        let Some(value) = values.get(&key) else {
+            anyhow::bail!("{file}: {key} is missing");
+        };
+        let hex = format!("#{}", value.trim_start_matches('#'));
+        palette.insert(key, parse_rgb(&hex)?);
+    }
+    let color = |key: &str| palette[key];
+
+    let token_styles = SYNTAX_COLORS
+        .iter()
+        .map(|(scope, key)| TokenStyle {
+            scope: vec![scope.to_string()],
+            style: Style {
+                fg: Some(color(key)),
+                italic: *scope == "comment",
+                ..Default::default()
+            },
+        })
+        .collect();
+
+    let style = Style {
+        fg: Some(color("base05")),
+        bg: Some(color("base00")),
+        ..Default::default()
+    };
+
+    Ok(Theme {
+        name: values
+            .get("scheme")
+            .or(values.get("name"))
+            .cloned()
+            .unwrap_or_default(),
+        gutter_style: Style {
+            fg: Some(color("base03")),
+            bg: Some(color("base00")),
+            ..Default::default()
+        },
+        bracket_match_style: Style {
+            bg: Some(color("base02")),
+            ..Default::default()
+        },
+        selection_style: Style {
+            bg: Some(color("base02")),
+            ..Default::default()
+        },
+        ruler_style: Style {
+            bg: Some(color("base01")),
+            ..Default::default()
+        },
+        flash_style: Style {
+            fg: Some(color("base00")),
+            bg: Some(color("base0A")),
+            ..Default::default()
+        },
+        popup_style: Style {
+            fg: Some(color("base05")),
+            bg: Some(color("base01")),
+            ..Default::default()
+        },
+        popup_selected_style: Style {
+            fg: Some(color("base06")),
+            bg: Some(color("base02")),
+            ..Default::default()
+        },
+        diagnostic_styles: DiagnosticStyles {
+            error: color("base08"),
+            warning: color("base0A"),
+            info: color("base0D"),
+            hint: color("base03"),
+        },
+        git_styles: GitStyles {
+            added: color("base0B"),
+            modified: color("base0E"),
+            deleted: color("base08"),
+        },
+        statusline_style: StatuslineStyle {
+            outer_style: Style {
+                fg: Some(color("base00")),
+                bg: Some(color("base0D")),
+                bold: true,
+                ..Default::default()
+            },
+            outer_chars: POWERLINE_CHARS,
+            inner_style: Style {
+                fg: Some(color("base05")),
+                bg: Some(color("base02")),
+                bold: true,
+                ..Default::default()
+            },
+        },
+        style,
+        token_styles,
+    })
+}
+
+/// Reads the `key: value` pairs of a YAML file, ignoring nesting, which is
+/// all base16 schemes use. Quotes around values and comments are stripped.
+fn parse_yaml(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let value = match value.trim() {
+                v if v.starts_with('"') || v.starts_with('\'') => {
+                    let quote = &v[..1];
+                    v[1..].split(quote).next().unwrap_or_default()
+                }
+                v => v.split(" #").next().unwrap_or_default().trim(),
+            };
+            let key = key.trim();
+            (!key.starts_with('#') && !value.is_empty())
+                .then(|| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crossterm::style::Color;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_base16_theme() {
+        let theme = parse_base16_theme("./src/fixtures/base16-default-dark.yaml").unwrap();
+        assert_eq!(theme.name, "Default Dark");
+        assert_eq!(
+            theme.style.bg,
+            Some(Color::Rgb {
+                r: 0x18,
+                g: 0x18,
+                b: 0x18
+            })
+        );
+        let keyword = theme.get_style("keyword").unwrap();
+        assert_eq!(
+            keyword.fg,
+            Some(Color::Rgb {
+                r: 0xba,
+                g: 0x8b,
+                b: 0xaf
+            })
+        );
+    }
+}
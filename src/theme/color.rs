@@ -0,0 +1,156 @@
+use std::env;
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+/// How many colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorDepth {
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Guesses the depth from `COLORTERM` and `TERM`, as terminals that
+    /// support RGB colors set `COLORTERM=truecolor` and most others
+    /// advertise 256 colors in their terminfo name.
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        Self::from_env(&colorterm, &term)
+    }
+
+    fn from_env(colorterm: &str, term: &str) -> Self {
+        if matches!(colorterm, "truecolor" | "24bit") || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    /// Returns the closest color the terminal can show.
+    pub fn convert(self, color: Color) -> Color {
+        let rgb = match color {
+            Color::Rgb { r, g, b } => (r, g, b),
+            Color::AnsiValue(n) if self == ColorDepth::Ansi16 => ansi_rgb(n),
+            color => return color,
+        };
+        match self {
+            ColorDepth::TrueColor => color,
+            ColorDepth::Ansi256 => Color::AnsiValue(nearest(rgb, 16..=255)),
+            ColorDepth::Ansi16 => ANSI_COLORS[nearest(rgb, 0..=15) as usize],
+        }
+    }
+}
+
+/// The colors 0 to 15 as crossterm names them.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkMagenta,
+    Color::DarkCyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+/// The levels of each channel in the 6x6x6 color cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The RGB value xterm uses for 256-color palette entry `n`.
+fn ansi_rgb(n: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    match n {
+        0..=15 => BASIC[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            let level = |i: u8| CUBE_LEVELS[i as usize];
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Returns the palette entry in `range` closest to `rgb`.
+fn nearest((r, g, b): (u8, u8, u8), range: std::ops::RangeInclusive<u8>) -> u8 {
+    let distance = |n: &u8| {
+        let (pr, pg, pb) = ansi_rgb(*n);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    range.min_by_key(distance).expect("range is not empty")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            ColorDepth::from_env("truecolor", "xterm-256color"),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env("", "xterm-256color"),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(ColorDepth::from_env("", "linux"), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn test_convert() {
+        let mauve = Color::Rgb {
+            r: 203,
+            g: 166,
+            b: 247,
+        };
+        assert_eq!(ColorDepth::TrueColor.convert(mauve), mauve);
+        assert_eq!(ColorDepth::Ansi256.convert(mauve), Color::AnsiValue(183));
+        assert_eq!(ColorDepth::Ansi16.convert(mauve), Color::Grey);
+
+        let base = Color::Rgb {
+            r: 30,
+            g: 30,
+            b: 46,
+        };
+        assert_eq!(ColorDepth::Ansi256.convert(base), Color::AnsiValue(235));
+        assert_eq!(ColorDepth::Ansi16.convert(base), Color::Black);
+        assert_eq!(ColorDepth::Ansi16.convert(Color::Red), Color::Red);
+    }
+}
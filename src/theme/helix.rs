@@ -0,0 +1,219 @@
+use crossterm::style::Color;
+use std::{collections::HashMap, fs, path::Path};
+use toml::Value;
+
+use super::{
+    parse_rgb, DiagnosticStyles, GitStyles, StatuslineStyle, Style, Theme, TokenStyle,
+    POWERLINE_CHARS,
+};
+
+/// Loads a Helix style TOML theme: scopes set to a color or to a
+/// `{ fg, bg, modifiers }` table, where colors are `#rrggbb`, one of the
+/// terminal's named colors or a name from the `[palette]` table.
+pub fn parse_helix_theme(file: &str) -> anyhow::Result<Theme> {
+    let contents = fs::read_to_string(file)?;
+    let mut table: toml::Table = toml::from_str(&contents)?;
+
+    let name = match table.remove("name") {
+        Some(Value::String(name)) => name,
+        _ => Path::new(file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    let palette: HashMap<String, String> = match table.remove("palette") {
+        Some(Value::Table(palette)) => palette
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value.as_str()?.to_string())))
+            .collect(),
+        _ => HashMap::new(),
+    };
+
+    let mut styles = HashMap::new();
+    flatten(String::new(), Value::Table(table), &palette, &mut styles)?;
+    let style = |scope: &str| styles.get(scope).cloned();
+    let fg = |scope: &str| style(scope).and_then(|s| s.fg);
+
+    let defaults = Theme::default();
+    let base = Style {
+        fg: fg("ui.text").or(defaults.style.fg),
+        bg: style("ui.background")
+            .and_then(|s| s.bg)
+            .or(defaults.style.bg),
+        ..Default::default()
+    };
+
+    let diagnostics = DiagnosticStyles::default();
+    let git = GitStyles::default();
+    let token_styles = styles
+        .iter()
+        .filter(|(scope, _)| !is_ui_scope(scope))
+        .map(|(scope, style)| TokenStyle {
+            scope: vec![scope.clone()],
+            style: style.clone(),
+        })
+        .collect();
+
+    Ok(Theme {
+        name,
+        gutter_style: style("ui.linenr").unwrap_or_default(),
+        bracket_match_style: style("ui.cursor.match").unwrap_or(defaults.bracket_match_style),
+        selection_style: style("ui.selection").unwrap_or(defaults.selection_style),
+        ruler_style: style("ui.virtual.ruler").unwrap_or(defaults.ruler_style),
+        flash_style: style("ui.highlight").unwrap_or(defaults.flash_style),
+        popup_style: style("ui.popup")
+            .or(style("ui.menu"))
+            .unwrap_or(defaults.popup_style.clone()),
+        popup_selected_style: style("ui.menu.selected").unwrap_or(defaults.popup_selected_style),
+        diagnostic_styles: DiagnosticStyles {
+            error: fg("error").unwrap_or(diagnostics.error),
+            warning: fg("warning").unwrap_or(diagnostics.warning),
+            info: fg("info").unwrap_or(diagnostics.info),
+            hint: fg("hint").unwrap_or(diagnostics.hint),
+        },
+        git_styles: GitStyles {
+            added: fg("diff.plus").unwrap_or(git.added),
+            modified: fg("diff.delta").unwrap_or(git.modified),
+            deleted: fg("diff.minus").unwrap_or(git.deleted),
+        },
+        statusline_style: StatuslineStyle {
+            outer_style: style("ui.statusline.normal").unwrap_or(Style {
+                fg: base.bg,
+                bg: fg("function").or(base.fg),
+                bold: true,
+                ..Default::default()
+            }),
+            outer_chars: POWERLINE_CHARS,
+            inner_style: style("ui.statusline").unwrap_or(Style {
+                fg: base.fg,
+                bg: defaults.popup_style.bg,
+                ..Default::default()
+            }),
+        },
+        style: base,
+        token_styles,
+    })
+}
+
+fn is_ui_scope(scope: &str) -> bool {
+    scope.starts_with("ui.")
+        || scope.starts_with("diff.")
+        || matches!(scope, "error" | "warning" | "info" | "hint")
+}
+
+/// Collects the styles of `value`, joining the keys of nested tables with
+/// dots so that `[ui.background]` is the same as `"ui.background"`.
+fn flatten(
+    scope: String,
+    value: Value,
+    palette: &HashMap<String, String>,
+    styles: &mut HashMap<String, Style>,
+) -> anyhow::Result<()> {
+    let is_style = |table: &toml::Table| {
+        ["fg", "bg", "modifiers"]
+            .iter()
+            .any(|key| table.contains_key(*key))
+    };
+
+    match value {
+        Value::Table(table) if !is_style(&table) => {
+            for (key, value) in table {
+                let scope = match scope.is_empty() {
+                    true => key,
+                    false => format!("{scope}.{key}"),
+                };
+                flatten(scope, value, palette, styles)?;
+            }
+        }
+        value => {
+            let style =
+                parse_style(&value, palette).map_err(|err| anyhow::anyhow!("{scope}: {err}"))?;
+            styles.insert(scope, style);
+        }
+    }
+    Ok(())
+}
+
+fn parse_style(value: &Value, palette: &HashMap<String, String>) -> anyhow::Result<Style> {
+    let color = |value: Option<&Value>| -> anyhow::Result<Option<Color>> {
+        value
+            .map(|v| match v.as_str() {
+                Some(name) => parse_color(name, palette),
+                None => anyhow::bail!("expected a color, found {}", v.type_str()),
+            })
+            .transpose()
+    };
+
+    match value {
+        Value::String(_) => Ok(Style {
+            fg: color(Some(value))?,
+            ..Default::default()
+        }),
+        Value::Table(table) => {
+            let modifiers: Vec<&str> = table
+                .get("modifiers")
+                .and_then(|m| m.as_array())
+                .map(|m| m.iter().filter_map(|m| m.as_str()).collect())
+                .unwrap_or_default();
+            Ok(Style {
+                fg: color(table.get("fg"))?,
+                bg: color(table.get("bg"))?,
+                bold: modifiers.contains(&"bold"),
+                italic: modifiers.contains(&"italic"),
+            })
+        }
+        value => anyhow::bail!("expected a color or a style, found {}", value.type_str()),
+    }
+}
+
+fn parse_color(name: &str, palette: &HashMap<String, String>) -> anyhow::Result<Color> {
+    let name = palette.get(name).map_or(name, String::as_str);
+    let color = match name {
+        "black" => Color::Black,
+        "red" => Color::DarkRed,
+        "green" => Color::DarkGreen,
+        "yellow" => Color::DarkYellow,
+        "blue" => Color::DarkBlue,
+        "magenta" => Color::DarkMagenta,
+        "cyan" => Color::DarkCyan,
+        "gray" => Color::DarkGrey,
+        "light-red" => Color::Red,
+        "light-green" => Color::Green,
+        "light-yellow" => Color::Yellow,
+        "light-blue" => Color::Blue,
+        "light-magenta" => Color::Magenta,
+        "light-cyan" => Color::Cyan,
+        "light-gray" => Color::Grey,
+        "white" => Color::White,
+        hex => parse_rgb(hex)?,
+    };
+    Ok(color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_helix_theme() {
+        let theme = parse_helix_theme("./src/fixtures/helix.toml").unwrap();
+        assert_eq!(theme.name, "helix");
+        let rgb = |r, g, b| Some(Color::Rgb { r, g, b });
+        assert_eq!(theme.style.bg, rgb(0x1e, 0x1e, 0x2e));
+        assert_eq!(theme.style.fg, rgb(0xcd, 0xd6, 0xf4));
+
+        let keyword = theme.get_style("keyword").unwrap();
+        assert_eq!(keyword.fg, rgb(0xcb, 0xa6, 0xf7));
+        assert!(keyword.italic);
+        assert_eq!(
+            theme.get_style("string").unwrap().fg,
+            Some(Color::DarkGreen)
+        );
+        assert_eq!(
+            theme.get_style("function.macro").unwrap().fg,
+            rgb(0x89, 0xb4, 0xfa)
+        );
+        assert!(theme.get_style("ui.background").is_none());
+        assert_eq!(theme.diagnostic_styles.error, Color::Red);
+    }
+}
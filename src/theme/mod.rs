@@ -1,19 +1,182 @@
-use crossterm::style::{Attribute, Attributes, Color, ContentStyle};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
+use crossterm::style::Color;
+
+use crate::{diagnostics::Severity, git::HunkKind};
+
+mod base16;
+mod color;
+mod helix;
 mod vscode;
 
-pub use vscode::parse_vscode_theme;
+pub use color::ColorDepth;
+pub use vscode::{extensions_dir, import_vscode_theme, parse_vscode_theme};
+
+/// File extensions of the theme formats that can be loaded.
+const EXTENSIONS: &[&str] = &["json", "yaml", "yml", "toml"];
+
+/// The separators drawn around the statusline's highlighted edges.
+const POWERLINE_CHARS: [char; 4] = [' ', '\u{e0b0}', '\u{e0b2}', ' '];
+
+/// Loads a theme in the format its extension says: VSCode JSON, base16 YAML
+/// or Helix style TOML, with its colors converted to the ones `depth` has.
+pub fn load(file: &str, depth: ColorDepth) -> anyhow::Result<Theme> {
+    let extension = Path::new(file).extension().and_then(|ext| ext.to_str());
+    let theme = match extension {
+        Some("yaml" | "yml") => base16::parse_base16_theme(file)?,
+        Some("toml") => helix::parse_helix_theme(file)?,
+        _ => parse_vscode_theme(file)?,
+    };
+    Ok(theme.downsample(depth))
+}
+
+/// Returns the themes found in `dirs` as `(name, path)` pairs sorted by name,
+/// the name being the file name without its extension. A theme in an earlier
+/// directory hides one of the same name in a later one.
+pub fn discover(dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut themes: Vec<(String, PathBuf)> = vec![];
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, PathBuf)> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_theme(path))
+            .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+            .filter(|(name, _)| !themes.iter().any(|(n, _)| n == name))
+            .collect();
+        themes.append(&mut found);
+    }
+    themes.sort();
+    themes
+}
+
+fn parse_rgb(s: &str) -> anyhow::Result<Color> {
+    Ok(parse_rgba(s)?.0)
+}
+
+/// Parses `#rrggbb`, or `#rrggbbaa` with an alpha channel, which is 255
+/// without one.
+fn parse_rgba(s: &str) -> anyhow::Result<(Color, u8)> {
+    let Some(hex) = s.strip_prefix('#') else {
+        anyhow::bail!("Invalid color format : {s}");
+    };
+    if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Format must be in #rrggbb or #rrggbbaa, got : {s}");
+    }
+
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    let alpha = if hex.len() == 8 { byte(6)? } else { 255 };
+    Ok((
+        Color::Rgb {
+            r: byte(0)?,
+            g: byte(2)?,
+            b: byte(4)?,
+        },
+        alpha,
+    ))
+}
+
+fn is_theme(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext))
+}
 
 #[derive(Debug, Clone)]
 pub struct Theme {
     pub name: String,
     pub style: Style,
     pub gutter_style: Style,
+    pub bracket_match_style: Style,
+    pub selection_style: Style,
+    /// The background of the color columns.
+    pub ruler_style: Style,
+    /// Yanked text flashes in this style.
+    pub flash_style: Style,
+    pub popup_style: Style,
+    pub popup_selected_style: Style,
+    pub diagnostic_styles: DiagnosticStyles,
+    pub git_styles: GitStyles,
     pub statusline_style: StatuslineStyle,
     pub token_styles: Vec<TokenStyle>,
 }
 
 impl Theme {
+    pub fn diagnostic_style(&self, severity: Severity) -> Style {
+        let fg = match severity {
+            Severity::Error => self.diagnostic_styles.error,
+            Severity::Warning => self.diagnostic_styles.warning,
+            Severity::Info => self.diagnostic_styles.info,
+            Severity::Hint => self.diagnostic_styles.hint,
+        };
+        Style {
+            fg: Some(fg),
+            ..self.style.clone()
+        }
+    }
+
+    pub fn git_style(&self, kind: HunkKind) -> Style {
+        let fg = match kind {
+            HunkKind::Added => self.git_styles.added,
+            HunkKind::Modified => self.git_styles.modified,
+            HunkKind::Deleted => self.git_styles.deleted,
+        };
+        Style {
+            fg: Some(fg),
+            ..self.style.clone()
+        }
+    }
+
+    /// Converts every color to the closest one `depth` has.
+    pub fn downsample(mut self, depth: ColorDepth) -> Self {
+        if depth == ColorDepth::TrueColor {
+            return self;
+        }
+
+        let convert = |color: &mut Color| *color = depth.convert(*color);
+        let convert_style = |style: &mut Style| {
+            for color in [&mut style.fg, &mut style.bg].into_iter().flatten() {
+                convert(color);
+            }
+        };
+        for style in [
+            &mut self.style,
+            &mut self.gutter_style,
+            &mut self.bracket_match_style,
+            &mut self.selection_style,
+            &mut self.ruler_style,
+            &mut self.flash_style,
+            &mut self.popup_style,
+            &mut self.popup_selected_style,
+            &mut self.statusline_style.outer_style,
+            &mut self.statusline_style.inner_style,
+        ] {
+            convert_style(style);
+        }
+        for token in &mut self.token_styles {
+            convert_style(&mut token.style);
+        }
+        let diagnostics = &mut self.diagnostic_styles;
+        let git = &mut self.git_styles;
+        for color in [
+            &mut diagnostics.error,
+            &mut diagnostics.warning,
+            &mut diagnostics.info,
+            &mut diagnostics.hint,
+            &mut git.added,
+            &mut git.modified,
+            &mut git.deleted,
+        ] {
+            convert(color);
+        }
+        self
+    }
+
     pub fn get_style(&self, scope: &str) -> Option<Style> {
         self.token_styles.iter().find_map(|ts| {
             if ts.scope.contains(&scope.to_string()) {
@@ -36,15 +199,78 @@ impl Default for Theme {
                 italic: false,
             },
             gutter_style: Style::default(),
+            bracket_match_style: Style {
+                bg: Some(Color::DarkGrey),
+                ..Default::default()
+            },
+            selection_style: Style {
+                bg: Some(Color::DarkBlue),
+                ..Default::default()
+            },
+            ruler_style: Style {
+                bg: Some(Color::DarkGrey),
+                ..Default::default()
+            },
+            flash_style: Style {
+                bg: Some(Color::DarkYellow),
+                ..Default::default()
+            },
+            popup_style: Style {
+                fg: Some(Color::White),
+                bg: Some(Color::DarkGrey),
+                ..Default::default()
+            },
+            popup_selected_style: Style {
+                fg: Some(Color::Black),
+                bg: Some(Color::Grey),
+                ..Default::default()
+            },
+            diagnostic_styles: DiagnosticStyles::default(),
+            git_styles: GitStyles::default(),
             statusline_style: StatuslineStyle::default(),
             token_styles: vec![],
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct DiagnosticStyles {
+    pub error: Color,
+    pub warning: Color,
+    pub info: Color,
+    pub hint: Color,
+}
+
+impl Default for DiagnosticStyles {
+    fn default() -> Self {
+        Self {
+            error: Color::Red,
+            warning: Color::Yellow,
+            info: Color::Blue,
+            hint: Color::Grey,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GitStyles {
+    pub added: Color,
+    pub modified: Color,
+    pub deleted: Color,
+}
+
+impl Default for GitStyles {
+    fn default() -> Self {
+        Self {
+            added: Color::Green,
+            modified: Color::Blue,
+            deleted: Color::Red,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TokenStyle {
-    pub name: Option<String>,
     pub scope: Vec<String>,
     pub style: Style,
 }
@@ -63,3 +289,26 @@ pub struct Style {
     pub bold: bool,
     pub italic: bool,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_discover() {
+        let user = std::env::temp_dir().join("rustik-themes-test");
+        fs::create_dir_all(&user).unwrap();
+        fs::write(user.join("mocha.json"), "{}").unwrap();
+        fs::write(user.join("notes.txt"), "").unwrap();
+
+        let themes = discover(&[user.clone(), PathBuf::from("themes")]);
+        fs::remove_dir_all(&user).unwrap();
+
+        let names: Vec<&str> = themes.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(names.windows(2).all(|w| w[0] < w[1]), "{names:?}");
+        assert!(names.contains(&"dracula"));
+        assert!(!names.contains(&"notes"));
+        let mocha = themes.iter().find(|(name, _)| name == "mocha").unwrap();
+        assert_eq!(mocha.1, user.join("mocha.json"));
+    }
+}
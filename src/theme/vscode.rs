@@ -1,10 +1,17 @@
+use anyhow::Context;
 use crossterm::style::Color;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use serde_json::{Map, Value};
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
 
-use super::{StatuslineStyle, Style, Theme, TokenStyle};
+use crate::{config, log};
+
+use super::{parse_rgba, DiagnosticStyles, GitStyles, StatuslineStyle, Style, Theme, TokenStyle};
 
 static SYNTAX_HIGHLIGHTING_MAP: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
     let mut m = HashMap::new();
@@ -47,34 +54,213 @@ static SYNTAX_HIGHLIGHTING_MAP: Lazy<HashMap<&'static str, &'static str>> = Lazy
     m
 });
 
+/// Where VSCode keeps the extensions `:vscode-theme` imports themes from.
+pub fn extensions_dir() -> Option<PathBuf> {
+    Some(PathBuf::from(std::env::var_os("HOME")?).join(".vscode/extensions"))
+}
+
+/// Where the themes imported before are listed, by name.
+fn cache_path() -> Option<PathBuf> {
+    Some(config::state_dir()?.join("vscode_themes.json"))
+}
+
+/// Finds the theme called `name` in the VSCode extensions in `extensions`
+/// and converts it. Where it was found is cached, so importing it again
+/// doesn't go through every extension, unless the extension was updated or
+/// removed since.
+pub fn import_vscode_theme(name: &str, extensions: &Path) -> anyhow::Result<(PathBuf, Theme)> {
+    let cache = cache_path();
+    let mut cached: BTreeMap<String, PathBuf> = cache
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+
+    let key = theme_key(name);
+    let path = match cached.get(&key).filter(|path| path.is_file()) {
+        Some(path) => path.clone(),
+        None => {
+            let Some(path) = find_vscode_theme(extensions, name) else {
+                anyhow::bail!("No VSCode theme {name} in {}", extensions.display());
+            };
+            cached.insert(key, path.clone());
+            if let Some(Err(err)) = cache.map(|cache| save_cache(&cache, &cached)) {
+                log!(Warn, "VSCode theme cache: {err}");
+            }
+            path
+        }
+    };
+
+    let theme = parse_vscode_theme(&path.to_string_lossy())
+        .map_err(|err| anyhow::anyhow!("{}: {err}", path.display()))?;
+    Ok((path, theme))
+}
+
+fn save_cache(path: &Path, cached: &BTreeMap<String, PathBuf>) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(cached)?)?;
+    Ok(())
+}
+
+/// The theme called `name` among the extensions in `extensions`: one their
+/// `package.json` labels so, or else a `themes/*.json` file by that name.
+/// Names are matched ignoring case, spaces and dashes.
+fn find_vscode_theme(extensions: &Path, name: &str) -> Option<PathBuf> {
+    let key = theme_key(name);
+    let mut dirs: Vec<PathBuf> = fs::read_dir(extensions)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    // in name order, so of the versions of an extension kept side by side
+    // the last one wins
+    dirs.sort();
+    let mut found = None;
+    for dir in dirs {
+        let labelled = fs::read_to_string(dir.join("package.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<Value>(&json).ok())
+            .and_then(|package| {
+                let themes = package.pointer("/contributes/themes")?.as_array()?.clone();
+                themes.iter().find_map(|theme| {
+                    let label = theme.get("label")?.as_str()?;
+                    let path = theme.get("path")?.as_str()?;
+                    (theme_key(label) == key).then(|| dir.join(path))
+                })
+            });
+        let named = || {
+            fs::read_dir(dir.join("themes"))
+                .ok()?
+                .flatten()
+                .map(|entry| entry.path())
+                .find(|path| {
+                    path.extension().is_some_and(|ext| ext == "json")
+                        && path
+                            .file_stem()
+                            .is_some_and(|stem| theme_key(&stem.to_string_lossy()) == key)
+                })
+        };
+        if let Some(path) = labelled.or_else(named).filter(|path| path.is_file()) {
+            found = Some(path);
+        }
+    }
+    found
+}
+
+fn theme_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// How deep `include`s can nest, which also stops themes including each
+/// other.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
 pub fn parse_vscode_theme(file: &str) -> anyhow::Result<Theme> {
-    let contents = fs::read_to_string(file)?;
-    let vscode_theme: VsCodeTheme = serde_json::from_str(&contents)?;
+    let vscode_theme = read_theme(Path::new(file), 0)?;
+    let global = vscode_theme
+        .token_colors
+        .iter()
+        .find(|tc| tc.scope.is_none())
+        .map(|tc| &tc.settings);
+    let global_color = |key: &str| -> anyhow::Result<Option<(Color, u8)>> {
+        match global.and_then(|settings| settings.get(key)) {
+            Some(value) => parse_value(value).with_context(|| format!("tokenColors {key}")),
+            None => Ok(None),
+        }
+    };
+    let raw_color = |key: &str| -> anyhow::Result<Option<(Color, u8)>> {
+        match vscode_theme.colors.get(key) {
+            Some(value) => parse_value(value).with_context(|| format!("colors {key}")),
+            None => Ok(None),
+        }
+    };
+
+    let (default_fg, default_bg) = default_colors(vscode_theme.typ.as_deref());
+    let background = raw_color("editor.background")?
+        .or(global_color("background")?)
+        .map_or(default_bg, |(color, alpha)| blend(color, alpha, default_bg));
+    let foreground = raw_color("editor.foreground")?
+        .or(global_color("foreground")?)
+        .or(raw_color("foreground")?)
+        .map_or(default_fg, |(color, alpha)| blend(color, alpha, background));
+    // colors with an alpha channel are drawn over the editor's background
+    let color = |key: &str| -> anyhow::Result<Option<Color>> {
+        Ok(raw_color(key)?.map(|(color, alpha)| blend(color, alpha, background)))
+    };
 
     let token_styles = vscode_theme
         .token_colors
         .into_iter()
-        .map(|tc| tc.try_into())
-        .collect::<Result<Vec<TokenStyle>, _>>()?;
+        .filter(|tc| tc.scope.is_some())
+        .map(|tc| tc.into_token_style(background))
+        .collect::<anyhow::Result<Vec<TokenStyle>>>()?;
 
     let gutter_style = Style {
-        fg: vscode_theme
-            .colors
-            .iter()
-            .find(|(c, _)| **c == "editorLineNumber.foreground".to_string())
-            .map(|(_, hex)| {
-                parse_rgb(hex.as_str().expect("editorLineNumber.foreground is string")).unwrap()
-            }),
-        bg: vscode_theme
-            .colors
-            .iter()
-            .find(|(c, _)| **c == "editorLineNumber.background".to_string())
-            .map(|(_, hex)| {
-                parse_rgb(hex.as_str().expect("editorLineNumber.background is string")).unwrap()
-            }),
+        fg: color("editorLineNumber.foreground")?,
+        bg: color("editorLineNumber.background")?,
+        ..Default::default()
+    };
+
+    let bracket_match_style = Style {
+        bg: color("editorBracketMatch.border")?.or(Some(Color::DarkGrey)),
+        ..Default::default()
+    };
+
+    let selection_style = Style {
+        fg: color("editor.selectionForeground")?,
+        bg: color("editor.selectionBackground")?.or(Some(Color::DarkBlue)),
+        ..Default::default()
+    };
+
+    let ruler_style = Style {
+        bg: color("editorRuler.foreground")?.or(Some(Color::DarkGrey)),
+        ..Default::default()
+    };
+
+    let flash_style = Style {
+        bg: color("editor.findMatchBackground")?.or(Some(Color::DarkYellow)),
+        ..Default::default()
+    };
+
+    let popup_style = Style {
+        fg: color("editorHoverWidget.foreground")?.or(Some(foreground)),
+        bg: color("editorHoverWidget.background")?
+            .or(color("editorWidget.background")?)
+            .or(Some(Color::DarkGrey)),
+        ..Default::default()
+    };
+
+    let popup_selected_style = Style {
+        fg: color("editorSuggestWidget.selectedForeground")?
+            .or(color("list.activeSelectionForeground")?)
+            .or(popup_style.fg),
+        bg: color("editorSuggestWidget.selectedBackground")?
+            .or(color("list.activeSelectionBackground")?)
+            .or(Some(Color::Grey)),
         ..Default::default()
     };
 
+    let defaults = DiagnosticStyles::default();
+    let diagnostic_styles = DiagnosticStyles {
+        error: color("editorError.foreground")?.unwrap_or(defaults.error),
+        warning: color("editorWarning.foreground")?.unwrap_or(defaults.warning),
+        info: color("editorInfo.foreground")?.unwrap_or(defaults.info),
+        hint: color("editorHint.foreground")?.unwrap_or(defaults.hint),
+    };
+
+    let defaults = GitStyles::default();
+    let git_styles = GitStyles {
+        added: color("editorGutter.addedBackground")?.unwrap_or(defaults.added),
+        modified: color("editorGutter.modifiedBackground")?.unwrap_or(defaults.modified),
+        deleted: color("editorGutter.deletedBackground")?.unwrap_or(defaults.deleted),
+    };
+
     let statusline_style = StatuslineStyle {
         outer_style: Style {
             fg: Some(Color::Rgb { r: 0, g: 0, b: 0 }),
@@ -106,38 +292,107 @@ pub fn parse_vscode_theme(file: &str) -> anyhow::Result<Theme> {
     Ok(Theme {
         name: vscode_theme.name.unwrap_or_default(),
         style: Style {
-            fg: Some(parse_rgb(
-                vscode_theme
-                    .colors
-                    .get("editor.foreground")
-                    .expect("editor.foreground is present")
-                    .as_str()
-                    .expect("editor.foreground is string"),
-            )?),
-            bg: Some(parse_rgb(
-                vscode_theme
-                    .colors
-                    .get("editor.background")
-                    .expect("editor.background is present")
-                    .as_str()
-                    .expect("editor.background is string"),
-            )?),
+            fg: Some(foreground),
+            bg: Some(background),
             bold: false,
             italic: false,
         },
         token_styles,
         gutter_style,
+        bracket_match_style,
+        selection_style,
+        ruler_style,
+        flash_style,
+        popup_style,
+        popup_selected_style,
+        diagnostic_styles,
+        git_styles,
         statusline_style,
     })
 }
 
+/// Reads the theme at `path` with the one it includes under it: the colors
+/// it sets replace the included ones, and its token colors come first, so
+/// they're the ones found for a scope both have.
+fn read_theme(path: &Path, depth: usize) -> anyhow::Result<VsCodeTheme> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("can't read {}", path.display()))?;
+    let mut theme: VsCodeTheme = serde_json::from_str(&contents)
+        .with_context(|| format!("invalid theme {}", path.display()))?;
+    let Some(include) = theme.include.take() else {
+        return Ok(theme);
+    };
+    if depth == MAX_INCLUDE_DEPTH {
+        anyhow::bail!("{}: includes nest too deep", path.display());
+    }
+    let included = path.parent().unwrap_or(Path::new(".")).join(include);
+    let base = read_theme(&included, depth + 1)?;
+    let mut colors = base.colors;
+    colors.extend(theme.colors);
+    theme.colors = colors;
+    theme.token_colors.extend(base.token_colors);
+    theme.name = theme.name.or(base.name);
+    theme.typ = theme.typ.or(base.typ);
+    Ok(theme)
+}
+
+/// The foreground and background VSCode gives a theme of `typ` that leaves
+/// them out.
+fn default_colors(typ: Option<&str>) -> (Color, Color) {
+    let rgb = |r, g, b| Color::Rgb { r, g, b };
+    match typ {
+        Some("light") => (rgb(0x00, 0x00, 0x00), rgb(0xff, 0xff, 0xff)),
+        Some("hcLight") => (rgb(0x29, 0x29, 0x29), rgb(0xff, 0xff, 0xff)),
+        Some("hc" | "hcDark" | "hc-black") => (rgb(0xff, 0xff, 0xff), rgb(0x00, 0x00, 0x00)),
+        _ => (rgb(0xd4, 0xd4, 0xd4), rgb(0x1e, 0x1e, 0x1e)),
+    }
+}
+
+/// A color in the theme, `None` when it's `null`, which VSCode takes as
+/// not set.
+fn parse_value(value: &Value) -> anyhow::Result<Option<(Color, u8)>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(hex) => Ok(Some(parse_rgba(hex)?)),
+        value => anyhow::bail!("expected a color, got {value}"),
+    }
+}
+
+/// `color` seen through at `alpha` over `background`.
+fn blend(color: Color, alpha: u8, background: Color) -> Color {
+    match (color, background) {
+        (
+            Color::Rgb { r, g, b },
+            Color::Rgb {
+                r: br,
+                g: bg,
+                b: bb,
+            },
+        ) if alpha < 255 => {
+            let mix = |c: u8, under: u8| {
+                ((c as u16 * alpha as u16 + under as u16 * (255 - alpha as u16)) / 255) as u8
+            };
+            Color::Rgb {
+                r: mix(r, br),
+                g: mix(g, bg),
+                b: mix(b, bb),
+            }
+        }
+        _ => color,
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct VsCodeTheme {
     name: Option<String>,
     #[serde(rename = "type")]
     typ: Option<String>,
+    /// Another theme this one is built on, relative to it.
+    include: Option<String>,
+    #[serde(default)]
     colors: Map<String, Value>,
+    #[serde(default)]
     token_colors: Vec<VsCodeTokenColor>,
 }
 
@@ -145,51 +400,51 @@ struct VsCodeTheme {
 #[serde(rename_all = "camelCase")]
 struct VsCodeTokenColor {
     name: Option<String>,
-    scope: VsCodeScope,
+    /// Left out for the colors of text no other scope matches.
+    scope: Option<VsCodeScope>,
+    #[serde(default)]
     settings: Map<String, Value>,
 }
 
-impl TryFrom<VsCodeTokenColor> for TokenStyle {
-    type Error = anyhow::Error;
+impl VsCodeTokenColor {
+    /// The style of the token's scopes, its colors drawn over `background`
+    /// when they have an alpha channel.
+    fn into_token_style(self, background: Color) -> anyhow::Result<TokenStyle> {
+        let scope: Vec<String> = self.scope.map(Into::into).unwrap_or_default();
+        let what = self.name.unwrap_or_else(|| scope.join(", "));
+        let color = |key: &str| -> anyhow::Result<Option<Color>> {
+            let Some(value) = self.settings.get(key) else {
+                return Ok(None);
+            };
+            let parsed = parse_value(value).with_context(|| format!("tokenColors {what} {key}"))?;
+            Ok(parsed.map(|(color, alpha)| blend(color, alpha, background)))
+        };
 
-    fn try_from(tc: VsCodeTokenColor) -> Result<Self, Self::Error> {
-        let mut style = Style::default();
-
-        if let Some(fg) = tc.settings.get("foreground") {
-            style.fg =
-                Some(parse_rgb(fg.as_str().expect("fg is string")).expect("parsing rgb works"));
-        }
-
-        if let Some(bg) = tc.settings.get("background") {
-            style.bg =
-                Some(parse_rgb(bg.as_str().expect("bg is string")).expect("parsing rgb works"));
-        }
-
-        if let Some(font_styles) = tc.settings.get("fontStyle") {
-            style.bold = font_styles
-                .as_str()
-                .expect("font_styles is string")
-                .contains("bold");
-            style.italic = font_styles
-                .as_str()
-                .expect("font_styles is string")
-                .contains("italic");
+        let mut style = Style {
+            fg: color("foreground")?,
+            bg: color("background")?,
+            ..Default::default()
+        };
+        match self.settings.get("fontStyle") {
+            Some(Value::String(font_style)) => {
+                style.bold = font_style.contains("bold");
+                style.italic = font_style.contains("italic");
+            }
+            Some(Value::Null) | None => {}
+            Some(value) => {
+                anyhow::bail!("tokenColors {what} fontStyle: expected a string, got {value}")
+            }
         }
 
-        Ok(Self {
-            name: tc.name,
-            scope: tc.scope.into(),
-            style,
-        })
+        Ok(TokenStyle { scope, style })
     }
 }
 
 fn translate_scope(vscode_scope: String) -> String {
-    let vscode_scope = SYNTAX_HIGHLIGHTING_MAP
+    SYNTAX_HIGHLIGHTING_MAP
         .get(&vscode_scope.as_str())
         .map(|s| s.to_string())
-        .unwrap_or(vscode_scope);
-    return vscode_scope;
+        .unwrap_or(vscode_scope)
 }
 
 #[derive(Debug, Deserialize)]
@@ -208,24 +463,6 @@ impl From<VsCodeScope> for Vec<String> {
     }
 }
 
-fn parse_rgb(s: &str) -> anyhow::Result<Color> {
-    if !s.starts_with("#") {
-        anyhow::bail!("Invalid color format : {s}");
-    }
-
-    if s.len() != 7 {
-        anyhow::bail!("Format must be in #rrggbb, got : {s}");
-    }
-
-    let r = u8::from_str_radix(&s[1..=2], 16)?;
-    let g = u8::from_str_radix(&s[3..=4], 16)?;
-    let b = u8::from_str_radix(&s[5..=6], 16)?;
-
-    // println!("{r}, {g}, {b}");
-
-    Ok(Color::Rgb { r, g, b })
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -237,9 +474,84 @@ mod test {
     }
 
     #[test]
-    fn test_parse_rgb() {
-        let rgb = "#08afBB";
-        let rgb = parse_rgb(rgb);
-        println!("{rgb:#?}");
+    fn test_find_vscode_theme() {
+        let extensions = std::env::temp_dir().join("rustik-vscode-extensions");
+        let labelled = extensions.join("catppuccin.catppuccin-vsc-3.0.0");
+        fs::create_dir_all(labelled.join("themes")).unwrap();
+        fs::write(
+            labelled.join("package.json"),
+            r#"{"contributes": {"themes": [{"label": "Catppuccin Mocha", "path": "./themes/mocha.json"}]}}"#,
+        )
+        .unwrap();
+        fs::write(labelled.join("themes/mocha.json"), "{}").unwrap();
+        let named = extensions.join("dracula-theme.theme-dracula-2.24.0");
+        fs::create_dir_all(named.join("themes")).unwrap();
+        fs::write(named.join("themes/dracula-soft.json"), "{}").unwrap();
+
+        let mocha = find_vscode_theme(&extensions, "catppuccin-mocha");
+        let dracula = find_vscode_theme(&extensions, "Dracula Soft");
+        let missing = find_vscode_theme(&extensions, "nord");
+        fs::remove_dir_all(&extensions).unwrap();
+        assert_eq!(mocha, Some(labelled.join("./themes/mocha.json")));
+        assert_eq!(dracula, Some(named.join("themes/dracula-soft.json")));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn test_parse_rgba() {
+        let rgb = |r, g, b| Color::Rgb { r, g, b };
+        assert_eq!(parse_rgba("#08afBB").unwrap(), (rgb(0x08, 0xaf, 0xbb), 255));
+        assert_eq!(
+            parse_rgba("#62688066").unwrap(),
+            (rgb(0x62, 0x68, 0x80), 0x66)
+        );
+        assert!(parse_rgba("#fff").is_err());
+        assert!(parse_rgba("#ééé").is_err());
+        assert_eq!(
+            blend(rgb(255, 255, 255), 0x80, rgb(0, 0, 0)),
+            rgb(128, 128, 128)
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("rustik-vscode-{}", std::process::id()));
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::write(
+            dir.join("base/dark.json"),
+            r##"{"type": "light", "colors": {"editor.selectionBackground": "#ffffff80"},
+                "tokenColors": [{"scope": "comment", "settings": {"foreground": "#00ff00"}},
+                                {"scope": "keyword", "settings": {"foreground": "#0000ff"}}]}"##,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("theme.json"),
+            r##"{"name": "Mine", "include": "./base/dark.json", "colors": {"editor.background": "#000000"},
+                "tokenColors": [{"settings": {"foreground": "#eeeeee"}},
+                                {"scope": "comment", "settings": {"foreground": "#ff0000", "fontStyle": "italic"}}]}"##,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("bad.json"),
+            r#"{"colors": {"editor.background": 12}}"#,
+        )
+        .unwrap();
+
+        let theme = parse_vscode_theme(&dir.join("theme.json").to_string_lossy());
+        let bad = parse_vscode_theme(&dir.join("bad.json").to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        let theme = theme.unwrap();
+        let rgb = |r, g, b| Some(Color::Rgb { r, g, b });
+        assert_eq!(theme.name, "Mine");
+        assert_eq!(theme.style.fg, rgb(0xee, 0xee, 0xee));
+        assert_eq!(theme.style.bg, rgb(0, 0, 0));
+        assert_eq!(theme.selection_style.bg, rgb(128, 128, 128));
+        let comment = theme.get_style("comment").unwrap();
+        assert_eq!((comment.fg, comment.italic), (rgb(0xff, 0, 0), true));
+        assert_eq!(theme.get_style("keyword").unwrap().fg, rgb(0, 0, 0xff));
+        assert_eq!(
+            format!("{:#}", bad.unwrap_err()),
+            "colors editor.background: expected a color, got 12"
+        );
     }
 }
@@ -0,0 +1,83 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, editor::Action};
+
+/// The undo history of a file as it was written, along with a hash of the
+/// text it applies to.
+#[derive(Debug, Serialize, Deserialize)]
+struct UndoFile {
+    hash: u64,
+    actions: Vec<Action>,
+}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Where the undo history of `file` is kept.
+pub fn path(file: &str) -> Option<PathBuf> {
+    config::state_file("undo", &fs::canonicalize(file).ok()?)
+}
+
+/// Saves the last `max_entries` undo actions for `text`, the contents the
+/// file was just written with.
+pub fn save(path: &Path, text: &str, actions: &[Action], max_entries: usize) -> anyhow::Result<()> {
+    if actions.is_empty() {
+        _ = fs::remove_file(path);
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let skip = actions.len().saturating_sub(max_entries);
+    let undo = UndoFile {
+        hash: hash(text),
+        actions: actions[skip..].to_vec(),
+    };
+    fs::write(path, serde_json::to_string(&undo)?)?;
+    Ok(())
+}
+
+/// Loads the undo history saved for `text`. A history saved for other
+/// contents, because the file was changed outside the editor, is dropped.
+pub fn load(path: &Path, text: &str) -> Option<Vec<Action>> {
+    let json = fs::read_to_string(path).ok()?;
+    let undo: UndoFile = serde_json::from_str(&json).ok()?;
+    if undo.hash != hash(text) {
+        _ = fs::remove_file(path);
+        return None;
+    }
+    Some(undo.actions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join("rustik-test-undo.json");
+        let actions = vec![
+            Action::DeleteLineAt(0),
+            Action::InsertTextAt(1, 2, "ab".to_string()),
+            Action::RemoveCharAt(3, 4),
+        ];
+        save(&path, "a\nb", &actions, 2).unwrap();
+
+        let loaded = load(&path, "a\nb").unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(matches!(loaded[1], Action::RemoveCharAt(3, 4)));
+
+        assert!(load(&path, "a\nchanged").is_none());
+        assert!(!path.exists());
+    }
+}
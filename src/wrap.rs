@@ -0,0 +1,46 @@
+/// Whether a line can wrap after `c`: spaces, hyphens and soft hyphens.
+fn breaks_after(c: char) -> bool {
+    matches!(c, ' ' | '-' | '\u{ad}')
+}
+
+/// Where the rows of `line` start when it's wrapped to `width` columns. Rows
+/// end after the last space or hyphen that fits, or at `width` for words
+/// longer than a row. The first row starts at 0.
+pub fn row_starts(line: &str, width: usize) -> Vec<usize> {
+    let width = width.max(1);
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut starts = vec![0];
+    let mut start = 0;
+    while chars.len() - start > width {
+        let end = start + width;
+        let next = (start + 1..=end)
+            .rev()
+            .find(|&i| breaks_after(chars[i - 1].1))
+            .unwrap_or(end);
+        starts.push(chars[next].0);
+        start = next;
+    }
+    starts
+}
+
+/// The row of `starts` that byte `x` is on.
+pub fn row_of(starts: &[usize], x: usize) -> usize {
+    starts
+        .partition_point(|start| *start <= x)
+        .saturating_sub(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_row_starts() {
+        assert_eq!(row_starts("short", 10), [0]);
+        assert_eq!(row_starts("the quick brown fox", 10), [0, 10]);
+        assert_eq!(row_starts("a well-known fact", 10), [0, 7]);
+        assert_eq!(row_starts("abcdefghijkl", 5), [0, 5, 10]);
+        let starts = row_starts("the quick brown fox", 10);
+        assert_eq!((row_of(&starts, 9), row_of(&starts, 10)), (0, 1));
+    }
+}